@@ -0,0 +1,185 @@
+//! Buildkite reports a repo's checks as commit statuses linking to the build which ran them. Its API
+//! is used to link failures to the job which failed instead, as Github does for check runs.
+
+use crate::{config::BuildkiteConfig, Result};
+use anyhow::anyhow;
+use reqwest::{header::AUTHORIZATION, StatusCode};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::warn;
+
+const API_URL: &str = "https://api.buildkite.com/v2";
+
+#[derive(Debug)]
+pub struct Buildkite {
+    config: BuildkiteConfig,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct Build {
+    #[serde(default)]
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Job {
+    state: Option<String>,
+    web_url: Option<String>,
+}
+
+impl Buildkite {
+    pub fn new(config: BuildkiteConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn config(&self) -> &BuildkiteConfig {
+        &self.config
+    }
+
+    /// Where a failed status should link to: the first job of its build which failed, if it can be
+    /// looked up, otherwise the build itself
+    pub async fn failure_url(&self, build_url: &str) -> String {
+        if self.config.api_token.is_none() {
+            return build_url.to_owned();
+        }
+
+        match self.failed_job_url(build_url).await {
+            Ok(Some(job_url)) => job_url,
+            Ok(None) => build_url.to_owned(),
+            Err(e) => {
+                warn!("Unable to look up the failed job of {}: {:?}", build_url, e);
+                build_url.to_owned()
+            }
+        }
+    }
+
+    async fn failed_job_url(&self, build_url: &str) -> Result<Option<String>> {
+        let (pipeline, number) = match parse_build_url(&self.config.organization, build_url) {
+            Some(build) => build,
+            None => return Ok(None),
+        };
+        let url = format!(
+            "{}/organizations/{}/pipelines/{}/builds/{}",
+            API_URL, self.config.organization, pipeline, number
+        );
+
+        let build: Build = self.get(&url).await?;
+        Ok(failed_job(&build))
+    }
+
+    /// GET from the API, retrying with a backoff if the request fails transiently, e.g. times out or
+    /// is rate limited
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let token = self.config.api_token.as_deref().unwrap_or_default();
+        let attempts = self.config.api_attempts();
+
+        for attempt in 1.. {
+            let response = self
+                .client
+                .get(url)
+                .header(AUTHORIZATION, format!("Bearer {}", token))
+                .timeout(Duration::from_secs(30))
+                .send()
+                .await;
+
+            let error = match response {
+                Ok(response) if response.status().is_success() => {
+                    return Ok(response.json().await?)
+                }
+                Ok(response) if is_transient(response.status()) => {
+                    anyhow!("GET {} failed: {}", url, response.status())
+                }
+                Ok(response) => return Err(anyhow!("GET {} failed: {}", url, response.status())),
+                Err(e) if e.is_timeout() || e.is_connect() => e.into(),
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt >= attempts {
+                return Err(error);
+            }
+            warn!("{}, retrying: attempt {}", error, attempt);
+            tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        }
+
+        unreachable!()
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// The pipeline and number of a build from its URL, e.g.
+/// `https://buildkite.com/<organization>/<pipeline>/builds/<number>`
+fn parse_build_url<'a>(organization: &str, url: &'a str) -> Option<(&'a str, u64)> {
+    let path = url
+        .strip_prefix("https://buildkite.com/")?
+        .strip_prefix(organization)?
+        .strip_prefix('/')?;
+    let path = path.split(['#', '?']).next()?;
+
+    match path.split('/').collect::<Vec<_>>()[..] {
+        [pipeline, "builds", number] => Some((pipeline, number.parse().ok()?)),
+        _ => None,
+    }
+}
+
+fn failed_job(build: &Build) -> Option<String> {
+    build
+        .jobs
+        .iter()
+        .find(|job| matches!(job.state.as_deref(), Some("failed") | Some("timed_out")))
+        .and_then(|job| job.web_url.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds() {
+        assert_eq!(
+            parse_build_url("aptos", "https://buildkite.com/aptos/ci/builds/1234"),
+            Some(("ci", 1234))
+        );
+        assert_eq!(
+            parse_build_url(
+                "aptos",
+                "https://buildkite.com/aptos/ci/builds/1234#0189-a2"
+            ),
+            Some(("ci", 1234))
+        );
+        assert_eq!(
+            parse_build_url("aptos", "https://buildkite.com/other/ci/builds/1234"),
+            None
+        );
+        assert_eq!(
+            parse_build_url("aptos", "https://ci.example.com/aptos/ci/builds/1234"),
+            None
+        );
+
+        let build: Build = serde_json::from_str(
+            r#"{
+                "jobs": [
+                    {"type": "waiter"},
+                    {"state": "passed", "web_url": "https://buildkite.com/aptos/ci/builds/1234#a"},
+                    {"state": "failed", "web_url": "https://buildkite.com/aptos/ci/builds/1234#b"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            failed_job(&build).as_deref(),
+            Some("https://buildkite.com/aptos/ci/builds/1234#b")
+        );
+
+        let config: BuildkiteConfig = toml::from_str(r#"organization = "aptos""#).unwrap();
+        assert_eq!(config.check_name("buildkite/ci"), "ci");
+        assert_eq!(config.check_name("buildkite/ci/lint"), "ci/lint");
+        assert_eq!(config.check_name("jenkins"), "jenkins");
+    }
+}
@@ -0,0 +1,146 @@
+//! Changelog entries, taken from a section of a PR's description or from its title, which are
+//! appended to a changelog file in the commit landing the PR so that releases can be cut without
+//! mining PR titles
+
+use crate::{config::ChangelogConfig, state::PullRequestState};
+
+/// The lines a PR adds to the changelog, if it has an entry
+pub fn entry(config: &ChangelogConfig, pull: &PullRequestState) -> Option<String> {
+    let mut labels: Vec<&str> = pull
+        .labels
+        .iter()
+        .filter_map(|label| config.category(label))
+        .collect();
+    labels.sort_unstable();
+    let category = labels.first();
+
+    let items = match section(&pull.body, config.section()) {
+        Some(section) => {
+            let items: Vec<String> = section
+                .lines()
+                .map(|line| {
+                    let line = line.trim();
+                    line.strip_prefix("- ")
+                        .or_else(|| line.strip_prefix("* "))
+                        .unwrap_or(line)
+                        .trim()
+                        .to_owned()
+                })
+                .filter(|item| !item.is_empty())
+                .collect();
+
+            // Templates commonly ask for "None" when a change doesn't need an entry
+            match &items[..] {
+                [] => return None,
+                [item] if item.eq_ignore_ascii_case("none") || item.eq_ignore_ascii_case("n/a") => {
+                    return None
+                }
+                _ => items,
+            }
+        }
+        // Without a section, only labeled PRs have an entry
+        None if category.is_some() => vec![pull.title.trim().to_owned()],
+        None => return None,
+    };
+
+    let mut entry = String::new();
+    for item in items {
+        entry.push_str("- ");
+        if let Some(category) = category {
+            entry.push_str(category);
+            entry.push_str(": ");
+        }
+        entry.push_str(&format!("{} (#{})\n", item, pull.number));
+    }
+    Some(entry)
+}
+
+/// The contents of the section of a markdown document under the heading `heading`, up to the next
+/// heading, with any HTML comments left by a PR template removed
+fn section(body: &str, heading: &str) -> Option<String> {
+    let body = strip_comments(body);
+    let mut lines = body.lines();
+    lines.find(|line| {
+        line.trim_start()
+            .strip_prefix('#')
+            .map(|title| title.trim_start_matches('#').trim())
+            .is_some_and(|title| title.eq_ignore_ascii_case(heading))
+    })?;
+
+    let section: Vec<&str> = lines
+        .take_while(|line| !line.trim_start().starts_with('#'))
+        .collect();
+    Some(section.join("\n"))
+}
+
+fn strip_comments(body: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<!--") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start..].find("-->") {
+            Some(end) => &rest[start + end + 3..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeGithub;
+
+    fn pull(title: &str, body: &str, labels: &[&str]) -> PullRequestState {
+        let mut pull = PullRequestState::from_pull_request(&FakeGithub::new().open_pull(7, "head"));
+        pull.title = title.to_owned();
+        pull.body = body.to_owned();
+        pull.labels = labels.iter().map(|label| label.to_string()).collect();
+        pull
+    }
+
+    #[test]
+    fn entries() {
+        let config: ChangelogConfig = toml::from_str(
+            r#"
+            file = "CHANGELOG.md"
+            labels = { bug = "Fixed", feature = "Added" }
+            "#,
+        )
+        .unwrap();
+
+        let body = "Fixes the thing\n\n## Changelog\n<!-- Describe the change for users -->\n\
+                    - Fixed the thing\n* Documented the thing\n\n## Test Plan\nRan it";
+        assert_eq!(
+            entry(&config, &pull("Fix", body, &[])).as_deref(),
+            Some("- Fixed the thing (#7)\n- Documented the thing (#7)\n")
+        );
+        assert_eq!(
+            entry(
+                &config,
+                &pull("Fix", "### changelog\nThe thing works", &["bug"])
+            )
+            .as_deref(),
+            Some("- Fixed: The thing works (#7)\n")
+        );
+
+        // Labeled PRs without a section use their title
+        assert_eq!(
+            entry(&config, &pull("Add a thing", "", &["feature", "chore"])).as_deref(),
+            Some("- Added: Add a thing (#7)\n")
+        );
+        assert_eq!(entry(&config, &pull("Refactor", "", &["chore"])), None);
+        assert_eq!(
+            entry(&config, &pull("Add", "## Changelog\nNone\n", &["feature"])),
+            None
+        );
+        assert_eq!(
+            entry(
+                &config,
+                &pull("Add", "## Changelog\n<!-- - Entry -->\n", &[])
+            ),
+            None
+        );
+    }
+}
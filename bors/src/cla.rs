@@ -0,0 +1,74 @@
+//! Contributor License Agreements, which the authors of PRs must have signed before their PRs can
+//! land. Whether an author has signed is reported by an external CLA service, either as a check on
+//! the PR or through an allowlist API, rather than being one of the checks the PR is tested with.
+
+use crate::{
+    config::{ClaConfig, RepoConfig},
+    github_api::GithubApi,
+    state::PullRequestState,
+    Result,
+};
+use anyhow::anyhow;
+use reqwest::{header::AUTHORIZATION, StatusCode};
+use tracing::info;
+
+/// Whether the author of a PR has signed the CLA, according to each of the configured sources
+pub async fn is_signed(
+    config: &ClaConfig,
+    repo: &RepoConfig,
+    github: &dyn GithubApi,
+    pull: &PullRequestState,
+) -> Result<bool> {
+    let author = pull.author.as_deref();
+    if author.is_some_and(|author| config.exempt.iter().any(|user| user == author)) {
+        return Ok(true);
+    }
+
+    if let Some(check) = &config.check {
+        let passed = github
+            .commit_checks(repo.owner(), repo.name(), &pull.head_ref_oid)
+            .await?
+            .iter()
+            .filter(|c| &c.name == check)
+            .any(|c| matches!(c.conclusion, Some(github::Conclusion::Success)));
+        if !passed {
+            info!(
+                "pr #{} hasn't passed the '{}' CLA check",
+                pull.number, check
+            );
+            return Ok(false);
+        }
+    }
+
+    if let Some(url) = &config.allowlist_url {
+        let author = match author {
+            Some(author) => author,
+            None => return Ok(false),
+        };
+        if !is_allowlisted(config, url, author).await? {
+            info!("{} isn't on the CLA allowlist", author);
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+async fn is_allowlisted(config: &ClaConfig, url: &str, user: &str) -> Result<bool> {
+    let url = url.replace("{user}", user);
+    let mut request = reqwest::Client::new().get(&url);
+    if let Some(token) = &config.api_token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    let response = request.send().await?;
+    match response.status() {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND => Ok(false),
+        status => Err(anyhow!(
+            "checking the CLA allowlist {} failed: {}",
+            url,
+            status
+        )),
+    }
+}
@@ -1,15 +1,17 @@
 //! Defines commands which can be asked to be performed
 
 use crate::{
-    config::RepoConfig,
+    config::{CanaryPermission, RepoConfig},
     event_processor::{ActivePullRequestContext, CommandContext},
+    gate::{Candidate, Gate},
+    git::CHERRY_PICK_BRANCH_PREFIX,
     project_board::ProjectBoard,
     state::{Priority, Status},
     Result,
 };
 use github::client::NewPullRequest;
-use log::info;
 use thiserror::Error;
+use tracing::info;
 
 #[derive(Error, Debug)]
 #[error("invalid command")]
@@ -29,6 +31,7 @@ enum CommandType {
     CherryPick(CherryPick),
     Help,
     Priority(PriorityCommand),
+    Update(Update),
 }
 
 impl CommandType {
@@ -40,6 +43,7 @@ impl CommandType {
             CommandType::CherryPick(_) => "CherryPick",
             CommandType::Help => "Help",
             CommandType::Priority(_) => "Priority",
+            CommandType::Update(_) => "Update",
         }
     }
 }
@@ -125,6 +129,7 @@ impl Command {
             "cherry" | "cherry-pick" => CommandType::CherryPick(CherryPick::with_args(args)?),
             "help" | "h" => CommandType::Help,
             "priority" => CommandType::Priority(PriorityCommand::with_args(args)?),
+            "update" | "sync" => CommandType::Update(Update::with_args(args)?),
 
             _ => return Err(ParseCommandError),
         };
@@ -140,29 +145,40 @@ impl Command {
         Help::new(config, project_board)
     }
 
+    pub fn name(&self) -> &'static str {
+        self.command_type.name()
+    }
+
+    /// The command as it was given, e.g. `/land priority=high`
+    pub fn as_str(&self) -> &str {
+        &self.cmd
+    }
+
     pub async fn is_authorized(&self, ctx: &CommandContext<'_>) -> Result<bool> {
         let mut is_authorized = false;
         let mut reason = None;
 
-        // Check to see if the user is a collaborator
-        if ctx
-            .github()
-            .repos()
-            .is_collaborator(ctx.config().owner(), ctx.config().name(), ctx.sender())
-            .await?
-            .into_inner()
+        // Check the repo's own allow and deny lists before the user's Github permissions
+        if let Some(denied) = ctx.config().denies_user(ctx.sender()) {
+            reason = Some(denied);
+        } else if self.is_canary_by_author(ctx)
+            || ctx
+                .github()
+                .is_collaborator(ctx.config().owner(), ctx.config().name(), ctx.sender())
+                .await?
         {
             is_authorized = true;
         } else {
-            reason = Some("Not Collaborator");
+            reason = Some("only collaborators on this repo can run commands, ask one of its admins for access");
         }
 
         // Post a comment to Github if there was a reason why the user wasn't authorized
         if !is_authorized {
             if let Some(reason) = reason {
                 ctx.create_pr_comment(&format!(
-                    "@{}: :key: Insufficient privileges: {}",
+                    "@{} :key: Insufficient privileges to run `{}`: {}",
                     ctx.sender(),
+                    self.as_str(),
                     reason
                 ))
                 .await?;
@@ -172,11 +188,22 @@ impl Command {
         Ok(is_authorized)
     }
 
+    /// Whether this is the author of a PR canarying it, in a repo which lets them do so without
+    /// needing to be able to land it
+    fn is_canary_by_author(&self, ctx: &CommandContext<'_>) -> bool {
+        matches!(self.command_type, CommandType::Canary)
+            && ctx.config().canary_permission() == CanaryPermission::Authors
+            && ctx
+                .pr()
+                .and_then(|pr| pr.author.as_deref())
+                .is_some_and(|author| author == ctx.sender())
+    }
+
     pub async fn execute(&self, ctx: &mut CommandContext<'_>) -> Result<()> {
         info!("Executing command '{}'", self.command_type.name());
 
         match &self.command_type {
-            CommandType::Land(l) => Self::execute_land(ctx, l.priority(), l.squash).await?,
+            CommandType::Land(l) => Self::execute_land(ctx, l).await?,
             CommandType::Cancel => Self::cancel_land(ctx).await?,
             CommandType::Canary => Self::canary_land(ctx).await?,
             CommandType::CherryPick(c) => Self::cherry_pick(ctx, c.target()).await?,
@@ -185,30 +212,27 @@ impl Command {
                     .await?
             }
             CommandType::Priority(p) => Self::execute_priority(ctx, p.priority()).await?,
+            CommandType::Update(u) => Self::update(ctx, u.rebase).await?,
         }
 
         Ok(())
     }
 
-    async fn execute_land(
-        ctx: &mut CommandContext<'_>,
-        priority: Option<Priority>,
-        squash: Option<bool>,
-    ) -> Result<()> {
+    async fn execute_land(ctx: &mut CommandContext<'_>, land: &Land) -> Result<()> {
         let mut ctx = if let Some(ctx) = ctx.active_pull_request_context().await {
             ctx
         } else {
             return Ok(());
         };
 
-        if let Some(priority) = priority {
+        if let Some(priority) = land.priority() {
             Self::set_priority(&mut ctx, priority).await?;
         }
-        if let Some(squash) = squash {
+        if let Some(squash) = land.squash {
             Self::set_squash(&mut ctx, squash).await?;
         }
 
-        Self::mark_pr_ready_to_land(&mut ctx).await
+        Self::mark_pr_ready_to_land(&mut ctx, land).await
     }
 
     async fn execute_priority(ctx: &mut CommandContext<'_>, priority: Priority) -> Result<()> {
@@ -261,7 +285,10 @@ impl Command {
         Ok(())
     }
 
-    async fn mark_pr_ready_to_land(ctx: &mut ActivePullRequestContext<'_>) -> Result<()> {
+    async fn mark_pr_ready_to_land(
+        ctx: &mut ActivePullRequestContext<'_>,
+        land: &Land,
+    ) -> Result<()> {
         info!("attempting to mark pr #{} ReadyToLand", ctx.pr().number);
 
         // Skip marking for land on draft PRs
@@ -274,37 +301,7 @@ impl Command {
         }
 
         match ctx.pr().status {
-            Status::InReview => {
-                // double check the approval on the PR
-                if ctx.config().require_review() && !ctx.pr().approved {
-                    let approved = ctx
-                        .github()
-                        .get_review_decision(
-                            ctx.config().repo().owner(),
-                            ctx.config().repo().name(),
-                            ctx.pr().number,
-                        )
-                        .await?;
-
-                    ctx.pr_mut().approved = approved;
-                }
-
-                if ctx.pr().approved || !ctx.config().require_review() {
-                    ctx.update_pr_status(Status::queued()).await?;
-                    info!("pr #{} queued for landing", ctx.pr().number);
-                } else {
-                    info!(
-                        "pr #{} is missing approvals, unable to queue for landing",
-                        ctx.pr().number
-                    );
-
-                    let msg = format!(
-                        "@{} :exclamation: This PR is still missing approvals, unable to queue for landing",
-                        ctx.sender(),
-                    );
-                    ctx.create_pr_comment(&msg).await?;
-                }
-            }
+            Status::InReview => {}
             Status::Queued(_) | Status::Testing { .. } => {
                 info!("pr #{} already queued for landing", ctx.pr().number);
 
@@ -314,6 +311,7 @@ impl Command {
                 );
 
                 ctx.create_pr_comment(&msg).await?;
+                return Ok(());
             }
             Status::Canary { .. } => {
                 let msg = format!(
@@ -322,9 +320,61 @@ impl Command {
                 );
 
                 ctx.create_pr_comment(&msg).await?;
+                return Ok(());
+            }
+        }
+
+        // double check the approval on the PR, unless it was dismissed by a push which Github
+        // doesn't know about
+        if ctx.config().require_review()
+            && !ctx.pr().approved
+            && ctx.pr().approval_dismissed_by.is_none()
+        {
+            let approved = ctx
+                .github()
+                .get_review_decision(
+                    ctx.config().repo().owner(),
+                    ctx.config().repo().name(),
+                    ctx.pr().number,
+                )
+                .await?;
+
+            ctx.pr_mut().approved = approved;
+        }
+
+        let gates = Gate::configured(ctx.config(), land.allow_binaries);
+        let candidate = Candidate::fetch(
+            ctx.config(),
+            ctx.github(),
+            ctx.pr(),
+            ctx.sender(),
+            land.oversize,
+            &gates,
+        )
+        .await?;
+        for gate in gates {
+            if let Some(refusal) = gate.refusal(&candidate).await? {
+                info!(
+                    "pr #{} didn't pass the {:?} gate, unable to queue for landing",
+                    ctx.pr().number,
+                    gate
+                );
+                ctx.create_pr_comment(&refusal).await?;
+                return Ok(());
             }
         }
 
+        if ctx.config().trial_merge() && !ctx.trial_merge().await? {
+            info!(
+                "pr #{} has a merge conflict, unable to queue for landing",
+                ctx.pr().number
+            );
+            return Ok(());
+        }
+
+        ctx.update_pr_status(Status::queued()).await?;
+        info!("pr #{} queued for landing", ctx.pr().number);
+
         Ok(())
     }
 
@@ -352,7 +402,7 @@ impl Command {
         match ctx.pr().status {
             Status::InReview => {
                 let canary_running = if let Some(board) = ctx.project_board() {
-                    board.list_canary_cards(&ctx).await.map_or(false, |cards| cards.len() > 0)
+                    board.list_canary_cards(&ctx).await.is_ok_and(|cards| !cards.is_empty())
                 } else {
                     false
                 };
@@ -382,6 +432,97 @@ impl Command {
         Ok(())
     }
 
+    async fn update(ctx: &mut CommandContext<'_>, rebase: bool) -> Result<()> {
+        let mut ctx = if let Some(ctx) = ctx.active_pull_request_context().await {
+            ctx
+        } else {
+            return Ok(());
+        };
+
+        info!("Updating pr #{} with its base branch", ctx.pr().number);
+
+        match ctx.pr().status {
+            Status::InReview | Status::Queued(_) => {}
+            Status::Testing { .. } | Status::Canary { .. } => {
+                let msg = format!(
+                    "@{} :bulb: This PR is currently being tested, cancel first if you want to update it",
+                    ctx.sender(),
+                );
+                ctx.create_pr_comment(&msg).await?;
+                return Ok(());
+            }
+        }
+
+        let head_repo = if let Some(head_repo) = ctx.pr().head_repo.clone() {
+            head_repo
+        } else {
+            let msg = format!(
+                "@{} :exclamation: Unable to update this PR as its head repository no longer exists",
+                ctx.sender(),
+            );
+            ctx.create_pr_comment(&msg).await?;
+            return Ok(());
+        };
+
+        let base_ref = ctx.pr().base_ref_name.clone();
+        let head_ref = ctx.pr().head_ref_name.clone();
+        let head_oid = ctx.pr().head_ref_oid.clone();
+
+        let branch = format!("update/{}", ctx.pr().number);
+
+        let new_oid = if let Some(oid) = ctx
+            .git_repository()
+            .fetch_and_update(&base_ref, &head_oid, &branch, rebase)?
+        {
+            oid
+        } else {
+            let msg = format!(
+                "@{} :lock: Merge Conflict: unable to update this PR with the latest changes from \
+                `{}`. The conflicts will need to be resolved manually.",
+                ctx.sender(),
+                base_ref,
+            );
+            ctx.create_pr_comment(&msg).await?;
+            return Ok(());
+        };
+
+        if new_oid == head_oid {
+            let msg = format!(
+                "@{} :bulb: This PR is already up to date with `{}`",
+                ctx.sender(),
+                base_ref,
+            );
+            ctx.create_pr_comment(&msg).await?;
+            return Ok(());
+        }
+
+        if ctx
+            .git_repository()
+            .push_to_remote(&head_repo, &head_ref, &head_oid, &new_oid)
+            .is_err()
+        {
+            info!(
+                "unable to update pr #{} in-place. maintainer_can_modify: {}",
+                ctx.pr().number,
+                ctx.pr().maintainer_can_modify
+            );
+
+            let msg = format!(
+                "@{} :exclamation: failed to push the updated branch to this PR. Make sure that \
+                [\"Allow edits from maintainers\"]\
+                (https://help.github.com/en/github/collaborating-with-issues-and-pull-requests/allowing-changes-to-a-pull-request-branch-created-from-a-fork) \
+                is enabled so that Bors is able to update the PR in-place.",
+                ctx.sender(),
+            );
+            ctx.create_pr_comment(&msg).await?;
+            return Ok(());
+        }
+
+        info!("pr #{} updated to {}", ctx.pr().number, new_oid);
+
+        Ok(())
+    }
+
     async fn cherry_pick(ctx: &mut CommandContext<'_>, target: &str) -> Result<()> {
         // Check if target is a valid branch
         if ctx.git_repository().fetch_ref(target).is_err() {
@@ -398,14 +539,12 @@ impl Command {
         // Get Commit range from PR
         let pull = ctx
             .github()
-            .pulls()
-            .get(ctx.config().owner(), ctx.config().name(), ctx.number())
-            .await?
-            .into_inner();
+            .get_pull(ctx.config().owner(), ctx.config().name(), ctx.number())
+            .await?;
         let base_oid = pull.base.sha;
         let head_oid = pull.head.sha;
 
-        let branch = format!("pick/{}/{}", ctx.number(), target);
+        let branch = format!("{}{}/{}", CHERRY_PICK_BRANCH_PREFIX, ctx.number(), target);
 
         if ctx
             .git_repository()
@@ -476,10 +615,8 @@ impl Command {
 
         let new_pull = ctx
             .github()
-            .pulls()
-            .create(ctx.config().owner(), ctx.config().name(), request)
-            .await?
-            .into_inner();
+            .create_pull(ctx.config().owner(), ctx.config().name(), request)
+            .await?;
 
         let msg = format!(
             "@{} :cherries: Opened PR #{} to cherry-pick these changes into {}",
@@ -584,6 +721,18 @@ impl std::fmt::Display for Help<'_> {
             f,
             "| __Land__ | `land`, `merge` | attempt to land or merge a PR |"
         )?;
+        if self.config.max_diff().is_some() {
+            writeln!(
+                f,
+                "| __Land Oversize__ | `land oversize` | land a PR which is larger than this project allows (admins only) |"
+            )?;
+        }
+        if self.config.binary_files().is_some() {
+            writeln!(
+                f,
+                "| __Land Binaries__ | `land allow-binaries` | land a PR which changes binary or generated files |"
+            )?;
+        }
         writeln!(
             f,
             "| __Canary__ | `canary`, `try` | canary a PR by performing all checks without merging |"
@@ -600,6 +749,10 @@ impl std::fmt::Display for Help<'_> {
             f,
             "| __Priority__ | `priority` | set the priority level for a PR (`high`, `normal`, `low`) |"
         )?;
+        writeln!(
+            f,
+            "| __Update__ | `update`, `sync` | bring a PR up to date with its base branch by merging in the latest changes (`update rebase` rebases instead) |"
+        )?;
         writeln!(f, "| __Help__ | `help`, `h` | show this help message |")?;
         writeln!(f)?;
 
@@ -649,6 +802,8 @@ impl std::fmt::Display for Help<'_> {
 struct Land {
     priority: Option<PriorityCommand>,
     squash: Option<bool>,
+    oversize: bool,
+    allow_binaries: bool,
 }
 
 impl Land {
@@ -658,6 +813,8 @@ impl Land {
     {
         let mut priority = None;
         let mut squash = None;
+        let mut oversize = false;
+        let mut allow_binaries = false;
 
         for (key, value) in iter {
             match key {
@@ -670,13 +827,24 @@ impl Land {
                 "squash-" => {
                     squash = Some(false);
                 }
+                "oversize" => {
+                    oversize = true;
+                }
+                "allow-binaries" | "--allow-binaries" => {
+                    allow_binaries = true;
+                }
 
                 // First key we hit that we don't understand we should just bail
                 _ => break,
             }
         }
 
-        Ok(Self { priority, squash })
+        Ok(Self {
+            priority,
+            squash,
+            oversize,
+            allow_binaries,
+        })
     }
 
     fn priority(&self) -> Option<Priority> {
@@ -755,3 +923,29 @@ impl CherryPick {
         &self.target
     }
 }
+
+#[derive(Debug)]
+struct Update {
+    rebase: bool,
+}
+
+impl Update {
+    fn with_args<'a, I>(iter: I) -> Result<Self, ParseCommandError>
+    where
+        I: IntoIterator<Item = (&'a str, Option<&'a str>)>,
+    {
+        let mut rebase = false;
+
+        for (key, value) in iter {
+            match (key, value) {
+                ("rebase", None) => rebase = true,
+                ("merge", None) => rebase = false,
+
+                // First key we hit that we don't understand we should just bail
+                _ => break,
+            }
+        }
+
+        Ok(Self { rebase })
+    }
+}
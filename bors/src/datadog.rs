@@ -0,0 +1,170 @@
+//! Reporting of metrics and events to a Datadog agent over DogStatsD, for deployments which are
+//! monitored with Datadog rather than by scraping `/metrics`
+
+use crate::{
+    config::DatadogConfig,
+    event_processor::{EventProcessorSender, QueueEvent},
+    notify::Notifier,
+    state::Repo,
+    store::LandingResult,
+    Result,
+};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct DogStatsd {
+    socket: Arc<UdpSocket>,
+    /// Tags added to every metric and event
+    tags: Vec<String>,
+    config: DatadogConfig,
+}
+
+impl DogStatsd {
+    pub async fn new(config: &DatadogConfig) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(config.agent()).await?;
+
+        Ok(Self {
+            socket: Arc::new(socket),
+            tags: config.tags.clone(),
+            config: config.clone(),
+        })
+    }
+
+    fn tags(&self, repo: &Repo) -> Vec<String> {
+        std::iter::once(format!("repo:{}/{}", repo.owner(), repo.name()))
+            .chain(self.tags.iter().cloned())
+            .collect()
+    }
+
+    async fn send(&self, datagram: &str) {
+        // Metrics are best effort, as with any other statsd client
+        if let Err(e) = self.socket.send(datagram.as_bytes()).await {
+            warn!("Unable to send to the Datadog agent: {}", e);
+        }
+    }
+}
+
+/// Report the metrics of a repo's event processor, as exposed from `/metrics`, every interval for
+/// as long as it is running
+pub async fn report_metrics(repo: Repo, event_processor: EventProcessorSender, datadog: DogStatsd) {
+    let mut interval = tokio::time::interval(datadog.config.interval());
+    let tags = datadog.tags(&repo);
+    // Counters are reported as the change since they were last reported
+    let mut send_failures = 0;
+    let mut stuck_alerts = 0;
+
+    loop {
+        interval.tick().await;
+
+        let metrics = event_processor.metrics();
+        let datagrams = [
+            metric(
+                "bors.event_queue.capacity",
+                metrics.capacity() as u64,
+                "g",
+                &tags,
+            ),
+            metric("bors.event_queue.depth", metrics.depth() as u64, "g", &tags),
+            metric(
+                "bors.event_queue.send_failures",
+                metrics.send_failures() - send_failures,
+                "c",
+                &tags,
+            ),
+            metric("bors.queue.stuck", metrics.is_stuck() as u64, "g", &tags),
+            metric(
+                "bors.queue.stuck_alerts",
+                metrics.stuck_alerts() - stuck_alerts,
+                "c",
+                &tags,
+            ),
+        ];
+        send_failures = metrics.send_failures();
+        stuck_alerts = metrics.stuck_alerts();
+
+        for datagram in datagrams.iter() {
+            datadog.send(datagram).await;
+        }
+    }
+}
+
+/// Landings and failures are emitted as Datadog events, so that they can be overlaid on dashboards
+#[async_trait]
+impl Notifier for DogStatsd {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        let (number, result, failing_checks) = match event {
+            QueueEvent::Finished {
+                number,
+                result,
+                failing_checks,
+                ..
+            } => (number, result, failing_checks),
+            _ => return Ok(()),
+        };
+
+        let (title, alert_type) = match result {
+            LandingResult::Landed => ("Landed", "success"),
+            LandingResult::TimedOut => ("Timed out landing", "warning"),
+            LandingResult::Failed | LandingResult::MergeFailed => ("Failed to land", "error"),
+        };
+        let title = format!("{} {}/{}#{}", title, repo.owner(), repo.name(), number);
+        let mut text = format!(
+            "https://github.com/{}/{}/pull/{}",
+            repo.owner(),
+            repo.name(),
+            number
+        );
+        if !failing_checks.is_empty() {
+            text.push_str(&format!("\nFailing checks: {}", failing_checks.join(", ")));
+        }
+
+        let mut tags = self.tags(repo);
+        tags.push(format!("result:{}", result.as_str()));
+        self.send(&datadog_event(&title, &text, alert_type, &tags))
+            .await;
+
+        Ok(())
+    }
+}
+
+/// A metric in the DogStatsD datagram format, e.g. `bors.event_queue.depth:3|g|#repo:o/n`
+fn metric(name: &str, value: u64, kind: &str, tags: &[String]) -> String {
+    format!("{}:{}|{}|#{}", name, value, kind, tags.join(","))
+}
+
+/// An event in the DogStatsD datagram format, which is prefixed with the lengths of its title and
+/// text and can't contain literal newlines
+fn datadog_event(title: &str, text: &str, alert_type: &str, tags: &[String]) -> String {
+    let text = text.replace('\n', "\\n");
+    format!(
+        "_e{{{},{}}}:{}|{}|t:{}|#{}",
+        title.len(),
+        text.len(),
+        title,
+        text,
+        alert_type,
+        tags.join(",")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn datagrams() {
+        let tags = vec!["repo:aptos-labs/bors".to_owned(), "env:prod".to_owned()];
+        assert_eq!(
+            metric("bors.event_queue.depth", 3, "g", &tags),
+            "bors.event_queue.depth:3|g|#repo:aptos-labs/bors,env:prod"
+        );
+        assert_eq!(
+            datadog_event("Failed", "ci\nlint", "error", &tags[..1]),
+            "_e{6,8}:Failed|ci\\nlint|t:error|#repo:aptos-labs/bors"
+        );
+    }
+}
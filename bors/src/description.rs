@@ -0,0 +1,68 @@
+//! Checks that PR descriptions fill in the sections of the repo's template, e.g. how a change was
+//! tested and what could go wrong, so that PRs are described well enough to be reviewed
+
+use crate::config::DescriptionConfig;
+use regex::Regex;
+use std::collections::HashSet;
+
+/// The required sections a PR's description is missing, or leaves empty
+pub fn missing_sections<'a>(config: &'a DescriptionConfig, body: &str) -> Vec<&'a str> {
+    // Templates usually explain what goes in each section in comments, which don't count
+    let comments = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    let body = comments.replace_all(body, "");
+
+    let mut filled = HashSet::new();
+    let mut section = None;
+    for line in body.lines().map(str::trim) {
+        if line.starts_with('#') {
+            section = Some(heading(line));
+        } else if !line.is_empty() {
+            if let Some(section) = &section {
+                filled.insert(section.clone());
+            }
+        }
+    }
+
+    config
+        .sections()
+        .iter()
+        .filter(|section| !filled.contains(&heading(section)))
+        .map(|section| section.as_str())
+        .collect()
+}
+
+/// A heading's text, without the `#`s marking its level, compared case-insensitively
+fn heading(line: &str) -> String {
+    line.trim_start_matches('#').trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sections() {
+        let config: DescriptionConfig =
+            toml::from_str(r###"sections = ["## Testing", "Risk"]"###).unwrap();
+        assert!(missing_sections(
+            &config,
+            "Fixes a bug.\n\n## Testing\nAdded a unit test\n\n### risk\n\nLow, it's a one-liner"
+        )
+        .is_empty());
+        assert_eq!(
+            missing_sections(&config, "## Testing\nRan it locally"),
+            vec!["Risk"]
+        );
+        assert_eq!(
+            missing_sections(
+                &config,
+                "## Testing\n<!-- How was this tested? -->\n\n## Risk\n\n## Other\nNone"
+            ),
+            vec!["## Testing", "Risk"]
+        );
+        assert_eq!(
+            missing_sections(&config, "Testing: ran it locally"),
+            vec!["## Testing", "Risk"]
+        );
+    }
+}
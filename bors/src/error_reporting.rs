@@ -0,0 +1,107 @@
+//! Reporting of errors to Sentry, along with the repo, PR and command they happened while handling,
+//! since errors which are only logged are easy to miss
+
+use crate::{config::SentryConfig, state::Repo, Error, Result};
+#[cfg(not(feature = "sentry"))]
+use anyhow::anyhow;
+#[cfg(feature = "sentry")]
+use {crate::git, sentry::ClientInitGuard};
+
+/// Start reporting errors, and panics, to Sentry. Reports still buffered are sent when the returned
+/// guard is dropped.
+#[cfg(feature = "sentry")]
+pub fn init_sentry(config: &SentryConfig) -> Result<ClientInitGuard> {
+    Ok(sentry::init((
+        config.dsn.as_str(),
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            environment: config.environment.clone().map(Into::into),
+            ..Default::default()
+        },
+    )))
+}
+
+#[cfg(not(feature = "sentry"))]
+pub fn init_sentry(_config: &SentryConfig) -> Result<()> {
+    Err(anyhow!("bors was built without the `sentry` feature"))
+}
+
+/// What an error happened while doing
+#[derive(Debug, Default)]
+#[cfg_attr(not(feature = "sentry"), allow(dead_code))]
+pub struct ErrorContext<'a> {
+    pub repo: Option<&'a Repo>,
+    pub pr: Option<u64>,
+    /// The kind of request being handled
+    pub request: Option<&'a str>,
+    pub delivery_id: Option<&'a str>,
+    /// The command being run, as it was given
+    pub command: Option<&'a str>,
+}
+
+/// Report an error to Sentry, if it's configured
+#[cfg(feature = "sentry")]
+pub fn capture(error: &Error, context: &ErrorContext) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("origin", origin(error));
+            if let Some(repo) = context.repo {
+                scope.set_tag("repo", repo);
+            }
+            if let Some(pr) = context.pr {
+                scope.set_tag("pr", pr);
+            }
+            if let Some(request) = context.request {
+                scope.set_tag("request", request);
+            }
+            if let Some(delivery_id) = context.delivery_id {
+                scope.set_tag("delivery_id", delivery_id);
+            }
+            if let Some(command) = context.command {
+                scope.set_extra("command", command.into());
+            }
+        },
+        || sentry::capture_error(AsRef::<dyn std::error::Error + Send + Sync>::as_ref(error)),
+    );
+}
+
+/// Errors are only logged without the `sentry` feature
+#[cfg(not(feature = "sentry"))]
+pub fn capture(_error: &Error, _context: &ErrorContext) {}
+
+/// Where an error came from, so that Github outages and git failures can be told apart from bugs
+#[cfg(feature = "sentry")]
+fn origin(error: &Error) -> &'static str {
+    if error
+        .chain()
+        .any(|cause| cause.is::<github::client::Error>())
+    {
+        "github"
+    } else if error
+        .chain()
+        .any(|cause| cause.is::<git::CommandFailed>() || cause.is::<git::TimedOut>())
+    {
+        "git"
+    } else {
+        "bors"
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "sentry")]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn origins() {
+        let github = Error::new(github::client::Error::Message("not found".into()))
+            .context("fetching PR #7");
+        assert_eq!(origin(&github), "github");
+
+        let git: Result<(), _> = Err(git::CommandFailed("conflict".to_owned()));
+        assert_eq!(origin(&git.context("rebasing").unwrap_err()), "git");
+
+        assert_eq!(origin(&anyhow!("PR #7 isn't tracked")), "bors");
+    }
+}
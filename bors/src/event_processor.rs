@@ -1,69 +1,526 @@
 use crate::{
+    buildkite::Buildkite,
     command::Command,
-    config::{GitConfig, GithubConfig, RepoConfig},
-    git::GitRepository,
+    config::{Bump, GitConfig, GithubConfig, RepoConfig},
+    error_reporting::{self, ErrorContext},
+    git::{GitApi, GitRepository},
+    github_api::{is_drift, GithubApi},
     graphql::GithubClient,
+    metrics::Histogram,
     project_board::ProjectBoard,
-    queue::MergeQueue,
+    queue::{has_boarded, MergeQueue},
+    release,
+    snapshot::FailureSnapshot,
     state::{PullRequestState, Status},
-    Result,
+    store::{
+        AuditEntry, CommandSource, LandingAttempt, LandingResult, RepoState, Store,
+        LEASE_RENEWAL_INTERVAL, LEASE_TTL,
+    },
+    telemetry, Error, Result,
 };
-use futures::{
-    channel::{mpsc, oneshot},
-    sink::SinkExt,
-    stream::StreamExt,
+use anyhow::anyhow;
+use futures::channel::oneshot;
+use github::{Event, NodeId, Oid, PullRequestReviewEvent};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Instant, SystemTime},
 };
-use github::{Event, NodeId, PullRequestReviewEvent};
-use log::{error, info, warn};
-use std::collections::HashMap;
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+    },
+    task::JoinHandle,
+};
+use tracing::{error, field, info, instrument, warn, Span};
 
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)]
 pub enum Request {
-    Webhook { event: Event, delivery_id: String },
+    Webhook {
+        event: Event,
+        delivery_id: String,
+    },
     GetState(oneshot::Sender<(MergeQueue, HashMap<u64, PullRequestState>)>),
     Synchronize,
+    /// Evict any tracked PRs which are no longer open
+    Sweep,
+    /// Check whether the merge queue has stopped making progress, alerting if it just has
+    CheckStuck,
+    /// Stop testing new PRs from the merge queue. The PR being tested, if any, is still landed.
+    Pause,
+    Resume,
+    /// Stop tracking a PR, removing it from the merge queue
+    Evict(u64),
+    /// Refetch a PR from Github, discarding bors' state for it
+    Resync(u64),
+    ProcessQueue,
+    /// Open a release PR, bumping the version by the given part or else the configured one
+    Release(Option<Bump>),
+    /// Ping PRs which have had no activity for a while
+    NagStale,
+    /// Run a command as if `sender` had commented it on the PR, for when commenting isn't possible
+    RunCommand {
+        sender: String,
+        number: u64,
+        command: String,
+    },
+    /// Finish handling any outstanding requests, persist state and then stop
+    Shutdown(oneshot::Sender<()>),
+}
+
+impl Request {
+    /// Commands, admin requests and state queries come from users who are waiting on a response,
+    /// so they are serviced ahead of bulk events like status and check updates, which can arrive
+    /// in the thousands
+    fn is_priority(&self) -> bool {
+        matches!(
+            self,
+            Request::Webhook {
+                event: Event::IssueComment(_),
+                ..
+            } | Request::GetState(_)
+                | Request::Pause
+                | Request::Resume
+                | Request::Evict(_)
+                | Request::Resync(_)
+                | Request::ProcessQueue
+                | Request::Release(_)
+                | Request::RunCommand { .. }
+        )
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Request::Webhook { .. } => "webhook",
+            Request::GetState(_) => "get-state",
+            Request::Synchronize => "synchronize",
+            Request::Sweep => "sweep",
+            Request::CheckStuck => "check-stuck",
+            Request::Pause => "pause",
+            Request::Resume => "resume",
+            Request::Evict(_) => "evict",
+            Request::Resync(_) => "resync",
+            Request::ProcessQueue => "process-queue",
+            Request::Release(_) => "release",
+            Request::NagStale => "nag-stale",
+            Request::RunCommand { .. } => "run-command",
+            Request::Shutdown(_) => "shutdown",
+        }
+    }
+
+    /// The PR a comment or command is on, which may be a command to land it, and when it was made
+    fn command_issued(&self) -> Option<(u64, SystemTime)> {
+        match self {
+            Request::Webhook {
+                event: Event::IssueComment(e),
+                ..
+            } if e.issue.is_pull_request() => {
+                Some((e.issue.number, e.comment.created_at.clone().into()))
+            }
+            Request::RunCommand { number, .. } => Some((*number, SystemTime::now())),
+            _ => None,
+        }
+    }
+
+    /// Whether the request is a webhook for something done by a bot, either a Github App's bot
+    /// account or bors itself as `my_username`, which is often a regular user account
+    fn sent_by_bot(&self, my_username: &str) -> bool {
+        let sender = match self {
+            Request::Webhook { event, .. } => match event {
+                Event::PullRequest(e) => &e.sender,
+                Event::IssueComment(e) => &e.sender,
+                Event::PullRequestReview(e) => &e.sender,
+                Event::PullRequestReviewComment(e) => &e.sender,
+                _ => return false,
+            },
+            _ => return false,
+        };
+
+        matches!(sender.user_type, github::UserType::Bot)
+            || sender.login.eq_ignore_ascii_case(my_username)
+    }
+
+    /// The PR a webhook or command is about, if any
+    fn pull_number(&self) -> Option<u64> {
+        let event = match self {
+            Request::Webhook { event, .. } => event,
+            Request::RunCommand { number, .. } => return Some(*number),
+            _ => return None,
+        };
+
+        match event {
+            Event::PullRequest(e) => Some(e.pull_request.number),
+            Event::IssueComment(e) if e.issue.is_pull_request() => Some(e.issue.number),
+            Event::PullRequestReview(e) => Some(e.pull_request.number),
+            Event::PullRequestReviewComment(e) => Some(e.pull_request.number),
+            _ => None,
+        }
+    }
+}
+
+/// How many queue events are buffered for each subscriber before the oldest are dropped
+const QUEUE_EVENTS_CAPACITY: usize = 64;
+
+/// A change to a repo's merge queue, published to subscribers such as dashboards or deploy
+/// pipelines so that they can act on it as it happens
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum QueueEvent {
+    Enqueued {
+        number: u64,
+    },
+    TestingStarted {
+        number: u64,
+        merge_oid: Oid,
+    },
+    /// An attempt to land a PR finished, whether or not it landed
+    Finished {
+        number: u64,
+        author: Option<String>,
+        merge_oid: Oid,
+        result: LandingResult,
+        failing_checks: Vec<String>,
+    },
+    /// A PR left the queue, whether because it landed, failed or was canceled, so every enqueued
+    /// PR is eventually dequeued
+    Dequeued {
+        number: u64,
+        author: Option<String>,
+        /// Whether the PR was evicted from the queue, e.g. by being canceled, closed or evicted by
+        /// an admin, rather than leaving it because an attempt to land it finished
+        evicted: bool,
+    },
+    Paused,
+    Resumed,
+    /// The queue stopped making progress without having been paused, with the PR holding it up
+    Stuck {
+        number: Option<u64>,
+        reason: StuckReason,
+    },
+}
+
+/// Why a merge queue is considered stuck
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StuckReason {
+    /// The PR being tested is past the test timeout without any checks having reported since.
+    /// Any report would have timed the PR out.
+    TestsNotReporting,
+    /// PRs are queued but none has started testing
+    NothingStarted,
+}
+
+impl QueueEvent {
+    fn finished(attempt: &LandingAttempt, author: Option<String>) -> Self {
+        QueueEvent::Finished {
+            number: attempt.number,
+            author,
+            merge_oid: attempt.merge_oid.clone(),
+            result: attempt.result,
+            failing_checks: attempt.failing_checks.clone(),
+        }
+    }
+}
+
+/// Error returned when a request is rejected because an event processor's queue is full
+#[derive(Debug, thiserror::Error)]
+#[error("event processor queue is full")]
+pub struct QueueFull;
+
+/// Metrics describing an event processor's request queue
+#[derive(Debug)]
+pub struct QueueMetrics {
+    capacity: usize,
+    /// Number of requests which have been sent but not yet picked up by the event processor
+    depth: AtomicUsize,
+    /// Number of requests which couldn't be sent, either because the queue was full or because
+    /// the event processor had stopped
+    send_failures: AtomicU64,
+    /// Whether the merge queue is currently stuck
+    stuck: AtomicBool,
+    /// Number of times the merge queue has gotten stuck
+    stuck_alerts: AtomicU64,
+    /// How long after a command to land a PR it was queued
+    command_to_queued: Histogram,
+    /// How long PRs waited in the queue before being tested
+    queued_to_testing: Histogram,
+    /// How long PRs which landed were tested for
+    testing_to_merged: Histogram,
+}
+
+impl QueueMetrics {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            depth: AtomicUsize::new(0),
+            send_failures: AtomicU64::new(0),
+            stuck: AtomicBool::new(false),
+            stuck_alerts: AtomicU64::new(0),
+            command_to_queued: Histogram::new(),
+            queued_to_testing: Histogram::new(),
+            testing_to_merged: Histogram::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn send_failures(&self) -> u64 {
+        self.send_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        self.stuck.load(Ordering::Relaxed)
+    }
+
+    pub fn stuck_alerts(&self) -> u64 {
+        self.stuck_alerts.load(Ordering::Relaxed)
+    }
+
+    pub fn command_to_queued(&self) -> &Histogram {
+        &self.command_to_queued
+    }
+
+    pub fn queued_to_testing(&self) -> &Histogram {
+        &self.queued_to_testing
+    }
+
+    pub fn testing_to_merged(&self) -> &Histogram {
+        &self.testing_to_merged
+    }
 }
 
+/// Sends requests to an event processor over two lanes, a priority lane for requests made by users
+/// and a bulk lane for everything else. Each lane can hold up to the queue's capacity.
 #[derive(Clone, Debug)]
 pub struct EventProcessorSender {
-    inner: mpsc::Sender<Request>,
+    priority: mpsc::Sender<Request>,
+    bulk: mpsc::Sender<Request>,
+    metrics: Arc<QueueMetrics>,
+    queue_events: broadcast::Sender<QueueEvent>,
 }
 
 impl EventProcessorSender {
-    pub fn new(inner: mpsc::Sender<Request>) -> Self {
-        Self { inner }
+    /// Create the channel used to send requests to an event processor. The receiving half is
+    /// handed to `EventProcessor::start` and outlives any individual event processor, allowing a
+    /// processor to be restarted without losing the requests queued for it.
+    pub fn channel(capacity: usize) -> (Self, EventProcessorReceiver) {
+        let (priority_tx, priority_rx) = mpsc::channel(capacity);
+        let (bulk_tx, bulk_rx) = mpsc::channel(capacity);
+        let metrics = Arc::new(QueueMetrics::new(capacity));
+        let (queue_events, _) = broadcast::channel(QUEUE_EVENTS_CAPACITY);
+
+        let sender = Self {
+            priority: priority_tx,
+            bulk: bulk_tx,
+            metrics: metrics.clone(),
+            queue_events: queue_events.clone(),
+        };
+        let receiver = EventProcessorReceiver {
+            priority: priority_rx,
+            bulk: bulk_rx,
+            metrics,
+            queue_events,
+        };
+        (sender, receiver)
     }
 
-    pub async fn webhook(&self, event: Event, delivery_id: String) -> Result<(), mpsc::SendError> {
-        self.inner
-            .clone()
-            .send(Request::Webhook { event, delivery_id })
-            .await
+    fn lane(&self, request: &Request) -> &mpsc::Sender<Request> {
+        if request.is_priority() {
+            &self.priority
+        } else {
+            &self.bulk
+        }
     }
 
-    pub async fn get_state(
-        &self,
-    ) -> Result<(MergeQueue, HashMap<u64, PullRequestState>), mpsc::SendError> {
+    pub fn metrics(&self) -> &QueueMetrics {
+        &self.metrics
+    }
+
+    /// Subscribe to the changes made to the repo's merge queue from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.queue_events.subscribe()
+    }
+
+    async fn send(&self, request: Request) -> Result<()> {
+        self.metrics.depth.fetch_add(1, Ordering::Relaxed);
+        if self.lane(&request).send(request).await.is_err() {
+            self.record_send_failure();
+            return Err(anyhow!("event processor has stopped"));
+        }
+        Ok(())
+    }
+
+    /// Send a request without waiting for room in the queue, failing with `QueueFull` if there
+    /// isn't any
+    fn try_send(&self, request: Request) -> Result<()> {
+        self.metrics.depth.fetch_add(1, Ordering::Relaxed);
+        match self.lane(&request).try_send(request) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.record_send_failure();
+                match e {
+                    TrySendError::Full(_) => Err(QueueFull.into()),
+                    TrySendError::Closed(_) => Err(anyhow!("event processor has stopped")),
+                }
+            }
+        }
+    }
+
+    fn record_send_failure(&self) {
+        self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+        self.metrics.send_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub async fn webhook(&self, event: Event, delivery_id: String) -> Result<()> {
+        self.send(Request::Webhook { event, delivery_id }).await
+    }
+
+    /// Like `webhook` but fails with `QueueFull` instead of waiting when the queue is full
+    pub fn try_webhook(&self, event: Event, delivery_id: String) -> Result<()> {
+        self.try_send(Request::Webhook { event, delivery_id })
+    }
+
+    pub async fn get_state(&self) -> Result<(MergeQueue, HashMap<u64, PullRequestState>)> {
         let (tx, rx) = oneshot::channel();
-        self.inner.clone().send(Request::GetState(tx)).await?;
+        self.send(Request::GetState(tx)).await?;
         Ok(rx.await.unwrap())
     }
 
-    pub async fn sync(&self) -> Result<(), mpsc::SendError> {
-        self.inner.clone().send(Request::Synchronize).await
+    pub async fn sync(&self) -> Result<()> {
+        self.send(Request::Synchronize).await
+    }
+
+    pub async fn sweep(&self) -> Result<()> {
+        self.send(Request::Sweep).await
+    }
+
+    pub async fn check_stuck(&self) -> Result<()> {
+        self.send(Request::CheckStuck).await
+    }
+
+    pub async fn pause(&self) -> Result<()> {
+        self.send(Request::Pause).await
+    }
+
+    pub async fn resume(&self) -> Result<()> {
+        self.send(Request::Resume).await
+    }
+
+    pub async fn evict(&self, number: u64) -> Result<()> {
+        self.send(Request::Evict(number)).await
+    }
+
+    pub async fn resync(&self, number: u64) -> Result<()> {
+        self.send(Request::Resync(number)).await
+    }
+
+    pub async fn process_queue(&self) -> Result<()> {
+        self.send(Request::ProcessQueue).await
+    }
+
+    pub async fn nag_stale(&self) -> Result<()> {
+        self.send(Request::NagStale).await
+    }
+
+    pub async fn release(&self, bump: Option<Bump>) -> Result<()> {
+        self.send(Request::Release(bump)).await
+    }
+
+    pub async fn command(&self, sender: String, number: u64, command: String) -> Result<()> {
+        self.send(Request::RunCommand {
+            sender,
+            number,
+            command,
+        })
+        .await
+    }
+
+    /// Shutdown the event processor, returning once all previously sent requests have been
+    /// handled and its state has been persisted
+    pub async fn shutdown(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.send(Request::Shutdown(tx)).await?;
+        // The event processor may have already exited, which is just as good
+        let _ = rx.await;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct EventProcessorReceiver {
+    priority: mpsc::Receiver<Request>,
+    bulk: mpsc::Receiver<Request>,
+    metrics: Arc<QueueMetrics>,
+    queue_events: broadcast::Sender<QueueEvent>,
+}
+
+impl EventProcessorReceiver {
+    /// Receive the next request, draining the priority lane before the bulk lane, returning `None`
+    /// once all senders have been dropped
+    pub async fn recv(&mut self) -> Option<Request> {
+        let request = tokio::select! {
+            biased;
+            Some(request) = self.priority.recv() => request,
+            Some(request) = self.bulk.recv() => request,
+            else => return None,
+        };
+        self.metrics.depth.fetch_sub(1, Ordering::Relaxed);
+        Some(request)
     }
 }
 
 #[derive(Debug)]
 pub struct EventProcessor {
     config: RepoConfig,
-    github: GithubClient,
-    git_repository: GitRepository,
+    github: Box<dyn GithubApi>,
+    git_repository: Box<dyn GitApi>,
     merge_queue: MergeQueue,
     project_board: Option<ProjectBoard>,
+    /// Client for the Buildkite pipelines which report the repo's checks, if it uses Buildkite
+    buildkite: Option<Buildkite>,
     pulls: HashMap<u64, PullRequestState>,
-    requests_rx: mpsc::Receiver<Request>,
+    store: Arc<dyn Store>,
+    /// Where changes to the merge queue are published
+    queue_events: broadcast::Sender<QueueEvent>,
+    metrics: Arc<QueueMetrics>,
+    /// When PRs were first seen queued with none being tested, used to tell when the queue is stuck
+    idle_since: Option<SystemTime>,
+    /// Whether the queue has been alerted as stuck, so that each time it gets stuck alerts once
+    stuck: bool,
+    /// When checks last reported on, or were polled for, a PR being tested
+    checks_heard_at: Option<SystemTime>,
+    /// PRs whose attempt to land finished while handling the current request, which left the queue
+    /// because of it rather than being evicted
+    finished: HashSet<u64>,
+    /// The command being run while handling the current request, reported with any error
+    command: Option<String>,
+    /// The PR the current request may command to land, and when the command was issued
+    command_issued: Option<(u64, SystemTime)>,
+
+    /// Indicates if this instance still holds the repo's lease
+    leader: Arc<AtomicBool>,
+    /// Background task which keeps the repo's lease renewed
+    lease_renewal: Option<JoinHandle<()>>,
+}
+
+impl Drop for EventProcessor {
+    fn drop(&mut self) {
+        if let Some(lease_renewal) = self.lease_renewal.take() {
+            lease_renewal.abort();
+        }
+    }
 }
 
 impl EventProcessor {
@@ -71,41 +528,412 @@ impl EventProcessor {
         config: RepoConfig,
         github_config: &GithubConfig,
         git_config: &GitConfig,
-    ) -> Result<(EventProcessorSender, Self)> {
-        let (tx, rx) = mpsc::channel(1024);
-        let github = GithubClient::new(&github_config.github_api_token);
-        let git_repository = GitRepository::from_config(git_config, config.repo())?;
-
-        Ok((
-            EventProcessorSender::new(tx),
-            Self {
-                config,
-                github,
-                git_repository,
-                merge_queue: MergeQueue::new(),
-                project_board: None,
-                pulls: HashMap::new(),
-                requests_rx: rx,
-            },
+        store: Arc<dyn Store>,
+    ) -> Result<Self> {
+        let github = GithubClient::new(github_config);
+        let git_repository = GitRepository::from_config(git_config, &config)?;
+
+        Ok(Self::with_clients(
+            config,
+            Box::new(github),
+            Box::new(git_repository),
+            store,
         ))
     }
 
-    pub async fn start(mut self) {
+    /// Create an event processor which uses the provided clients to interact with Github and the
+    /// on-disk repo
+    pub fn with_clients(
+        config: RepoConfig,
+        github: Box<dyn GithubApi>,
+        git_repository: Box<dyn GitApi>,
+        store: Arc<dyn Store>,
+    ) -> Self {
+        let buildkite = config.buildkite().cloned().map(Buildkite::new);
+
+        Self {
+            config,
+            github,
+            git_repository,
+            merge_queue: MergeQueue::new(),
+            project_board: None,
+            buildkite,
+            pulls: HashMap::new(),
+            store,
+            queue_events: broadcast::channel(QUEUE_EVENTS_CAPACITY).0,
+            metrics: Arc::new(QueueMetrics::new(0)),
+            idle_since: None,
+            stuck: false,
+            checks_heard_at: None,
+            finished: HashSet::new(),
+            command: None,
+            command_issued: None,
+            leader: Arc::new(AtomicBool::new(false)),
+            lease_renewal: None,
+        }
+    }
+
+    /// Handle `requests` in order, starting from `state` instead of synchronizing with Github,
+    /// and return the resulting state. Used to reproduce how recorded webhooks were handled.
+    pub async fn replay(mut self, state: RepoState, requests: Vec<Request>) -> RepoState {
+        self.merge_queue = state.merge_queue;
+        self.pulls = state.pulls;
+
+        for request in requests {
+            if let Err(e) = self.handle_request(request).await {
+                error!("Error while handling request: {:?}", e);
+            }
+        }
+
+        RepoState {
+            merge_queue: std::mem::take(&mut self.merge_queue),
+            pulls: std::mem::take(&mut self.pulls),
+        }
+    }
+
+    /// Handle requests until the event processor is shutdown or all senders have been dropped
+    pub async fn start(mut self, requests_rx: &mut EventProcessorReceiver) {
+        self.queue_events = requests_rx.queue_events.clone();
+        self.metrics = requests_rx.metrics.clone();
+        self.become_leader().await;
+
+        while let Some(request) = requests_rx.recv().await {
+            if let Request::Shutdown(done) = request {
+                info!("Shutting down");
+                self.persist().await;
+                let _ = done.send(());
+                return;
+            }
+
+            // Another instance may have taken over the repo, e.g. if this instance was partitioned
+            // from the store, in which case it needs to wait its turn and pick up the changes made
+            // by the other instance
+            if !self.leader.load(Ordering::Acquire) {
+                warn!("Lost the lease on the repo");
+                self.become_leader().await;
+            }
+
+            self.process_request(request).await;
+        }
+    }
+
+    /// Handle a request, recovering from any error, and then persist the resulting state
+    #[instrument(
+        skip_all,
+        fields(
+            repo = %self.config.repo(),
+            request = request.name(),
+            pr = request.pull_number(),
+            delivery_id = field::Empty,
+        )
+    )]
+    async fn process_request(&mut self, request: Request) {
+        if let Request::Webhook { delivery_id, .. } = &request {
+            let span = Span::current();
+            span.record("delivery_id", delivery_id.as_str());
+            telemetry::link_to_delivery(&span, delivery_id);
+        }
+
+        // Capture the state the request is handled from so that a failure can be reproduced
+        let snapshot = self
+            .config
+            .snapshot_dir()
+            .map(|_| FailureSnapshot::new(self.repo_state(), &request));
+        let affected_pulls = self.affected_pulls(&request);
+        let previous_pulls: HashMap<_, _> = self
+            .pulls
+            .values()
+            .map(|pull| (pull.number, (pull.status.clone(), pull.author.clone())))
+            .collect();
+        let was_paused = self.merge_queue.is_paused();
+        self.finished.clear();
+        self.command = None;
+        self.command_issued = request.command_issued();
+        let (name, pr) = (request.name(), request.pull_number());
+        let by_bot = request.sent_by_bot(self.git_repository.user());
+        let delivery_id = match &request {
+            Request::Webhook { delivery_id, .. } => Some(delivery_id.clone()),
+            _ => None,
+        };
+
+        let result = self.handle_request(request).await;
+
+        // Anything done to a PR by a person means it isn't stale, while bors' own comments, e.g.
+        // warning that the PR is stale, don't
+        if let Some(pull) = pr
+            .filter(|_| !by_bot)
+            .and_then(|number| self.pulls.get_mut(&number))
+        {
+            pull.last_activity = Some(SystemTime::now());
+        }
+
+        if let Err(e) = result {
+            error!("Error while handling request: {:?}", e);
+            error_reporting::capture(
+                &e,
+                &ErrorContext {
+                    repo: Some(self.config.repo()),
+                    pr,
+                    request: Some(name),
+                    delivery_id: delivery_id.as_deref(),
+                    command: self.command.as_deref(),
+                },
+            );
+
+            if let Some(snapshot) = snapshot {
+                self.dump_snapshot(snapshot, &e).await;
+            }
+
+            // Otherwise every following event touching the same PRs is likely to fail in the same
+            // way until the next periodic synchronize
+            if is_drift(&e) {
+                for number in affected_pulls {
+                    if let Err(e) = self.resync_pull(number).await {
+                        error!("Error while resynchronizing PR #{}: {:?}", number, e);
+                        error_reporting::capture(
+                            &e,
+                            &ErrorContext {
+                                repo: Some(self.config.repo()),
+                                pr: Some(number),
+                                request: Some("resync"),
+                                ..Default::default()
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        self.publish_queue_changes(&previous_pulls, was_paused);
+        self.persist().await;
+    }
+
+    /// Publish the PRs which left or were added to the queue, or started being tested, while
+    /// handling a request, and whether the queue was paused or resumed. Finished landing attempts
+    /// are published as they happen by `process_merge_queue`. `previous_pulls` are the status and
+    /// author of each PR from before the request was handled.
+    fn publish_queue_changes(
+        &self,
+        previous_pulls: &HashMap<u64, (Status, Option<String>)>,
+        was_paused: bool,
+    ) {
+        // There may not be anyone subscribed, so failures to publish are ignored
+        match (was_paused, self.merge_queue.is_paused()) {
+            (false, true) => {
+                let _ = self.queue_events.send(QueueEvent::Paused);
+            }
+            (true, false) => {
+                let _ = self.queue_events.send(QueueEvent::Resumed);
+            }
+            _ => {}
+        }
+
+        let mut dequeued: Vec<_> = previous_pulls
+            .iter()
+            .filter(|(_, (status, _))| status.is_queued() || status.is_testing())
+            .filter(|(number, _)| {
+                self.pulls
+                    .get(number)
+                    .is_none_or(|pull| !pull.status.is_queued() && !pull.status.is_testing())
+            })
+            .collect();
+        dequeued.sort_unstable_by_key(|(number, _)| **number);
+        for (number, (_, author)) in dequeued {
+            let _ = self.queue_events.send(QueueEvent::Dequeued {
+                number: *number,
+                author: author.clone(),
+                evicted: !self.finished.contains(number),
+            });
+        }
+
+        let mut pulls: Vec<_> = self.pulls.values().collect();
+        pulls.sort_unstable_by_key(|pull| pull.to_queue_entry(&self.config));
+
+        for pull in pulls {
+            let previous = previous_pulls.get(&pull.number).map(|(status, _)| status);
+            let was_queued =
+                matches!(previous, Some(status) if status.is_queued() || status.is_testing());
+            let was_testing = matches!(previous, Some(status) if status.is_testing());
+
+            if (pull.status.is_queued() || pull.status.is_testing()) && !was_queued {
+                let _ = self.queue_events.send(QueueEvent::Enqueued {
+                    number: pull.number,
+                });
+                if let (Some((number, issued_at)), Some(queued_at)) =
+                    (self.command_issued, pull.status.queued_at())
+                {
+                    if number == pull.number {
+                        self.metrics
+                            .command_to_queued
+                            .observe(queued_at.duration_since(issued_at).unwrap_or_default());
+                    }
+                }
+            }
+            if let Status::Testing {
+                merge_oid,
+                queued_at,
+                tests_started_at,
+                ..
+            } = &pull.status
+            {
+                if !was_testing {
+                    self.metrics.queued_to_testing.observe(
+                        tests_started_at
+                            .duration_since(*queued_at)
+                            .unwrap_or_default(),
+                    );
+                    let _ = self.queue_events.send(QueueEvent::TestingStarted {
+                        number: pull.number,
+                        merge_oid: merge_oid.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// The PRs which handling a request may touch on Github: the PR the request is about, if any,
+    /// and those being tested since the merge queue is processed after every webhook
+    fn affected_pulls(&self, request: &Request) -> Vec<u64> {
+        let mut numbers: Vec<u64> = self
+            .pulls
+            .values()
+            .filter(|pull| pull.status.is_testing() || pull.status.is_canary())
+            .map(|pull| pull.number)
+            .chain(request.pull_number())
+            .collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        numbers
+    }
+
+    /// Wait until this instance holds the repo's lease, ensuring that only a single instance
+    /// processes the repo at a time, and then load its latest state
+    async fn become_leader(&mut self) {
+        let mut waiting = false;
+        loop {
+            match self.store.acquire_lease(self.config.repo()).await {
+                Ok(true) => break,
+                Ok(false) if !waiting => {
+                    info!("Waiting for another instance to release the repo's lease");
+                    waiting = true;
+                }
+                Ok(false) => {}
+                Err(e) => error!("Error while acquiring lease: {:?}", e),
+            }
+
+            tokio::time::sleep(LEASE_RENEWAL_INTERVAL).await;
+        }
+
+        self.leader.store(true, Ordering::Release);
+        if let Some(lease_renewal) = self.lease_renewal.take() {
+            lease_renewal.abort();
+        }
+        self.lease_renewal = Some(tokio::spawn(renew_lease(
+            self.store.clone(),
+            self.config.clone(),
+            self.leader.clone(),
+        )));
+
+        // Load any persisted state before the initial synchronize so that it can be restored
+        // afterwards, making restarts invisible to users
+        let persisted = self
+            .store
+            .load(self.config.repo())
+            .await
+            .expect("unable to load persisted state");
+
         self.synchronize()
             .await
             .expect("unable to synchronize initial state");
 
-        while let Some(request) = self.requests_rx.next().await {
-            if let Err(e) = self.handle_request(request).await {
-                error!("Error while handling request: {:?}", e);
+        if let Some(state) = persisted {
+            self.restore(state)
+                .await
+                .expect("unable to restore persisted state");
+        }
+        self.persist().await;
+    }
+
+    fn repo_state(&self) -> RepoState {
+        RepoState {
+            merge_queue: self.merge_queue.clone(),
+            pulls: self.pulls.clone(),
+        }
+    }
+
+    async fn persist(&self) {
+        if let Err(e) = self
+            .store
+            .save(self.config.repo(), &self.repo_state())
+            .await
+        {
+            error!("Error while persisting state: {:?}", e);
+        }
+    }
+
+    async fn dump_snapshot(&self, snapshot: FailureSnapshot, error: &Error) {
+        let dir = match self.config.snapshot_dir() {
+            Some(dir) => dir,
+            None => return,
+        };
+
+        match snapshot
+            .write(dir, self.config.repo(), error, &*self.store)
+            .await
+        {
+            Ok(path) => info!("Dumped snapshot of failed request to {}", path.display()),
+            Err(e) => error!("Error while dumping snapshot: {:?}", e),
+        }
+    }
+
+    // Restore the state of any PRs which haven't been updated since the state was persisted
+    async fn restore(&mut self, state: RepoState) -> Result<()> {
+        info!("Restoring persisted state");
+
+        for (number, persisted) in state.pulls {
+            if let Some(pull) = self.pulls.get_mut(&number) {
+                pull.carry_over(persisted);
+            }
+        }
+        self.update_project_board().await?;
+
+        // The queue will notice if its head is no longer being tested and pick a new head
+        self.merge_queue = state.merge_queue;
+
+        // The tree may have been closed or reopened, and the default branch fixed or broken,
+        // since the state was persisted
+        self.recheck_pause_reasons().await;
+
+        info!("Done Restoring");
+        Ok(())
+    }
+
+    // Move the cards of any PRs which aren't in review to the column matching their status
+    async fn update_project_board(&self) -> Result<()> {
+        let board = match &self.project_board {
+            Some(board) => board,
+            None => return Ok(()),
+        };
+
+        for pull in self.pulls.values() {
+            if !matches!(pull.status, Status::InReview) {
+                board.move_pr_to_status_column(&*self.github, pull).await?;
             }
         }
+
+        Ok(())
     }
 
     async fn handle_request(&mut self, request: Request) -> Result<()> {
         use Request::*;
         match request {
-            Webhook { event, delivery_id } => self.handle_webhook(event, delivery_id).await?,
+            Webhook { event, delivery_id } => {
+                self.handle_webhook(event, delivery_id.clone()).await?;
+
+                // Only mark the delivery as processed once it has been successfully handled so
+                // that it can be replayed otherwise
+                self.store.mark_delivery_processed(&delivery_id).await?;
+            }
 
             Request::GetState(oneshot) => {
                 if oneshot
@@ -117,6 +945,52 @@ impl EventProcessor {
             }
 
             Synchronize => self.synchronize().await?,
+            Sweep => self.sweep().await?,
+            CheckStuck => {
+                self.poll_checks(SystemTime::now()).await?;
+                self.check_stuck(SystemTime::now());
+            }
+            Pause => {
+                info!("Pausing the merge queue");
+                self.merge_queue.set_paused(true);
+            }
+            Resume => {
+                info!("Resuming the merge queue");
+                self.merge_queue.set_paused(false);
+                self.process_merge_queue().await?;
+            }
+            Evict(number) => {
+                info!("Evicting PR #{}", number);
+                self.forget_pull(number).await?;
+                // The queue may have been waiting on the evicted PR
+                self.process_merge_queue().await?;
+            }
+            Resync(number) => {
+                self.resync_pull(number).await?;
+                self.process_merge_queue().await?;
+            }
+            ProcessQueue => self.process_merge_queue().await?,
+            Release(bump) => self.open_release_pull(bump).await?,
+            NagStale => self.nag_stale(SystemTime::now()).await?,
+            RunCommand {
+                sender,
+                number,
+                command,
+            } => match Command::from_comment(&command) {
+                Some(Ok(command)) => {
+                    info!(
+                        "{} requested command '{}' on PR #{} through the admin API",
+                        sender,
+                        command.name(),
+                        number
+                    );
+                    self.execute_command(&sender, number, command, CommandSource::Admin, None)
+                        .await?;
+                    self.process_merge_queue().await?;
+                }
+                _ => warn!("Ignoring invalid command for PR #{}: {}", number, command),
+            },
+            Shutdown(_) => unreachable!("shutdown requests are handled by the event loop"),
         }
 
         Ok(())
@@ -144,32 +1018,29 @@ impl EventProcessor {
         match &event {
             Event::PullRequest(e) => self.handle_pull_request_event(e).await?,
             Event::CheckRun(e) => self.handle_check_run_event(e),
-            Event::Status(e) => self.handle_status_event(e),
-            Event::IssueComment(e) => {
-                // Only process commands from newly created comments
-                if e.action.is_created() && e.issue.is_pull_request() {
-                    self.process_comment(
-                        &e.sender.login,
-                        e.issue.number,
-                        e.comment.body(),
-                        &e.comment.node_id,
-                    )
-                    .await?
-                }
+            Event::Status(e) => self.handle_status_event(e).await,
+            // Only process commands from newly created comments
+            Event::IssueComment(e) if e.action.is_created() && e.issue.is_pull_request() => {
+                self.process_comment(
+                    &e.sender.login,
+                    e.issue.number,
+                    e.comment.body(),
+                    &e.comment.node_id,
+                )
+                .await?
             }
             Event::PullRequestReview(e) => self.handle_pull_request_review_event(e).await?,
-            Event::PullRequestReviewComment(e) => {
-                if e.action.is_created() {
-                    self.process_comment(
-                        &e.sender.login,
-                        e.pull_request.number,
-                        e.comment.body(),
-                        &e.comment.node_id,
-                    )
-                    .await?
-                }
+            Event::PullRequestReviewComment(e) if e.action.is_created() => {
+                self.process_comment(
+                    &e.sender.login,
+                    e.pull_request.number,
+                    e.comment.body(),
+                    &e.comment.node_id,
+                )
+                .await?
             }
             Event::WorkflowRun(e) => self.handle_workflow_run_event(e),
+            Event::Issues(e) => self.update_tree(&e.issue),
             // Unsupported Event
             _ => {}
         }
@@ -192,12 +1063,16 @@ impl EventProcessor {
                 if let Some(pr) = self.pulls.get_mut(&event.pull_request.number) {
                     pr.update_head(
                         event.pull_request.head.sha.clone(),
+                        &event.sender.login,
+                        self.git_repository.user(),
                         &self.config,
-                        &self.github,
+                        &*self.github,
                         self.project_board.as_ref(),
                     )
                     .await?;
                 }
+
+                self.queue_trusted(event.pull_request.number).await?;
             }
             PullRequestEventAction::Opened | PullRequestEventAction::Reopened => {
                 let mut state = PullRequestState::from_pull_request(&event.pull_request);
@@ -218,7 +1093,6 @@ impl EventProcessor {
                     && !pr_is_from_base_repo
                 {
                     self.github
-                        .issues()
                         .create_comment(
                             self.config.repo().owner(),
                             self.config.repo().name(),
@@ -233,12 +1107,15 @@ impl EventProcessor {
                 }
 
                 if let Some(board) = &self.project_board {
-                    board.create_card(&self.github, &mut state).await?;
+                    board.create_card(&*self.github, &mut state).await?;
                 }
 
-                if self.pulls.insert(state.number, state).is_some() {
+                let number = state.number;
+                if self.pulls.insert(number, state).is_some() {
                     warn!("Opened/Reopened event replaced an existing PullRequestState");
                 }
+
+                self.queue_trusted(number).await?;
             }
             PullRequestEventAction::Closed => {
                 // From [Github's API docs](https://developer.github.com/v3/activity/events/types/#events-api-payload-31):
@@ -253,11 +1130,7 @@ impl EventProcessor {
 
                 // XXX Do we need to call into the MergeQueue to notify it that a PR was merged or
                 // closed?
-                if let Some(mut pull) = self.pulls.remove(&event.pull_request.number) {
-                    if let Some(board) = &self.project_board {
-                        board.delete_card(&self.github, &mut pull).await?;
-                    }
-                }
+                self.forget_pull(event.pull_request.number).await?;
             }
             PullRequestEventAction::Labeled => {
                 if let Some(label) = &event.label {
@@ -282,6 +1155,8 @@ impl EventProcessor {
                 if let Some(pull) = self.pulls.get_mut(&event.pull_request.number) {
                     pull.is_draft = false;
                 }
+
+                self.queue_trusted(event.pull_request.number).await?;
             }
             PullRequestEventAction::Edited => {
                 // TODO maybe factor this out and run it on every PullRequestEvent type
@@ -299,7 +1174,7 @@ impl EventProcessor {
                         &event.pull_request.base.git_ref,
                         &event.pull_request.base.sha,
                         &self.config,
-                        &self.github,
+                        &*self.github,
                         self.project_board.as_ref(),
                     )
                     .await?;
@@ -317,8 +1192,185 @@ impl EventProcessor {
         Ok(())
     }
 
-    fn pull_from_merge_oid(&mut self, oid: &github::Oid) -> Option<&mut PullRequestState> {
-        self.pulls
+    /// Queue a PR by a trusted author, e.g. dependabot, for landing without waiting for it to be
+    /// approved, as long as it only changes the paths the author is trusted with. It's still tested
+    /// like any other PR before it lands.
+    async fn queue_trusted(&mut self, number: u64) -> Result<()> {
+        let pull = match self.pulls.get_mut(&number) {
+            Some(pull) if !pull.is_draft() && matches!(pull.status, Status::InReview) => pull,
+            _ => return Ok(()),
+        };
+        let author = match &pull.author {
+            Some(author) => author.clone(),
+            None => return Ok(()),
+        };
+        let trusted = match self.config.trusted_author(&author) {
+            Some(trusted) => trusted,
+            None => return Ok(()),
+        };
+
+        if trusted.restricts_paths() {
+            let files = self
+                .github
+                .changed_files(self.config.owner(), self.config.name(), number)
+                .await?;
+            if !trusted.permits(&files) {
+                info!(
+                    "pr #{} by trusted author {} changes paths it isn't trusted with, leaving it for review",
+                    number, author
+                );
+                return Ok(());
+            }
+        }
+
+        info!(
+            "pr #{} is by trusted author {}, queueing it for landing",
+            number, author
+        );
+        pull.update_status(
+            Status::queued(),
+            &self.config,
+            &*self.github,
+            self.project_board.as_ref(),
+        )
+        .await?;
+        self.github
+            .create_comment(
+                self.config.owner(),
+                self.config.name(),
+                number,
+                ":robot: This PR is by a trusted author, so it has been queued for landing without \
+                waiting for approval",
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Pause or resume the queue as the repo's tracking issue closes or reopens the tree, so that
+    /// incident responders can stop PRs from landing without needing access to bors
+    fn update_tree(&mut self, issue: &github::Issue) {
+        let config = match self.config.tree_closed() {
+            Some(config) if config.issue == issue.number => config,
+            _ => return,
+        };
+
+        let closed = matches!(issue.state, github::State::Open)
+            && config
+                .label
+                .as_ref()
+                .is_none_or(|label| issue.labels.iter().any(|l| &l.name == label));
+        let was_closed = self.merge_queue.is_tree_closed();
+        self.merge_queue.set_tree_closed(closed);
+        if closed == was_closed {
+            return;
+        }
+        if closed {
+            info!(
+                "Tree closed by issue #{}, pausing the merge queue",
+                issue.number
+            );
+        } else {
+            info!(
+                "Tree reopened by issue #{}, resuming the merge queue",
+                issue.number
+            );
+        }
+    }
+
+    /// Pause the queue while `checks` are failing on the head of the repo's default branch, and
+    /// resume it once they pass, so that PRs aren't landed on top of a broken tree
+    fn update_base(&mut self, sha: &github::Oid, name: &str, conclusion: github::Conclusion) {
+        if !self.config.pause_on_red_base() || !self.config.checks().any(|check| check == name) {
+            return;
+        }
+
+        let was_red = self.merge_queue.is_base_red();
+        self.merge_queue.add_base_result(sha, name, conclusion);
+
+        let red = self.merge_queue.is_base_red();
+        if red == was_red {
+            return;
+        }
+        if red {
+            info!(
+                "{} is failing on the default branch at {}, pausing the merge queue",
+                name, sha
+            );
+        } else {
+            info!(
+                "The default branch is green again at {}, resuming the merge queue",
+                sha
+            );
+        }
+    }
+
+    /// Recheck whether the tree is closed and the default branch is red against Github's current
+    /// state, rather than trusting what was last heard
+    async fn recheck_pause_reasons(&mut self) {
+        if let Some(tree_closed) = self.config.tree_closed() {
+            let owner = self.config.owner();
+            let name = self.config.name();
+            match self.github.get_issue(owner, name, tree_closed.issue).await {
+                Ok(issue) => self.update_tree(&issue),
+                Err(e) => warn!(
+                    "Unable to check whether tracking issue #{} closes the tree: {:?}",
+                    tree_closed.issue, e
+                ),
+            }
+        }
+
+        if let Err(e) = self.recheck_base().await {
+            warn!("Unable to check whether the default branch is red: {:?}", e);
+        }
+    }
+
+    /// Recheck which of `checks` are failing on the head of the repo's default branch, in case
+    /// it changed or its checks reported while no one was listening
+    async fn recheck_base(&mut self) -> Result<()> {
+        if !self.config.pause_on_red_base() {
+            return Ok(());
+        }
+
+        let owner = self.config.owner();
+        let name = self.config.name();
+        let sha = match self.github.default_branch_head(owner, name).await? {
+            Some(sha) => sha,
+            None => return Ok(()),
+        };
+        let failures = self
+            .github
+            .commit_checks(owner, name, &sha)
+            .await?
+            .into_iter()
+            .filter(|check| {
+                check
+                    .conclusion
+                    .as_ref()
+                    .is_some_and(|c| !matches!(c, github::Conclusion::Success))
+                    && self.config.checks().any(|name| name == check.name)
+            })
+            .map(|check| check.name)
+            .collect();
+
+        let was_red = self.merge_queue.is_base_red();
+        self.merge_queue.set_base_failures(sha.clone(), failures);
+        match (was_red, self.merge_queue.is_base_red()) {
+            (false, true) => info!(
+                "Checks are failing on the default branch at {}, pausing the merge queue",
+                sha
+            ),
+            (true, false) => info!(
+                "The default branch is green again at {}, resuming the merge queue",
+                sha
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn pull_from_merge_oid(&mut self, oid: &github::Oid) -> Option<&mut PullRequestState> {
+        self.pulls
             .iter_mut()
             .find(|(_n, pr)| match &pr.status {
                 Status::Testing { merge_oid, .. } | Status::Canary { merge_oid, .. } => {
@@ -352,6 +1404,13 @@ impl EventProcessor {
                 &event.check_run.details_url,
                 conclusion,
             );
+            self.checks_heard_at = Some(SystemTime::now());
+        }
+
+        if event.check_run.check_suite.head_branch.as_ref()
+            == Some(&event.repository.default_branch)
+        {
+            self.update_base(&event.check_run.head_sha, &event.check_run.name, conclusion);
         }
     }
 
@@ -377,11 +1436,19 @@ impl EventProcessor {
                 conclusion,
             );
         }
+
+        if event.workflow_run.head_branch == event.repository.default_branch {
+            self.update_base(
+                &event.workflow_run.head_sha,
+                &event.workflow_run.name,
+                conclusion,
+            );
+        }
     }
 
     // XXX This currently shoehorns github's statuses to fit into the new checks api. We should
     // probably introduce a few types to distinguish between the two
-    fn handle_status_event(&mut self, event: &github::StatusEvent) {
+    async fn handle_status_event(&mut self, event: &github::StatusEvent) {
         // Skip the event if it hasn't completed
         let conclusion = match event.state {
             github::StatusEventState::Pending => return,
@@ -390,33 +1457,148 @@ impl EventProcessor {
             github::StatusEventState::Error => github::Conclusion::Failure,
         };
 
-        if let Some(pr) = self.pull_from_merge_oid(&event.sha) {
-            pr.add_build_result(
-                &event.context,
-                &event.target_url.as_deref().unwrap_or(""),
-                conclusion,
-            );
+        // Github lists the branches containing the commit, which only matter while it's their head
+        let base = event.branches.iter().any(|branch| {
+            branch.name == event.repository.default_branch && branch.commit.sha == event.sha
+        });
+        self.add_status_result(
+            &event.sha,
+            &event.context,
+            event.target_url.as_deref().unwrap_or(""),
+            conclusion,
+            base,
+        )
+        .await;
+    }
+
+    async fn add_status_result(
+        &mut self,
+        sha: &github::Oid,
+        context: &str,
+        target_url: &str,
+        conclusion: github::Conclusion,
+        base: bool,
+    ) {
+        let mut name = context.to_owned();
+        let mut details_url = target_url.to_owned();
+        if let Some(buildkite) = &self.buildkite {
+            name = buildkite.config().check_name(&name).to_owned();
+            if matches!(conclusion, github::Conclusion::Failure) && !details_url.is_empty() {
+                details_url = buildkite.failure_url(&details_url).await;
+            }
+        }
+        if let Some(jenkins) = self.config.jenkins() {
+            name = jenkins.check_name(&name);
+        }
+
+        if let Some(pr) = self.pull_from_merge_oid(sha) {
+            pr.add_build_result(&name, &details_url, conclusion);
+            self.checks_heard_at = Some(SystemTime::now());
+        }
+
+        if base {
+            self.update_base(sha, &name, conclusion);
+        }
+    }
+
+    /// Ask Github for the results of the checks of the PR being tested once none have reported on
+    /// it for `poll-checks-minutes`, in case their webhooks were lost or a CI system never
+    /// delivered a final status. The PR then lands or fails on whatever results Github has.
+    async fn poll_checks(&mut self, now: SystemTime) -> Result<()> {
+        let poll_after = match self.config.poll_checks_after() {
+            Some(poll_after) => poll_after,
+            None => return Ok(()),
+        };
+        let (number, merge_oid, tests_started_at) =
+            match self.pulls.values().find_map(|pull| match &pull.status {
+                Status::Testing {
+                    merge_oid,
+                    tests_started_at,
+                    ..
+                } => Some((pull.number, merge_oid.clone(), *tests_started_at)),
+                _ => None,
+            }) {
+                Some(testing) => testing,
+                None => return Ok(()),
+            };
+
+        let heard_at = self
+            .checks_heard_at
+            .map_or(tests_started_at, |heard_at| heard_at.max(tests_started_at));
+        if now.duration_since(heard_at).unwrap_or_default() < poll_after {
+            return Ok(());
+        }
+        self.checks_heard_at = Some(now);
+
+        info!(
+            "No checks have reported on #{} for {:?}, polling Github for their results",
+            number, poll_after
+        );
+        let checks = self
+            .github
+            .commit_checks(self.config.owner(), self.config.name(), &merge_oid)
+            .await?;
+        for check in checks {
+            let conclusion = match check.conclusion {
+                Some(conclusion) => conclusion,
+                None => continue,
+            };
+            if check.is_status {
+                self.add_status_result(
+                    &merge_oid,
+                    &check.name,
+                    &check.details_url,
+                    conclusion,
+                    false,
+                )
+                .await;
+            } else if let Some(pr) = self.pull_from_merge_oid(&merge_oid) {
+                pr.add_build_result(&check.name, &check.details_url, conclusion);
+            }
         }
+
+        self.process_merge_queue().await
     }
 
+    #[instrument(skip_all)]
     async fn process_merge_queue(&mut self) -> Result<()> {
-        self.merge_queue
+        // A PR which lands is no longer tracked by the time its attempt has finished
+        let author = self
+            .pulls
+            .values()
+            .find(|pull| pull.status.is_testing())
+            .and_then(|pull| pull.author.clone());
+        let finished = self
+            .merge_queue
             .process_queue(
                 &self.config,
-                &self.github,
-                &mut self.git_repository,
+                &*self.github,
+                &mut *self.git_repository,
                 self.project_board.as_ref(),
                 &mut self.pulls,
+                &*self.store,
             )
-            .await
+            .await?;
+
+        if let Some(attempt) = finished {
+            self.finished.insert(attempt.number);
+            if attempt.result == LandingResult::Landed {
+                self.metrics.testing_to_merged.observe(attempt.duration());
+            }
+            let _ = self
+                .queue_events
+                .send(QueueEvent::finished(&attempt, author));
+        }
+
+        Ok(())
     }
 
     fn command_context<'a>(&'a mut self, sender: &'a str, pr_number: u64) -> CommandContext<'a> {
         CommandContext {
             number: pr_number,
             pull_request: self.pulls.get_mut(&pr_number),
-            repo: &mut self.git_repository,
-            github: &self.github,
+            repo: &mut *self.git_repository,
+            github: &*self.github,
             config: &self.config,
             project_board: self.project_board.as_ref(),
             sender,
@@ -432,6 +1614,11 @@ impl EventProcessor {
     ) -> Result<()> {
         info!("comment: {:#?}", comment);
 
+        if self.config.ignores_sender(user) {
+            info!("Ignoring comment by {}", user);
+            return Ok(());
+        }
+
         match comment.and_then(|c| {
             if let Some(cmd) = Command::from_comment(c) {
                 Some(cmd)
@@ -442,20 +1629,18 @@ impl EventProcessor {
             Some(Ok(command)) => {
                 info!("Valid Command");
 
-                self.github
-                    .add_reaction(node_id, github::ReactionType::Rocket)
-                    .await?;
-
-                let mut ctx = self.command_context(user, pr_number);
-                // Check if the user is authorized before executing the command
-                if command.is_authorized(&ctx).await? {
-                    command.execute(&mut ctx).await?;
-                }
+                self.execute_command(
+                    user,
+                    pr_number,
+                    command,
+                    CommandSource::Comment,
+                    Some(node_id),
+                )
+                .await?;
             }
             Some(Err(_)) => {
                 info!("Invalid Command");
                 self.github
-                    .issues()
                     .create_comment(
                         self.config.repo().owner(),
                         self.config.repo().name(),
@@ -475,6 +1660,52 @@ impl EventProcessor {
         Ok(())
     }
 
+    async fn execute_command(
+        &mut self,
+        user: &str,
+        pr_number: u64,
+        command: Command,
+        source: CommandSource,
+        comment: Option<&NodeId>,
+    ) -> Result<()> {
+        self.command = Some(command.as_str().to_owned());
+
+        // Check if the user is authorized before executing the command
+        let authorized = command
+            .is_authorized(&self.command_context(user, pr_number))
+            .await?;
+
+        let entry = AuditEntry {
+            number: pr_number,
+            user: user.to_owned(),
+            command: command.as_str().to_owned(),
+            source,
+            authorized,
+            at: SystemTime::now(),
+        };
+        if let Err(e) = self.store.record_command(self.config.repo(), &entry).await {
+            error!("Error while recording command in the audit log: {:?}", e);
+        }
+
+        // Acknowledge the comment the command came from, marking whether it'll be run
+        if let Some(comment) = comment {
+            let reaction = if authorized {
+                github::ReactionType::Rocket
+            } else {
+                github::ReactionType::ThumbsDown
+            };
+            self.github.add_reaction(comment, reaction).await?;
+        }
+
+        if authorized {
+            command
+                .execute(&mut self.command_context(user, pr_number))
+                .await?;
+        }
+
+        Ok(())
+    }
+
     async fn handle_pull_request_review_event(&mut self, e: &PullRequestReviewEvent) -> Result<()> {
         use github::ReviewState;
 
@@ -520,7 +1751,32 @@ impl EventProcessor {
                 _ => {}
             }
 
+            // Github's review decision only counts once the PR has been approved again since its
+            // approval was dismissed by a push
+            if let ReviewState::Approved = e.review.state {
+                pr.approval_dismissed_by = None;
+            }
+            approved &= pr.approval_dismissed_by.is_none();
+
+            // Perform a trial merge the moment a PR becomes approved so that any conflicts are
+            // surfaced well before the PR is queued for landing
+            let newly_approved = approved && !pr.approved;
             pr.approved = approved;
+
+            match e.review.state {
+                ReviewState::Approved => {
+                    pr.approved_by.insert(e.review.user.login.clone());
+                }
+                ReviewState::ChangesRequested | ReviewState::Dismissed => {
+                    pr.approved_by.remove(&e.review.user.login);
+                }
+                _ => {}
+            }
+
+            if newly_approved && self.config.trial_merge() {
+                MergeQueue::trial_merge(&self.config, &*self.github, &mut *self.git_repository, pr)
+                    .await?;
+            }
         }
 
         if e.action.is_submitted() {
@@ -536,92 +1792,474 @@ impl EventProcessor {
         Ok(())
     }
 
-    async fn synchronize(&mut self) -> Result<()> {
-        info!("Synchronizing");
+    /// Resynchronize a single PR with Github, recreating its project card from scratch. A PR which
+    /// is no longer open is forgotten.
+    async fn resync_pull(&mut self, number: u64) -> Result<()> {
+        info!("Resynchronizing PR #{}", number);
 
-        let pulls = self
-            .github
-            .open_pulls(self.config.repo().owner(), self.config.repo().name())
-            .await?;
-        info!("{} Open PullRequests", pulls.len());
+        let owner = self.config.owner();
+        let name = self.config.name();
+        let pull = match self.github.get_pull(owner, name, number).await {
+            Ok(pull) if matches!(pull.state, github::State::Open) => Some(pull),
+            Ok(_) => None,
+            Err(e) if is_drift(&e) => None,
+            Err(e) => return Err(e),
+        };
+        let approved = match &pull {
+            Some(_) => self.github.get_review_decision(owner, name, number).await?,
+            None => false,
+        };
 
-        // TODO: Scrape the comments/Reviews of each PR to pull out reviewer/approval data
+        let previous = self.pulls.remove(&number);
+        if let Some(card_id) = previous.as_ref().and_then(|pull| pull.project_card_id) {
+            match self.github.delete_project_card(card_id).await {
+                Err(e) if !is_drift(&e) => return Err(e),
+                _ => {}
+            }
+        }
 
-        self.pulls.clear();
-        self.pulls
-            .extend(pulls.into_iter().map(|pr| (pr.number, pr)));
-        self.merge_queue.reset();
+        let mut pull = match pull {
+            Some(pull) => PullRequestState::from_pull_request(&pull),
+            None => {
+                info!("PR #{} is no longer open", number);
+                return Ok(());
+            }
+        };
+        pull.approved = approved;
+        if let Some(previous) = previous {
+            if !pull.carry_over(previous) {
+                info!("PR #{} was updated since it was last seen", number);
+            }
+        }
 
-        // Sync and reset project board
-        let board = crate::project_board::ProjectBoard::synchronize_or_init(
-            &self.github,
+        let pull = self.pulls.entry(number).or_insert(pull);
+        if let Some(board) = &self.project_board {
+            board.create_card(&*self.github, pull).await?;
+            board.move_pr_to_status_column(&*self.github, pull).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Open a release PR, unless one is already open or there's no release configured
+    async fn open_release_pull(&mut self, bump: Option<Bump>) -> Result<()> {
+        let release = match self.config.release() {
+            Some(release) => release,
+            None => {
+                info!("No release is configured, skipping release");
+                return Ok(());
+            }
+        };
+        if let Some(pull) = self
+            .pulls
+            .values()
+            .find(|pull| release::is_release_pull(&self.config, pull))
+        {
+            info!(
+                "Release PR #{} is still open, skipping release",
+                pull.number
+            );
+            return Ok(());
+        }
+
+        // The PR is tracked once the webhook for it being opened arrives
+        let bump = bump.unwrap_or_else(|| release.bump());
+        release::open_pull(
             &self.config,
-            &mut self.pulls,
+            release,
+            &*self.github,
+            &mut *self.git_repository,
+            bump,
         )
         .await?;
 
-        // Ensure all labels exist
-        let owner = self.config.owner();
-        let name = self.config.name();
-        for label in self.config.labels().all() {
-            if self
-                .github
-                .issues()
-                .get_label(owner, name, label)
-                .await
-                .is_err()
+        Ok(())
+    }
+
+    /// Ping each PR which has had no activity for the configured number of days, with what it's
+    /// waiting on, unless it's queued or has an exempt label. If configured, PRs which go on to be
+    /// abandoned are warned and then closed.
+    async fn nag_stale(&mut self, now: SystemTime) -> Result<()> {
+        enum Action {
+            Nag,
+            WarnClosing,
+            Close,
+        }
+
+        let stale = match self.config.stale() {
+            Some(stale) => stale.clone(),
+            None => return Ok(()),
+        };
+
+        let mut actions = Vec::new();
+        for pull in self.pulls.values_mut() {
+            if !matches!(pull.status, Status::InReview)
+                || pull.labels.iter().any(|l| stale.exempt_labels.contains(l))
             {
+                continue;
+            }
+
+            let last_activity = *pull.last_activity.get_or_insert(now);
+            let idle = now.duration_since(last_activity).unwrap_or_default();
+            let since_nagged = pull
+                .nagged_at
+                .map(|nagged_at| now.duration_since(nagged_at).unwrap_or_default());
+
+            let action = match stale.close_after() {
+                Some(close_after) if idle >= close_after => {
+                    // Only a warning can have been made this long after the last activity
+                    let warned = pull
+                        .nagged_at
+                        .is_some_and(|nagged_at| nagged_at >= last_activity + close_after);
+                    if !warned {
+                        Some(Action::WarnClosing)
+                    } else if since_nagged.is_some_and(|since| since >= stale.close_grace()) {
+                        Some(Action::Close)
+                    } else {
+                        None
+                    }
+                }
+                // Nag again only once the PR has gone as long again without activity
+                _ if idle >= stale.after()
+                    && since_nagged.is_none_or(|since| since >= stale.after()) =>
+                {
+                    Some(Action::Nag)
+                }
+                _ => None,
+            };
+            if let Some(action) = action {
+                actions.push((pull.number, action));
+            }
+        }
+        actions.sort_unstable_by_key(|(number, _)| *number);
+
+        for (number, action) in actions {
+            let days = match action {
+                Action::Nag => stale.days(),
+                Action::WarnClosing | Action::Close => stale.close_after_days().unwrap_or_default(),
+            };
+
+            if let Action::Close = action {
+                info!("Closing abandoned PR #{}", number);
+                let msg = format!(
+                    ":wastebasket: Closing this PR as it has had no activity for over {} days. \
+                    It can be reopened once it's picked back up.",
+                    days
+                );
                 self.github
-                    .issues()
-                    .create_label(owner, name, label, "D0D8D8", None)
+                    .create_comment(self.config.owner(), self.config.name(), number, &msg)
+                    .await?;
+                self.github
+                    .close_pull(self.config.owner(), self.config.name(), number)
                     .await?;
+                self.forget_pull(number).await?;
+                continue;
             }
-        }
 
-        self.project_board = Some(board);
+            let pull = &self.pulls[&number];
+            let author = match &pull.author {
+                Some(author) => format!("@{}", author),
+                None => "its author".to_owned(),
+            };
+
+            // Github only works out whether a PR conflicts when it's fetched
+            let conflicts = !pull.is_draft()
+                && self
+                    .github
+                    .get_pull(self.config.owner(), self.config.name(), number)
+                    .await?
+                    .mergeable
+                    == Some(false);
+            let waiting_on = if conflicts {
+                format!(
+                    "needs a rebase onto `{}` to resolve its conflicts, {}",
+                    pull.base_ref_name, author
+                )
+            } else if !pull.is_draft() && self.config.require_review() && !pull.approved {
+                "is waiting on review".to_owned()
+            } else {
+                format!("is waiting on {}", author)
+            };
+
+            let mut msg = format!(
+                ":hourglass: This PR has had no activity for {} days and {}",
+                days, waiting_on
+            );
+            if let Action::WarnClosing = action {
+                info!("Warning abandoned PR #{} that it will be closed", number);
+                msg.push_str(&format!(
+                    ". It will be closed in {} days unless there's activity on it.",
+                    stale.close_grace_days()
+                ));
+            } else {
+                info!("Nagging stale PR #{}", number);
+            }
+            self.github
+                .create_comment(self.config.owner(), self.config.name(), number, &msg)
+                .await?;
+
+            if let Some(pull) = self.pulls.get_mut(&number) {
+                pull.nagged_at = Some(now);
+            }
+        }
 
-        info!("Done Synchronizing");
         Ok(())
     }
-}
 
-pub struct ActivePullRequestContext<'a> {
-    pull_request: &'a mut PullRequestState,
-    github: &'a GithubClient,
-    config: &'a RepoConfig,
-    project_board: Option<&'a ProjectBoard>,
-    sender: &'a str,
-}
+    /// Stop tracking a PR and delete its project card
+    async fn forget_pull(&mut self, number: u64) -> Result<()> {
+        if let Some(mut pull) = self.pulls.remove(&number) {
+            if let Some(board) = &self.project_board {
+                board.delete_card(&*self.github, &mut pull).await?;
+            }
+        }
 
-impl<'a> ActivePullRequestContext<'a> {
-    pub fn pr(&self) -> &PullRequestState {
-        &self.pull_request
+        Ok(())
     }
 
-    pub fn pr_mut(&mut self) -> &mut PullRequestState {
-        &mut self.pull_request
-    }
+    /// Evict any tracked PRs which are no longer open, e.g. because the webhook for one being
+    /// closed was missed, so that they stop matching the statuses of merge commits and holding up
+    /// the queue. Unlike `synchronize`, the state of the PRs which are still open is left as is.
+    async fn sweep(&mut self) -> Result<()> {
+        let open: HashSet<u64> = self
+            .github
+            .open_pulls(self.config.owner(), self.config.name())
+            .await?
+            .iter()
+            .map(|pull| pull.number)
+            .collect();
+        let stale: Vec<u64> = self
+            .pulls
+            .keys()
+            .filter(|number| !open.contains(number))
+            .copied()
+            .collect();
 
-    pub fn github(&self) -> &GithubClient {
-        &self.github
-    }
+        if stale.is_empty() {
+            return Ok(());
+        }
 
-    pub fn config(&self) -> &RepoConfig {
-        &self.config
-    }
+        for number in stale {
+            info!("Evicting PR #{} which is no longer open", number);
+            self.forget_pull(number).await?;
+        }
 
-    #[allow(dead_code)]
-    pub fn project_board(&self) -> Option<&'a ProjectBoard> {
-        self.project_board
+        // The queue may have been waiting on an evicted PR
+        self.process_merge_queue().await
     }
 
-    pub fn sender(&self) -> &str {
-        &self.sender
+    /// Check whether the merge queue has stopped making progress, which can otherwise go unnoticed
+    /// since the queue only moves when Github sends a webhook. Each time the queue gets stuck an
+    /// alert is published as a queue event and, if configured, sent to the stuck queue webhook.
+    fn check_stuck(&mut self, now: SystemTime) {
+        let stuck = self.stuck_reason(now);
+        self.metrics.stuck.store(stuck.is_some(), Ordering::Relaxed);
+
+        match stuck {
+            Some((number, reason)) if !self.stuck => {
+                self.stuck = true;
+                self.metrics.stuck_alerts.fetch_add(1, Ordering::Relaxed);
+                self.alert_stuck(number, reason);
+            }
+            Some(_) => {}
+            None => self.stuck = false,
+        }
+    }
+
+    fn stuck_reason(&mut self, now: SystemTime) -> Option<(Option<u64>, StuckReason)> {
+        if let Some(pull) = self.pulls.values().find(|pull| pull.status.is_testing()) {
+            self.idle_since = None;
+            return match &pull.status {
+                Status::Testing {
+                    tests_started_at, ..
+                } if now.duration_since(*tests_started_at).unwrap_or_default()
+                    >= self.config.timeout() =>
+                {
+                    Some((Some(pull.number), StuckReason::TestsNotReporting))
+                }
+                _ => None,
+            };
+        }
+
+        // The PR which would be tested next, with PRs waiting for a landing train to depart not
+        // holding up the queue
+        let next = self
+            .pulls
+            .values()
+            .filter(|pull| pull.status.is_queued())
+            .filter(|pull| has_boarded(&self.config, pull, now))
+            .min_by_key(|pull| pull.to_queue_entry(&self.config))
+            .map(|pull| pull.number);
+        if next.is_none() || self.merge_queue.is_paused() {
+            self.idle_since = None;
+            return None;
+        }
+
+        let idle_since = *self.idle_since.get_or_insert(now);
+        if now.duration_since(idle_since).unwrap_or_default() >= self.config.stuck_queue_after() {
+            Some((next, StuckReason::NothingStarted))
+        } else {
+            None
+        }
+    }
+
+    fn alert_stuck(&self, number: Option<u64>, reason: StuckReason) {
+        let message = match (reason, number) {
+            (StuckReason::TestsNotReporting, Some(number)) => format!(
+                "{}/{}: merge queue is stuck, #{} has been testing for longer than {:?} without \
+                 any checks reporting",
+                self.config.owner(),
+                self.config.name(),
+                number,
+                self.config.timeout()
+            ),
+            _ => format!(
+                "{}/{}: merge queue is stuck, PRs have been queued for {:?} without any starting \
+                 testing",
+                self.config.owner(),
+                self.config.name(),
+                self.config.stuck_queue_after()
+            ),
+        };
+        warn!("{}", message);
+
+        let _ = self.queue_events.send(QueueEvent::Stuck { number, reason });
+
+        if let Some(url) = self.config.stuck_queue_webhook() {
+            // `text` is what Slack and similar chat webhooks display
+            let body = serde_json::json!({
+                "text": message,
+                "repo": format!("{}/{}", self.config.owner(), self.config.name()),
+                "number": number,
+                "reason": reason,
+            });
+            let url = url.to_owned();
+            tokio::spawn(async move {
+                let response = reqwest::Client::new().post(&url).json(&body).send().await;
+                if let Err(e) = response.and_then(|response| response.error_for_status()) {
+                    error!("Unable to send stuck queue alert: {}", e);
+                }
+            });
+        }
+    }
+
+    async fn synchronize(&mut self) -> Result<()> {
+        info!("Synchronizing");
+
+        let pulls = self
+            .github
+            .open_pulls(self.config.repo().owner(), self.config.repo().name())
+            .await?;
+        info!("{} Open PullRequests", pulls.len());
+
+        // TODO: Scrape the comments/Reviews of each PR to pull out reviewer/approval data
+
+        // Diff against the existing state so that the state of PRs which haven't changed, e.g.
+        // their place in the queue or an in-flight test, is preserved. The queue will notice if
+        // its head is no longer being tested and pick a new head.
+        let mut previous = std::mem::take(&mut self.pulls);
+        for mut pull in pulls {
+            if let Some(existing) = previous.remove(&pull.number) {
+                if !pull.carry_over(existing) {
+                    info!("PR #{} was updated since it was last seen", pull.number);
+                }
+            }
+            self.pulls.insert(pull.number, pull);
+        }
+        for number in previous.keys() {
+            info!("PR #{} is no longer open", number);
+        }
+
+        // Sync and reset project board
+        let board = crate::project_board::ProjectBoard::synchronize_or_init(
+            &*self.github,
+            &self.config,
+            &mut self.pulls,
+        )
+        .await?;
+
+        // Ensure all labels exist
+        let owner = self.config.owner();
+        let name = self.config.name();
+        for label in self.config.labels().all() {
+            if !self.github.label_exists(owner, name, label).await? {
+                self.github
+                    .create_label(owner, name, label, "D0D8D8", None)
+                    .await?;
+            }
+        }
+
+        self.project_board = Some(board);
+        self.update_project_board().await?;
+
+        // The tree may have been closed or reopened, and the default branch fixed or broken, while
+        // no one was listening
+        self.recheck_pause_reasons().await;
+
+        info!("Done Synchronizing");
+        Ok(())
+    }
+}
+
+/// Periodically renew the repo's lease until it is lost, either because another instance acquired
+/// it or because it couldn't be renewed before it expired
+async fn renew_lease(store: Arc<dyn Store>, config: RepoConfig, leader: Arc<AtomicBool>) {
+    let mut renewed_at = Instant::now();
+    loop {
+        tokio::time::sleep(LEASE_RENEWAL_INTERVAL).await;
+
+        match store.acquire_lease(config.repo()).await {
+            Ok(true) => renewed_at = Instant::now(),
+            Ok(false) => break,
+            Err(e) => {
+                error!("Error while renewing lease: {:?}", e);
+                if renewed_at.elapsed() >= LEASE_TTL {
+                    break;
+                }
+            }
+        }
+    }
+
+    leader.store(false, Ordering::Release);
+}
+
+pub struct ActivePullRequestContext<'a> {
+    pull_request: &'a mut PullRequestState,
+    repo: &'a mut dyn GitApi,
+    github: &'a dyn GithubApi,
+    config: &'a RepoConfig,
+    project_board: Option<&'a ProjectBoard>,
+    sender: &'a str,
+}
+
+impl<'a> ActivePullRequestContext<'a> {
+    pub fn pr(&self) -> &PullRequestState {
+        self.pull_request
+    }
+
+    pub fn pr_mut(&mut self) -> &mut PullRequestState {
+        self.pull_request
+    }
+
+    pub fn github(&self) -> &dyn GithubApi {
+        self.github
+    }
+
+    pub fn config(&self) -> &RepoConfig {
+        self.config
+    }
+
+    #[allow(dead_code)]
+    pub fn project_board(&self) -> Option<&'a ProjectBoard> {
+        self.project_board
+    }
+
+    pub fn sender(&self) -> &str {
+        self.sender
     }
 
     pub async fn create_pr_comment(&self, body: &str) -> Result<()> {
         self.github()
-            .issues()
             .create_comment(
                 self.config().owner(),
                 self.config().name(),
@@ -640,6 +2278,14 @@ impl<'a> ActivePullRequestContext<'a> {
         Ok(())
     }
 
+    pub fn git_repository(&mut self) -> &mut dyn GitApi {
+        self.repo
+    }
+
+    pub async fn trial_merge(&mut self) -> Result<bool> {
+        MergeQueue::trial_merge(self.config, self.github, self.repo, self.pull_request).await
+    }
+
     pub async fn set_label(&mut self, label: &str) -> Result<()> {
         self.pull_request
             .add_label(self.config, self.github, label)
@@ -656,9 +2302,9 @@ impl<'a> ActivePullRequestContext<'a> {
 pub struct CommandContext<'a> {
     number: u64,
     pull_request: Option<&'a mut PullRequestState>,
-    github: &'a GithubClient,
+    github: &'a dyn GithubApi,
     config: &'a RepoConfig,
-    repo: &'a mut GitRepository,
+    repo: &'a mut dyn GitApi,
     project_board: Option<&'a ProjectBoard>,
     sender: &'a str,
 }
@@ -677,6 +2323,7 @@ impl<'a> CommandContext<'a> {
         if let Some(pull_request) = &mut self.pull_request {
             Some(ActivePullRequestContext {
                 pull_request,
+                repo: self.repo,
                 github: self.github,
                 config: self.config,
                 project_board: self.project_board,
@@ -701,16 +2348,16 @@ impl<'a> CommandContext<'a> {
         self.pull_request.as_deref_mut()
     }
 
-    pub fn git_repository(&mut self) -> &mut GitRepository {
-        &mut self.repo
+    pub fn git_repository(&mut self) -> &mut dyn GitApi {
+        &mut *self.repo
     }
 
-    pub fn github(&self) -> &GithubClient {
-        &self.github
+    pub fn github(&self) -> &dyn GithubApi {
+        self.github
     }
 
     pub fn config(&self) -> &RepoConfig {
-        &self.config
+        self.config
     }
 
     pub fn project_board(&self) -> Option<&'a ProjectBoard> {
@@ -718,12 +2365,11 @@ impl<'a> CommandContext<'a> {
     }
 
     pub fn sender(&self) -> &str {
-        &self.sender
+        self.sender
     }
 
     pub async fn create_pr_comment(&self, body: &str) -> Result<()> {
         self.github()
-            .issues()
             .create_comment(
                 self.config().owner(),
                 self.config().name(),
@@ -734,3 +2380,1797 @@ impl<'a> CommandContext<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fake::{
+            base_status_event, comment_event, comment_payload, merge_oid, oid, rebased_oid,
+            status_event, FakeGithub, NAME, OWNER,
+        },
+        github_api::CommitCheck,
+        state::{CanaryResult, Repo},
+        store::{Delivery, LandingResult, SqliteStore, StateSnapshot},
+    };
+    use github::{EventType, IssueCommentEvent, ReactionType, StatusEventState};
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    fn config() -> RepoConfig {
+        toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            "#,
+        )
+        .unwrap()
+    }
+
+    /// Start an event processor against `github` and run it until it has handled `events`
+    async fn process(github: &FakeGithub, store: &Arc<dyn Store>, events: Vec<Event>) {
+        Box::pin(process_with_config(config(), github, store, events)).await
+    }
+
+    async fn process_with_config(
+        config: RepoConfig,
+        github: &FakeGithub,
+        store: &Arc<dyn Store>,
+        events: Vec<Event>,
+    ) {
+        let event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        let (tx, mut rx) = EventProcessorSender::channel(events.len() + 1);
+
+        let send = async {
+            for (i, event) in events.into_iter().enumerate() {
+                tx.webhook(event, i.to_string()).await.unwrap();
+            }
+            tx.shutdown().await.unwrap();
+        };
+        // The event processor's future is too large for a test thread's stack
+        tokio::join!(Box::pin(event_processor.start(&mut rx)), send);
+    }
+
+    #[tokio::test]
+    async fn priority_lane() {
+        let status = Event::from_json(
+            EventType::Status,
+            include_bytes!("../../github/test-input/status-event.json"),
+        )
+        .unwrap();
+        let comments: Vec<IssueCommentEvent> = serde_json::from_str(include_str!(
+            "../../github/test-input/issue-comment-event.json"
+        ))
+        .unwrap();
+        let comment = Event::IssueComment(comments.into_iter().next().unwrap());
+
+        let (tx, mut rx) = EventProcessorSender::channel(10);
+        tx.webhook(status.clone(), "1".to_owned()).await.unwrap();
+        tx.sync().await.unwrap();
+        tx.webhook(status, "2".to_owned()).await.unwrap();
+        tx.webhook(comment, "3".to_owned()).await.unwrap();
+        assert_eq!(tx.metrics().depth(), 4);
+
+        // The comment is serviced first, followed by the bulk requests in the order they were sent
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            order.push(match rx.recv().await.unwrap() {
+                Request::Webhook { delivery_id, .. } => delivery_id,
+                Request::Synchronize => "sync".to_owned(),
+                request => panic!("unexpected request {:?}", request),
+            });
+        }
+        assert_eq!(order, ["3", "1", "sync", "2"]);
+        assert_eq!(tx.metrics().depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn land_across_restart() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.approve(1);
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        process(
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land p=high")],
+        )
+        .await;
+
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        assert_eq!(github.reactions(), [ReactionType::Rocket]);
+        assert!(github.labels(1).contains("bors-high-priority"));
+        assert_eq!(github.branch("auto"), Some(merge_oid.clone()));
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+
+        // A restarted event processor picks up the in-flight test where the last one left off
+        process(
+            &github,
+            &store,
+            vec![status_event(&merge_oid, "ci", "success")],
+        )
+        .await;
+
+        assert_eq!(github.branch("master"), Some(merge_oid));
+        assert_eq!(github.card_column(1), None);
+        let landings = store.landings(&Repo::new(OWNER, NAME)).await.unwrap();
+        assert_eq!(landings.len(), 1);
+        assert_eq!(landings[0].result, LandingResult::Landed);
+    }
+
+    #[tokio::test]
+    async fn resync_on_drift() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        github.remove_card(1);
+
+        // Moving the missing card fails, after which the PR is resynchronized and given a new card
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "0".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        assert_eq!(github.card_column(1).as_deref(), Some("Queued"));
+
+        // Which means the PR can go on to be tested
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        let status = Request::Webhook {
+            event: status_event(&oid("unrelated"), "ci", "success"),
+            delivery_id: "1".to_owned(),
+        };
+        Box::pin(event_processor.process_request(status)).await;
+        assert_eq!(github.branch("auto"), Some(merge_oid));
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+    }
+
+    #[tokio::test]
+    async fn command_from_admin_api() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        Box::pin(event_processor.become_leader()).await;
+
+        // Commands are authorized as if their sender had commented them
+        let command = |sender: &str| Request::RunCommand {
+            sender: sender.to_owned(),
+            number: 1,
+            command: "/land".to_owned(),
+        };
+        Box::pin(event_processor.process_request(command("mallory"))).await;
+        assert_eq!(github.card_column(1).as_deref(), Some("In Review"));
+        assert!(github.comments(1)[0].contains("Insufficient privileges"));
+
+        Box::pin(event_processor.process_request(command("alice"))).await;
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+        // There is no comment to react to
+        assert!(github.reactions().is_empty());
+
+        // Both are audited, refused or not
+        let entries = store.audit_log(&Repo::new(OWNER, NAME)).await.unwrap();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.user.as_str(), entry.authorized, entry.source))
+                .collect::<Vec<_>>(),
+            [
+                ("mallory", false, CommandSource::Admin),
+                ("alice", true, CommandSource::Admin)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sweep_closed_pulls() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "0".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+
+        // The PR being tested is closed without bors hearing about it
+        github.close_pull(1);
+        Box::pin(event_processor.process_request(Request::Sweep)).await;
+
+        assert!(!event_processor.pulls.contains_key(&1));
+        assert!(event_processor.pulls.contains_key(&2));
+        assert_eq!(github.card_column(1), None);
+        assert_eq!(github.card_column(2).as_deref(), Some("In Review"));
+    }
+
+    #[tokio::test]
+    async fn canary_history() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        // Comments jump ahead of status events, so each canary is run separately
+        let canary_oid = merge_oid(&oid("base"), &oid("head-1"));
+        for state in &["success", "failure"] {
+            process(
+                &github,
+                &store,
+                vec![
+                    comment_event(1, "alice", "/canary"),
+                    status_event(&canary_oid, "ci", state),
+                ],
+            )
+            .await;
+        }
+
+        // The second canary is compared against the first
+        let comments = github.comments(1);
+        assert_eq!(comments[0], ":sunny: Canary successful");
+        assert!(comments[1].starts_with(":broken_heart: Test Failed"));
+        assert!(comments[2].contains("| ci | :white_check_mark: | :x: |"));
+
+        let state = store.load(&Repo::new(OWNER, NAME)).await.unwrap().unwrap();
+        let runs = &state.pulls[&1].canary_runs;
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].result, CanaryResult::Passed);
+        assert_eq!(runs[1].result, CanaryResult::Failed);
+        assert_eq!(runs[1].merge_oid, canary_oid);
+        assert!(!runs[1].test_results["ci"].passed);
+    }
+
+    #[tokio::test]
+    async fn canary_permission() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            canary-permission = "authors"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        // The PR's author can canary it, but not land it nor canary someone else's PR
+        Box::pin(process_with_config(
+            config.clone(),
+            &github,
+            &store,
+            vec![
+                comment_event(1, "Codertocat", "/land"),
+                comment_event(1, "mallory", "/canary"),
+            ],
+        ))
+        .await;
+        assert_eq!(github.comments(1).len(), 2);
+        assert!(github
+            .comments(1)
+            .iter()
+            .all(|c| c.contains("Insufficient privileges")));
+        assert_eq!(github.branch("canary"), None);
+
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![comment_event(1, "Codertocat", "/canary")],
+        ))
+        .await;
+        assert_eq!(
+            github.branch("canary"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_tests() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let failed_oid = merge_oid(&oid("base"), &oid("head-1"));
+        process(
+            &github,
+            &store,
+            vec![
+                comment_event(1, "alice", "/land"),
+                comment_event(2, "alice", "/land"),
+                status_event(&failed_oid, "ci", "failure"),
+            ],
+        )
+        .await;
+
+        // The failed PR is returned to review and the next PR in the queue is tested
+        assert_eq!(github.branch("master"), Some(oid("base")));
+        assert_eq!(github.card_column(1).as_deref(), Some("In Review"));
+        assert!(github.comments(1)[0].starts_with(":broken_heart: Test Failed"));
+        assert_eq!(github.card_column(2).as_deref(), Some("Testing"));
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-2")))
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_events() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        let (tx, mut rx) = EventProcessorSender::channel(10);
+        let mut queue_events = tx.subscribe();
+
+        let failed_oid = merge_oid(&oid("base"), &oid("head-1"));
+        tx.webhook(comment_event(1, "alice", "/land"), "1".to_owned())
+            .await
+            .unwrap();
+        tx.webhook(comment_event(2, "alice", "/land"), "2".to_owned())
+            .await
+            .unwrap();
+        tx.webhook(status_event(&failed_oid, "ci", "failure"), "3".to_owned())
+            .await
+            .unwrap();
+        let shutdown = async { tx.shutdown().await.unwrap() };
+        tokio::join!(Box::pin(event_processor.start(&mut rx)), shutdown);
+
+        let mut events = Vec::new();
+        while let Ok(event) = queue_events.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            [
+                QueueEvent::Enqueued { number: 1 },
+                QueueEvent::TestingStarted {
+                    number: 1,
+                    merge_oid: failed_oid.clone(),
+                },
+                QueueEvent::Enqueued { number: 2 },
+                QueueEvent::Finished {
+                    number: 1,
+                    author: Some("Codertocat".to_owned()),
+                    merge_oid: failed_oid,
+                    result: LandingResult::Failed,
+                    failing_checks: vec!["ci".to_owned()],
+                },
+                QueueEvent::Dequeued {
+                    number: 1,
+                    author: Some("Codertocat".to_owned()),
+                    evicted: false,
+                },
+                QueueEvent::TestingStarted {
+                    number: 2,
+                    merge_oid: merge_oid(&oid("base"), &oid("head-2")),
+                },
+            ]
+        );
+
+        let json = serde_json::to_value(&events[3]).unwrap();
+        assert_eq!(json["event"], "finished");
+        assert_eq!(json["result"], "failed");
+
+        // Both PRs were queued by a command and started testing, but neither landed
+        let count = |histogram: &Histogram| {
+            histogram
+                .samples("latency", "")
+                .lines()
+                .last()
+                .unwrap()
+                .to_owned()
+        };
+        let metrics = tx.metrics();
+        assert_eq!(count(metrics.command_to_queued()), "latency_count{} 2");
+        assert_eq!(count(metrics.queued_to_testing()), "latency_count{} 2");
+        assert_eq!(count(metrics.testing_to_merged()), "latency_count{} 0");
+    }
+
+    #[tokio::test]
+    async fn pause_queue() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let mut queue_events = event_processor.queue_events.subscribe();
+
+        // While paused PRs can be queued but none are tested
+        Box::pin(event_processor.process_request(Request::Pause)).await;
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "1".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        assert!(event_processor.merge_queue.is_paused());
+        assert_eq!(github.card_column(1).as_deref(), Some("Queued"));
+        assert_eq!(github.branch("auto"), None);
+
+        Box::pin(event_processor.process_request(Request::Resume)).await;
+        assert!(!event_processor.merge_queue.is_paused());
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+
+        // An evicted PR no longer holds up the queue
+        Box::pin(event_processor.process_request(Request::Evict(1))).await;
+        assert!(event_processor.pulls.is_empty());
+        assert_eq!(github.card_column(1), None);
+
+        let mut events = Vec::new();
+        while let Ok(event) = queue_events.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            [
+                QueueEvent::Paused,
+                QueueEvent::Enqueued { number: 1 },
+                QueueEvent::Resumed,
+                QueueEvent::TestingStarted {
+                    number: 1,
+                    merge_oid: merge_oid(&oid("base"), &oid("head-1")),
+                },
+                QueueEvent::Dequeued {
+                    number: 1,
+                    author: Some("Codertocat".to_owned()),
+                    evicted: true,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tree_closed_by_issue() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [tree-closed]
+            issue = 42
+            label = "tree-closed"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config.clone(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let webhook = |event| Request::Webhook {
+            event,
+            delivery_id: "1".to_owned(),
+        };
+
+        // The tracking issue only closes the tree while it's labeled
+        let opened = github.update_issue(42, "opened", true, &[]);
+        Box::pin(event_processor.process_request(webhook(opened))).await;
+        assert!(!event_processor.merge_queue.is_paused());
+        let labeled = github.update_issue(42, "labeled", true, &["tree-closed"]);
+        Box::pin(event_processor.process_request(webhook(labeled))).await;
+        assert!(event_processor.merge_queue.is_paused());
+
+        // Other issues don't
+        let other = github.update_issue(7, "closed", false, &["tree-closed"]);
+        Box::pin(event_processor.process_request(webhook(other))).await;
+        assert!(event_processor.merge_queue.is_paused());
+
+        let land = webhook(comment_event(1, "alice", "/land"));
+        Box::pin(event_processor.process_request(land)).await;
+        assert_eq!(github.branch("auto"), None);
+
+        // A restarted event processor finds the tree still closed
+        let mut restarted = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(restarted.become_leader()).await;
+        assert!(restarted.merge_queue.is_paused());
+
+        let closed = github.update_issue(42, "closed", false, &["tree-closed"]);
+        Box::pin(restarted.process_request(webhook(closed))).await;
+        assert!(!restarted.merge_queue.is_paused());
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn tree_reopened_while_down() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [tree-closed]
+            issue = 42
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config.clone(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let webhook = |event| Request::Webhook {
+            event,
+            delivery_id: "1".to_owned(),
+        };
+
+        // Reopening the tree doesn't resume a queue paused by an admin
+        Box::pin(event_processor.process_request(Request::Pause)).await;
+        let opened = github.update_issue(42, "opened", true, &[]);
+        Box::pin(event_processor.process_request(webhook(opened))).await;
+        let closed = github.update_issue(42, "closed", false, &[]);
+        Box::pin(event_processor.process_request(webhook(closed))).await;
+        assert!(event_processor.merge_queue.is_paused());
+        Box::pin(event_processor.process_request(Request::Resume)).await;
+        assert!(!event_processor.merge_queue.is_paused());
+
+        // A restarted event processor notices the tree was reopened while it was down
+        let reopened = github.update_issue(42, "reopened", true, &[]);
+        Box::pin(event_processor.process_request(webhook(reopened))).await;
+        assert!(event_processor.merge_queue.is_paused());
+        github.update_issue(42, "closed", false, &[]);
+        let mut restarted = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(restarted.become_leader()).await;
+        assert!(!restarted.merge_queue.is_paused());
+    }
+
+    #[tokio::test]
+    async fn pause_on_red_base() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            pause-on-red-base = true
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config.clone(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let webhook = |event| Request::Webhook {
+            event,
+            delivery_id: "1".to_owned(),
+        };
+
+        // Only failures of required checks on the head of the base branch pause the queue
+        let base = oid("base");
+        let off_base = status_event(&base, "ci", "failure");
+        Box::pin(event_processor.process_request(webhook(off_base))).await;
+        let not_required = base_status_event(&base, "lint", "failure");
+        Box::pin(event_processor.process_request(webhook(not_required))).await;
+        assert!(!event_processor.merge_queue.is_paused());
+        let red = base_status_event(&base, "ci", "failure");
+        Box::pin(event_processor.process_request(webhook(red))).await;
+        assert!(event_processor.merge_queue.is_paused());
+
+        let land = webhook(comment_event(1, "alice", "/land"));
+        Box::pin(event_processor.process_request(land)).await;
+        assert_eq!(github.branch("auto"), None);
+
+        // A restarted event processor finds the base branch still red, and resumes once it's green
+        github.report_check(
+            &base,
+            CommitCheck {
+                name: "ci".to_owned(),
+                details_url: "https://ci.example.com/1".to_owned(),
+                conclusion: Some(github::Conclusion::Failure),
+                is_status: true,
+            },
+        );
+        let mut restarted = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(restarted.become_leader()).await;
+        assert!(restarted.merge_queue.is_paused());
+        let green = base_status_event(&base, "ci", "success");
+        Box::pin(restarted.process_request(webhook(green))).await;
+        assert!(!restarted.merge_queue.is_paused());
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn pause_reasons() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            pause-on-red-base = true
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let webhook = |event| Request::Webhook {
+            event,
+            delivery_id: "1".to_owned(),
+        };
+
+        // The base branch going green doesn't resume a queue paused by an admin
+        let base = oid("base");
+        Box::pin(event_processor.process_request(Request::Pause)).await;
+        let red = base_status_event(&base, "ci", "failure");
+        Box::pin(event_processor.process_request(webhook(red))).await;
+        let green = base_status_event(&base, "ci", "success");
+        Box::pin(event_processor.process_request(webhook(green))).await;
+        assert!(event_processor.merge_queue.is_paused());
+
+        // Nor does an admin resuming it resume a queue paused by a red base branch
+        let red = base_status_event(&base, "ci", "failure");
+        Box::pin(event_processor.process_request(webhook(red))).await;
+        Box::pin(event_processor.process_request(Request::Resume)).await;
+        assert!(event_processor.merge_queue.is_paused());
+        let green = base_status_event(&base, "ci", "success");
+        Box::pin(event_processor.process_request(webhook(green))).await;
+        assert!(!event_processor.merge_queue.is_paused());
+    }
+
+    #[tokio::test]
+    async fn stuck_queue() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let mut queue_events = event_processor.queue_events.subscribe();
+
+        // A paused queue isn't stuck
+        Box::pin(event_processor.process_request(Request::Pause)).await;
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "1".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        let now = SystemTime::now();
+        event_processor.check_stuck(now);
+        event_processor.check_stuck(now + Duration::from_secs(60 * 60));
+        assert!(!event_processor.metrics.is_stuck());
+
+        // Unpausing without processing the queue leaves the PR waiting, as a lost webhook would
+        event_processor.merge_queue.set_paused(false);
+        event_processor.check_stuck(now);
+        event_processor.check_stuck(now + Duration::from_secs(60 * 29));
+        assert!(!event_processor.metrics.is_stuck());
+        event_processor.check_stuck(now + Duration::from_secs(60 * 30));
+        event_processor.check_stuck(now + Duration::from_secs(60 * 31));
+        assert!(event_processor.metrics.is_stuck());
+        assert_eq!(event_processor.metrics.stuck_alerts(), 1);
+
+        Box::pin(event_processor.process_request(Request::ProcessQueue)).await;
+        event_processor.check_stuck(now);
+        assert!(!event_processor.metrics.is_stuck());
+
+        // The PR being tested hasn't heard from its checks by the timeout
+        event_processor
+            .check_stuck(now + event_processor.config.timeout() + Duration::from_secs(1));
+        assert!(event_processor.metrics.is_stuck());
+        assert_eq!(event_processor.metrics.stuck_alerts(), 2);
+
+        let mut events = Vec::new();
+        while let Ok(event) = queue_events.try_recv() {
+            if let QueueEvent::Stuck { .. } = event {
+                events.push(event);
+            }
+        }
+        assert_eq!(
+            events,
+            [
+                QueueEvent::Stuck {
+                    number: Some(1),
+                    reason: StuckReason::NothingStarted,
+                },
+                QueueEvent::Stuck {
+                    number: Some(1),
+                    reason: StuckReason::TestsNotReporting,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_checks() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            poll-checks-minutes = 10
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "1".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        assert_eq!(github.branch("auto"), Some(merge_oid.clone()));
+
+        // CI finished without its webhook being delivered
+        github.report_check(
+            &merge_oid,
+            CommitCheck {
+                name: "ci".to_owned(),
+                details_url: "https://ci.example.com/1".to_owned(),
+                conclusion: Some(github::Conclusion::Success),
+                is_status: true,
+            },
+        );
+        let now = SystemTime::now();
+        Box::pin(event_processor.poll_checks(now + Duration::from_secs(60 * 9)))
+            .await
+            .unwrap();
+        assert_eq!(github.branch("master"), Some(oid("base")));
+
+        Box::pin(event_processor.poll_checks(now + Duration::from_secs(60 * 10)))
+            .await
+            .unwrap();
+        assert_eq!(github.branch("master"), Some(merge_oid));
+        assert!(!event_processor.pulls.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn changelog_entry() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [changelog]
+            file = "CHANGELOG.md"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.set_body(1, "## Changelog\nFixed the thing\n");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        process_with_config(
+            config.clone(),
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        )
+        .await;
+
+        // The entry is part of the commit which is tested and landed
+        let merge_oid = oid(&format!(
+            "{}+CHANGELOG.md",
+            merge_oid(&oid("base"), &oid("head-1"))
+        ));
+        assert_eq!(github.branch("auto"), Some(merge_oid.clone()));
+        assert_eq!(
+            github.file("CHANGELOG.md").as_deref(),
+            Some("- Fixed the thing (#1)\n")
+        );
+
+        process_with_config(
+            config,
+            &github,
+            &store,
+            vec![status_event(&merge_oid, "ci", "success")],
+        )
+        .await;
+        assert_eq!(github.branch("master"), Some(merge_oid));
+    }
+
+    #[tokio::test]
+    async fn release() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [release]
+            base = "master"
+            version-file = "Cargo.toml"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.add_file("Cargo.toml", "[package]\nversion = \"1.2.3\"\n");
+        github.commit("Release v1.2.3", Some("v1.2.3"));
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config.clone(),
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store.clone(),
+        );
+        Box::pin(event_processor.become_leader()).await;
+
+        // Nothing has changed since the last release
+        Box::pin(event_processor.process_request(Request::Release(None))).await;
+        assert_eq!(github.branch("bors-release/v1.3.0"), None);
+
+        github.commit("Fix the thing", None);
+        Box::pin(event_processor.process_request(Request::Release(None))).await;
+        let head = oid("master+Cargo.toml");
+        assert_eq!(github.branch("bors-release/v1.3.0"), Some(head.clone()));
+        assert_eq!(
+            github.file("Cargo.toml").as_deref(),
+            Some("[package]\nversion = \"1.3.0\"\n")
+        );
+        let pull = Box::pin(github.get_pull("Codertocat", "Hello-World", 1))
+            .await
+            .unwrap();
+        assert_eq!(pull.title, "Release v1.3.0");
+        assert!(pull
+            .body
+            .unwrap()
+            .starts_with("Changes since v1.2.3:\n\n- Fix the thing\n"));
+        drop(event_processor);
+
+        // The release PR lands through the queue like any other, and is then tagged
+        Box::pin(process_with_config(
+            config.clone(),
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        ))
+        .await;
+        let merge_oid = merge_oid(&oid("base"), &head);
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![status_event(&merge_oid, "ci", "success")],
+        ))
+        .await;
+        assert_eq!(github.branch("master"), Some(merge_oid.clone()));
+        assert_eq!(github.tag("v1.3.0"), Some(merge_oid));
+    }
+
+    #[tokio::test]
+    async fn cla_gate() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [cla]
+            check = "license/cla"
+            sign-url = "https://cla.example.com"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        // Without a signature the PR isn't queued, and the author is pointed at the CLA
+        Box::pin(process_with_config(
+            config.clone(),
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        ))
+        .await;
+        assert_eq!(github.branch("auto"), None);
+        assert!(github
+            .comments(1)
+            .iter()
+            .any(|comment| comment.contains("(https://cla.example.com)")));
+
+        github.report_check(
+            &oid("head-1"),
+            CommitCheck {
+                name: "license/cla".to_owned(),
+                details_url: "https://cla.example.com".to_owned(),
+                conclusion: Some(github::Conclusion::Success),
+                is_status: true,
+            },
+        );
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        ))
+        .await;
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn nag_stale() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            require-review = true
+
+            [stale]
+            days = 7
+            exempt-labels = ["on-hold"]
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        event_processor
+            .pulls
+            .get_mut(&2)
+            .unwrap()
+            .labels
+            .insert("on-hold".to_owned());
+
+        // Both PRs were last updated long ago, but only the one without an exempt label is nagged
+        let now = SystemTime::now();
+        Box::pin(event_processor.nag_stale(now)).await.unwrap();
+        assert_eq!(
+            github.comments(1),
+            vec![":hourglass: This PR has had no activity for 7 days and is waiting on review"]
+        );
+        assert!(github.comments(2).is_empty());
+
+        // Once nagged a PR isn't nagged again until it's gone as long again without activity
+        let later = now + Duration::from_secs(60 * 60);
+        Box::pin(event_processor.nag_stale(later)).await.unwrap();
+        assert_eq!(github.comments(1).len(), 1);
+
+        github.approve(1);
+        Box::pin(event_processor.synchronize()).await.unwrap();
+        let later = now + Duration::from_secs(7 * 24 * 60 * 60);
+        Box::pin(event_processor.nag_stale(later)).await.unwrap();
+        assert_eq!(
+            github.comments(1)[1],
+            ":hourglass: This PR has had no activity for 7 days and is waiting on @Codertocat"
+        );
+    }
+
+    #[tokio::test]
+    async fn close_abandoned() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+
+            [stale]
+            days = 10
+            close-after-days = 30
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+
+        // Both PRs were last updated long ago, so are warned straight away
+        let now = SystemTime::now();
+        Box::pin(event_processor.nag_stale(now)).await.unwrap();
+        assert_eq!(
+            github.comments(1),
+            vec![
+                ":hourglass: This PR has had no activity for 30 days and is waiting on \
+                @Codertocat. It will be closed in 7 days unless there's activity on it."
+            ]
+        );
+
+        // bors' own comments aren't activity, even when it runs as a regular user, and neither
+        // are other bots', while anyone else's are
+        let warning = github.comments(1).remove(0);
+        let mut payload = comment_payload(1, "dependabot[bot]", "Rebased");
+        payload["sender"]["type"] = serde_json::json!("Bot");
+        let bot_comment =
+            Event::from_json(EventType::IssueComment, payload.to_string().as_bytes()).unwrap();
+        for (i, event) in vec![
+            comment_event(1, "bors", &warning),
+            bot_comment,
+            comment_event(2, "alice", "Still on it"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let request = Request::Webhook {
+                event,
+                delivery_id: i.to_string(),
+            };
+            Box::pin(event_processor.process_request(request)).await;
+        }
+
+        // Not until the grace period is up is the abandoned PR closed
+        Box::pin(event_processor.nag_stale(now + Duration::from_secs(24 * 60 * 60)))
+            .await
+            .unwrap();
+        assert_eq!(github.comments(1).len(), 1);
+        Box::pin(event_processor.nag_stale(now + Duration::from_secs(7 * 24 * 60 * 60)))
+            .await
+            .unwrap();
+        assert!(github.comments(1)[1].starts_with(":wastebasket: Closing this PR"));
+        let open: Vec<u64> = github
+            .open_pulls(OWNER, NAME)
+            .await
+            .unwrap()
+            .iter()
+            .map(|pull| pull.number)
+            .collect();
+        assert_eq!(open, vec![2]);
+        assert!(!event_processor.pulls.contains_key(&1));
+        assert_eq!(github.card_column(1), None);
+        assert_eq!(github.comments(2).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn landing_trains() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            landing-trains = ["morning", "afternoon"]
+
+            [[release-trains]]
+            name = "morning"
+            first-departure = "2020-01-01T09:00:00Z"
+            interval-days = 1
+
+            [[release-trains]]
+            name = "afternoon"
+            first-departure = "2020-01-01T16:00:00Z"
+            interval-days = 1
+
+            [[release-trains]]
+            name = "weekly"
+            first-departure = "2020-01-01T12:00:00Z"
+            interval-days = 7
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+
+        // The PR is queued but waits for the next train to depart, without the queue being stuck
+        let land = Request::Webhook {
+            event: comment_event(1, "alice", "/land"),
+            delivery_id: "0".to_owned(),
+        };
+        Box::pin(event_processor.process_request(land)).await;
+        assert!(event_processor.pulls[&1].status.is_queued());
+        assert_eq!(github.branch("auto"), None);
+        let now = SystemTime::now();
+        event_processor.idle_since = Some(now - Duration::from_secs(24 * 60 * 60));
+        assert!(event_processor.stuck_reason(now).is_none());
+
+        // Once a train has departed since it was queued it's landed
+        let departed = event_processor.config.landing_cutoff(now).unwrap();
+        event_processor.pulls.get_mut(&1).unwrap().status =
+            Status::Queued(departed - Duration::from_secs(60));
+        Box::pin(event_processor.process_request(Request::ProcessQueue)).await;
+        assert_eq!(
+            github.branch("auto"),
+            Some(merge_oid(&oid("base"), &oid("head-1")))
+        );
+    }
+
+    #[tokio::test]
+    async fn comment_on_testing() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            comment-on-testing = true
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        ))
+        .await;
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        assert_eq!(
+            github.comments(1),
+            vec![format!(
+                ":arrow_forward: @Codertocat, this PR has reached the front of the queue and is \
+                being tested as {0}. Its CI can be followed \
+                [here](https://github.com/Codertocat/Hello-World/commit/{0}/checks).",
+                merge_oid
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn advisory_checks() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            advisory-checks = ["lint"]
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        Box::pin(process_with_config(
+            config.clone(),
+            &github,
+            &store,
+            vec![comment_event(1, "alice", "/land")],
+        ))
+        .await;
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+
+        // A failed advisory check is reported, without stopping the PR from landing
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![
+                status_event(&merge_oid, "lint", "failure"),
+                status_event(&merge_oid, "ci", "success"),
+            ],
+        ))
+        .await;
+        assert_eq!(github.branch("master"), Some(merge_oid));
+        let comments = github.comments(1);
+        assert!(comments[0].starts_with(":sunny: Landed on `master`"));
+        assert!(comments[0].contains("\n* :warning: Advisory checks failed: [lint]("));
+    }
+
+    /// Each of `/land`'s gates, and the commenter filters in front of them, through the event
+    /// processor. What each one decides is tested where it's implemented, this checks that they're
+    /// wired up: a PR failing a gate is refused with its explanation, while one passing is queued.
+    #[tokio::test]
+    async fn land_gates() {
+        struct Case {
+            config: &'static str,
+            setup: fn(&FakeGithub),
+            /// Batches of events, each processed by a fresh event processor so that comments
+            /// can't overtake the events before them
+            events: fn(&FakeGithub) -> Vec<Vec<Event>>,
+            comments: &'static [&'static str],
+            queued: bool,
+        }
+        fn land(_: &FakeGithub) -> Vec<Vec<Event>> {
+            vec![vec![comment_event(1, "alice", "/land")]]
+        }
+        let cases = [
+            Case {
+                config: "require-review = true",
+                setup: |github| github.approve(1),
+                events: |github| {
+                    vec![
+                        vec![github.push(1, "head-1b", "Codertocat")],
+                        vec![comment_event(1, "alice", "/land")],
+                    ]
+                },
+                comments: &["@alice :exclamation: This PR's approval was dismissed when @Codertocat \
+                    pushed new commits to it, as this repo requires PRs to be approved again after \
+                    being pushed to by anyone other than its trusted pushers. Unable to queue for \
+                    landing until it's re-approved"],
+                queued: false,
+            },
+            Case {
+                config: "require-review = true",
+                setup: |github| github.approve(1),
+                events: |github| {
+                    vec![
+                        vec![github.push(1, "head-1b", "bors")],
+                        vec![comment_event(1, "alice", "/land")],
+                    ]
+                },
+                comments: &[],
+                queued: true,
+            },
+            Case {
+                config: "require-review = true\napproval-on-push = \"keep\"\n\
+                    approval-freshness-days = 30",
+                setup: |github| {
+                    let approved_at = SystemTime::now() - Duration::from_secs(45 * 24 * 60 * 60);
+                    github.approve_by(1, "Octocat", approved_at);
+                },
+                events: |github| {
+                    vec![
+                        vec![github.push(1, "head-1b", "Codertocat")],
+                        vec![comment_event(1, "alice", "/land")],
+                    ]
+                },
+                comments: &["@alice :hourglass: This PR was last approved 45 days before its latest \
+                    commits were pushed, while approvals only count for 30 days in this repo. Unable \
+                    to queue for landing until it's re-approved"],
+                queued: false,
+            },
+            Case {
+                config: "require-review = true\nblock-self-approval = true",
+                setup: |github| github.approve_by(1, "Codertocat", SystemTime::now()),
+                events: land,
+                comments: &["@alice :eyes: This PR has only been approved by its author, while this \
+                    repo requires PRs to be approved by someone else. Unable to queue for landing \
+                    until it is"],
+                queued: false,
+            },
+            Case {
+                config: "[[required-reviewers]]\npaths = [\"consensus/**\"]\n\
+                    team = \"aptos-labs/consensus\"",
+                setup: |github| github.change_files(1, &["consensus/src/lib.rs"]),
+                events: land,
+                comments: &["@alice :lock: This PR changes paths owned by `aptos-labs/consensus`, \
+                    whose approval is required in this repo. Unable to queue for landing until it's \
+                    approved by a member of each"],
+                queued: false,
+            },
+            Case {
+                config: "[protected-paths]\npaths = [\".github/**\"]\nadmins = [\"carol\"]",
+                setup: |github| {
+                    github.change_files(1, &[".github/workflows/ci.yaml"]);
+                    github.approve_by(1, "carol", SystemTime::now());
+                },
+                events: land,
+                comments: &[],
+                queued: true,
+            },
+            Case {
+                config: "large-diff-lines = 500",
+                setup: |github| {
+                    github.set_size(1, 400, 200);
+                    github.approve(1);
+                },
+                events: land,
+                comments: &["@alice :mag: This PR changes 600 lines, while PRs changing more than 500 \
+                    lines need 2 approvals in this repo. Unable to queue for landing until it's \
+                    approved by another reviewer"],
+                queued: false,
+            },
+            Case {
+                config: "required-labels = [\"qa-signed-off\"]",
+                setup: |github| github.label(1, "qa-signed-off"),
+                events: land,
+                comments: &[],
+                queued: true,
+            },
+            Case {
+                config: "[description]\nsections = [\"Testing\", \"Risk\"]",
+                setup: |github| github.set_body(1, "## Testing\nAdded a unit test\n\n## Risk\n"),
+                events: land,
+                comments: &["@alice :page_facing_up: This PR's description is missing content under \
+                    `Risk`, which this repo requires PRs to fill in. Unable to queue for landing until \
+                    the description is updated"],
+                queued: false,
+            },
+            Case {
+                config: "[max-diff]\nlines = 1000",
+                setup: |github| github.set_size(1, 1500, 100),
+                events: |_| vec![vec![comment_event(1, "alice", "/land oversize")]],
+                comments: &["@alice :elephant: Only admins of this repo can land PRs which are larger \
+                    than it allows. Unable to queue for landing until it's split into smaller PRs, or \
+                    an admin lands it anyway"],
+                queued: false,
+            },
+            Case {
+                config: "[max-diff]\nlines = 1000",
+                setup: |github| github.set_size(1, 1500, 100),
+                events: |_| vec![vec![comment_event(1, "carol", "/land oversize")]],
+                comments: &[],
+                queued: true,
+            },
+            Case {
+                config: "[binary-files]",
+                setup: |github| github.change_binary_files(1, &["assets/logo.png"]),
+                events: |_| vec![vec![comment_event(1, "alice", "/land --allow-binaries")]],
+                comments: &[],
+                queued: true,
+            },
+            Case {
+                config: "allowed-users = [\"alice\", \"mallory\"]\ndenied-users = [\"Mallory\"]",
+                setup: |_| {},
+                events: |_| {
+                    vec![vec![
+                        comment_event(1, "mallory", "/land"),
+                        comment_event(1, "bob", "/land"),
+                    ]]
+                },
+                comments: &[
+                    "@mallory :key: Insufficient privileges to run `/land`: you're in this repo's \
+                    `denied-users`, so can't run commands whatever your Github permissions",
+                    "@bob :key: Insufficient privileges to run `/land`: only the users in this repo's \
+                    `allowed-users` can run commands, ask a maintainer to add you",
+                ],
+                queued: false,
+            },
+            Case {
+                config: "ignored-senders = [\"Echo-Bot\"]\nignore-bots = true",
+                setup: |_| {},
+                events: |_| {
+                    vec![vec![
+                        comment_event(1, "echo-bot", "/land"),
+                        comment_event(1, "dependabot[bot]", "/bogus"),
+                    ]]
+                },
+                comments: &[],
+                queued: false,
+            },
+            Case {
+                config: "require-review = true\n[[trusted-authors]]\nuser = \"Codertocat\"\n\
+                    paths = [\"**/Cargo.lock\"]",
+                setup: |github| github.change_files(1, &["Cargo.lock", "tools/Cargo.lock"]),
+                events: |github| vec![vec![github.opened(1)]],
+                comments: &[":robot: This PR is by a trusted author, so it has been queued for landing \
+                    without waiting for approval"],
+                queued: true,
+            },
+        ];
+
+        for (i, case) in cases.iter().enumerate() {
+            let config: RepoConfig = toml::from_str(&format!(
+                "owner = \"Codertocat\"\nname = \"Hello-World\"\nchecks = [\"ci\"]\n{}",
+                case.config
+            ))
+            .unwrap();
+            let github = FakeGithub::new();
+            for user in &["alice", "bob", "mallory", "echo-bot", "dependabot[bot]"] {
+                github.add_collaborator(user);
+            }
+            github.add_admin("carol");
+            github.open_pull(1, "head-1");
+            (case.setup)(&github);
+            let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+            for events in (case.events)(&github) {
+                Box::pin(process_with_config(config.clone(), &github, &store, events)).await;
+            }
+            assert_eq!(github.comments(1), case.comments, "case {}", i);
+            assert_eq!(github.branch("auto").is_some(), case.queued, "case {}", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn clean_up_landed() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            delete-landed-branches = true
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        Box::pin(github.update_ref(OWNER, NAME, "heads/pr-1", &oid("head-1"), false))
+            .await
+            .unwrap();
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        Box::pin(process_with_config(
+            config,
+            &github,
+            &store,
+            vec![
+                comment_event(1, "alice", "/land"),
+                status_event(&merge_oid, "ci", "success"),
+            ],
+        ))
+        .await;
+        assert_eq!(github.branch("master"), Some(merge_oid.clone()));
+
+        // The PR wasn't updated in-place so Github won't have marked it merged
+        let pull = Box::pin(github.get_pull(OWNER, NAME, 1)).await.unwrap();
+        assert!(matches!(pull.state, github::State::Closed));
+        let summary = format!(":sunny: Landed on `master` as {}\n", merge_oid);
+        assert!(github
+            .comments(1)
+            .iter()
+            .any(|comment| comment.starts_with(&summary) && comment.contains("* Retries: none")));
+        assert_eq!(github.branch("pr-1"), None);
+    }
+
+    #[tokio::test]
+    async fn foreign_push() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.approve(1);
+        github.push_after_bors("auto", "foreign");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        process(&github, &store, vec![comment_event(1, "alice", "/land")]).await;
+
+        // The merge commit is abandoned rather than pushed over whatever raced it, with the PR
+        // left queued
+        assert_eq!(github.branch("auto"), Some(oid("foreign")));
+        assert_eq!(github.card_column(1).as_deref(), Some("Queued"));
+        assert!(github.comments(1).is_empty());
+        assert!(github.statuses().is_empty());
+
+        // It's rebuilt from fresh state on the queue's next pass, e.g. once CI reports on whatever
+        // was pushed
+        process(
+            &github,
+            &store,
+            vec![status_event(&oid("foreign"), "ci", "success")],
+        )
+        .await;
+
+        let merge_oid = merge_oid(&oid("base"), &oid("head-1"));
+        assert_eq!(github.branch("auto"), Some(merge_oid));
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+    }
+
+    #[tokio::test]
+    async fn trial_merge_on_approval() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["ci"]
+            trial-merge = true
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        github.conflict(2);
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        let mut event_processor = EventProcessor::with_clients(
+            config,
+            Box::new(github.clone()),
+            Box::new(github.git()),
+            store,
+        );
+        Box::pin(event_processor.become_leader()).await;
+        for number in [1, 2] {
+            let request = Request::Webhook {
+                event: github.approved(number),
+                delivery_id: number.to_string(),
+            };
+            Box::pin(event_processor.process_request(request)).await;
+        }
+
+        // Only the conflicting PR is told about it, long before it's queued
+        assert!(github.comments(1).is_empty());
+        assert_eq!(
+            github.comments(2),
+            [
+                ":lock: Merge Conflict: this PR no longer applies cleanly on top of `master`. \
+            Please rebase it before queuing it for landing."
+            ]
+        );
+        let statuses = github.statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].ref_name, oid("head-2").to_string());
+        assert!(matches!(statuses[0].state, StatusEventState::Error));
+
+        // And it's refused when queued, while the PR which merges cleanly is queued
+        for number in [2, 1] {
+            let request = Request::Webhook {
+                event: comment_event(number, "alice", "/land"),
+                delivery_id: format!("land-{}", number),
+            };
+            Box::pin(event_processor.process_request(request)).await;
+        }
+        assert_eq!(github.comments(2).len(), 2);
+        assert_eq!(github.card_column(2).as_deref(), Some("In Review"));
+        assert_eq!(github.card_column(1).as_deref(), Some("Testing"));
+    }
+
+    #[tokio::test]
+    async fn update() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        github.open_pull(3, "base+head-3");
+        github.open_pull(4, "head-4");
+        github.conflict(4);
+        github.open_pull(5, "head-5");
+        github.make_read_only("pr-5");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        process(
+            &github,
+            &store,
+            vec![
+                comment_event(1, "alice", "/update"),
+                comment_event(2, "alice", "/update rebase"),
+                comment_event(3, "alice", "/update"),
+                comment_event(4, "alice", "/update"),
+                comment_event(5, "alice", "/update"),
+            ],
+        )
+        .await;
+
+        // PRs are updated in-place by merging in, or rebasing onto, their base branch
+        let base = oid("base");
+        assert_eq!(
+            github.branch("pr-1"),
+            Some(merge_oid(&base, &oid("head-1")))
+        );
+        assert_eq!(
+            github.branch("pr-2"),
+            Some(rebased_oid(&base, &oid("head-2")))
+        );
+        assert!(github.comments(1).is_empty());
+        assert!(github.comments(2).is_empty());
+
+        // Unless there's nothing to update, or the update can't be made or pushed
+        assert_eq!(github.branch("pr-3"), None);
+        assert_eq!(
+            github.comments(3),
+            ["@alice :bulb: This PR is already up to date with `master`"]
+        );
+        assert_eq!(github.branch("pr-4"), None);
+        assert_eq!(
+            github.comments(4),
+            ["@alice :lock: Merge Conflict: unable to update this PR with the latest changes from \
+            `master`. The conflicts will need to be resolved manually."]
+        );
+        assert_eq!(github.branch("pr-5"), None);
+        assert_eq!(github.comments(5).len(), 1);
+        assert!(github.comments(5)[0]
+            .starts_with("@alice :exclamation: failed to push the updated branch to this PR"));
+    }
+
+    #[tokio::test]
+    async fn rejected_commands() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        github.open_pull(2, "head-2");
+        github.conflict(2);
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        process(
+            &github,
+            &store,
+            vec![
+                comment_event(1, "mallory", "/land"),
+                comment_event(2, "alice", "/land"),
+            ],
+        )
+        .await;
+
+        // The refusal says which command was refused and what would permit it
+        assert_eq!(
+            github.comments(1),
+            ["@mallory :key: Insufficient privileges to run `/land`: only collaborators on this \
+            repo can run commands, ask one of its admins for access"]
+        );
+        assert_eq!(github.card_column(1).as_deref(), Some("In Review"));
+        assert_eq!(
+            github.reactions(),
+            [ReactionType::ThumbsDown, ReactionType::Rocket]
+        );
+
+        assert_eq!(github.comments(2), [":lock: Merge Conflict"]);
+        assert_eq!(github.card_column(2).as_deref(), Some("In Review"));
+        assert!(github.statuses().iter().any(|s| s.ref_name == "head-2"
+            && s.context == "bors"
+            && matches!(s.state, StatusEventState::Error)));
+        assert_eq!(github.branch("auto"), None);
+    }
+
+    #[tokio::test]
+    async fn snapshot_on_failure() {
+        let dir = std::env::temp_dir().join(format!("bors-snapshots-{}", std::process::id()));
+        let config: RepoConfig = toml::from_str(&format!(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            snapshot-dir = "{}"
+            "#,
+            dir.display()
+        ))
+        .unwrap();
+
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.open_pull(1, "head-1");
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::in_memory().unwrap());
+
+        // Cherry-picking a PR which doesn't exist fails once Github is asked for the PR
+        let payload = comment_payload(2, "alice", "/cherry-pick master");
+        store
+            .record_delivery(&Delivery {
+                delivery_id: "0".to_owned(),
+                event_type: "issue_comment".to_owned(),
+                payload: payload.to_string().into_bytes(),
+                received_at: SystemTime::now(),
+            })
+            .await
+            .unwrap();
+        let event = comment_event(2, "alice", "/cherry-pick master");
+        process_with_config(config, &github, &store, vec![event]).await;
+
+        let snapshots: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(snapshots.len(), 1);
+        let snapshot = snapshots[0].as_ref().unwrap().path();
+
+        let state: StateSnapshot =
+            serde_json::from_slice(&fs::read(snapshot.join("state.json")).unwrap()).unwrap();
+        assert!(state["Codertocat/Hello-World"].pulls.contains_key(&1));
+        assert_eq!(
+            fs::read(snapshot.join("0001-issue_comment.json")).unwrap(),
+            payload.to_string().into_bytes()
+        );
+        let error = fs::read_to_string(snapshot.join("error.txt")).unwrap();
+        assert!(error.contains("pr #2 not found"));
+        assert!(error.contains("/cherry-pick master"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+//! Export of a repo's merge history as CSV or Parquet, for ingestion into BI tools
+
+use crate::{state::Repo, store::LandingAttempt, Result};
+use anyhow::anyhow;
+#[cfg(feature = "parquet")]
+use parquet::{
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use serde::Serialize;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The Parquet schema of exported landings, which must be kept in sync with `ExportRow`
+#[cfg(feature = "parquet")]
+const PARQUET_SCHEMA: &str = "
+    message landing {
+        REQUIRED BYTE_ARRAY repo (UTF8);
+        REQUIRED INT64 number;
+        REQUIRED INT64 attempt;
+        REQUIRED BYTE_ARRAY merge_oid (UTF8);
+        REQUIRED BYTE_ARRAY result (UTF8);
+        OPTIONAL INT64 queued_at;
+        REQUIRED INT64 started_at;
+        REQUIRED INT64 finished_at;
+        OPTIONAL INT64 time_in_queue_secs;
+        REQUIRED INT64 test_duration_secs;
+        REQUIRED BYTE_ARRAY passed_checks (UTF8);
+        REQUIRED BYTE_ARRAY failing_checks (UTF8);
+    }
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl ExportFormat {
+    /// The format of the export requested at `path`, if it's one bors was built to export
+    pub fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with("/export.csv") {
+            return Some(ExportFormat::Csv);
+        }
+        #[cfg(feature = "parquet")]
+        if path.ends_with("/export.parquet") {
+            return Some(ExportFormat::Parquet);
+        }
+        None
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            #[cfg(feature = "parquet")]
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// A single attempt to land a PR, flattened into a row. Timestamps are in seconds since the unix
+/// epoch, and checks are comma separated.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ExportRow {
+    pub repo: String,
+    pub number: u64,
+    /// Which attempt to land the PR this was, so that retries are those after the first
+    pub attempt: u64,
+    pub merge_oid: String,
+    pub result: &'static str,
+    pub queued_at: Option<u64>,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub time_in_queue_secs: Option<u64>,
+    pub test_duration_secs: u64,
+    pub passed_checks: String,
+    pub failing_checks: String,
+}
+
+/// Flatten `history`, oldest first, into rows. Only attempts which finished at or after `since`
+/// are included, though earlier attempts still count towards each PR's retries.
+pub fn rows(repo: &Repo, history: &[LandingAttempt], since: Option<SystemTime>) -> Vec<ExportRow> {
+    let mut attempts = HashMap::new();
+    history
+        .iter()
+        .map(|attempt| {
+            let count = attempts.entry(attempt.number).or_insert(0);
+            *count += 1;
+            (*count, attempt)
+        })
+        .filter(|(_, attempt)| since.is_none_or(|since| attempt.finished_at >= since))
+        .map(|(count, attempt)| ExportRow {
+            repo: format!("{}/{}", repo.owner(), repo.name()),
+            number: attempt.number,
+            attempt: count,
+            merge_oid: attempt.merge_oid.to_string(),
+            result: attempt.result.as_str(),
+            queued_at: attempt.queued_at.map(unix_secs),
+            started_at: unix_secs(attempt.started_at),
+            finished_at: unix_secs(attempt.finished_at),
+            time_in_queue_secs: attempt.time_in_queue().map(|time| time.as_secs()),
+            test_duration_secs: attempt.duration().as_secs(),
+            passed_checks: attempt.passed_checks.join(","),
+            failing_checks: attempt.failing_checks.join(","),
+        })
+        .collect()
+}
+
+pub fn encode(rows: &[ExportRow], format: ExportFormat) -> Result<Vec<u8>> {
+    match format {
+        ExportFormat::Csv => to_csv(rows),
+        #[cfg(feature = "parquet")]
+        ExportFormat::Parquet => to_parquet(rows),
+    }
+}
+
+fn to_csv(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    // A header is still written when there are no rows so that the columns are known
+    if rows.is_empty() {
+        writer.write_record([
+            "repo",
+            "number",
+            "attempt",
+            "merge_oid",
+            "result",
+            "queued_at",
+            "started_at",
+            "finished_at",
+            "time_in_queue_secs",
+            "test_duration_secs",
+            "passed_checks",
+            "failing_checks",
+        ])?;
+    }
+
+    writer.into_inner().map_err(|e| anyhow!("{}", e))
+}
+
+/// The values of a single Parquet column, in row order
+#[cfg(feature = "parquet")]
+enum Column {
+    Strings(Vec<String>),
+    Ints(Vec<u64>),
+    OptionalInts(Vec<Option<u64>>),
+}
+
+#[cfg(feature = "parquet")]
+fn to_parquet(rows: &[ExportRow]) -> Result<Vec<u8>> {
+    let strings = |f: fn(&ExportRow) -> String| Column::Strings(rows.iter().map(f).collect());
+    let ints = |f: fn(&ExportRow) -> u64| Column::Ints(rows.iter().map(f).collect());
+    let optional_ints =
+        |f: fn(&ExportRow) -> Option<u64>| Column::OptionalInts(rows.iter().map(f).collect());
+
+    // In the order of `PARQUET_SCHEMA`
+    let columns = vec![
+        strings(|row| row.repo.clone()),
+        ints(|row| row.number),
+        ints(|row| row.attempt),
+        strings(|row| row.merge_oid.clone()),
+        strings(|row| row.result.to_owned()),
+        optional_ints(|row| row.queued_at),
+        ints(|row| row.started_at),
+        ints(|row| row.finished_at),
+        optional_ints(|row| row.time_in_queue_secs),
+        ints(|row| row.test_duration_secs),
+        strings(|row| row.passed_checks.clone()),
+        strings(|row| row.failing_checks.clone()),
+    ];
+
+    let mut buffer = Vec::new();
+    let mut writer = SerializedFileWriter::new(
+        &mut buffer,
+        Arc::new(parse_message_type(PARQUET_SCHEMA)?),
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_group = writer.next_row_group()?;
+    for column in &columns {
+        let mut writer = row_group
+            .next_column()?
+            .ok_or_else(|| anyhow!("parquet schema has fewer columns than exported"))?;
+        match column {
+            Column::Strings(values) => {
+                let values: Vec<ByteArray> =
+                    values.iter().map(|value| value.as_str().into()).collect();
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)?;
+            }
+            Column::Ints(values) => {
+                let values: Vec<i64> = values.iter().map(|value| *value as i64).collect();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, None, None)?;
+            }
+            Column::OptionalInts(values) => {
+                // Only values which are present are written, with nulls marked by their
+                // definition level
+                let present: Vec<i64> =
+                    values.iter().flatten().map(|value| *value as i64).collect();
+                let levels: Vec<i16> = values.iter().map(|value| value.is_some() as i16).collect();
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&present, Some(&levels), None)?;
+            }
+        }
+        writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+
+    Ok(buffer)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::LandingResult;
+    use github::Oid;
+    use std::time::Duration;
+
+    fn history() -> Vec<LandingAttempt> {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let attempt = |number, started_at, result, failing_checks: &[&str]| LandingAttempt {
+            number,
+            merge_oid: Oid::from_str("deadbeef"),
+            queued_at: if number == 1 { Some(at(50)) } else { None },
+            started_at: at(started_at),
+            finished_at: at(started_at + 60),
+            result,
+            passed_checks: vec!["lint".to_owned()],
+            failing_checks: failing_checks.iter().map(|s| s.to_string()).collect(),
+        };
+        vec![
+            attempt(1, 100, LandingResult::Failed, &["ci", "docs"]),
+            attempt(2, 200, LandingResult::Landed, &[]),
+            attempt(1, 300, LandingResult::Landed, &[]),
+        ]
+    }
+
+    #[test]
+    fn landing_rows() {
+        let repo = Repo::new("bors-rs", "bors");
+        let rows = rows(&repo, &history(), None);
+        assert_eq!(
+            rows.iter()
+                .map(|row| (row.number, row.attempt, row.result))
+                .collect::<Vec<_>>(),
+            [(1, 1, "failed"), (2, 1, "landed"), (1, 2, "landed")]
+        );
+        assert_eq!(rows[0].time_in_queue_secs, Some(50));
+        assert_eq!(rows[1].queued_at, None);
+        assert_eq!(rows[0].failing_checks, "ci,docs");
+
+        // Retries before the window are still counted
+        let since = UNIX_EPOCH + Duration::from_secs(250);
+        let recent = super::rows(&repo, &history(), Some(since));
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[1].attempt, 2);
+
+        let csv = String::from_utf8(encode(&rows, ExportFormat::Csv).unwrap()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("repo,number,attempt,merge_oid,result,queued_at"));
+        assert_eq!(
+            lines[1],
+            "bors-rs/bors,1,1,deadbeef,failed,50,100,160,50,60,lint,\"ci,docs\""
+        );
+        let empty = String::from_utf8(encode(&[], ExportFormat::Csv).unwrap()).unwrap();
+        assert_eq!(empty.lines().next(), lines.first().copied());
+    }
+
+    #[test]
+    #[cfg(feature = "parquet")]
+    fn parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let rows = rows(&Repo::new("bors-rs", "bors"), &history(), None);
+        let parquet = encode(&rows, ExportFormat::Parquet).unwrap();
+        let reader = SerializedFileReader::new(bytes::Bytes::from(parquet)).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 3);
+        assert_eq!(metadata.file_metadata().schema_descr().num_columns(), 12);
+    }
+}
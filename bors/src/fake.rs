@@ -0,0 +1,1057 @@
+//! In-memory fakes of Github and of a repo's on-disk clone, allowing the event processor to be
+//! driven end-to-end in tests without touching the network
+
+use crate::{
+    git::GitApi,
+    github_api::{Approval, CommitCheck, GithubApi},
+    state::PullRequestState,
+    state::Repo,
+    Error, Result,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use github::{
+    client::{
+        CreateProjectCardRequest, CreateStatusRequest, MoveProjectCardRequest, NewPullRequest,
+    },
+    Event, EventType, Issue, NodeId, Oid, Project, ProjectCard, ProjectColumn, PullRequest,
+    ReactionType, StatusEventState,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+pub const OWNER: &str = "Codertocat";
+pub const NAME: &str = "Hello-World";
+const BASE_BRANCH: &str = "master";
+/// Who approves PRs approved with `FakeGithub::approve`
+const REVIEWER: &str = "Octocat";
+const TIMESTAMP: &str = "1970-01-01T00:00:00Z";
+
+#[derive(Debug)]
+struct FakePull {
+    /// The PR as it would be returned by Github's API
+    pull: Value,
+    approved: bool,
+    approvals: Vec<Approval>,
+    labels: HashSet<String>,
+    /// Paths of the files the PR changes
+    files: Vec<String>,
+    /// Which of `files` are binary
+    binaries: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeStatus {
+    pub ref_name: String,
+    pub context: String,
+    pub state: StatusEventState,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pulls: BTreeMap<u64, FakePull>,
+    collaborators: HashSet<String>,
+    /// Users who can see the repo but aren't collaborators
+    readers: HashSet<String>,
+    admins: HashSet<String>,
+    /// Members of each team, as `org/team`
+    teams: HashMap<String, HashSet<String>>,
+    comments: Vec<(u64, String)>,
+    /// Issues, as they would be returned by Github's API
+    issues: BTreeMap<u64, Value>,
+    statuses: Vec<FakeStatus>,
+    /// The contents of files in the repo, and text appended to them by bors when landing PRs
+    files: HashMap<String, String>,
+    /// Checks reported on commits by CI, which may not have sent a webhook
+    checks: Vec<(Oid, CommitCheck)>,
+    reactions: Vec<ReactionType>,
+    labels: HashSet<String>,
+
+    /// The branches of the repo on Github
+    branches: HashMap<String, Oid>,
+    tags: HashMap<String, Oid>,
+    /// Subjects of the commits on the base branch, oldest first, and the tag on each, if any
+    history: Vec<(String, Option<String>)>,
+    /// Heads of PRs which conflict with their base branch
+    conflicts: HashSet<String>,
+    /// Commits something other than bors pushes to a branch right after bors next pushes to it
+    foreign_pushes: HashMap<String, Oid>,
+    /// Branches which bors isn't allowed to push PRs' updated heads to
+    read_only_branches: HashSet<String>,
+
+    projects: Vec<Project>,
+    columns: Vec<(u64, ProjectColumn)>,
+    cards: Vec<(u64, ProjectCard)>,
+    next_id: u64,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn pull(&self, number: u64) -> Result<&FakePull> {
+        self.pulls
+            .get(&number)
+            .ok_or_else(|| not_found(format!("pr #{} not found", number)))
+    }
+}
+
+/// A fake of the Github repo `Codertocat/Hello-World`.
+///
+/// Clones share the same underlying state so that a test can keep a handle to inspect what the
+/// event processor did, or hand the same repo to a new event processor to simulate a restart.
+#[derive(Clone, Debug, Default)]
+pub struct FakeGithub {
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeGithub {
+    pub fn new() -> Self {
+        let github = Self::default();
+        github
+            .state()
+            .branches
+            .insert(BASE_BRANCH.to_owned(), oid("base"));
+        github
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// A fake of an on-disk clone of this repo
+    pub fn git(&self) -> FakeGit {
+        FakeGit {
+            github: self.clone(),
+            branches: HashMap::new(),
+        }
+    }
+
+    pub fn add_collaborator(&self, user: &str) {
+        self.state().collaborators.insert(user.to_owned());
+    }
+
+    pub fn add_admin(&self, user: &str) {
+        let mut state = self.state();
+        state.collaborators.insert(user.to_owned());
+        state.admins.insert(user.to_owned());
+    }
+
+    pub fn add_reader(&self, user: &str) {
+        self.state().readers.insert(user.to_owned());
+    }
+
+    pub fn add_team_member(&self, team: &str, user: &str) {
+        self.state()
+            .teams
+            .entry(team.to_owned())
+            .or_default()
+            .insert(user.to_owned());
+    }
+
+    /// Open a PR against the base branch, with `head` as its head commit
+    pub fn open_pull(&self, number: u64, head: &str) -> PullRequest {
+        let mut pull: Value = serde_json::from_str(include_str!("../../github/test-input/pr.json"))
+            .expect("valid pr fixture");
+        pull["number"] = json!(number);
+        pull["id"] = json!(1000 + number);
+        pull["head"]["sha"] = json!(oid(head));
+        pull["head"]["ref"] = json!(format!("pr-{}", number));
+        pull["base"]["sha"] = json!(self.branch(BASE_BRANCH));
+
+        let pull_request = serde_json::from_value(pull.clone()).expect("valid pr");
+        self.state().pulls.insert(
+            number,
+            FakePull {
+                pull,
+                approved: false,
+                approvals: Vec::new(),
+                labels: HashSet::new(),
+                files: Vec::new(),
+                binaries: Vec::new(),
+            },
+        );
+        pull_request
+    }
+
+    /// The webhook Github delivers when a PR is opened
+    pub fn opened(&self, number: u64) -> Event {
+        let state = self.state();
+        let pull = &state.pulls[&number].pull;
+        let event = json!({
+            "action": "opened",
+            "number": number,
+            "pull_request": pull.clone(),
+            "repository": pull["base"]["repo"].clone(),
+            "sender": pull["user"].clone(),
+        });
+        to_event(EventType::PullRequest, event)
+    }
+
+    /// Push `head` to a PR as `sender`, returning the webhook Github delivers for it
+    pub fn push(&self, number: u64, head: &str, sender: &str) -> Event {
+        let mut state = self.state();
+        let pull = &mut state.pulls.get_mut(&number).unwrap().pull;
+        pull["head"]["sha"] = json!(oid(head));
+
+        let mut user = pull["user"].clone();
+        user["login"] = json!(sender);
+        let event = json!({
+            "action": "synchronize",
+            "number": number,
+            "pull_request": pull.clone(),
+            "repository": pull["base"]["repo"].clone(),
+            "sender": user,
+        });
+        to_event(EventType::PullRequest, event)
+    }
+
+    /// Open, close, label or unlabel an issue, returning the webhook Github delivers for it
+    pub fn update_issue(&self, number: u64, action: &str, open: bool, labels: &[&str]) -> Event {
+        let mut event: Value =
+            serde_json::from_str(include_str!("../../github/test-input/issue-event.json"))
+                .expect("valid issue fixture");
+        event["action"] = json!(action);
+        event["issue"]["number"] = json!(number);
+        event["issue"]["state"] = json!(if open { "open" } else { "closed" });
+        let label = event["issue"]["labels"][0].clone();
+        event["issue"]["labels"] = labels
+            .iter()
+            .map(|name| {
+                let mut label = label.clone();
+                label["name"] = json!(name);
+                label
+            })
+            .collect();
+        event.as_object_mut().unwrap().remove("changes");
+
+        self.state().issues.insert(number, event["issue"].clone());
+        to_event(EventType::Issues, event)
+    }
+
+    pub fn set_body(&self, number: u64, body: &str) {
+        self.state().pulls.get_mut(&number).unwrap().pull["body"] = json!(body);
+    }
+
+    pub fn change_files(&self, number: u64, files: &[&str]) {
+        let mut state = self.state();
+        let pull = state.pulls.get_mut(&number).unwrap();
+        pull.pull["changed_files"] = json!(files.len());
+        pull.files = files.iter().map(|&file| file.to_owned()).collect();
+    }
+
+    /// Make some of the files a PR changes binary, e.g. images
+    pub fn change_binary_files(&self, number: u64, files: &[&str]) {
+        let mut state = self.state();
+        let pull = state.pulls.get_mut(&number).unwrap();
+        for &file in files {
+            pull.files.push(file.to_owned());
+            pull.binaries.push(file.to_owned());
+        }
+        pull.pull["changed_files"] = json!(pull.files.len());
+    }
+
+    pub fn set_size(&self, number: u64, additions: u64, deletions: u64) {
+        let mut state = self.state();
+        let pull = &mut state.pulls.get_mut(&number).unwrap().pull;
+        pull["additions"] = json!(additions);
+        pull["deletions"] = json!(deletions);
+    }
+
+    /// Close a PR without sending a webhook, as if the webhook had gone missing
+    pub fn close_pull(&self, number: u64) {
+        self.state().pulls.get_mut(&number).unwrap().pull["state"] = json!("closed");
+    }
+
+    pub fn approve(&self, number: u64) {
+        self.approve_by(number, REVIEWER, SystemTime::now());
+    }
+
+    /// Approve a PR, returning the webhook Github delivers for the review
+    pub fn approved(&self, number: u64) -> Event {
+        self.approve(number);
+        let mut event: Value = serde_json::from_str(include_str!(
+            "../../github/test-input/pull-request-review-event.json"
+        ))
+        .expect("valid review fixture");
+        event["action"] = json!("submitted");
+        event["review"]["state"] = json!("approved");
+        event["review"]["body"] = json!("LGTM");
+        event["review"]["user"]["login"] = json!(REVIEWER);
+        let pull = self.state().pulls[&number].pull.clone();
+        event["repository"] = pull["base"]["repo"].clone();
+        event["pull_request"] = pull;
+        event.as_object_mut().unwrap().remove("changes");
+        to_event(EventType::PullRequestReview, event)
+    }
+
+    /// Approve a PR with a review by `user` submitted at `at`, replacing any earlier approval of
+    /// theirs
+    pub fn approve_by(&self, number: u64, user: &str, at: SystemTime) {
+        let mut state = self.state();
+        let pull = state.pulls.get_mut(&number).unwrap();
+        pull.approved = true;
+        pull.approvals.retain(|approval| approval.user != user);
+        pull.approvals.push(Approval {
+            user: user.to_owned(),
+            submitted_at: at,
+        });
+    }
+
+    /// Have something other than bors push `commit` to `branch` right after bors next does
+    pub fn push_after_bors(&self, branch: &str, commit: &str) {
+        self.state()
+            .foreign_pushes
+            .insert(branch.to_owned(), oid(commit));
+    }
+
+    /// Refuse bors' pushes to `branch`, as Github does for a fork that doesn't allow edits from
+    /// maintainers
+    pub fn make_read_only(&self, branch: &str) {
+        self.state().read_only_branches.insert(branch.to_owned());
+    }
+
+    /// Make a PR conflict with its base branch
+    pub fn conflict(&self, number: u64) {
+        let mut state = self.state();
+        let head = state.pulls[&number].pull["head"]["sha"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        state.conflicts.insert(head);
+    }
+
+    pub fn branch(&self, name: &str) -> Option<Oid> {
+        self.state().branches.get(name).cloned()
+    }
+
+    pub fn tag(&self, name: &str) -> Option<Oid> {
+        self.state().tags.get(name).cloned()
+    }
+
+    /// Add a commit to the base branch's history, which is tagged `tag` if given
+    pub fn commit(&self, subject: &str, tag: Option<&str>) {
+        self.state()
+            .history
+            .push((subject.to_owned(), tag.map(ToOwned::to_owned)));
+    }
+
+    /// Commit a file to the base branch
+    pub fn add_file(&self, path: &str, contents: &str) {
+        self.state()
+            .files
+            .insert(path.to_owned(), contents.to_owned());
+    }
+
+    pub fn comments(&self, number: u64) -> Vec<String> {
+        self.state()
+            .comments
+            .iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, body)| body.clone())
+            .collect()
+    }
+
+    /// The contents of a file, including what bors has committed to it
+    pub fn file(&self, path: &str) -> Option<String> {
+        self.state().files.get(path).cloned()
+    }
+
+    /// Report a check on a commit as CI would, without sending a webhook
+    pub fn report_check(&self, oid: &Oid, check: CommitCheck) {
+        self.state().checks.push((oid.clone(), check));
+    }
+
+    pub fn statuses(&self) -> Vec<FakeStatus> {
+        self.state().statuses.clone()
+    }
+
+    pub fn reactions(&self) -> Vec<ReactionType> {
+        self.state().reactions.clone()
+    }
+
+    /// Label a PR without sending a webhook
+    pub fn label(&self, number: u64, label: &str) {
+        let mut state = self.state();
+        state
+            .pulls
+            .get_mut(&number)
+            .unwrap()
+            .labels
+            .insert(label.to_owned());
+    }
+
+    pub fn labels(&self, number: u64) -> HashSet<String> {
+        self.state().pulls[&number].labels.clone()
+    }
+
+    /// Delete a PR's project card behind bors' back
+    pub fn remove_card(&self, number: u64) {
+        self.state()
+            .cards
+            .retain(|(_, card)| card.issue_number() != Some(number));
+    }
+
+    /// A PR's project card and the name of the column it's in
+    pub fn card_column(&self, number: u64) -> Option<String> {
+        let state = self.state();
+        let (column_id, _) = state
+            .cards
+            .iter()
+            .find(|(_, card)| card.issue_number() == Some(number))?;
+        state
+            .columns
+            .iter()
+            .find(|(_, column)| column.id == *column_id)
+            .map(|(_, column)| column.name.clone())
+    }
+}
+
+#[async_trait]
+impl GithubApi for FakeGithub {
+    async fn add_reaction(&self, _id: &NodeId, reaction: ReactionType) -> Result<()> {
+        self.state().reactions.push(reaction);
+        Ok(())
+    }
+
+    async fn open_pulls(&self, _owner: &str, _name: &str) -> Result<Vec<PullRequestState>> {
+        let state = self.state();
+        state
+            .pulls
+            .values()
+            .filter(|fake| fake.pull["state"] == "open")
+            .map(|fake| {
+                let pull: PullRequest = serde_json::from_value(fake.pull.clone())?;
+                let mut pull = PullRequestState::from_pull_request(&pull);
+                pull.approved = fake.approved;
+                pull.labels = fake.labels.clone();
+                Ok(pull)
+            })
+            .collect()
+    }
+
+    async fn get_review_decision(&self, _owner: &str, _name: &str, number: u64) -> Result<bool> {
+        Ok(self.state().pull(number)?.approved)
+    }
+
+    async fn approvals(&self, _owner: &str, _name: &str, number: u64) -> Result<Vec<Approval>> {
+        Ok(self.state().pull(number)?.approvals.clone())
+    }
+
+    async fn changed_files(&self, _owner: &str, _name: &str, number: u64) -> Result<Vec<String>> {
+        Ok(self.state().pull(number)?.files.clone())
+    }
+
+    async fn binary_files(&self, _owner: &str, _name: &str, number: u64) -> Result<Vec<String>> {
+        Ok(self.state().pull(number)?.binaries.clone())
+    }
+
+    async fn get_pull(&self, _owner: &str, _name: &str, number: u64) -> Result<PullRequest> {
+        Ok(serde_json::from_value(
+            self.state().pull(number)?.pull.clone(),
+        )?)
+    }
+
+    async fn get_issue(&self, _owner: &str, _name: &str, number: u64) -> Result<Issue> {
+        let issue = self
+            .state()
+            .issues
+            .get(&number)
+            .cloned()
+            .ok_or_else(|| not_found(format!("issue #{} not found", number)))?;
+        Ok(serde_json::from_value(issue)?)
+    }
+
+    async fn commit_checks(
+        &self,
+        _owner: &str,
+        _name: &str,
+        oid: &Oid,
+    ) -> Result<Vec<CommitCheck>> {
+        Ok(self
+            .state()
+            .checks
+            .iter()
+            .filter(|(commit, _)| commit == oid)
+            .map(|(_, check)| check.clone())
+            .collect())
+    }
+
+    async fn default_branch_head(&self, _owner: &str, _name: &str) -> Result<Option<Oid>> {
+        Ok(self.branch(BASE_BRANCH))
+    }
+
+    async fn create_pull(
+        &self,
+        _owner: &str,
+        _name: &str,
+        pull: NewPullRequest,
+    ) -> Result<PullRequest> {
+        let number = self.state().pulls.keys().last().copied().unwrap_or(0) + 1;
+        let head = self
+            .branch(&pull.head)
+            .ok_or_else(|| anyhow!("branch '{}' not found", pull.head))?;
+        self.open_pull(number, &head.to_string());
+
+        let mut state = self.state();
+        let created = &mut state.pulls.get_mut(&number).unwrap().pull;
+        created["head"]["ref"] = json!(pull.head);
+        created["title"] = json!(pull.title);
+        created["body"] = json!(pull.body);
+        Ok(serde_json::from_value(created.clone())?)
+    }
+
+    async fn close_pull(&self, _owner: &str, _name: &str, number: u64) -> Result<()> {
+        self.state().pull(number)?;
+        FakeGithub::close_pull(self, number);
+        Ok(())
+    }
+
+    async fn is_collaborator(&self, _owner: &str, _name: &str, user: &str) -> Result<bool> {
+        Ok(self.state().collaborators.contains(user))
+    }
+
+    async fn is_team_member(&self, org: &str, team: &str, user: &str) -> Result<bool> {
+        Ok(self
+            .state()
+            .teams
+            .get(&format!("{}/{}", org, team))
+            .is_some_and(|members| members.contains(user)))
+    }
+
+    async fn collaborator_permission(
+        &self,
+        _owner: &str,
+        _name: &str,
+        user: &str,
+    ) -> Result<String> {
+        let state = self.state();
+        let permission = if state.admins.contains(user) {
+            "admin"
+        } else if state.collaborators.contains(user) {
+            "write"
+        } else if state.readers.contains(user) {
+            "read"
+        } else {
+            "none"
+        };
+        Ok(permission.to_owned())
+    }
+
+    async fn create_comment(
+        &self,
+        _owner: &str,
+        _name: &str,
+        number: u64,
+        body: &str,
+    ) -> Result<()> {
+        self.state().comments.push((number, body.to_owned()));
+        Ok(())
+    }
+
+    async fn label_exists(&self, _owner: &str, _name: &str, label: &str) -> Result<bool> {
+        Ok(self.state().labels.contains(label))
+    }
+
+    async fn create_label(
+        &self,
+        _owner: &str,
+        _name: &str,
+        label: &str,
+        _color: &str,
+        _description: Option<&str>,
+    ) -> Result<()> {
+        self.state().labels.insert(label.to_owned());
+        Ok(())
+    }
+
+    async fn add_labels(
+        &self,
+        _owner: &str,
+        _name: &str,
+        number: u64,
+        labels: Vec<String>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        let pull = state
+            .pulls
+            .get_mut(&number)
+            .ok_or_else(|| not_found(format!("pr #{} not found", number)))?;
+        pull.labels.extend(labels);
+        Ok(())
+    }
+
+    async fn remove_label(
+        &self,
+        _owner: &str,
+        _name: &str,
+        number: u64,
+        label: &str,
+    ) -> Result<()> {
+        let mut state = self.state();
+        let pull = state
+            .pulls
+            .get_mut(&number)
+            .ok_or_else(|| not_found(format!("pr #{} not found", number)))?;
+        if !pull.labels.remove(label) {
+            return Err(not_found(format!("label '{}' not found", label)));
+        }
+        Ok(())
+    }
+
+    async fn create_status(
+        &self,
+        _owner: &str,
+        _name: &str,
+        ref_name: &str,
+        request: &CreateStatusRequest<'_>,
+    ) -> Result<()> {
+        self.state().statuses.push(FakeStatus {
+            ref_name: ref_name.to_owned(),
+            context: request.context.to_owned(),
+            state: request.state.clone(),
+        });
+        Ok(())
+    }
+
+    async fn update_ref(
+        &self,
+        _owner: &str,
+        _name: &str,
+        ref_name: &str,
+        oid: &Oid,
+        _force: bool,
+    ) -> Result<()> {
+        let branch = ref_name
+            .strip_prefix("heads/")
+            .ok_or_else(|| anyhow!("invalid ref '{}'", ref_name))?;
+        self.state().branches.insert(branch.to_owned(), oid.clone());
+        Ok(())
+    }
+
+    async fn create_ref(&self, _owner: &str, _name: &str, ref_name: &str, oid: &Oid) -> Result<()> {
+        let tag = ref_name
+            .strip_prefix("refs/tags/")
+            .ok_or_else(|| anyhow!("invalid ref '{}'", ref_name))?;
+        let mut state = self.state();
+        if state.tags.contains_key(tag) {
+            return Err(github::client::Error::GithubClientError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                serde_json::from_value(json!({ "message": "Reference already exists" })).unwrap(),
+            )
+            .into());
+        }
+        state.tags.insert(tag.to_owned(), oid.clone());
+        Ok(())
+    }
+
+    async fn delete_ref(&self, _owner: &str, _name: &str, ref_name: &str) -> Result<()> {
+        let branch = ref_name
+            .strip_prefix("heads/")
+            .ok_or_else(|| anyhow!("invalid ref '{}'", ref_name))?;
+        match self.state().branches.remove(branch) {
+            Some(_) => Ok(()),
+            None => Err(github::client::Error::GithubClientError(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                serde_json::from_value(json!({ "message": "Reference does not exist" })).unwrap(),
+            )
+            .into()),
+        }
+    }
+
+    async fn list_projects(&self, _owner: &str, _name: &str) -> Result<Vec<Project>> {
+        Ok(self.state().projects.clone())
+    }
+
+    async fn create_project(&self, _owner: &str, _name: &str, project: &str) -> Result<Project> {
+        let mut state = self.state();
+        let id = state.next_id();
+        let project: Project = serde_json::from_value(json!({
+            "id": id,
+            "node_id": "",
+            "url": "",
+            "html_url": "",
+            "columns_url": "",
+            "owner_url": "",
+            "name": project,
+            "body": null,
+            "number": id,
+            "state": "open",
+            "created_at": TIMESTAMP,
+            "updated_at": null,
+            "creator": user(),
+        }))?;
+        state.projects.push(project.clone());
+        Ok(project)
+    }
+
+    async fn list_project_columns(&self, project_id: u64) -> Result<Vec<ProjectColumn>> {
+        Ok(self
+            .state()
+            .columns
+            .iter()
+            .filter(|(project, _)| *project == project_id)
+            .map(|(_, column)| column.clone())
+            .collect())
+    }
+
+    async fn create_project_column(&self, project_id: u64, column: &str) -> Result<ProjectColumn> {
+        let mut state = self.state();
+        let column: ProjectColumn = serde_json::from_value(json!({
+            "id": state.next_id(),
+            "node_id": "",
+            "url": "",
+            "name": column,
+            "project_url": "",
+            "cards_url": "",
+            "created_at": TIMESTAMP,
+            "updated_at": null,
+        }))?;
+        state.columns.push((project_id, column.clone()));
+        Ok(column)
+    }
+
+    async fn delete_project_column(&self, column_id: u64) -> Result<()> {
+        let mut state = self.state();
+        state.columns.retain(|(_, column)| column.id != column_id);
+        state.cards.retain(|(column, _)| *column != column_id);
+        Ok(())
+    }
+
+    async fn list_project_cards(&self, column_id: u64) -> Result<Vec<ProjectCard>> {
+        Ok(self
+            .state()
+            .cards
+            .iter()
+            .filter(|(column, _)| *column == column_id)
+            .map(|(_, card)| card.clone())
+            .collect())
+    }
+
+    async fn create_project_card(
+        &self,
+        column_id: u64,
+        request: &CreateProjectCardRequest,
+    ) -> Result<ProjectCard> {
+        let mut state = self.state();
+        let number = state
+            .pulls
+            .iter()
+            .find(|(_, fake)| Some(fake.pull["id"].as_u64().unwrap()) == request.content_id)
+            .map(|(number, _)| *number);
+        let card: ProjectCard = serde_json::from_value(json!({
+            "id": state.next_id(),
+            "node_id": "",
+            "url": "",
+            "column_url": format!("projects/columns/{}", column_id),
+            "content_url": number.map(|n| format!("repos/{}/{}/issues/{}", OWNER, NAME, n)),
+            "note": request.note,
+            "creator": user(),
+            "created_at": TIMESTAMP,
+            "updated_at": null,
+            "archived": false,
+        }))?;
+        state.cards.push((column_id, card.clone()));
+        Ok(card)
+    }
+
+    async fn move_project_card(
+        &self,
+        card_id: u64,
+        request: &MoveProjectCardRequest,
+    ) -> Result<()> {
+        let mut state = self.state();
+        let (column, _) = state
+            .cards
+            .iter_mut()
+            .find(|(_, card)| card.id == card_id)
+            .ok_or_else(|| not_found(format!("card {} not found", card_id)))?;
+        if let Some(column_id) = request.column_id {
+            *column = column_id;
+        }
+        Ok(())
+    }
+
+    async fn delete_project_card(&self, card_id: u64) -> Result<()> {
+        self.state().cards.retain(|(_, card)| card.id != card_id);
+        Ok(())
+    }
+}
+
+/// A fake of an on-disk clone of a `FakeGithub` repo.
+///
+/// Merges always succeed, unless the PR has been made to conflict, and produce a commit whose oid
+/// is derived from the commits being merged so that tests can predict it.
+#[derive(Debug)]
+pub struct FakeGit {
+    github: FakeGithub,
+    /// Local branches which haven't been pushed yet
+    branches: HashMap<String, Oid>,
+}
+
+impl FakeGit {
+    fn merge(&mut self, base_ref: &str, head_oid: &Oid, branch: &str) -> Result<Option<Oid>> {
+        let github = self.github.state();
+        if github.conflicts.contains(&head_oid.to_string()) {
+            return Ok(None);
+        }
+
+        let base_oid = github
+            .branches
+            .get(base_ref)
+            .ok_or_else(|| anyhow!("branch '{}' not found", base_ref))?;
+        let merge_oid = merge_oid(base_oid, head_oid);
+        self.branches.insert(branch.to_owned(), merge_oid.clone());
+        Ok(Some(merge_oid))
+    }
+}
+
+impl GitApi for FakeGit {
+    fn user(&self) -> &str {
+        "bors"
+    }
+
+    fn push_branch(&mut self, branch: &str) -> Result<()> {
+        let oid = self
+            .branches
+            .get(branch)
+            .ok_or_else(|| anyhow!("branch '{}' not found", branch))?;
+        let mut state = self.github.state();
+        let oid = state
+            .foreign_pushes
+            .remove(branch)
+            .unwrap_or_else(|| oid.clone());
+        state.branches.insert(branch.to_owned(), oid);
+        Ok(())
+    }
+
+    fn push_to_remote(
+        &mut self,
+        _repo: &Repo,
+        branch: &str,
+        _old_oid: &Oid,
+        new_oid: &Oid,
+    ) -> Result<()> {
+        let mut state = self.github.state();
+        if state.read_only_branches.contains(branch) {
+            return Err(anyhow!("permission denied to push to '{}'", branch));
+        }
+        state.branches.insert(branch.to_owned(), new_oid.clone());
+        Ok(())
+    }
+
+    fn fetch_ref(&mut self, r: &str) -> Result<Oid> {
+        self.github
+            .branch(r)
+            .ok_or_else(|| anyhow!("ref '{}' not found", r))
+    }
+
+    fn fetch_and_rebase(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        _trailers: &[String],
+        _fixup_all: bool,
+    ) -> Result<Option<Oid>> {
+        self.merge(base_ref, head_oid, branch)
+    }
+
+    fn temporary_commits(&mut self, _base_ref: &str, _head_oid: &Oid) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn append_to_tip(&mut self, branch: &str, tip: &Oid, path: &str, text: &str) -> Result<Oid> {
+        self.github
+            .state()
+            .files
+            .entry(path.to_owned())
+            .or_default()
+            .push_str(text);
+        let amended = oid(&format!("{}+{}", tip, path));
+        self.branches.insert(branch.to_owned(), amended.clone());
+        Ok(amended)
+    }
+
+    fn changes_since_tag(
+        &mut self,
+        _base_ref: &str,
+        tag_prefix: &str,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        let github = self.github.state();
+        let mut subjects = Vec::new();
+        for (subject, tag) in github.history.iter().rev() {
+            if let Some(tag) = tag.as_ref().filter(|tag| tag.starts_with(tag_prefix)) {
+                return Ok((Some(tag.clone()), subjects));
+            }
+            subjects.push(subject.clone());
+        }
+        Ok((None, subjects))
+    }
+
+    fn read_file(&mut self, _base_ref: &str, path: &str) -> Result<String> {
+        self.github
+            .file(path)
+            .ok_or_else(|| anyhow!("file '{}' not found", path))
+    }
+
+    fn commit_file(
+        &mut self,
+        base_ref: &str,
+        branch: &str,
+        path: &str,
+        contents: &str,
+        _message: &str,
+    ) -> Result<Oid> {
+        self.github.add_file(path, contents);
+        let commit = oid(&format!("{}+{}", base_ref, path));
+        self.branches.insert(branch.to_owned(), commit.clone());
+        Ok(commit)
+    }
+
+    fn trial_merge(&mut self, base_ref: &str, head_oid: &Oid, _pr_number: u64) -> Result<bool> {
+        Ok(self
+            .merge(base_ref, head_oid, &format!("trial/{}", head_oid))?
+            .is_some())
+    }
+
+    fn fetch_and_update(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        rebase: bool,
+    ) -> Result<Option<Oid>> {
+        let base_oid = self.fetch_ref(base_ref)?;
+        if head_oid.to_string().starts_with(&format!("{}+", base_oid)) {
+            // The head already has the base branch's tip merged or rebased in
+            return Ok(Some(head_oid.clone()));
+        }
+
+        let merged = self.merge(base_ref, head_oid, branch)?;
+        if !rebase {
+            return Ok(merged);
+        }
+        let rebased = merged.map(|_| rebased_oid(&base_oid, head_oid));
+        if let Some(rebased) = &rebased {
+            self.branches.insert(branch.to_owned(), rebased.clone());
+        }
+        Ok(rebased)
+    }
+
+    fn fetch_and_cherry_pick(
+        &mut self,
+        target_ref: &str,
+        branch: &str,
+        _base_oid: &Oid,
+        head_oid: &Oid,
+    ) -> Result<Option<Oid>> {
+        self.merge(target_ref, head_oid, branch)
+    }
+}
+
+/// A stand-in for the oid of a commit
+/// The error the real client returns when Github responds that something doesn't exist
+fn not_found(message: String) -> Error {
+    let body = serde_json::from_value(json!({ "message": message })).unwrap();
+    github::client::Error::GithubClientError(StatusCode::NOT_FOUND, body).into()
+}
+
+pub fn oid(name: &str) -> Oid {
+    Oid::from_str(name)
+}
+
+/// The oid of the commit created by merging `head_oid` onto `base_oid`
+pub fn merge_oid(base_oid: &Oid, head_oid: &Oid) -> Oid {
+    oid(&format!("{}+{}", base_oid, head_oid))
+}
+
+/// The oid of `head_oid` once rebased onto `base_oid`
+pub fn rebased_oid(base_oid: &Oid, head_oid: &Oid) -> Oid {
+    oid(&format!("{}+{}'", base_oid, head_oid))
+}
+
+fn user() -> Value {
+    json!({
+        "login": "bors",
+        "id": 0,
+        "node_id": "",
+        "avatar_url": "",
+        "gravatar_id": "",
+        "url": "",
+        "html_url": "",
+        "followers_url": "",
+        "following_url": "",
+        "gists_url": "",
+        "starred_url": "",
+        "subscriptions_url": "",
+        "organizations_url": "",
+        "repos_url": "",
+        "events_url": "",
+        "received_events_url": "",
+        "type": "Bot",
+        "site_admin": false,
+    })
+}
+
+/// A webhook for a comment on a PR
+pub fn comment_event(number: u64, sender: &str, body: &str) -> Event {
+    to_event(
+        EventType::IssueComment,
+        comment_payload(number, sender, body),
+    )
+}
+
+/// The payload of a webhook for a comment on a PR, as delivered by Github
+pub fn comment_payload(number: u64, sender: &str, body: &str) -> Value {
+    let mut events: Vec<Value> = serde_json::from_str(include_str!(
+        "../../github/test-input/issue-comment-event.json"
+    ))
+    .expect("valid issue comment fixture");
+    let mut event = events.remove(0);
+    event["action"] = json!("created");
+    event["issue"]["number"] = json!(number);
+    event["issue"]["pull_request"] = json!({
+        "url": "",
+        "html_url": "",
+        "diff_url": "",
+        "patch_url": "",
+    });
+    event["comment"]["body"] = json!(body);
+    event["sender"]["login"] = json!(sender);
+    event
+}
+
+/// A webhook for a status of a commit being reported
+pub fn status_event(sha: &Oid, context: &str, state: &str) -> Event {
+    let mut event: Value =
+        serde_json::from_str(include_str!("../../github/test-input/status-event.json"))
+            .expect("valid status fixture");
+    event["sha"] = json!(sha);
+    event["context"] = json!(context);
+    event["state"] = json!(state);
+    to_event(EventType::Status, event)
+}
+
+/// The webhook for a status reported on `sha` while it's the head of the base branch
+pub fn base_status_event(sha: &Oid, context: &str, state: &str) -> Event {
+    let mut event: Value =
+        serde_json::from_str(include_str!("../../github/test-input/status-event.json"))
+            .expect("valid status fixture");
+    event["sha"] = json!(sha);
+    event["context"] = json!(context);
+    event["state"] = json!(state);
+    event["branches"] = json!([{ "name": BASE_BRANCH, "commit": { "sha": sha } }]);
+    to_event(EventType::Status, event)
+}
+
+fn to_event(event_type: EventType, event: Value) -> Event {
+    Event::from_json(event_type, event.to_string().as_bytes()).expect("valid event")
+}
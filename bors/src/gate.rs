@@ -0,0 +1,876 @@
+//! The checks a PR has to pass before `/land` queues it, each of which can refuse to queue it and
+//! explain why. What they need to know about the PR from Github, e.g. its approvals and the files it
+//! changes, is fetched once up front for all of the gates which are configured.
+
+use crate::{
+    cla,
+    config::RepoConfig,
+    description,
+    github_api::{Approval, GithubApi, Permission},
+    linked_issue, release, reviewers,
+    state::PullRequestState,
+    title, Result,
+};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// A check a PR has to pass before it's queued for landing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gate {
+    /// The PR's title follows the repo's format, as it becomes the subject of the commit the PR is
+    /// squashed into
+    Title,
+    /// The PR links an issue, unless it's a release PR
+    LinkedIssue,
+    /// The PR's description fills in the sections the repo requires, unless it's a release PR
+    Description,
+    /// The PR has the labels the repo requires
+    Labels,
+    /// The PR is no larger than the repo allows, unless an admin lands it with `/land oversize`
+    MaxDiff,
+    /// The PR changes no binary or generated files, unless landed with `/land allow-binaries`
+    BinaryFiles,
+    /// The PR's author has signed the CLA, unless it's a release PR
+    Cla,
+    /// The PR is approved
+    Approved,
+    /// The PR is approved by someone other than its author
+    SelfApproval,
+    /// The PR was approved recently enough before its latest commits were pushed
+    Freshness,
+    /// The teams owning paths the PR changes have approved it
+    RequiredReviewers,
+    /// One of the repo's admins has approved the PR, if it changes protected paths
+    ProtectedPaths,
+    /// The PR has a second approval, if it's large
+    LargeDiff,
+}
+
+impl Gate {
+    /// The gates a repo's PRs have to pass, in the order they're checked
+    pub fn configured(config: &RepoConfig, allow_binaries: bool) -> Vec<Gate> {
+        let mut gates = Vec::new();
+        if config.title_check().is_some() {
+            gates.push(Gate::Title);
+        }
+        if config.linked_issue().is_some() {
+            gates.push(Gate::LinkedIssue);
+        }
+        if config.description().is_some() {
+            gates.push(Gate::Description);
+        }
+        if !config.required_labels().is_empty() {
+            gates.push(Gate::Labels);
+        }
+        if config.max_diff().is_some() {
+            gates.push(Gate::MaxDiff);
+        }
+        if config.binary_files().is_some() && !allow_binaries {
+            gates.push(Gate::BinaryFiles);
+        }
+        if config.cla().is_some() {
+            gates.push(Gate::Cla);
+        }
+        if config.require_review() {
+            gates.push(Gate::Approved);
+            if config.block_self_approval() {
+                gates.push(Gate::SelfApproval);
+            }
+            if config.approval_freshness().is_some() {
+                gates.push(Gate::Freshness);
+            }
+        }
+        if !config.required_reviewers().is_empty() {
+            gates.push(Gate::RequiredReviewers);
+        }
+        if config.protected_paths().is_some() {
+            gates.push(Gate::ProtectedPaths);
+        }
+        if config.large_diff_lines().is_some() {
+            gates.push(Gate::LargeDiff);
+        }
+        gates
+    }
+
+    fn needs_approvals(self) -> bool {
+        matches!(
+            self,
+            Gate::SelfApproval
+                | Gate::Freshness
+                | Gate::RequiredReviewers
+                | Gate::ProtectedPaths
+                | Gate::LargeDiff
+        )
+    }
+
+    fn needs_changed_files(self) -> bool {
+        matches!(
+            self,
+            Gate::BinaryFiles | Gate::RequiredReviewers | Gate::ProtectedPaths
+        )
+    }
+
+    fn needs_diff_size(self) -> bool {
+        matches!(self, Gate::MaxDiff | Gate::LargeDiff)
+    }
+
+    /// Why `candidate` may not be queued for landing, if this gate refuses it
+    pub async fn refusal(self, candidate: &Candidate<'_>) -> Result<Option<String>> {
+        let refusal = match self {
+            Gate::Title => candidate.title(),
+            Gate::LinkedIssue => candidate.linked_issue(),
+            Gate::Description => candidate.description(),
+            Gate::Labels => candidate.labels(),
+            Gate::MaxDiff => candidate.max_diff().await?,
+            Gate::BinaryFiles => candidate.binary_files(),
+            Gate::Cla => candidate.cla().await?,
+            Gate::Approved => candidate.approved(),
+            Gate::SelfApproval => candidate.self_approval(),
+            Gate::Freshness => candidate.freshness(),
+            Gate::RequiredReviewers => candidate.required_reviewers().await?,
+            Gate::ProtectedPaths => candidate.protected_paths().await?,
+            Gate::LargeDiff => candidate.large_diff(),
+        };
+
+        Ok(refusal.map(|refusal| format!("@{} {}", candidate.sender, refusal)))
+    }
+}
+
+/// A PR which someone asked to land, along with what the gates need to know about it from Github
+pub struct Candidate<'a> {
+    config: &'a RepoConfig,
+    github: &'a dyn GithubApi,
+    pr: &'a PullRequestState,
+    sender: &'a str,
+    /// Whether the sender asked to land the PR even if it's larger than the repo allows
+    oversize: bool,
+
+    /// The PR's approvals which count, i.e. not its author's if self-approval is blocked
+    approvals: Vec<Approval>,
+    /// The paths of the files the PR changes
+    changed_files: Vec<String>,
+    /// The paths of the binary files the PR adds or modifies
+    binary_files: Vec<String>,
+    /// How many lines the PR changes, counting both additions and deletions
+    changed_lines: u64,
+    /// How many files the PR changes
+    changed_file_count: u64,
+}
+
+impl<'a> Candidate<'a> {
+    /// Fetch what `gates` need to know about `pr`, which `sender` asked to land
+    pub async fn fetch(
+        config: &'a RepoConfig,
+        github: &'a dyn GithubApi,
+        pr: &'a PullRequestState,
+        sender: &'a str,
+        oversize: bool,
+        gates: &[Gate],
+    ) -> Result<Candidate<'a>> {
+        let (owner, name, number) = (config.owner(), config.name(), pr.number);
+
+        let mut approvals = Vec::new();
+        if gates.iter().any(|gate| gate.needs_approvals()) {
+            approvals = github.approvals(owner, name, number).await?;
+            if config.block_self_approval() {
+                approvals.retain(|approval| Some(&approval.user) != pr.author.as_ref());
+            }
+        }
+
+        let mut changed_files = Vec::new();
+        if gates.iter().any(|gate| gate.needs_changed_files()) {
+            changed_files = github.changed_files(owner, name, number).await?;
+        }
+
+        let mut binary_files = Vec::new();
+        if gates.contains(&Gate::BinaryFiles) {
+            binary_files = github.binary_files(owner, name, number).await?;
+        }
+
+        let (mut changed_lines, mut changed_file_count) = (0, 0);
+        if gates.iter().any(|gate| gate.needs_diff_size()) {
+            let pull = github.get_pull(owner, name, number).await?;
+            changed_lines = pull.additions.unwrap_or(0) + pull.deletions.unwrap_or(0);
+            changed_file_count = pull.changed_files.unwrap_or(0);
+        }
+
+        Ok(Candidate {
+            config,
+            github,
+            pr,
+            sender,
+            oversize,
+            approvals,
+            changed_files,
+            binary_files,
+            changed_lines,
+            changed_file_count,
+        })
+    }
+
+    fn title(&self) -> Option<String> {
+        let problem = title::problem(self.config.title_check()?, &self.pr.title)?;
+        Some(format!(
+            ":pencil2: This PR's title {}, unable to queue for landing until it's fixed",
+            problem,
+        ))
+    }
+
+    fn linked_issue(&self) -> Option<String> {
+        let check = self.config.linked_issue()?;
+        if release::is_release_pull(self.config, self.pr)
+            || linked_issue::is_linked(check, &self.pr.title, &self.pr.body)
+        {
+            return None;
+        }
+
+        Some(format!(
+            ":link: This PR doesn't link an issue, unable to queue for landing until it does. \
+            To link one, {}",
+            linked_issue::instructions(check),
+        ))
+    }
+
+    fn description(&self) -> Option<String> {
+        let missing = description::missing_sections(self.config.description()?, &self.pr.body);
+        if missing.is_empty() || release::is_release_pull(self.config, self.pr) {
+            return None;
+        }
+
+        Some(format!(
+            ":page_facing_up: This PR's description is missing content under {}, which this repo \
+            requires PRs to fill in. Unable to queue for landing until the description is updated",
+            code_list(&missing),
+        ))
+    }
+
+    fn labels(&self) -> Option<String> {
+        let missing = self
+            .config
+            .required_labels()
+            .iter()
+            .filter(|label| !self.pr.labels.contains(*label))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            ":label: This PR is missing the {} {}, which this repo requires PRs to have before \
+            they're queued. Unable to queue for landing until {}",
+            code_list(&missing),
+            if missing.len() == 1 {
+                "label"
+            } else {
+                "labels"
+            },
+            if missing.len() == 1 {
+                "it's added"
+            } else {
+                "they're added"
+            },
+        ))
+    }
+
+    async fn max_diff(&self) -> Result<Option<String>> {
+        let exceeded = match self.config.max_diff() {
+            Some(limits) => limits.exceeded(self.changed_lines, self.changed_file_count),
+            None => return Ok(None),
+        };
+        if exceeded.is_empty() {
+            return Ok(None);
+        }
+
+        if !self.oversize {
+            return Ok(Some(format!(
+                ":elephant: This PR changes {}, which is more than this repo allows so that PRs \
+                stay easy to review and bisect. Unable to queue for landing until it's split into \
+                smaller PRs, or an admin of this repo lands it anyway with `/land oversize`",
+                exceeded.join(" and "),
+            )));
+        }
+
+        let permission = self
+            .github
+            .collaborator_permission(self.config.owner(), self.config.name(), self.sender)
+            .await?;
+        if Permission::from_level(&permission) < Permission::Admin {
+            return Ok(Some(
+                ":elephant: Only admins of this repo can land PRs which are larger than it allows. \
+                Unable to queue for landing until it's split into smaller PRs, or an admin lands it \
+                anyway"
+                    .to_owned(),
+            ));
+        }
+
+        info!(
+            "pr #{} is too large, but an admin is landing it anyway",
+            self.pr.number
+        );
+        Ok(None)
+    }
+
+    fn binary_files(&self) -> Option<String> {
+        let config = self.config.binary_files()?;
+        let mut flagged = self.binary_files.iter().collect::<Vec<_>>();
+        for path in &self.changed_files {
+            if config.is_generated(path) && !flagged.contains(&path) {
+                flagged.push(path);
+            }
+        }
+        if flagged.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            ":package: This PR changes binary or generated files ({}), which are rarely meant to \
+            be committed. If they are, confirm it by landing it with `/land allow-binaries`",
+            code_list(&flagged),
+        ))
+    }
+
+    async fn cla(&self) -> Result<Option<String>> {
+        let cla = match self.config.cla() {
+            Some(cla) => cla,
+            None => return Ok(None),
+        };
+        if release::is_release_pull(self.config, self.pr)
+            || cla::is_signed(cla, self.config, self.github, self.pr).await?
+        {
+            return Ok(None);
+        }
+
+        let author = match &self.pr.author {
+            Some(author) => format!("@{}", author),
+            None => "its author".to_owned(),
+        };
+        Ok(Some(format!(
+            ":memo: Thanks for the contribution! Before this PR can be queued for landing, {} \
+            needs to sign the [Contributor License Agreement]({}). Once it's signed, comment \
+            `/land` again.",
+            author, cla.sign_url,
+        )))
+    }
+
+    fn approved(&self) -> Option<String> {
+        if self.pr.approved || !self.config.require_review() {
+            return None;
+        }
+
+        Some(match &self.pr.approval_dismissed_by {
+            Some(pusher) => format!(
+                ":exclamation: This PR's approval was dismissed when @{} pushed new commits to \
+                it, as this repo requires PRs to be approved again after being pushed to by anyone \
+                other than its trusted pushers. Unable to queue for landing until it's re-approved",
+                pusher,
+            ),
+            None => ":exclamation: This PR is still missing approvals, unable to queue for landing"
+                .to_owned(),
+        })
+    }
+
+    fn self_approval(&self) -> Option<String> {
+        if !self.approvals.is_empty() {
+            return None;
+        }
+
+        Some(
+            ":eyes: This PR has only been approved by its author, while this repo requires PRs to \
+            be approved by someone else. Unable to queue for landing until it is"
+                .to_owned(),
+        )
+    }
+
+    /// Approvals only count for so long before the commits they're for are superseded, so that
+    /// PRs which were dormant for a while before being pushed to again are looked over again
+    fn freshness(&self) -> Option<String> {
+        let freshness = self.config.approval_freshness()?;
+        let age = approval_age(&self.approvals, self.pr.head_pushed_at);
+        if age.is_some_and(|age| age <= freshness) {
+            return None;
+        }
+
+        let approval = match age {
+            Some(age) => format!(
+                "was last approved {} days before its latest commits were pushed",
+                age.as_secs() / (24 * 60 * 60)
+            ),
+            None => "has no approving reviews".to_owned(),
+        };
+        Some(format!(
+            ":hourglass: This PR {}, while approvals only count for {} days in this repo. Unable \
+            to queue for landing until it's re-approved",
+            approval,
+            self.config.approval_freshness_days().unwrap_or_default(),
+        ))
+    }
+
+    async fn required_reviewers(&self) -> Result<Option<String>> {
+        let missing = reviewers::missing_approvals(
+            self.config,
+            self.github,
+            &self.changed_files,
+            &self.approvals,
+        )
+        .await?;
+        if missing.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(format!(
+            ":lock: This PR changes paths owned by {}, whose approval is required in this repo. \
+            Unable to queue for landing until it's approved by a member of each",
+            code_list(&missing),
+        )))
+    }
+
+    async fn protected_paths(&self) -> Result<Option<String>> {
+        let files = match reviewers::missing_admin_approval(
+            self.config,
+            self.github,
+            &self.changed_files,
+            &self.approvals,
+        )
+        .await?
+        {
+            Some(files) => files,
+            None => return Ok(None),
+        };
+
+        let admins = self
+            .config
+            .protected_paths()
+            .into_iter()
+            .flat_map(|protected_paths| protected_paths.admins())
+            .collect::<Vec<_>>();
+        Ok(Some(format!(
+            ":shield: This PR changes protected paths ({}), which need approval from one of this \
+            repo's admins ({}) on top of the usual review. Unable to queue for landing until one of \
+            them approves it",
+            code_list(&files),
+            code_list(&admins),
+        )))
+    }
+
+    fn large_diff(&self) -> Option<String> {
+        if !reviewers::missing_large_diff_approval(self.config, self.changed_lines, &self.approvals)
+        {
+            return None;
+        }
+
+        Some(format!(
+            ":mag: This PR changes {} lines, while PRs changing more than {} lines need {} \
+            approvals in this repo. Unable to queue for landing until it's approved by another \
+            reviewer",
+            self.changed_lines,
+            self.config.large_diff_lines().unwrap_or_default(),
+            reviewers::LARGE_DIFF_APPROVALS,
+        ))
+    }
+}
+
+/// How long before a PR's head was pushed it was last approved, which is zero if it's been
+/// approved since then or it isn't known when the head was pushed, or `None` if it's unapproved
+fn approval_age(approvals: &[Approval], head_pushed_at: Option<SystemTime>) -> Option<Duration> {
+    let approved_at = approvals
+        .iter()
+        .map(|approval| approval.submitted_at)
+        .max()?;
+    Some(
+        head_pushed_at
+            .and_then(|pushed_at| pushed_at.duration_since(approved_at).ok())
+            .unwrap_or_default(),
+    )
+}
+
+/// `items` formatted as code and separated by commas, e.g. "`a`, `b`"
+fn code_list<T: std::fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("`{}`", item))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeGithub;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn config(extra: &str) -> RepoConfig {
+        toml::from_str(&format!(
+            "owner = \"Codertocat\"\nname = \"Hello-World\"\n{}",
+            extra
+        ))
+        .unwrap()
+    }
+
+    /// Why `gate` refuses to queue `pr` when `sender` asks to land it, if it does
+    async fn refusal(
+        config: &RepoConfig,
+        github: &FakeGithub,
+        pr: &PullRequestState,
+        sender: &str,
+        gate: Gate,
+    ) -> Option<String> {
+        let candidate = Candidate::fetch(config, github, pr, sender, false, &[gate])
+            .await
+            .unwrap();
+        gate.refusal(&candidate).await.unwrap()
+    }
+
+    #[test]
+    fn configured() {
+        assert!(Gate::configured(&config(""), false).is_empty());
+
+        let config = config(
+            r#"
+            required-labels = ["qa-signed-off"]
+            block-self-approval = true
+            large-diff-lines = 500
+
+            [binary-files]
+            "#,
+        );
+        assert_eq!(
+            Gate::configured(&config, false),
+            vec![Gate::Labels, Gate::BinaryFiles, Gate::LargeDiff]
+        );
+        // `/land allow-binaries` skips the binary files gate, and self-approval only matters if
+        // approval is required at all
+        assert_eq!(
+            Gate::configured(&config, true),
+            vec![Gate::Labels, Gate::LargeDiff]
+        );
+    }
+
+    #[test]
+    fn approval_age() {
+        let now = SystemTime::now();
+        let approval = |days_ago: u32| Approval {
+            user: "Octocat".to_owned(),
+            submitted_at: now - DAY * days_ago,
+        };
+
+        assert_eq!(super::approval_age(&[], Some(now)), None);
+        // measured against the newest approval, up to when the head was pushed
+        assert_eq!(
+            super::approval_age(&[approval(45), approval(40)], Some(now - DAY * 10)),
+            Some(DAY * 30)
+        );
+        // approving after the push, or before a push bors didn't see, leaves nothing to measure
+        assert_eq!(
+            super::approval_age(&[approval(45)], Some(now - DAY * 50)),
+            Some(Duration::default())
+        );
+        assert_eq!(
+            super::approval_age(&[approval(45)], None),
+            Some(Duration::default())
+        );
+    }
+
+    #[tokio::test]
+    async fn labels() {
+        let config = config(r#"required-labels = ["release-notes-written", "qa-signed-off"]"#);
+        let github = FakeGithub::new();
+        let mut pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Labels)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :label: This PR is missing the `release-notes-written`, `qa-signed-off` \
+                labels, which this repo requires PRs to have before they're queued. Unable to \
+                queue for landing until they're added"
+            )
+        );
+
+        pr.labels.insert("qa-signed-off".to_owned());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Labels)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :label: This PR is missing the `release-notes-written` label, which this \
+                repo requires PRs to have before they're queued. Unable to queue for landing until \
+                it's added"
+            )
+        );
+
+        pr.labels.insert("release-notes-written".to_owned());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Labels).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn max_diff() {
+        let config = config("[max-diff]\nlines = 1000\nfiles = 2");
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.add_admin("carol");
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.set_size(1, 900, 100);
+        github.change_files(1, &["a.rs", "b.rs"]);
+        let refusal = |sender: &'static str, oversize: bool| {
+            let (config, github, pr) = (&config, &github, &pr);
+            async move {
+                let gates = [Gate::MaxDiff];
+                let candidate = Candidate::fetch(config, github, pr, sender, oversize, &gates)
+                    .await
+                    .unwrap();
+                Gate::MaxDiff.refusal(&candidate).await.unwrap()
+            }
+        };
+
+        assert_eq!(refusal("alice", false).await, None);
+
+        github.set_size(1, 1500, 100);
+        github.change_files(1, &["a.rs", "b.rs", "c.rs"]);
+        assert_eq!(
+            refusal("alice", false).await.as_deref(),
+            Some(
+                "@alice :elephant: This PR changes 1600 lines (the limit is 1000) and 3 files (the \
+                limit is 2), which is more than this repo allows so that PRs stay easy to review \
+                and bisect. Unable to queue for landing until it's split into smaller PRs, or an \
+                admin of this repo lands it anyway with `/land oversize`"
+            )
+        );
+        // Only admins can override the limits
+        assert_eq!(
+            refusal("alice", true).await.as_deref(),
+            Some(
+                "@alice :elephant: Only admins of this repo can land PRs which are larger than it \
+                allows. Unable to queue for landing until it's split into smaller PRs, or an admin \
+                lands it anyway"
+            )
+        );
+        assert_eq!(refusal("carol", true).await, None);
+    }
+
+    #[tokio::test]
+    async fn binary_files() {
+        let config = config("[binary-files]\ngenerated-paths = [\"**/*.pb.go\"]");
+        let github = FakeGithub::new();
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.change_files(1, &["src/lib.rs"]);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::BinaryFiles).await,
+            None
+        );
+
+        github.change_files(1, &["src/lib.rs", "api/types.pb.go"]);
+        github.change_binary_files(1, &["assets/logo.png"]);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::BinaryFiles)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :package: This PR changes binary or generated files (`assets/logo.png`, \
+                `api/types.pb.go`), which are rarely meant to be committed. If they are, confirm it \
+                by landing it with `/land allow-binaries`"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn approved() {
+        let config = config("require-review = true");
+        let github = FakeGithub::new();
+        let mut pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Approved)
+                .await
+                .as_deref(),
+            Some("@alice :exclamation: This PR is still missing approvals, unable to queue for landing")
+        );
+
+        pr.approval_dismissed_by = Some("Codertocat".to_owned());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Approved)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :exclamation: This PR's approval was dismissed when @Codertocat pushed new \
+                commits to it, as this repo requires PRs to be approved again after being pushed to \
+                by anyone other than its trusted pushers. Unable to queue for landing until it's \
+                re-approved"
+            )
+        );
+
+        pr.approved = true;
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Approved).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn self_approval() {
+        let config = config("require-review = true\nblock-self-approval = true");
+        let github = FakeGithub::new();
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.approve_by(1, "Codertocat", SystemTime::now());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::SelfApproval)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :eyes: This PR has only been approved by its author, while this repo \
+                requires PRs to be approved by someone else. Unable to queue for landing until it is"
+            )
+        );
+
+        github.approve(1);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::SelfApproval).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn freshness() {
+        let config = config("require-review = true\napproval-freshness-days = 30");
+        let github = FakeGithub::new();
+        let mut pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.approve_by(1, "Octocat", SystemTime::now() - DAY * 45);
+
+        // An old approval of commits which haven't changed since still stands
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Freshness).await,
+            None
+        );
+
+        pr.head_pushed_at = Some(SystemTime::now());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Freshness)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :hourglass: This PR was last approved 45 days before its latest commits \
+                were pushed, while approvals only count for 30 days in this repo. Unable to queue \
+                for landing until it's re-approved"
+            )
+        );
+
+        github.approve(1);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::Freshness).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn required_reviewers() {
+        let config = config(
+            r#"
+            [[required-reviewers]]
+            paths = ["consensus/**"]
+            team = "@aptos-labs/consensus"
+
+            [[required-reviewers]]
+            paths = ["docs/**"]
+            team = "aptos-labs/docs"
+            "#,
+        );
+        let github = FakeGithub::new();
+        github.add_team_member("aptos-labs/consensus", "bob");
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.change_files(1, &["consensus/src/lib.rs", "README.md"]);
+        github.approve(1);
+
+        // Approval from someone outside the team owning the changed paths isn't enough
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::RequiredReviewers)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :lock: This PR changes paths owned by `aptos-labs/consensus`, whose \
+                approval is required in this repo. Unable to queue for landing until it's approved \
+                by a member of each"
+            )
+        );
+
+        github.approve_by(1, "bob", SystemTime::now());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::RequiredReviewers).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn protected_paths() {
+        let config = config(
+            r#"
+            [protected-paths]
+            paths = [".github/**", "deploy/*.yaml"]
+            admins = ["carol", "@aptos-labs/security"]
+            "#,
+        );
+        let github = FakeGithub::new();
+        github.add_team_member("aptos-labs/security", "dave");
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.change_files(1, &["src/lib.rs"]);
+        github.approve(1);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::ProtectedPaths).await,
+            None
+        );
+
+        github.change_files(1, &["src/lib.rs", ".github/workflows/ci.yaml"]);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::ProtectedPaths)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :shield: This PR changes protected paths (`.github/workflows/ci.yaml`), \
+                which need approval from one of this repo's admins (`carol`, `aptos-labs/security`) \
+                on top of the usual review. Unable to queue for landing until one of them approves it"
+            )
+        );
+
+        // Either an admin or a member of an admin team will do
+        github.approve_by(1, "dave", SystemTime::now());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::ProtectedPaths).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn large_diff() {
+        let config = config("large-diff-lines = 500");
+        let github = FakeGithub::new();
+        let pr = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        github.set_size(1, 500, 0);
+        github.approve(1);
+
+        // PRs up to the threshold need only one approval, while larger ones need two
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::LargeDiff).await,
+            None
+        );
+
+        github.set_size(1, 400, 200);
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::LargeDiff)
+                .await
+                .as_deref(),
+            Some(
+                "@alice :mag: This PR changes 600 lines, while PRs changing more than 500 lines \
+                need 2 approvals in this repo. Unable to queue for landing until it's approved by \
+                another reviewer"
+            )
+        );
+
+        github.approve_by(1, "bob", SystemTime::now());
+        assert_eq!(
+            refusal(&config, &github, &pr, "alice", Gate::LargeDiff).await,
+            None
+        );
+    }
+}
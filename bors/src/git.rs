@@ -1,98 +1,138 @@
-use crate::{config::GitConfig, state::Repo, Result};
+use crate::{
+    config::{GitConfig, RepoConfig},
+    state::Repo,
+    Result,
+};
 use anyhow::{anyhow, Context};
 use github::Oid;
-use log::{debug, info};
 use std::{
+    fs,
+    io::{self, Read},
+    os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
+use tracing::{debug, info, instrument, warn};
 
 const REPOS_DIR: &str = "repos";
 
+/// Number of times a git command which talks to Github is attempted if it times out
+const NETWORK_ATTEMPTS: usize = 3;
+
+/// Prefix of the branches created when cherry-picking a PR onto another branch
+pub const CHERRY_PICK_BRANCH_PREFIX: &str = "pick/";
+
+/// Prefix of the branches of release PRs, followed by the tag the release is tagged with
+pub const RELEASE_BRANCH_PREFIX: &str = "bors-release/";
+
+/// Error returned when a git command is killed for not completing within its timeout
+#[derive(Debug, thiserror::Error)]
+#[error("git command timed out after {0:?}")]
+pub struct TimedOut(Duration);
+
+/// Error returned when a git command exits unsuccessfully, holding what it wrote to stderr
+#[derive(Debug, thiserror::Error)]
+#[error("failed to run git command:\n{0}")]
+pub struct CommandFailed(pub String);
+
 #[derive(Debug)]
 pub struct GitRepository {
     directory: PathBuf,
     github_repo: Repo,
     git_config: GitConfig,
+
+    /// Branches in `github_repo` which bors is allowed to force-push to
+    push_allowlist: Vec<String>,
+
+    /// Indicates if the on-disk repo has been cloned and verified
+    cloned: bool,
+
+    /// Indicates if pushes should be skipped, leaving the remote repo untouched
+    dry_run: bool,
 }
 
 impl GitRepository {
-    pub fn from_config(git_config: &GitConfig, repo: &Repo) -> Result<Self> {
-        let github_repo = repo.clone();
+    pub fn from_config(git_config: &GitConfig, config: &RepoConfig) -> Result<Self> {
+        let github_repo = config.repo().clone();
         let git_config = git_config.clone();
         let mut directory = std::env::current_dir()?;
         directory.push(REPOS_DIR);
         directory.push(github_repo.owner());
         directory.push(github_repo.name());
 
-        if !Git::new().current_dir(&directory).is_git_repo()? {
+        let push_allowlist = vec![
+            config.auto_branch().to_owned(),
+            config.canary_branch().to_owned(),
+        ];
+
+        Ok(Self {
+            directory,
+            github_repo,
+            git_config,
+            push_allowlist,
+            cloned: false,
+            dry_run: false,
+        })
+    }
+
+    /// Skip all pushes, e.g. when replaying recorded webhooks, so that the remote repo is never
+    /// modified
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Clone the repo, if it hasn't already been cloned, the first time it's actually needed.
+    ///
+    /// Cloning large repos can take a significant amount of time so this is done lazily instead of
+    /// at startup in order to avoid delaying the processing of webhooks.
+    fn ensure_cloned(&mut self) -> Result<()> {
+        if self.cloned {
+            return Ok(());
+        }
+
+        if !Git::new().current_dir(&self.directory).is_git_repo()? {
             info!(
                 "cloning '{}' to '{}'",
-                github_repo.to_github_ssh_url(),
-                directory.display()
+                self.github_repo.to_github_ssh_url(),
+                self.directory.display()
             );
             Git::new()
-                .with_ssh(&git_config.ssh_key_file)
-                .clone(&directory, &github_repo)?;
+                .with_ssh(&self.git_config.ssh_key_file)
+                .with_timeout(self.git_config.timeout())
+                .clone(&self.directory, &self.github_repo)?;
         } else {
-            info!("using existing on-disk repo at {}", directory.display());
+            info!(
+                "using existing on-disk repo at {}",
+                self.directory.display()
+            );
         }
 
         if !Git::new()
-            .current_dir(&directory)
-            .remote_matches_github_repo(&github_repo)?
+            .current_dir(&self.directory)
+            .remote_matches_github_repo(&self.github_repo)?
         {
             return Err(anyhow!(
                 "on-disk repo's 'origin' remote doesn't match config"
             ));
         }
 
-        Ok(Self {
-            directory,
-            github_repo,
-            git_config,
-        })
-    }
-
-    pub fn user(&self) -> &str {
-        &self.git_config.user
-    }
-
-    pub fn push_branch(&mut self, branch: &str) -> Result<()> {
-        self.git().push_branch(branch, true)
-    }
-
-    pub fn push_to_remote(
-        &mut self,
-        repo: &Repo,
-        branch: &str,
-        old_oid: &Oid,
-        new_oid: &Oid,
-    ) -> Result<()> {
-        self.git().push_to_remote(repo, branch, old_oid, new_oid)
-    }
-
-    pub fn fetch_ref(&mut self, r: &str) -> Result<Oid> {
-        self.git().fetch(&[r])?;
-        self.git().fetch_head_oid()
+        self.cloned = true;
+        Ok(())
     }
 
-    pub fn fetch_and_rebase(
-        &mut self,
-        base_ref: &str,
-        head_oid: &Oid,
-        branch: &str,
-        pr_number: u64,
-        fixup_all: bool,
-    ) -> Result<Option<Oid>> {
-        // Fetch base ref and head_oid
-        self.fetch(base_ref, head_oid)?;
-        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
-        self.rebase(&base_oid, head_oid, branch, pr_number, fixup_all)
+    /// Clone the repo, or fetch the latest state of all of its branches if it has already been
+    /// cloned, so that the on-disk repo is warm before it's needed for processing the queue
+    pub fn refresh(&mut self) -> Result<()> {
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch_all())
     }
 
     fn fetch(&mut self, base_ref: &str, oid: &Oid) -> Result<()> {
-        self.git().fetch(&[base_ref, &oid.to_string()])
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch([base_ref, &oid.to_string()]))
     }
 
     // None represents a Merge conflict
@@ -101,7 +141,7 @@ impl GitRepository {
         base_oid: &Oid,
         head_oid: &Oid,
         branch: &str,
-        pr_number: u64,
+        trailers: &[String],
         fixup_all: bool,
     ) -> Result<Option<Oid>> {
         // First create the branch to work on for the rebase
@@ -136,12 +176,15 @@ impl GitRepository {
             // commits resulted in no-ops
             if head_oid == *base_oid {
                 Ok(None)
+            } else if trailers.is_empty() {
+                Ok(Some(head_oid))
             } else {
-                // Amend the tip commit to annotate that it closes the PR
-                let editor = format!(
-                    "git interpret-trailers --trailer \"Closes: #{}\" --in-place",
-                    pr_number
-                );
+                // Amend the tip commit to annotate it with the PR's landing metadata
+                let mut editor = "git interpret-trailers --in-place".to_owned();
+                for trailer in trailers {
+                    editor.push_str(" --trailer ");
+                    editor.push_str(&shell_quote(trailer));
+                }
                 self.git().amend(&editor)?;
                 let head_oid = self.git().head_oid()?;
 
@@ -150,7 +193,410 @@ impl GitRepository {
         }
     }
 
-    pub fn fetch_and_cherry_pick(
+    /// Run a git command which talks to Github, retrying it if it times out since a wedged
+    /// connection is likely to succeed when tried again
+    fn retry_on_timeout<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut(Git) -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f(self.git()) {
+                Err(e) if e.is::<TimedOut>() && attempt < NETWORK_ATTEMPTS => {
+                    attempt += 1;
+                    warn!("{}, retrying: attempt {}", e, attempt);
+                    if let Err(e) = self.remove_stale_locks() {
+                        warn!("Unable to remove stale locks: {:?}", e);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Remove the lock files left behind by a git command which was killed, which would otherwise
+    /// make every later command on the repo fail. This is only safe as bors never runs more than
+    /// one command on the repo at a time.
+    fn remove_stale_locks(&self) -> io::Result<()> {
+        fn remove_locks(dir: &Path) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    // Objects are never locked, and there are far too many to walk
+                    if entry.file_name() != "objects" {
+                        remove_locks(&path)?;
+                    }
+                } else if path.extension().is_some_and(|ext| ext == "lock") {
+                    warn!("Removing stale lock {}", path.display());
+                    fs::remove_file(&path)?;
+                }
+            }
+            Ok(())
+        }
+
+        remove_locks(&self.directory.join(".git"))
+    }
+
+    fn git(&self) -> Git {
+        self.git_in(&self.directory)
+    }
+
+    fn git_in(&self, directory: &Path) -> Git {
+        Git::new()
+            .current_dir(directory)
+            .with_user(&self.git_config.user)
+            .with_email(&self.git_config.email)
+            .with_ssh(&self.git_config.ssh_key_file)
+            .with_timeout(self.git_config.timeout())
+    }
+}
+
+/// Operations bors performs on a repo's on-disk clone, abstracted so that the event processor can
+/// be driven against something other than a real repo, e.g. a fake in tests
+pub trait GitApi: std::fmt::Debug + Send + Sync {
+    fn user(&self) -> &str;
+
+    fn push_branch(&mut self, branch: &str) -> Result<()>;
+
+    fn push_to_remote(
+        &mut self,
+        repo: &Repo,
+        branch: &str,
+        old_oid: &Oid,
+        new_oid: &Oid,
+    ) -> Result<()>;
+
+    fn fetch_ref(&mut self, r: &str) -> Result<Oid>;
+
+    fn fetch_and_rebase(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        trailers: &[String],
+        fixup_all: bool,
+    ) -> Result<Option<Oid>>;
+
+    /// Find any temporary commits, e.g. `fixup!`, `squash!` or `wip` commits, between `base_ref`
+    /// and `head_oid` which weren't able to be folded into another commit when autosquashing.
+    ///
+    /// Returns the subject lines of the offending commits.
+    fn temporary_commits(&mut self, base_ref: &str, head_oid: &Oid) -> Result<Vec<String>>;
+
+    /// Append `text` to the file at `path` in `tip`, the tip commit of `branch`, amending it, e.g.
+    /// to add a PR's changelog entry to the commit landing it.
+    ///
+    /// Returns the oid of the amended commit
+    fn append_to_tip(&mut self, branch: &str, tip: &Oid, path: &str, text: &str) -> Result<Oid>;
+
+    /// Find the latest tag starting with `tag_prefix` reachable from `base_ref`, and the subject
+    /// lines of the commits made to `base_ref` since it, newest first.
+    ///
+    /// If no tag has been made yet, every commit on `base_ref` is returned.
+    fn changes_since_tag(
+        &mut self,
+        base_ref: &str,
+        tag_prefix: &str,
+    ) -> Result<(Option<String>, Vec<String>)>;
+
+    /// Read the file at `path` in the latest `base_ref`
+    fn read_file(&mut self, base_ref: &str, path: &str) -> Result<String>;
+
+    /// Create `branch` from the latest `base_ref` with a commit replacing the file at `path` with
+    /// `contents`.
+    ///
+    /// Returns the oid of the new commit
+    fn commit_file(
+        &mut self,
+        base_ref: &str,
+        branch: &str,
+        path: &str,
+        contents: &str,
+        message: &str,
+    ) -> Result<Oid>;
+
+    /// Perform a trial rebase of `head_oid` onto the latest `base_ref` in a temporary worktree so
+    /// that the primary checkout, which may be in use for testing the head of the queue, is left
+    /// untouched.
+    ///
+    /// Returns `false` if the rebase resulted in a merge conflict.
+    fn trial_merge(&mut self, base_ref: &str, head_oid: &Oid, pr_number: u64) -> Result<bool>;
+
+    /// Bring the PR's head up to date with the latest `base_ref`, either by merging `base_ref`
+    /// into the PR or by rebasing the PR on top of it.
+    ///
+    /// None represents a Merge conflict
+    fn fetch_and_update(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        rebase: bool,
+    ) -> Result<Option<Oid>>;
+
+    fn fetch_and_cherry_pick(
+        &mut self,
+        target_ref: &str,
+        branch: &str,
+        base_oid: &Oid,
+        head_oid: &Oid,
+    ) -> Result<Option<Oid>>;
+}
+
+impl GitApi for GitRepository {
+    fn user(&self) -> &str {
+        &self.git_config.user
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn push_branch(&mut self, branch: &str) -> Result<()> {
+        self.ensure_cloned()?;
+        check_ref_name(branch)?;
+
+        // Only ever force-push to branches that bors owns, guarding against a bug clobbering some
+        // other branch in the repository
+        let allowed = self.push_allowlist.iter().any(|b| b == branch)
+            || branch.starts_with(CHERRY_PICK_BRANCH_PREFIX)
+            || branch.starts_with(RELEASE_BRANCH_PREFIX);
+        if !allowed {
+            return Err(anyhow!(
+                "refusing to push to '{}' branch of {}/{} as it isn't managed by bors",
+                branch,
+                self.github_repo.owner(),
+                self.github_repo.name()
+            ));
+        }
+
+        if self.dry_run {
+            info!("dry run: skipping push to '{}'", branch);
+            return Ok(());
+        }
+
+        self.retry_on_timeout(|git| git.push_branch(branch, true))
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn push_to_remote(
+        &mut self,
+        repo: &Repo,
+        branch: &str,
+        old_oid: &Oid,
+        new_oid: &Oid,
+    ) -> Result<()> {
+        // Pushes to a PR's branch are guarded by `--force-with-lease` so only the ref name itself
+        // needs to be validated
+        check_ref_name(branch)?;
+        self.ensure_cloned()?;
+
+        if self.dry_run {
+            info!(
+                "dry run: skipping push to '{}' of {}/{}",
+                branch,
+                repo.owner(),
+                repo.name()
+            );
+            return Ok(());
+        }
+
+        let mut attempted = false;
+        self.retry_on_timeout(|git| {
+            // A push which timed out may still have updated the branch, in which case retrying it
+            // would fail its lease
+            if attempted && self.git().remote_ref(repo, branch)?.as_ref() == Some(new_oid) {
+                return Ok(());
+            }
+            attempted = true;
+            git.push_to_remote(repo, branch, old_oid, new_oid)
+        })
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn fetch_ref(&mut self, r: &str) -> Result<Oid> {
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch([r]))?;
+        self.git().fetch_head_oid()
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn fetch_and_rebase(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        trailers: &[String],
+        fixup_all: bool,
+    ) -> Result<Option<Oid>> {
+        // Fetch base ref and head_oid
+        self.fetch(base_ref, head_oid)?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+        self.rebase(&base_oid, head_oid, branch, trailers, fixup_all)
+    }
+
+    fn temporary_commits(&mut self, base_ref: &str, head_oid: &Oid) -> Result<Vec<String>> {
+        self.ensure_cloned()?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+        let subjects = self.git().commit_subjects(&base_oid, head_oid)?;
+
+        Ok(subjects
+            .into_iter()
+            .filter(|subject| is_temporary_commit(subject))
+            .collect())
+    }
+
+    #[instrument(skip(self, text), fields(repo = %self.github_repo))]
+    fn append_to_tip(&mut self, branch: &str, tip: &Oid, path: &str, text: &str) -> Result<Oid> {
+        self.ensure_cloned()?;
+        self.git().create_branch(branch, tip)?;
+
+        let file = self.directory.join(path);
+        let mut contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path)),
+        };
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(text);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, contents).with_context(|| format!("writing {}", path))?;
+
+        self.git().add(Path::new(path))?;
+        self.git().amend_staged()?;
+        self.git().head_oid()
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn changes_since_tag(
+        &mut self,
+        base_ref: &str,
+        tag_prefix: &str,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch(["--tags", base_ref]))?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+
+        let tag = self
+            .git()
+            .merged_tags(&base_oid, &format!("{}*", tag_prefix))?
+            .into_iter()
+            .next();
+        let subjects = match &tag {
+            Some(tag) => {
+                let tag_oid = self.git().ref_to_oid(&format!("{}^{{commit}}", tag))?;
+                self.git().commit_subjects(&tag_oid, &base_oid)?
+            }
+            None => self.git().all_commit_subjects(&base_oid)?,
+        };
+
+        Ok((tag, subjects))
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn read_file(&mut self, base_ref: &str, path: &str) -> Result<String> {
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch([base_ref]))?;
+        self.git().show_file(&format!("origin/{}", base_ref), path)
+    }
+
+    #[instrument(skip(self, contents), fields(repo = %self.github_repo))]
+    fn commit_file(
+        &mut self,
+        base_ref: &str,
+        branch: &str,
+        path: &str,
+        contents: &str,
+        message: &str,
+    ) -> Result<Oid> {
+        self.ensure_cloned()?;
+        self.retry_on_timeout(|git| git.fetch([base_ref]))?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+        self.git().create_branch(branch, &base_oid)?;
+
+        let file = self.directory.join(path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&file, contents).with_context(|| format!("writing {}", path))?;
+
+        self.git().add(Path::new(path))?;
+        self.git().commit(message)?;
+        self.git().head_oid()
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn trial_merge(&mut self, base_ref: &str, head_oid: &Oid, pr_number: u64) -> Result<bool> {
+        self.fetch(base_ref, head_oid)?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+
+        let worktree = self.directory.with_file_name(format!(
+            "{}.trial-{}",
+            self.github_repo.name(),
+            pr_number
+        ));
+
+        // Clean up after any previous trial which wasn't able to remove its worktree
+        if worktree.exists() && self.git().worktree_remove(&worktree).is_err() {
+            std::fs::remove_dir_all(&worktree)?;
+        }
+        self.git().worktree_prune()?;
+        self.git().worktree_add(&worktree, head_oid)?;
+
+        let merged = if let Err(e) = self.git_in(&worktree).rebase(&base_oid, true, None) {
+            info!("Trial merge of pr #{} failed: {}", pr_number, e);
+            self.git_in(&worktree).rebase_abort()?;
+            false
+        } else {
+            true
+        };
+
+        self.git().worktree_remove(&worktree)?;
+
+        Ok(merged)
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn fetch_and_update(
+        &mut self,
+        base_ref: &str,
+        head_oid: &Oid,
+        branch: &str,
+        rebase: bool,
+    ) -> Result<Option<Oid>> {
+        self.fetch(base_ref, head_oid)?;
+        let base_oid = self.git().ref_to_oid(&format!("origin/{}", base_ref))?;
+
+        // Nothing to do if the PR already contains all of the commits from `base_ref`
+        if self.git().number_of_commits(head_oid, &base_oid)? == 0 {
+            return Ok(Some(head_oid.clone()));
+        }
+
+        self.git().create_branch(branch, head_oid)?;
+
+        if rebase {
+            if let Err(e) = self.git().rebase(&base_oid, false, None) {
+                info!("Rebase failed: {}", e);
+                self.git().rebase_abort()?;
+                return Ok(None);
+            }
+        } else {
+            let message = format!("Merge branch '{}'", base_ref);
+            if let Err(e) = self.git().merge(&base_oid, &message) {
+                info!("Merge failed: {}", e);
+                self.git().merge_abort()?;
+                return Ok(None);
+            }
+        }
+
+        let head_oid = self.git().head_oid()?;
+        Ok(Some(head_oid))
+    }
+
+    #[instrument(skip(self), fields(repo = %self.github_repo))]
+    fn fetch_and_cherry_pick(
         &mut self,
         target_ref: &str,
         branch: &str,
@@ -173,18 +619,38 @@ impl GitRepository {
             Ok(Some(head_oid))
         }
     }
+}
 
-    fn git(&self) -> Git {
-        Git::new()
-            .current_dir(&self.directory)
-            .with_user(&self.git_config.user)
-            .with_email(&self.git_config.email)
-            .with_ssh(&self.git_config.ssh_key_file)
+fn is_temporary_commit(subject: &str) -> bool {
+    const PREFIXES: &[&str] = &["fixup!", "squash!", "amend!", "wip:", "wip ", "[wip]"];
+
+    let subject = subject.trim().to_lowercase();
+    subject == "wip" || PREFIXES.iter().any(|p| subject.starts_with(p))
+}
+
+/// Sanity check a branch name before it is used as the destination of a push, rejecting anything
+/// that could be interpreted as a refspec or option instead of a plain branch name
+fn check_ref_name(branch: &str) -> Result<()> {
+    let valid = !branch.is_empty()
+        && !branch.starts_with('-')
+        && !branch.starts_with("refs/")
+        && !branch.contains(|c: char| c == ':' || c == '+' || c.is_whitespace());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid branch name '{}'", branch))
     }
 }
 
+/// Quote a string so that it is passed as a single argument when interpreted by a shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
 struct Git {
     inner: Command,
+    timeout: Option<Duration>,
 }
 
 impl Git {
@@ -200,7 +666,10 @@ impl Git {
             // Don't try and open an editor for things like `rebase -i`
             .env("GIT_EDITOR", "cat");
 
-        Self { inner }
+        Self {
+            inner,
+            timeout: None,
+        }
     }
 
     // Use `-C <path>` instead of `Command::current_dir` so that attempting to run a command in a
@@ -245,13 +714,29 @@ impl Git {
         self
     }
 
+    /// Kill the command if it hasn't completed within `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     fn run(mut self) -> Result<String> {
-        let output = self.inner.output()?;
+        let args: Vec<_> = self
+            .inner
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        let _span = tracing::info_span!("git", command = %args.join(" ")).entered();
+
+        let output = match self.timeout {
+            Some(timeout) => self.output_with_timeout(timeout)?,
+            None => self.inner.output()?,
+        };
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
             debug!("Git command failed:\n$ {:?}\n{}", self.inner, stderr);
-            return Err(anyhow!("failed to run git command:\n{}", stderr));
+            return Err(CommandFailed(stderr.into_owned()).into());
         }
 
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -263,11 +748,69 @@ impl Git {
         Ok(stdout.into())
     }
 
+    fn output_with_timeout(&mut self, timeout: Duration) -> Result<Output> {
+        // Run the command in its own process group so that it can be killed along with any helpers
+        // it spawns, e.g. `ssh` or `pack-objects`, which would otherwise keep running and hold its
+        // output open
+        let mut child = self
+            .inner
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+
+        // Drain the child's output on separate threads so that it can't block on a full pipe
+        fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                buf
+            })
+        }
+        let stdout = read_to_end(child.stdout.take());
+        let stderr = read_to_end(child.stderr.take());
+
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(1);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                // The command may have completed in the meantime, in which case there is nothing
+                // to kill. Its process group shares its id.
+                unsafe {
+                    libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                }
+                child.wait()?;
+                let _ = (stdout.join(), stderr.join());
+                warn!(
+                    "Git command timed out after {:?} and was killed:\n$ {:?}",
+                    timeout, self.inner
+                );
+                return Err(TimedOut(timeout).into());
+            }
+
+            thread::sleep(backoff);
+            backoff = std::cmp::min(backoff * 2, Duration::from_millis(100));
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap(),
+            stderr: stderr.join().unwrap(),
+        })
+    }
+
     #[allow(clippy::wrong_self_convention)]
     pub fn is_git_repo(mut self) -> Result<bool> {
         let output = self
             .inner
-            .args(&["rev-parse", "--git-dir"])
+            .args(["rev-parse", "--git-dir"])
             .output()
             .context("checking if a directory is a git repo")?;
 
@@ -275,7 +818,7 @@ impl Git {
     }
 
     pub fn remote_matches_github_repo(mut self, github_repo: &Repo) -> Result<bool> {
-        self.inner.args(&["remote", "get-url", "origin"]);
+        self.inner.args(["remote", "get-url", "origin"]);
         let output = self.run()?;
 
         Ok(output.trim() == github_repo.to_github_ssh_url())
@@ -301,28 +844,74 @@ impl Git {
         Ok(())
     }
 
+    pub fn fetch_all(mut self) -> Result<()> {
+        self.inner.args(["fetch", "--prune", "origin"]);
+        self.run()?;
+        Ok(())
+    }
+
     pub fn create_branch(mut self, branch_name: &str, oid: &Oid) -> Result<()> {
         self.inner
-            .args(&["checkout", "-B", branch_name])
+            .args(["checkout", "-B", branch_name])
+            .arg(oid.to_string());
+        self.run()?;
+        Ok(())
+    }
+
+    pub fn worktree_add(mut self, path: &Path, oid: &Oid) -> Result<()> {
+        self.inner
+            .args(["worktree", "add", "--detach"])
+            .arg(path)
             .arg(oid.to_string());
         self.run()?;
         Ok(())
     }
 
+    pub fn worktree_remove(mut self, path: &Path) -> Result<()> {
+        self.inner.args(["worktree", "remove", "--force"]).arg(path);
+        self.run()?;
+        Ok(())
+    }
+
+    pub fn worktree_prune(mut self) -> Result<()> {
+        self.inner.args(["worktree", "prune"]);
+        self.run()?;
+        Ok(())
+    }
+
     pub fn amend(mut self, editor: &str) -> Result<()> {
-        self.inner.args(&["commit", "--amend"]);
+        self.inner.args(["commit", "--amend"]);
         self.with_editor(editor).run()?;
         Ok(())
     }
 
+    /// Amend the last commit with whatever is staged, keeping its message
+    pub fn amend_staged(mut self) -> Result<()> {
+        self.inner.args(["commit", "--amend", "--no-edit"]);
+        self.run()?;
+        Ok(())
+    }
+
+    pub fn commit(mut self, message: &str) -> Result<()> {
+        self.inner.args(["commit", "-m", message]);
+        self.run()?;
+        Ok(())
+    }
+
+    pub fn add(mut self, path: &Path) -> Result<()> {
+        self.inner.args(["add", "--"]).arg(path);
+        self.run()?;
+        Ok(())
+    }
+
     pub fn rebase_abort(mut self) -> Result<()> {
-        self.inner.args(&["rebase", "--abort"]);
+        self.inner.args(["rebase", "--abort"]);
         self.run()?;
         Ok(())
     }
 
     pub fn rebase(mut self, base_oid: &Oid, autosquash: bool, exec: Option<String>) -> Result<()> {
-        self.inner.args(&["rebase", "-i", "--force-rebase"]);
+        self.inner.args(["rebase", "-i", "--force-rebase"]);
         self.inner.arg(base_oid.to_string());
 
         if autosquash {
@@ -338,14 +927,28 @@ impl Git {
         Ok(())
     }
 
+    pub fn merge_abort(mut self) -> Result<()> {
+        self.inner.args(["merge", "--abort"]);
+        self.run()?;
+        Ok(())
+    }
+
+    pub fn merge(mut self, oid: &Oid, message: &str) -> Result<()> {
+        self.inner
+            .args(["merge", "--no-ff", "-m", message])
+            .arg(oid.to_string());
+        self.run()?;
+        Ok(())
+    }
+
     pub fn cherry_pick_abort(mut self) -> Result<()> {
-        self.inner.args(&["cherry-pick", "--abort"]);
+        self.inner.args(["cherry-pick", "--abort"]);
         self.run()?;
         Ok(())
     }
 
     pub fn cherry_pick(mut self, base_oid: &Oid, head_oid: &Oid) -> Result<()> {
-        self.inner.args(&["cherry-pick"]);
+        self.inner.args(["cherry-pick"]);
         self.inner.arg(format!("{}..{}", base_oid, head_oid));
 
         self.run()?;
@@ -355,7 +958,7 @@ impl Git {
     pub fn get_first_commit(mut self, base_oid: &Oid, head_oid: &Oid) -> Result<Oid> {
         self.inner
             .arg("rev-list")
-            .arg(&format!("{}..{}", base_oid, head_oid));
+            .arg(format!("{}..{}", base_oid, head_oid));
         let output = self.run()?;
         let first = output
             .lines()
@@ -364,10 +967,41 @@ impl Git {
         Ok(Oid::from_str(first.trim()))
     }
 
+    pub fn commit_subjects(mut self, base_oid: &Oid, head_oid: &Oid) -> Result<Vec<String>> {
+        self.inner
+            .args(["log", "--format=%s"])
+            .arg(format!("{}..{}", base_oid, head_oid));
+        let output = self.run()?;
+        Ok(output.lines().map(ToOwned::to_owned).collect())
+    }
+
+    pub fn all_commit_subjects(mut self, head_oid: &Oid) -> Result<Vec<String>> {
+        self.inner
+            .args(["log", "--format=%s"])
+            .arg(head_oid.to_string());
+        let output = self.run()?;
+        Ok(output.lines().map(ToOwned::to_owned).collect())
+    }
+
+    /// Tags matching `pattern` which are reachable from `oid`, highest version first
+    pub fn merged_tags(mut self, oid: &Oid, pattern: &str) -> Result<Vec<String>> {
+        self.inner
+            .args(["tag", "--sort=-v:refname", "--merged"])
+            .arg(oid.to_string())
+            .args(["--list", pattern]);
+        let output = self.run()?;
+        Ok(output.lines().map(ToOwned::to_owned).collect())
+    }
+
+    pub fn show_file(mut self, r: &str, path: &str) -> Result<String> {
+        self.inner.arg("show").arg(format!("{}:{}", r, path));
+        self.run()
+    }
+
     pub fn number_of_commits(mut self, base_oid: &Oid, head_oid: &Oid) -> Result<usize> {
         self.inner
             .arg("rev-list")
-            .arg(&format!("{}..{}", base_oid, head_oid));
+            .arg(format!("{}..{}", base_oid, head_oid));
         let output = self.run()?;
         Ok(output.lines().count())
     }
@@ -381,13 +1015,13 @@ impl Git {
     }
 
     pub fn ref_to_oid(mut self, r: &str) -> Result<Oid> {
-        self.inner.args(&["rev-parse", r]);
+        self.inner.args(["rev-parse", r]);
         let output = self.run()?;
         Ok(Oid::from_str(output.trim()))
     }
 
     pub fn push_branch(mut self, branch: &str, force: bool) -> Result<()> {
-        self.inner.args(&["push", "origin"]);
+        self.inner.args(["push", "origin"]);
         if force {
             self.inner.arg("--force");
         }
@@ -405,10 +1039,193 @@ impl Git {
     ) -> Result<()> {
         self.inner
             .arg("push")
-            .arg(&format!("--force-with-lease={}:{}", branch, old_oid))
+            .arg(format!("--force-with-lease={}:{}", branch, old_oid))
             .arg(repo.to_github_ssh_url())
             .arg(format!("{}:{}", new_oid, branch));
         self.run()?;
         Ok(())
     }
+
+    /// The commit `branch` of `repo` on Github points to, if the branch exists
+    pub fn remote_ref(mut self, repo: &Repo, branch: &str) -> Result<Option<Oid>> {
+        self.inner
+            .arg("ls-remote")
+            .arg(repo.to_github_ssh_url())
+            .arg(format!("refs/heads/{}", branch));
+        let output = self.run()?;
+        Ok(output.split_whitespace().next().map(Oid::from_str))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn temporary_commits() {
+        for subject in &[
+            "fixup! Add parser",
+            "squash! Add parser",
+            "amend! Add parser",
+            "WIP",
+            "wip: parser",
+            "WIP parser",
+            "[WIP] parser",
+        ] {
+            assert!(is_temporary_commit(subject), "{}", subject);
+        }
+        for subject in &[
+            "Add parser",
+            "Wipe caches on startup",
+            "Fix fixup! handling",
+        ] {
+            assert!(!is_temporary_commit(subject), "{}", subject);
+        }
+    }
+
+    #[test]
+    fn ref_names() {
+        assert!(check_ref_name("auto").is_ok());
+        assert!(check_ref_name("pick/1-release-1.0").is_ok());
+        for branch in &[
+            "",
+            "--force",
+            "refs/heads/master",
+            "a:master",
+            "+master",
+            "a b",
+        ] {
+            assert!(check_ref_name(branch).is_err(), "{}", branch);
+        }
+    }
+
+    #[test]
+    fn shell_quoting() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    /// Run git in `dir` to set up a test, returning what it printed
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=bors", "-c", "user.email=bors@example.com"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// Commit `contents` to `file` on top of `parent`, if any, returning the new commit
+    fn commit(dir: &Path, parent: Option<&Oid>, file: &str, contents: &str) -> Oid {
+        if let Some(parent) = parent {
+            git(dir, &["checkout", "-q", "--detach", &parent.to_string()]);
+        }
+        fs::write(dir.join(file), contents).unwrap();
+        git(dir, &["add", file]);
+        git(dir, &["commit", "-q", "-m", file]);
+        Oid::from_str(git(dir, &["rev-parse", "HEAD"]))
+    }
+
+    #[test]
+    fn trial_merge() {
+        let root = std::env::temp_dir().join(format!("bors-trial-merge-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let origin = root.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        let ssh_key_file = root.join("id");
+        fs::write(&ssh_key_file, "").unwrap();
+
+        // `master` and one of the PRs both change the same line
+        git(&origin, &["init", "-q"]);
+        let base = commit(&origin, None, "a", "base\n");
+        let clean = commit(&origin, Some(&base), "b", "clean\n");
+        let conflict = commit(&origin, Some(&base), "a", "theirs\n");
+        let master = commit(&origin, Some(&base), "a", "ours\n");
+        git(&origin, &["branch", "-f", "master", &master.to_string()]);
+
+        let directory = root.join("Hello-World");
+        git(
+            &root,
+            &["clone", "-q", origin.to_str().unwrap(), "Hello-World"],
+        );
+        let mut repo = GitRepository {
+            directory,
+            github_repo: Repo::new("Codertocat", "Hello-World"),
+            git_config: GitConfig {
+                ssh_key_file,
+                user: "bors".to_owned(),
+                email: "bors@example.com".to_owned(),
+                timeout_seconds: None,
+            },
+            push_allowlist: Vec::new(),
+            cloned: true,
+            dry_run: true,
+        };
+
+        // The trial's worktree is removed whether or not the PR merges cleanly
+        assert!(repo.trial_merge("master", &clean, 1).unwrap());
+        assert!(!root.join("Hello-World.trial-1").exists());
+        assert!(!repo.trial_merge("master", &conflict, 2).unwrap());
+        assert!(!root.join("Hello-World.trial-2").exists());
+        assert_eq!(
+            git(&root.join("Hello-World"), &["worktree", "list"])
+                .lines()
+                .count(),
+            1
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn timeout_kills_helpers() {
+        // The shell's `sleep` holds its output open long after the shell itself is killed
+        let mut inner = Command::new("sh");
+        inner.args(["-c", "sleep 30 | cat; echo done"]);
+        let git = Git {
+            inner,
+            timeout: Some(Duration::from_millis(200)),
+        };
+
+        let started = Instant::now();
+        let error = git.run().unwrap_err();
+        assert!(error.is::<TimedOut>(), "{:?}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn stale_locks() {
+        let root = std::env::temp_dir().join(format!("bors-stale-locks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        git(&root, &["init", "-q"]);
+        let dot_git = root.join(".git");
+        for lock in ["index.lock", "shallow.lock", "refs/heads/auto.lock"] {
+            fs::write(dot_git.join(lock), "").unwrap();
+        }
+
+        let repo = GitRepository {
+            directory: root.clone(),
+            github_repo: Repo::new("Codertocat", "Hello-World"),
+            git_config: GitConfig {
+                ssh_key_file: root.join("id"),
+                user: "bors".to_owned(),
+                email: "bors@example.com".to_owned(),
+                timeout_seconds: None,
+            },
+            push_allowlist: Vec::new(),
+            cloned: true,
+            dry_run: true,
+        };
+        repo.remove_stale_locks().unwrap();
+        for lock in ["index.lock", "shallow.lock", "refs/heads/auto.lock"] {
+            assert!(!dot_git.join(lock).exists(), "{}", lock);
+        }
+        assert!(dot_git.join("HEAD").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
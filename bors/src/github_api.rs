@@ -0,0 +1,573 @@
+//! The subset of the Github API used by bors, abstracted so that the event processor can be driven
+//! against something other than the real Github, e.g. a fake in tests
+
+use crate::{graphql::GithubClient, state::PullRequestState, Error, Result};
+use async_trait::async_trait;
+use github::{
+    client::{
+        CreateProjectCardRequest, CreateStatusRequest, ListProjectCardsOptions,
+        MoveProjectCardRequest, NewPullRequest, PaginationOptions, UpdatePullRequest,
+    },
+    Issue, NodeId, Oid, Project, ProjectCard, ProjectColumn, PullRequest, PullRequestFile,
+    ReactionType,
+};
+use std::{collections::HashMap, time::SystemTime};
+use tracing::instrument;
+
+/// What a user may do with a repo, based on their permission level on Github
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    None,
+    /// View the repo's dashboard and query its state
+    Read,
+    /// Act on the repo's queue, e.g. pausing it or evicting PRs
+    Write,
+    /// Override the repo's limits, e.g. landing PRs which are larger than it allows
+    Admin,
+}
+
+impl Permission {
+    /// The permission a user has given their permission level on Github, e.g. `admin` or `read`
+    pub fn from_level(level: &str) -> Self {
+        match level {
+            "admin" => Permission::Admin,
+            "maintain" | "write" => Permission::Write,
+            "triage" | "read" => Permission::Read,
+            _ => Permission::None,
+        }
+    }
+}
+
+/// Whether an error is Github reporting that something doesn't exist (e.g. a PR or project card) or
+/// is in a conflicting state (e.g. a branch which can't be updated), indicating that bors' state
+/// has drifted from Github's
+pub fn is_drift(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<github::client::Error>(),
+            Some(github::client::Error::GithubClientError(status, _))
+                if matches!(status.as_u16(), 404 | 409 | 422)
+        )
+    })
+}
+
+/// Every file a PR changes, across all pages of them
+async fn list_all_files(
+    client: &GithubClient,
+    owner: &str,
+    name: &str,
+    number: u64,
+) -> Result<Vec<PullRequestFile>> {
+    let mut options = PaginationOptions {
+        page: None,
+        per_page: Some(100),
+    };
+    let mut files = Vec::new();
+
+    loop {
+        let response = client
+            .pulls()
+            .list_files(owner, name, number, Some(options))
+            .await?;
+        let next_page = response.pagination().next_page;
+        files.extend(response.into_inner());
+
+        match next_page {
+            Some(page) => {
+                options = PaginationOptions {
+                    page: Some(page),
+                    per_page: Some(100),
+                }
+            }
+            None => break,
+        }
+    }
+
+    Ok(files)
+}
+
+/// The latest result of a check run or status reported on a commit, as Github reports it when asked
+/// rather than through a webhook
+#[derive(Clone, Debug)]
+pub struct CommitCheck {
+    /// The check run's name, or the status' context
+    pub name: String,
+    pub details_url: String,
+    /// `None` until the check has completed
+    pub conclusion: Option<github::Conclusion>,
+    /// Whether this is a status rather than a check run
+    pub is_status: bool,
+}
+
+/// A reviewer's approval of a PR
+#[derive(Clone, Debug)]
+pub struct Approval {
+    pub user: String,
+    pub submitted_at: SystemTime,
+}
+
+#[async_trait]
+pub trait GithubApi: std::fmt::Debug + Send + Sync {
+    async fn add_reaction(&self, id: &NodeId, reaction: ReactionType) -> Result<()>;
+
+    async fn open_pulls(&self, owner: &str, name: &str) -> Result<Vec<PullRequestState>>;
+
+    /// Whether a PR's reviews have approved it
+    async fn get_review_decision(&self, owner: &str, name: &str, number: u64) -> Result<bool>;
+
+    /// The approvals of a PR which still stand, i.e. each reviewer whose latest review approved it
+    async fn approvals(&self, owner: &str, name: &str, number: u64) -> Result<Vec<Approval>>;
+
+    /// The paths of the files a PR changes, including the previous paths of files it renames
+    async fn changed_files(&self, owner: &str, name: &str, number: u64) -> Result<Vec<String>>;
+
+    /// The paths of the binary files a PR adds or modifies, i.e. those Github shows no diff for
+    async fn binary_files(&self, owner: &str, name: &str, number: u64) -> Result<Vec<String>>;
+
+    async fn get_pull(&self, owner: &str, name: &str, number: u64) -> Result<PullRequest>;
+
+    async fn get_issue(&self, owner: &str, name: &str, number: u64) -> Result<Issue>;
+
+    /// The latest result of each check run and status reported on a commit
+    async fn commit_checks(&self, owner: &str, name: &str, oid: &Oid) -> Result<Vec<CommitCheck>>;
+
+    /// The commit at the head of the repo's default branch, if it has one
+    async fn default_branch_head(&self, owner: &str, name: &str) -> Result<Option<Oid>>;
+
+    async fn create_pull(
+        &self,
+        owner: &str,
+        name: &str,
+        pull: NewPullRequest,
+    ) -> Result<PullRequest>;
+
+    async fn close_pull(&self, owner: &str, name: &str, number: u64) -> Result<()>;
+
+    async fn is_collaborator(&self, owner: &str, name: &str, user: &str) -> Result<bool>;
+
+    /// A user's permission level on a repo, one of `admin`, `write`, `read` or `none`
+    async fn collaborator_permission(&self, owner: &str, name: &str, user: &str) -> Result<String>;
+
+    /// Whether a user is an active member of an org's team, rather than only invited to it
+    async fn is_team_member(&self, org: &str, team: &str, user: &str) -> Result<bool>;
+
+    async fn create_comment(&self, owner: &str, name: &str, number: u64, body: &str) -> Result<()>;
+
+    async fn label_exists(&self, owner: &str, name: &str, label: &str) -> Result<bool>;
+
+    async fn create_label(
+        &self,
+        owner: &str,
+        name: &str,
+        label: &str,
+        color: &str,
+        description: Option<&str>,
+    ) -> Result<()>;
+
+    async fn add_labels(
+        &self,
+        owner: &str,
+        name: &str,
+        number: u64,
+        labels: Vec<String>,
+    ) -> Result<()>;
+
+    async fn remove_label(&self, owner: &str, name: &str, number: u64, label: &str) -> Result<()>;
+
+    async fn create_status(
+        &self,
+        owner: &str,
+        name: &str,
+        ref_name: &str,
+        request: &CreateStatusRequest<'_>,
+    ) -> Result<()>;
+
+    async fn update_ref(
+        &self,
+        owner: &str,
+        name: &str,
+        ref_name: &str,
+        oid: &Oid,
+        force: bool,
+    ) -> Result<()>;
+
+    /// Create a ref, e.g. `refs/tags/v1.0.0`, pointing at `oid`
+    async fn create_ref(&self, owner: &str, name: &str, ref_name: &str, oid: &Oid) -> Result<()>;
+
+    /// Delete a ref, e.g. `heads/feature-a`
+    async fn delete_ref(&self, owner: &str, name: &str, ref_name: &str) -> Result<()>;
+
+    async fn list_projects(&self, owner: &str, name: &str) -> Result<Vec<Project>>;
+
+    async fn create_project(&self, owner: &str, name: &str, project: &str) -> Result<Project>;
+
+    async fn list_project_columns(&self, project_id: u64) -> Result<Vec<ProjectColumn>>;
+
+    async fn create_project_column(&self, project_id: u64, column: &str) -> Result<ProjectColumn>;
+
+    async fn delete_project_column(&self, column_id: u64) -> Result<()>;
+
+    /// All of the cards in a column, across every page
+    async fn list_project_cards(&self, column_id: u64) -> Result<Vec<ProjectCard>>;
+
+    async fn create_project_card(
+        &self,
+        column_id: u64,
+        request: &CreateProjectCardRequest,
+    ) -> Result<ProjectCard>;
+
+    async fn move_project_card(&self, card_id: u64, request: &MoveProjectCardRequest)
+        -> Result<()>;
+
+    async fn delete_project_card(&self, card_id: u64) -> Result<()>;
+}
+
+#[async_trait]
+impl GithubApi for GithubClient {
+    #[instrument(skip(self), err)]
+    async fn add_reaction(&self, id: &NodeId, reaction: ReactionType) -> Result<()> {
+        GithubClient::add_reaction(self, id, reaction).await
+    }
+
+    #[instrument(skip(self), err)]
+    async fn open_pulls(&self, owner: &str, name: &str) -> Result<Vec<PullRequestState>> {
+        GithubClient::open_pulls(self, owner, name).await
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_review_decision(&self, owner: &str, name: &str, number: u64) -> Result<bool> {
+        GithubClient::get_review_decision(self, owner, name, number).await
+    }
+
+    #[instrument(skip(self), err)]
+    async fn approvals(&self, owner: &str, name: &str, number: u64) -> Result<Vec<Approval>> {
+        let mut options = PaginationOptions {
+            page: None,
+            per_page: Some(100),
+        };
+        // Reviews are listed oldest first, so each reviewer's latest review wins
+        let mut latest = HashMap::new();
+
+        loop {
+            let response = self
+                .pulls()
+                .list_reviews(owner, name, number, Some(options))
+                .await?;
+            let next_page = response.pagination().next_page;
+
+            for review in response.into_inner() {
+                // Comments neither approve a PR nor withdraw an approval
+                if !matches!(review.state, github::ReviewState::Commented) {
+                    latest.insert(review.user.login.clone(), review);
+                }
+            }
+
+            match next_page {
+                Some(page) => {
+                    options = PaginationOptions {
+                        page: Some(page),
+                        per_page: Some(100),
+                    }
+                }
+                None => break,
+            }
+        }
+
+        Ok(latest
+            .into_iter()
+            .filter(|(_, review)| matches!(review.state, github::ReviewState::Approved))
+            .map(|(user, review)| Approval {
+                user,
+                submitted_at: review.submitted_at.into(),
+            })
+            .collect())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn changed_files(&self, owner: &str, name: &str, number: u64) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+        for file in list_all_files(self, owner, name, number).await? {
+            // Moving a file out of a path changes that path as well
+            files.extend(file.previous_filename);
+            files.push(file.filename);
+        }
+
+        Ok(files)
+    }
+
+    #[instrument(skip(self), err)]
+    async fn binary_files(&self, owner: &str, name: &str, number: u64) -> Result<Vec<String>> {
+        // Github leaves out the diff of binary files, as well as of text files whose diffs are too
+        // large to show, though those still count their changed lines
+        Ok(list_all_files(self, owner, name, number)
+            .await?
+            .into_iter()
+            .filter(|file| {
+                file.patch.is_none()
+                    && file.changes == 0
+                    && matches!(file.status.as_str(), "added" | "modified" | "changed")
+            })
+            .map(|file| file.filename)
+            .collect())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_pull(&self, owner: &str, name: &str, number: u64) -> Result<PullRequest> {
+        Ok(self.pulls().get(owner, name, number).await?.into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn get_issue(&self, owner: &str, name: &str, number: u64) -> Result<Issue> {
+        Ok(self.issues().get(owner, name, number).await?.into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn commit_checks(&self, owner: &str, name: &str, oid: &Oid) -> Result<Vec<CommitCheck>> {
+        GithubClient::commit_checks(self, owner, name, oid).await
+    }
+
+    #[instrument(skip(self), err)]
+    async fn default_branch_head(&self, owner: &str, name: &str) -> Result<Option<Oid>> {
+        GithubClient::default_branch_head(self, owner, name).await
+    }
+
+    #[instrument(skip(self, pull), err)]
+    async fn create_pull(
+        &self,
+        owner: &str,
+        name: &str,
+        pull: NewPullRequest,
+    ) -> Result<PullRequest> {
+        Ok(self.pulls().create(owner, name, pull).await?.into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn close_pull(&self, owner: &str, name: &str, number: u64) -> Result<()> {
+        let update = UpdatePullRequest {
+            state: Some(github::State::Closed),
+            ..Default::default()
+        };
+        self.pulls().update(owner, name, number, update).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn is_collaborator(&self, owner: &str, name: &str, user: &str) -> Result<bool> {
+        Ok(self
+            .repos()
+            .is_collaborator(owner, name, user)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn collaborator_permission(&self, owner: &str, name: &str, user: &str) -> Result<String> {
+        Ok(self
+            .repos()
+            .get_collaborator_permission_level(owner, name, user)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn is_team_member(&self, org: &str, team: &str, user: &str) -> Result<bool> {
+        match self.teams().get_membership(org, team, user).await {
+            Ok(membership) => Ok(membership.into_inner().state == "active"),
+            Err(github::client::Error::GithubClientError(status, _)) if status.as_u16() == 404 => {
+                Ok(false)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    #[instrument(skip(self, body), err)]
+    async fn create_comment(&self, owner: &str, name: &str, number: u64, body: &str) -> Result<()> {
+        self.issues()
+            .create_comment(owner, name, number, body)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn label_exists(&self, owner: &str, name: &str, label: &str) -> Result<bool> {
+        Ok(self.issues().get_label(owner, name, label).await.is_ok())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn create_label(
+        &self,
+        owner: &str,
+        name: &str,
+        label: &str,
+        color: &str,
+        description: Option<&str>,
+    ) -> Result<()> {
+        self.issues()
+            .create_label(owner, name, label, color, description)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn add_labels(
+        &self,
+        owner: &str,
+        name: &str,
+        number: u64,
+        labels: Vec<String>,
+    ) -> Result<()> {
+        self.issues()
+            .add_lables(owner, name, number, labels)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn remove_label(&self, owner: &str, name: &str, number: u64, label: &str) -> Result<()> {
+        self.issues()
+            .remove_label(owner, name, number, label)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self, request), err)]
+    async fn create_status(
+        &self,
+        owner: &str,
+        name: &str,
+        ref_name: &str,
+        request: &CreateStatusRequest<'_>,
+    ) -> Result<()> {
+        self.repos()
+            .create_status(owner, name, ref_name, request)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn update_ref(
+        &self,
+        owner: &str,
+        name: &str,
+        ref_name: &str,
+        oid: &Oid,
+        force: bool,
+    ) -> Result<()> {
+        self.git()
+            .update_ref(owner, name, ref_name, oid, force)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn create_ref(&self, owner: &str, name: &str, ref_name: &str, oid: &Oid) -> Result<()> {
+        self.git().create_ref(owner, name, ref_name, oid).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn delete_ref(&self, owner: &str, name: &str, ref_name: &str) -> Result<()> {
+        self.git().delete_ref(owner, name, ref_name).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn list_projects(&self, owner: &str, name: &str) -> Result<Vec<Project>> {
+        Ok(self
+            .projects()
+            .list_for_repo(owner, name, None)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn create_project(&self, owner: &str, name: &str, project: &str) -> Result<Project> {
+        Ok(self
+            .projects()
+            .create_for_repo(owner, name, project, None)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn list_project_columns(&self, project_id: u64) -> Result<Vec<ProjectColumn>> {
+        Ok(self
+            .projects()
+            .list_columns(project_id, None)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn create_project_column(&self, project_id: u64, column: &str) -> Result<ProjectColumn> {
+        Ok(self
+            .projects()
+            .create_column(project_id, column)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn delete_project_column(&self, column_id: u64) -> Result<()> {
+        self.projects().delete_column(column_id).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn list_project_cards(&self, column_id: u64) -> Result<Vec<ProjectCard>> {
+        let mut list_options = ListProjectCardsOptions {
+            archived_state: None,
+            pagination_options: PaginationOptions {
+                page: None,
+                per_page: Some(100),
+            },
+        };
+        let mut has_next_page = true;
+        let mut cards = Vec::new();
+
+        while has_next_page {
+            let response = self
+                .projects()
+                .list_cards(column_id, Some(&list_options))
+                .await?;
+
+            list_options.pagination_options.page = response.pagination().next_page;
+            if list_options.pagination_options.page.is_none() {
+                has_next_page = false;
+            }
+
+            cards.extend(response.into_inner())
+        }
+
+        Ok(cards)
+    }
+
+    #[instrument(skip(self, request), err)]
+    async fn create_project_card(
+        &self,
+        column_id: u64,
+        request: &CreateProjectCardRequest,
+    ) -> Result<ProjectCard> {
+        Ok(self
+            .projects()
+            .create_card(column_id, request)
+            .await?
+            .into_inner())
+    }
+
+    #[instrument(skip(self, request), err)]
+    async fn move_project_card(
+        &self,
+        card_id: u64,
+        request: &MoveProjectCardRequest,
+    ) -> Result<()> {
+        self.projects().move_card(card_id, request).await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self), err)]
+    async fn delete_project_card(&self, card_id: u64) -> Result<()> {
+        self.projects().delete_card(card_id).await?;
+        Ok(())
+    }
+}
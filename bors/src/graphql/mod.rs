@@ -6,11 +6,14 @@
 //! [Github's v4 API Explorer](https://developer.github.com/v4/explorer/)
 //! [Github's v4 API Docs](https://developer.github.com/v4/)
 
-use crate::{state::PullRequestState, Result};
-use github::{client::Response, Client, NodeId, ReactionType};
+use crate::{config::GithubConfig, github_api::CommitCheck, state::PullRequestState, Result};
+use github::{
+    client::{ClientBuilder, Response},
+    Client, NodeId, Oid, ReactionType,
+};
 use graphql_client::GraphQLQuery;
-use log::debug;
 use std::ops::Deref;
+use tracing::debug;
 
 mod query;
 
@@ -20,13 +23,32 @@ const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VE
 pub struct GithubClient(Client);
 
 impl GithubClient {
-    pub fn new(github_api_token: &str) -> Self {
-        let client = Client::builder()
-            .github_api_token(github_api_token)
+    pub fn new(config: &GithubConfig) -> Self {
+        Self(Self::builder(config).build().unwrap())
+    }
+
+    /// Create a client which talks to the Github API at `base_url`, e.g. a stub used when
+    /// replaying webhooks. `base_url` must end with a trailing slash.
+    pub fn with_base_url(config: &GithubConfig, base_url: &str) -> Self {
+        Self(Self::builder(config).base_url(base_url).build().unwrap())
+    }
+
+    /// Create a client which is authenticated with `token` instead of bors' own token, e.g. one
+    /// granted to a user logging in with Github
+    pub fn with_token(config: &GithubConfig, token: &str) -> Self {
+        Self(
+            Self::builder(config)
+                .github_api_token(token)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn builder(config: &GithubConfig) -> ClientBuilder {
+        Client::builder()
+            .github_api_token(&config.github_api_token)
             .user_agent(USER_AGENT)
-            .build()
-            .unwrap();
-        Self(client)
+            .timeout(config.timeout())
     }
 
     pub async fn add_reaction(&self, id: &NodeId, reaction: ReactionType) -> Result<()> {
@@ -104,14 +126,68 @@ impl GithubClient {
             .repository
             .and_then(|r| r.pull_request)
             .and_then(|p| p.review_decision)
-            .map(|d| match d {
-                PullRequestReviewDecision::APPROVED => true,
-                _ => false,
-            })
+            .map(|d| matches!(d, PullRequestReviewDecision::APPROVED))
             .unwrap_or(false);
 
         Ok(d)
     }
+
+    /// The latest result of each check run and status reported on a commit
+    pub async fn commit_checks(
+        &self,
+        owner: &str,
+        name: &str,
+        oid: &Oid,
+    ) -> Result<Vec<CommitCheck>> {
+        use query::{
+            get_commit_checks::{GetCommitChecksRepositoryObject, ResponseData, Variables},
+            GetCommitChecks,
+        };
+
+        let q = GetCommitChecks::build_query(Variables {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+            oid: oid.clone(),
+        });
+
+        let response: ResponseData = self.0.graphql().query(&q).await?.into_inner();
+
+        debug!("commit_checks {}: {:#?}", oid, response);
+
+        let checks = match response.repository.and_then(|r| r.object) {
+            Some(GetCommitChecksRepositoryObject::Commit(commit)) => commit
+                .status_check_rollup
+                .and_then(|rollup| rollup.contexts.nodes)
+                .into_iter()
+                .flat_map(|nodes| nodes.into_iter().flat_map(|node| node.map(Into::into)))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(checks)
+    }
+
+    /// The commit at the head of the repo's default branch, if it has one
+    pub async fn default_branch_head(&self, owner: &str, name: &str) -> Result<Option<Oid>> {
+        use query::{
+            get_default_branch_head::{ResponseData, Variables},
+            GetDefaultBranchHead,
+        };
+
+        let q = GetDefaultBranchHead::build_query(Variables {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+        });
+
+        let response: ResponseData = self.0.graphql().query(&q).await?.into_inner();
+
+        debug!("default_branch_head: {:#?}", response);
+
+        Ok(response
+            .repository
+            .and_then(|r| r.default_branch_ref)
+            .map(|r| r.target.oid))
+    }
 }
 
 impl Deref for GithubClient {
@@ -91,10 +91,10 @@ impl From<list_pulls::ListPullsRepositoryPullRequestsNodes> for crate::state::Pu
             None
         };
 
-        let approved = match review_decision {
-            Some(list_pulls::PullRequestReviewDecision::APPROVED) => true,
-            _ => false,
-        };
+        let approved = matches!(
+            review_decision,
+            Some(list_pulls::PullRequestReviewDecision::APPROVED)
+        );
 
         Self {
             number: number as u64,
@@ -118,10 +118,15 @@ impl From<list_pulls::ListPullsRepositoryPullRequestsNodes> for crate::state::Pu
 
             approved_by: std::collections::HashSet::new(),
             approved,
+            approval_dismissed_by: None,
             status: crate::state::Status::InReview,
             project_card_id: None,
 
             canary_requested: false,
+            canary_runs: Vec::new(),
+            last_activity: None,
+            head_pushed_at: None,
+            nagged_at: None,
         }
     }
 }
@@ -133,3 +138,80 @@ impl From<list_pulls::ListPullsRepositoryPullRequestsNodes> for crate::state::Pu
     response_derives = "Debug"
 )]
 pub struct GetReviewDecision;
+
+// Named after the scalar in the schema
+#[allow(clippy::upper_case_acronyms)]
+type URI = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/github-schema.graphql",
+    query_path = "src/graphql/get_commit_checks.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetCommitChecks;
+
+impl From<get_commit_checks::CheckConclusionState> for github::Conclusion {
+    fn from(conclusion: get_commit_checks::CheckConclusionState) -> Self {
+        use get_commit_checks::CheckConclusionState;
+        use github::Conclusion;
+
+        match conclusion {
+            CheckConclusionState::SUCCESS => Conclusion::Success,
+            CheckConclusionState::NEUTRAL => Conclusion::Neutral,
+            CheckConclusionState::CANCELLED => Conclusion::Cancelled,
+            CheckConclusionState::TIMED_OUT => Conclusion::TimedOut,
+            CheckConclusionState::ACTION_REQUIRED => Conclusion::ActionRequired,
+            CheckConclusionState::SKIPPED => Conclusion::Skipped,
+
+            // Treat stale and other conclusions as failures
+            CheckConclusionState::FAILURE
+            | CheckConclusionState::STALE
+            | CheckConclusionState::Other(_) => Conclusion::Failure,
+        }
+    }
+}
+
+impl From<get_commit_checks::GetCommitChecksRepositoryObjectOnCommitStatusCheckRollupContextsNodes>
+    for crate::github_api::CommitCheck
+{
+    fn from(
+        node: get_commit_checks::GetCommitChecksRepositoryObjectOnCommitStatusCheckRollupContextsNodes,
+    ) -> Self {
+        use get_commit_checks::{
+            CheckStatusState,
+            GetCommitChecksRepositoryObjectOnCommitStatusCheckRollupContextsNodes as Node,
+            StatusState,
+        };
+
+        match node {
+            Node::CheckRun(run) => Self {
+                name: run.name,
+                details_url: run.details_url.unwrap_or_default(),
+                conclusion: match run.status {
+                    CheckStatusState::COMPLETED => run.conclusion.map(Into::into),
+                    _ => None,
+                },
+                is_status: false,
+            },
+            Node::StatusContext(status) => Self {
+                name: status.context,
+                details_url: status.target_url.unwrap_or_default(),
+                conclusion: match status.state {
+                    StatusState::SUCCESS => Some(github::Conclusion::Success),
+                    StatusState::FAILURE | StatusState::ERROR => Some(github::Conclusion::Failure),
+                    _ => None,
+                },
+                is_status: true,
+            },
+        }
+    }
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/graphql/github-schema.graphql",
+    query_path = "src/graphql/get_default_branch_head.graphql",
+    response_derives = "Debug"
+)]
+pub struct GetDefaultBranchHead;
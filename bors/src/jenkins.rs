@@ -0,0 +1,122 @@
+//! Jenkins reports a repo's checks as commit statuses, and may need to be told to build the branch
+//! PRs are tested on when it doesn't build on Github's webhooks, e.g. multibranch pipelines which
+//! only discover branches by periodically scanning the repo.
+
+use crate::{config::JenkinsConfig, Result};
+use anyhow::anyhow;
+use github::Oid;
+use std::collections::HashMap;
+use tracing::info;
+
+/// Trigger the configured job to build `sha`, which has been pushed to `branch` for testing
+pub async fn trigger_build(config: &JenkinsConfig, branch: &str, sha: &Oid) -> Result<()> {
+    let url = match build_url(config, branch) {
+        Some(url) => url,
+        None => return Ok(()),
+    };
+
+    info!("Triggering Jenkins build of {} with {}", branch, url);
+
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .query(&parameters(config, branch, sha));
+    if let Some(user) = &config.user {
+        request = request.basic_auth(user, config.api_token.as_ref());
+    }
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "triggering Jenkins build {} failed: {}",
+            url,
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// The URL which triggers a build of the configured job, if there is one
+fn build_url(config: &JenkinsConfig, branch: &str) -> Option<String> {
+    let url = config.url.as_deref()?.trim_end_matches('/');
+    let job = config.job.as_deref()?.trim_matches('/');
+    let endpoint = if config.parameters.is_empty() {
+        "build"
+    } else {
+        "buildWithParameters"
+    };
+
+    Some(format!(
+        "{}/{}/{}",
+        url,
+        job.replace("{branch}", branch),
+        endpoint
+    ))
+}
+
+fn parameters(config: &JenkinsConfig, branch: &str, sha: &Oid) -> HashMap<String, String> {
+    config
+        .parameters
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace("{branch}", branch)
+                .replace("{sha}", &sha.to_string());
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn jobs() {
+        let config: JenkinsConfig = toml::from_str(
+            r#"
+            url = "https://jenkins.example.com/"
+            job = "job/bors/job/{branch}"
+
+            [[contexts]]
+            from = "continuous-integration/jenkins/branch"
+            to = "jenkins"
+
+            [[contexts]]
+            from = "jenkins/*/lint"
+            to = "lint-*"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.check_name("continuous-integration/jenkins/branch"),
+            "jenkins"
+        );
+        assert_eq!(config.check_name("jenkins/rust/lint"), "lint-rust");
+        assert_eq!(config.check_name("jenkins/lint"), "jenkins/lint");
+        assert_eq!(config.check_name("ci"), "ci");
+
+        assert_eq!(
+            build_url(&config, "auto").as_deref(),
+            Some("https://jenkins.example.com/job/bors/job/auto/build")
+        );
+
+        let config: JenkinsConfig = toml::from_str(
+            r#"
+            url = "https://jenkins.example.com"
+            job = "job/bors"
+            parameters = { BRANCH = "{branch}", COMMIT = "{sha}" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            build_url(&config, "auto").as_deref(),
+            Some("https://jenkins.example.com/job/bors/buildWithParameters")
+        );
+        let sha = Oid::from_str("fa3c2b14a4bbbb3bf29ef4d80b5ddd8f0a38f6ec");
+        let parameters = parameters(&config, "auto", &sha);
+        assert_eq!(parameters["BRANCH"], "auto");
+        assert_eq!(parameters["COMMIT"], sha.to_string());
+    }
+}
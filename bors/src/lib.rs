@@ -1,14 +1,44 @@
+mod buildkite;
+mod changelog;
+mod cla;
 mod command;
 mod config;
+mod datadog;
+mod description;
+mod error_reporting;
 mod event_processor;
+mod export;
+#[cfg(test)]
+mod fake;
+mod gate;
 mod git;
+mod github_api;
 mod graphql;
+mod jenkins;
+mod linked_issue;
+mod metrics;
+mod notify;
 mod project_board;
 mod queue;
+mod release;
+mod remote;
+mod replay;
+mod reviewers;
 mod server;
 mod service;
+mod snapshot;
 mod state;
+mod stats;
+mod store;
+mod telemetry;
+mod title;
 
 pub use anyhow::{Error, Result};
 pub use config::Config;
-pub use service::{run_serve, ServeOptions};
+pub use error_reporting::init_sentry;
+pub use remote::{run_pr, run_queue, PrCommand, QueueOptions};
+pub use service::{
+    run_git, run_replay, run_serve, run_state, GitCommand, ReplayOptions, ServeOptions,
+    StateCommand,
+};
+pub use telemetry::{init_logging, LogFormat};
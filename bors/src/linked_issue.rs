@@ -0,0 +1,59 @@
+//! Checks that PRs reference the issue they address, for orgs which need every change to be
+//! traceable to an issue
+
+use crate::config::LinkedIssueConfig;
+use regex::Regex;
+
+/// Whether a PR links an issue, either with one of Github's closing keywords, e.g. `Fixes #123`,
+/// or with a reference matching one of the configured tracker patterns
+pub fn is_linked(config: &LinkedIssueConfig, title: &str, body: &str) -> bool {
+    let closing = Regex::new(
+        r"(?i)\b(close[sd]?|fix(e[sd])?|resolve[sd]?):?\s+([\w.-]+/[\w.-]+#\d+|#\d+|https://github\.com/[\w.-]+/[\w.-]+/issues/\d+)\b",
+    )
+    .unwrap();
+
+    closing.is_match(body)
+        || config
+            .patterns()
+            .iter()
+            .any(|pattern| pattern.is_match(title) || pattern.is_match(body))
+}
+
+/// Instructions for linking an issue, given to authors whose PRs don't
+pub fn instructions(config: &LinkedIssueConfig) -> String {
+    match config.help() {
+        Some(help) => help.to_owned(),
+        None => "reference the issue it addresses in its description, e.g. `Fixes #123`".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn links() {
+        let config: LinkedIssueConfig = toml::from_str("").unwrap();
+        assert!(is_linked(&config, "", "Fixes #123"));
+        assert!(is_linked(
+            &config,
+            "",
+            "Some context.\n\ncloses: aptos-labs/bors#5"
+        ));
+        assert!(is_linked(
+            &config,
+            "",
+            "Resolves https://github.com/aptos-labs/bors/issues/5"
+        ));
+        assert!(!is_linked(&config, "", "Related to #123"));
+        assert!(!is_linked(&config, "", "Prefixes #123"));
+        assert!(!is_linked(&config, "Fix #123", ""));
+
+        let config: LinkedIssueConfig =
+            toml::from_str(r#"patterns = ["\\bBORS-\\d+\\b"]"#).unwrap();
+        assert!(is_linked(&config, "[BORS-12] Fix the thing", ""));
+        assert!(is_linked(&config, "", "See BORS-12"));
+        assert!(!is_linked(&config, "", "See BORS-"));
+        assert!(toml::from_str::<LinkedIssueConfig>(r#"patterns = ["("]"#).is_err());
+    }
+}
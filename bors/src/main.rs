@@ -1,7 +1,11 @@
-use bors::{run_serve, Config, Result, ServeOptions};
-use log::info;
+use bors::{
+    init_logging, init_sentry, run_git, run_pr, run_queue, run_replay, run_serve, run_state,
+    Config, GitCommand, LogFormat, PrCommand, QueueOptions, ReplayOptions, Result, ServeOptions,
+    StateCommand,
+};
 use std::path::PathBuf;
 use structopt::StructOpt;
+use tracing::info;
 
 #[derive(StructOpt)]
 struct Options {
@@ -9,6 +13,10 @@ struct Options {
     /// config file to use
     config: PathBuf,
 
+    #[structopt(long, default_value = "text")]
+    /// log format, either text or json
+    log_format: LogFormat,
+
     #[structopt(subcommand)]
     command: Command,
 }
@@ -18,20 +26,58 @@ enum Command {
     #[structopt(name = "serve")]
     /// Run the server
     Serve(ServeOptions),
+
+    #[structopt(name = "git")]
+    /// Manage the on-disk git repos
+    Git(GitCommand),
+
+    #[structopt(name = "state")]
+    /// Export or import the persisted state of the configured repos
+    State(StateCommand),
+
+    #[structopt(name = "replay")]
+    /// Replay recorded webhooks offline, against a stub of Github, to reproduce how they were
+    /// handled
+    Replay(ReplayOptions),
+
+    #[structopt(name = "pr")]
+    /// Act on a PR of a running instance, for when commenting on Github isn't possible
+    Pr(PrCommand),
+
+    #[structopt(name = "queue")]
+    /// Print a repo's queue, PR statuses and recent failures from a running instance
+    Queue(QueueOptions),
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Options::from_args();
 
-    // set up logging, allowing info level logging by default
-    env_logger::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    info!("bors starting");
+    // Talking to a running instance doesn't need a config
+    match &opts.command {
+        Command::Queue(options) => {
+            init_logging(opts.log_format, None)?;
+            return run_queue(options).await;
+        }
+        Command::Pr(command) => {
+            init_logging(opts.log_format, None)?;
+            return run_pr(command).await;
+        }
+        _ => {}
+    }
 
+    // Logging is set up once the config is loaded as it may configure exporting traces
     let config = Config::from_file(&opts.config)?;
+    init_logging(opts.log_format, config.tracing.as_ref())?;
+    let _sentry = config.sentry.as_ref().map(init_sentry).transpose()?;
+
+    info!("bors starting");
 
     match &opts.command {
         Command::Serve(options) => run_serve(config, options).await,
+        Command::Git(command) => run_git(config, command),
+        Command::State(command) => run_state(config, command).await,
+        Command::Replay(options) => run_replay(config, options).await,
+        Command::Queue(_) | Command::Pr(_) => unreachable!(),
     }
 }
@@ -0,0 +1,105 @@
+//! Distributions of how long PRs take to get through each stage of landing, so that teams can set
+//! and monitor SLOs for landing latency
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the buckets latencies are counted in, in seconds, from 10 seconds to a day
+const LATENCY_BUCKETS: &[u64] = &[
+    10, 30, 60, 120, 300, 600, 1200, 1800, 3600, 7200, 14400, 28800, 86400,
+];
+
+/// A histogram of latencies, exposed in the Prometheus text format
+#[derive(Debug)]
+pub struct Histogram {
+    /// Number of latencies observed in each bucket, plus one for those longer than any bucket
+    buckets: Vec<AtomicU64>,
+    sum_millis: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..=LATENCY_BUCKETS.len())
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            sum_millis: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency.as_secs_f64() <= *bound as f64)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_millis
+            .fetch_add(latency.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// The histogram's samples, labeled with `labels`, e.g. `repo="o/n"`
+    pub fn samples(&self, name: &str, labels: &str) -> String {
+        let mut samples = String::new();
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = match LATENCY_BUCKETS.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            samples.push_str(&format!(
+                "{}_bucket{{{},le=\"{}\"}} {}\n",
+                name, labels, le, cumulative
+            ));
+        }
+        samples.push_str(&format!(
+            "{}_sum{{{}}} {:.3}\n{}_count{{{}}} {}\n",
+            name,
+            labels,
+            self.sum_millis.load(Ordering::Relaxed) as f64 / 1000.0,
+            name,
+            labels,
+            cumulative
+        ));
+        samples
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_secs(5));
+        histogram.observe(Duration::from_secs(30));
+        histogram.observe(Duration::from_millis(45_500));
+        histogram.observe(Duration::from_secs(100_000));
+
+        let samples = histogram.samples("latency_seconds", "repo=\"o/n\"");
+        let lines: Vec<_> = samples.lines().collect();
+        assert_eq!(lines[0], "latency_seconds_bucket{repo=\"o/n\",le=\"10\"} 1");
+        assert_eq!(lines[1], "latency_seconds_bucket{repo=\"o/n\",le=\"30\"} 2");
+        assert_eq!(lines[2], "latency_seconds_bucket{repo=\"o/n\",le=\"60\"} 3");
+        assert_eq!(
+            lines[LATENCY_BUCKETS.len()],
+            "latency_seconds_bucket{repo=\"o/n\",le=\"+Inf\"} 4"
+        );
+        assert_eq!(
+            &lines[LATENCY_BUCKETS.len() + 1..],
+            [
+                "latency_seconds_sum{repo=\"o/n\"} 100080.500",
+                "latency_seconds_count{repo=\"o/n\"} 4"
+            ]
+        );
+    }
+}
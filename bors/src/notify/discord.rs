@@ -0,0 +1,45 @@
+//! Posts notifications to Discord through channel webhooks
+
+use super::{Notification, Notifier};
+use crate::{config::ChatConfig, event_processor::QueueEvent, state::Repo, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+pub struct Discord {
+    config: ChatConfig,
+    client: reqwest::Client,
+}
+
+impl Discord {
+    pub fn new(config: ChatConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Discord {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        let notification = match Notification::from_event(repo, event) {
+            Some(notification) if self.config.is_enabled(notification.kind) => notification,
+            _ => return Ok(()),
+        };
+        let url = match self.config.webhook_url(repo) {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let content = notification.render(self.config.message(notification.kind));
+        self.client
+            .post(url)
+            // Messages are never meant to ping anyone, even if a template happens to mention them
+            .json(&json!({ "content": content, "allowed_mentions": { "parse": [] } }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
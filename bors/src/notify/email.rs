@@ -0,0 +1,53 @@
+//! Emails notifications to the authors of PRs and a configured list of addresses, for teams where
+//! comments on Github get lost in the noise
+
+use super::{Notification, Notifier};
+use crate::{config::EmailConfig, event_processor::QueueEvent, state::Repo, Result};
+use async_trait::async_trait;
+use lettre::{message::Mailbox, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+pub struct Email {
+    config: EmailConfig,
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl Email {
+    pub fn new(config: EmailConfig) -> Result<Self> {
+        let from = config.from.parse()?;
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::from_url(&config.smtp_url)?.build();
+
+        Ok(Self {
+            config,
+            from,
+            transport,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for Email {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        let notification = match Notification::from_event(repo, event) {
+            Some(notification) if self.config.is_enabled(notification.kind) => notification,
+            _ => return Ok(()),
+        };
+        let recipients = self.config.recipients(notification.author.as_deref());
+        if recipients.is_empty() {
+            return Ok(());
+        }
+
+        let message = notification.kind.default_message();
+        let mut email = Message::builder()
+            .from(self.from.clone())
+            .subject(notification.render(&message.replace(" {url}", "")));
+        for recipient in recipients {
+            email = email.to(recipient.parse()?);
+        }
+        self.transport
+            .send(email.body(notification.render(message))?)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+//! Notifications of changes to each repo's merge queue, posted to chat services and emailed so that
+//! people hear about landings, failures and the tree closing without having to watch Github, and to
+//! webhooks so that other systems can react to them without polling
+
+mod discord;
+#[cfg(feature = "email")]
+mod email;
+mod pager;
+mod slack;
+mod webhook;
+
+use crate::{
+    config::NotificationsConfig,
+    event_processor::{QueueEvent, StuckReason},
+    state::Repo,
+    store::LandingResult,
+    Result,
+};
+#[cfg(not(feature = "email"))]
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, warn};
+
+pub use discord::Discord;
+#[cfg(feature = "email")]
+pub use email::Email;
+pub use pager::Pager;
+pub use slack::Slack;
+pub use webhook::Webhook;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationKind {
+    Landed,
+    /// A PR failed to land, either because its checks failed or because it couldn't be merged
+    Failed,
+    TimedOut,
+    /// A PR was removed from the queue without an attempt to land it finishing, e.g. by being
+    /// canceled
+    Evicted,
+    /// The merge queue was paused, closing the tree
+    Paused,
+    /// The merge queue was resumed, reopening the tree
+    Resumed,
+    /// The merge queue stopped making progress without having been paused
+    Stuck,
+}
+
+impl NotificationKind {
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            NotificationKind::Landed => "✅ {repo}#{number} landed {url}",
+            NotificationKind::Failed => "❌ {repo}#{number} failed to land, {reason} {url}",
+            NotificationKind::TimedOut => {
+                "⌛ {repo}#{number} timed out waiting on its checks {url}"
+            }
+            NotificationKind::Evicted => {
+                "🚫 {repo}#{number} was removed from the merge queue {url}"
+            }
+            NotificationKind::Paused => "⛔ {repo} is closed, its merge queue has been paused",
+            NotificationKind::Resumed => "🟢 {repo} is open, its merge queue has been resumed",
+            NotificationKind::Stuck => "⚠️ {repo}'s merge queue is stuck, {reason}",
+        }
+    }
+}
+
+/// A change to a repo's merge queue which people are notified of
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub repo: Repo,
+    /// The PR the notification is about, if any
+    pub number: Option<u64>,
+    /// The author of the PR, if known
+    pub author: Option<String>,
+    /// Why a PR failed or the queue is stuck
+    pub reason: String,
+}
+
+impl Notification {
+    /// The notification of a queue event, if it's one which people are notified of
+    pub fn from_event(repo: &Repo, event: &QueueEvent) -> Option<Self> {
+        let (kind, number, author, reason) = match event {
+            QueueEvent::Finished {
+                number,
+                author,
+                result,
+                failing_checks,
+                ..
+            } => {
+                let (kind, reason) = match result {
+                    LandingResult::Landed => (NotificationKind::Landed, String::new()),
+                    LandingResult::TimedOut => (NotificationKind::TimedOut, String::new()),
+                    LandingResult::Failed if failing_checks.is_empty() => {
+                        (NotificationKind::Failed, "its checks failed".to_owned())
+                    }
+                    LandingResult::Failed => (
+                        NotificationKind::Failed,
+                        format!("failing checks: {}", failing_checks.join(", ")),
+                    ),
+                    LandingResult::MergeFailed => (
+                        NotificationKind::Failed,
+                        "it couldn't be merged into its base branch".to_owned(),
+                    ),
+                };
+                (kind, Some(*number), author.clone(), reason)
+            }
+            QueueEvent::Dequeued {
+                number,
+                author,
+                evicted: true,
+            } => (
+                NotificationKind::Evicted,
+                Some(*number),
+                author.clone(),
+                String::new(),
+            ),
+            QueueEvent::Paused => (NotificationKind::Paused, None, None, String::new()),
+            QueueEvent::Resumed => (NotificationKind::Resumed, None, None, String::new()),
+            QueueEvent::Stuck { number, reason } => {
+                let reason = match (reason, number) {
+                    (StuckReason::TestsNotReporting, Some(number)) => format!(
+                        "#{} has been testing past its timeout without any checks reporting",
+                        number
+                    ),
+                    _ => "PRs are queued but none has started testing".to_owned(),
+                };
+                (NotificationKind::Stuck, *number, None, reason)
+            }
+            QueueEvent::Enqueued { .. }
+            | QueueEvent::TestingStarted { .. }
+            | QueueEvent::Dequeued { .. } => return None,
+        };
+
+        Some(Self {
+            kind,
+            repo: repo.clone(),
+            number,
+            author,
+            reason,
+        })
+    }
+
+    /// Fill in a message template's `{repo}`, `{number}`, `{author}`, `{url}` and `{reason}`
+    /// placeholders
+    pub fn render(&self, template: &str) -> String {
+        let repo = format!("{}/{}", self.repo.owner(), self.repo.name());
+        let (number, url) = match self.number {
+            Some(number) => (
+                number.to_string(),
+                format!("https://github.com/{}/pull/{}", repo, number),
+            ),
+            None => (String::new(), String::new()),
+        };
+
+        template
+            .replace("{repo}", &repo)
+            .replace("{number}", &number)
+            .replace("{author}", self.author.as_deref().unwrap_or_default())
+            .replace("{url}", &url)
+            .replace("{reason}", &self.reason)
+    }
+}
+
+/// Somewhere changes to merge queues are posted
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()>;
+}
+
+/// The notifiers which are configured, if any
+pub fn from_config(config: &NotificationsConfig) -> Result<Vec<Box<dyn Notifier>>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    if let Some(slack) = &config.slack {
+        notifiers.push(Box::new(Slack::new(slack.clone())));
+    }
+    if let Some(discord) = &config.discord {
+        notifiers.push(Box::new(Discord::new(discord.clone())));
+    }
+    #[cfg(feature = "email")]
+    if let Some(email) = &config.email {
+        notifiers.push(Box::new(Email::new(email.clone())?));
+    }
+    #[cfg(not(feature = "email"))]
+    if config.email.is_some() {
+        return Err(anyhow!("bors was built without the `email` feature"));
+    }
+    if let Some(paging) = &config.paging {
+        notifiers.push(Box::new(Pager::new(paging.clone())));
+    }
+    for webhook in &config.webhooks {
+        notifiers.push(Box::new(Webhook::new(webhook.clone())));
+    }
+    Ok(notifiers)
+}
+
+/// Post the events published by `repo`'s event processor to `notifiers` for as long as it publishes
+/// them
+pub async fn run(
+    repo: Repo,
+    mut events: broadcast::Receiver<QueueEvent>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(
+                    "{}/{}: dropped {} queue events which couldn't be posted in time",
+                    repo.owner(),
+                    repo.name(),
+                    skipped
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        for notifier in notifiers.iter() {
+            if let Err(e) = notifier.notify(&repo, &event).await {
+                error!("Unable to post notification: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{ChatConfig, EmailConfig};
+    use github::Oid;
+
+    #[test]
+    fn notifications() {
+        let repo = Repo::new("aptos-labs", "bors");
+        let finished = |result, failing_checks: &[&str]| QueueEvent::Finished {
+            number: 7,
+            author: Some("alice".to_owned()),
+            merge_oid: Oid::from_str("deadbeef"),
+            result,
+            failing_checks: failing_checks.iter().map(|c| c.to_string()).collect(),
+        };
+
+        let failed =
+            Notification::from_event(&repo, &finished(LandingResult::Failed, &["ci", "lint"]))
+                .unwrap();
+        assert_eq!(failed.kind, NotificationKind::Failed);
+        assert_eq!(
+            failed.render(failed.kind.default_message()),
+            "❌ aptos-labs/bors#7 failed to land, failing checks: ci, lint \
+             https://github.com/aptos-labs/bors/pull/7"
+        );
+        let merge_failed =
+            Notification::from_event(&repo, &finished(LandingResult::MergeFailed, &[])).unwrap();
+        assert_eq!(merge_failed.kind, NotificationKind::Failed);
+
+        let paused = Notification::from_event(&repo, &QueueEvent::Paused).unwrap();
+        assert_eq!(
+            paused.render(paused.kind.default_message()),
+            "⛔ aptos-labs/bors is closed, its merge queue has been paused"
+        );
+        assert_eq!(
+            Notification::from_event(&repo, &QueueEvent::Enqueued { number: 7 }),
+            None
+        );
+
+        let config: ChatConfig = toml::from_str(
+            r#"
+            webhook-url = "https://hooks.example.com/default"
+            events = ["landed", "paused"]
+
+            [repos]
+            "aptos-labs/bors" = "https://hooks.example.com/bors"
+
+            [messages]
+            landed = "{repo}#{number} is in"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.webhook_url(&repo),
+            Some("https://hooks.example.com/bors")
+        );
+        assert_eq!(
+            config.webhook_url(&Repo::new("aptos-labs", "aptos-core")),
+            Some("https://hooks.example.com/default")
+        );
+        assert!(config.is_enabled(NotificationKind::Paused));
+        assert!(!config.is_enabled(NotificationKind::Failed));
+        let landed =
+            Notification::from_event(&repo, &finished(LandingResult::Landed, &[])).unwrap();
+        assert_eq!(
+            landed.render(config.message(landed.kind)),
+            "aptos-labs/bors#7 is in"
+        );
+        assert_eq!(
+            config.message(NotificationKind::Resumed),
+            NotificationKind::Resumed.default_message()
+        );
+    }
+
+    #[test]
+    fn evictions() {
+        let repo = Repo::new("aptos-labs", "bors");
+        let dequeued = |evicted| QueueEvent::Dequeued {
+            number: 7,
+            author: Some("Alice".to_owned()),
+            evicted,
+        };
+        let evicted = Notification::from_event(&repo, &dequeued(true)).unwrap();
+        assert_eq!(evicted.kind, NotificationKind::Evicted);
+        assert_eq!(evicted.render("{author}"), "Alice");
+        // Those which left the queue because their attempt finished are notified of as it finished
+        assert_eq!(Notification::from_event(&repo, &dequeued(false)), None);
+
+        let config: EmailConfig = toml::from_str(
+            r#"
+            smtp-url = "smtp://localhost"
+            from = "bors <bors@example.com>"
+            recipients = ["releases@example.com"]
+
+            [authors]
+            alice = "alice@example.com"
+            "#,
+        )
+        .unwrap();
+        assert!(config.is_enabled(NotificationKind::Evicted));
+        assert!(!config.is_enabled(NotificationKind::Landed));
+        assert_eq!(
+            config.recipients(evicted.author.as_deref()),
+            ["alice@example.com", "releases@example.com"]
+        );
+        assert_eq!(config.recipients(Some("bob")), ["releases@example.com"]);
+    }
+}
@@ -0,0 +1,260 @@
+//! Pages on-call through PagerDuty or Opsgenie during queue emergencies: when a merge queue gets
+//! stuck, or when enough attempts to land PRs in a row fail that the base branch is probably
+//! broken. Pages are resolved once the queue is moving again or a PR lands.
+
+use super::Notifier;
+use crate::{
+    config::PagingConfig,
+    event_processor::{QueueEvent, StuckReason},
+    state::Repo,
+    store::LandingResult,
+    Result,
+};
+use async_trait::async_trait;
+use reqwest::header::AUTHORIZATION;
+use serde_json::json;
+use std::{collections::HashMap, sync::Mutex};
+
+/// What is paged about, each of which has at most one open page per repo
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Incident {
+    Stuck,
+    Failing,
+}
+
+impl Incident {
+    /// Key identifying the incident's page, so that it is only paged once and can be resolved
+    fn key(&self, repo: &Repo) -> String {
+        let incident = match self {
+            Incident::Stuck => "stuck",
+            Incident::Failing => "failing",
+        };
+        format!("bors/{}/{}/{}", repo.owner(), repo.name(), incident)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Page {
+    Trigger { incident: Incident, summary: String },
+    Resolve(Incident),
+}
+
+/// The emergencies a repo is in
+#[derive(Debug, Default)]
+struct Emergencies {
+    consecutive_failures: u32,
+    stuck: bool,
+}
+
+pub struct Pager {
+    config: PagingConfig,
+    client: reqwest::Client,
+    emergencies: Mutex<HashMap<Repo, Emergencies>>,
+}
+
+impl Pager {
+    pub fn new(config: PagingConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            emergencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The pages to trigger or resolve because of an event
+    fn pages(&self, repo: &Repo, event: &QueueEvent) -> Vec<Page> {
+        let mut emergencies = self.emergencies.lock().unwrap();
+        let emergencies = emergencies.entry(repo.clone()).or_default();
+        let mut pages = Vec::new();
+
+        // The queue is moving again
+        if matches!(
+            event,
+            QueueEvent::TestingStarted { .. } | QueueEvent::Finished { .. }
+        ) && emergencies.stuck
+        {
+            emergencies.stuck = false;
+            pages.push(Page::Resolve(Incident::Stuck));
+        }
+
+        match event {
+            QueueEvent::Stuck { number, reason } if !emergencies.stuck => {
+                emergencies.stuck = true;
+                let reason = match (reason, number) {
+                    (StuckReason::TestsNotReporting, Some(number)) => format!(
+                        "#{} has been testing past its timeout without any checks reporting",
+                        number
+                    ),
+                    _ => "PRs are queued but none has started testing".to_owned(),
+                };
+                pages.push(Page::Trigger {
+                    incident: Incident::Stuck,
+                    summary: format!(
+                        "{}/{}: merge queue is stuck, {}",
+                        repo.owner(),
+                        repo.name(),
+                        reason
+                    ),
+                });
+            }
+            QueueEvent::Finished {
+                result: LandingResult::Landed,
+                ..
+            } => {
+                if emergencies.consecutive_failures >= self.config.consecutive_failures() {
+                    pages.push(Page::Resolve(Incident::Failing));
+                }
+                emergencies.consecutive_failures = 0;
+            }
+            QueueEvent::Finished { .. } => {
+                emergencies.consecutive_failures += 1;
+                if emergencies.consecutive_failures == self.config.consecutive_failures() {
+                    pages.push(Page::Trigger {
+                        incident: Incident::Failing,
+                        summary: format!(
+                            "{}/{}: the last {} attempts to land PRs failed, the base branch may \
+                             be broken",
+                            repo.owner(),
+                            repo.name(),
+                            emergencies.consecutive_failures
+                        ),
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        pages
+    }
+
+    async fn pagerduty(&self, routing_key: &str, repo: &Repo, page: &Page) -> Result<()> {
+        let body = match page {
+            Page::Trigger { incident, summary } => json!({
+                "routing_key": routing_key,
+                "event_action": "trigger",
+                "dedup_key": incident.key(repo),
+                "payload": {
+                    "summary": summary,
+                    "source": format!("{}/{}", repo.owner(), repo.name()),
+                    "severity": "critical",
+                },
+            }),
+            Page::Resolve(incident) => json!({
+                "routing_key": routing_key,
+                "event_action": "resolve",
+                "dedup_key": incident.key(repo),
+            }),
+        };
+
+        self.client
+            .post("https://events.pagerduty.com/v2/enqueue")
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn opsgenie(&self, api_key: &str, repo: &Repo, page: &Page) -> Result<()> {
+        let alerts = format!("{}/v2/alerts", self.config.opsgenie_url());
+        let request = match page {
+            Page::Trigger { incident, summary } => self.client.post(&alerts).json(&json!({
+                "message": summary,
+                "alias": incident.key(repo),
+                "source": "bors",
+                "priority": "P1",
+            })),
+            Page::Resolve(incident) => self
+                .client
+                .post(format!("{}/{}/close", alerts, incident.key(repo)))
+                .query(&[("identifierType", "alias")])
+                .json(&json!({ "source": "bors" })),
+        };
+
+        request
+            .header(AUTHORIZATION, format!("GenieKey {}", api_key))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Notifier for Pager {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        for page in self.pages(repo, event) {
+            if let Some(routing_key) = &self.config.pagerduty_routing_key {
+                self.pagerduty(routing_key, repo, &page).await?;
+            }
+            if let Some(api_key) = &self.config.opsgenie_api_key {
+                self.opsgenie(api_key, repo, &page).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use github::Oid;
+
+    #[test]
+    fn emergencies() {
+        let pager = Pager::new(toml::from_str("consecutive-failures = 2").unwrap());
+        let repo = Repo::new("aptos-labs", "bors");
+        let finished = |result| QueueEvent::Finished {
+            number: 1,
+            author: None,
+            merge_oid: Oid::from_str("deadbeef"),
+            result,
+            failing_checks: Vec::new(),
+        };
+        let triggered = |pages: Vec<Page>| -> Vec<Incident> {
+            pages
+                .into_iter()
+                .filter_map(|page| match page {
+                    Page::Trigger { incident, .. } => Some(incident),
+                    Page::Resolve(_) => None,
+                })
+                .collect()
+        };
+
+        // Only paged once the threshold is reached, and not again while still failing
+        assert!(pager
+            .pages(&repo, &finished(LandingResult::Failed))
+            .is_empty());
+        assert_eq!(
+            triggered(pager.pages(&repo, &finished(LandingResult::TimedOut))),
+            [Incident::Failing]
+        );
+        assert!(pager
+            .pages(&repo, &finished(LandingResult::Failed))
+            .is_empty());
+        // Other repos are counted separately
+        let other = Repo::new("aptos-labs", "aptos-core");
+        assert!(pager
+            .pages(&other, &finished(LandingResult::Failed))
+            .is_empty());
+
+        let stuck = QueueEvent::Stuck {
+            number: None,
+            reason: StuckReason::NothingStarted,
+        };
+        assert_eq!(triggered(pager.pages(&repo, &stuck)), [Incident::Stuck]);
+        assert!(pager.pages(&repo, &stuck).is_empty());
+
+        assert_eq!(
+            pager.pages(&repo, &finished(LandingResult::Landed)),
+            [
+                Page::Resolve(Incident::Stuck),
+                Page::Resolve(Incident::Failing)
+            ]
+        );
+        assert!(pager
+            .pages(&repo, &finished(LandingResult::Landed))
+            .is_empty());
+    }
+}
@@ -0,0 +1,44 @@
+//! Posts notifications to Slack through incoming webhooks
+
+use super::{Notification, Notifier};
+use crate::{config::ChatConfig, event_processor::QueueEvent, state::Repo, Result};
+use async_trait::async_trait;
+use serde_json::json;
+
+pub struct Slack {
+    config: ChatConfig,
+    client: reqwest::Client,
+}
+
+impl Slack {
+    pub fn new(config: ChatConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Slack {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        let notification = match Notification::from_event(repo, event) {
+            Some(notification) if self.config.is_enabled(notification.kind) => notification,
+            _ => return Ok(()),
+        };
+        let url = match self.config.webhook_url(repo) {
+            Some(url) => url,
+            None => return Ok(()),
+        };
+
+        let text = notification.render(self.config.message(notification.kind));
+        self.client
+            .post(url)
+            .json(&json!({ "text": text }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Posts every queue event as JSON to a configured URL, signed so that the receiver can tell the
+//! payload came from bors
+
+use super::Notifier;
+use crate::{config::WebhookConfig, event_processor::QueueEvent, state::Repo, Result};
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::Value;
+
+/// Header carrying the payload's HMAC-SHA256 signature, as `sha256=<hex digest>`
+const SIGNATURE_HEADER: &str = "X-Bors-Signature-256";
+/// Header carrying the name of the queue event, e.g. `finished`
+const EVENT_HEADER: &str = "X-Bors-Event";
+
+pub struct Webhook {
+    config: WebhookConfig,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for Webhook {
+    async fn notify(&self, repo: &Repo, event: &QueueEvent) -> Result<()> {
+        let (name, payload) = payload(repo, event)?;
+        if !self.config.covers(repo) || !self.config.is_enabled(&name) {
+            return Ok(());
+        }
+
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &name);
+        if let Some(secret) = &self.config.secret {
+            request = request.header(SIGNATURE_HEADER, signature(&payload, secret));
+        }
+        request.body(payload).send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// The name of a queue event and its payload, which is the event as served by `/api/events`: the
+/// `QueueEvent` as JSON along with the `<owner>/<name>` of its repo
+fn payload(repo: &Repo, event: &QueueEvent) -> Result<(String, Vec<u8>)> {
+    let mut payload = serde_json::to_value(event)?;
+    let name = payload["event"].as_str().unwrap_or_default().to_owned();
+    if let Value::Object(fields) = &mut payload {
+        fields.insert(
+            "repo".to_owned(),
+            Value::String(format!("{}/{}", repo.owner(), repo.name())),
+        );
+    }
+
+    Ok((name, serde_json::to_vec(&payload)?))
+}
+
+fn signature(payload: &[u8], secret: &str) -> String {
+    let hash = hmac_sha256::HMAC::mac(payload, secret.as_bytes());
+    format!("sha256={}", hex::encode(hash))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signed_payload() {
+        let repo = Repo::new("aptos-labs", "bors");
+        let (name, payload) = payload(&repo, &QueueEvent::Enqueued { number: 7 }).unwrap();
+        assert_eq!(name, "enqueued");
+        let payload: Value = serde_json::from_slice(&payload).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({ "repo": "aptos-labs/bors", "event": "enqueued", "number": 7 })
+        );
+
+        // The same signature Github would send for the payload
+        assert_eq!(
+            signature(b"{}", "secret"),
+            format!(
+                "sha256={}",
+                hex::encode(hmac_sha256::HMAC::mac(b"{}", b"secret"))
+            )
+        );
+    }
+}
@@ -1,14 +1,11 @@
 use crate::event_processor::ActivePullRequestContext;
 use crate::{
     config::RepoConfig,
-    graphql::GithubClient,
+    github_api::GithubApi,
     state::{PullRequestState, Status},
     Result,
 };
-use github::{
-    client::{ListProjectCardsOptions, PaginationOptions},
-    Project, ProjectCard, ProjectColumn,
-};
+use github::{Project, ProjectCard, ProjectColumn};
 use std::collections::HashMap;
 
 const PROJECT_BOARD_NAME: &str = "bors";
@@ -33,7 +30,7 @@ impl ProjectBoard {
 
     pub async fn move_pr_to_status_column(
         &self,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         pull: &PullRequestState,
     ) -> Result<()> {
         if let Some(card_id) = pull.project_card_id {
@@ -51,7 +48,7 @@ impl ProjectBoard {
 
     pub async fn create_card(
         &self,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         pull: &mut PullRequestState,
     ) -> Result<()> {
         assert!(pull.project_card_id.is_none());
@@ -62,10 +59,8 @@ impl ProjectBoard {
             content_type: Some("PullRequest".into()),
         };
         let card = github
-            .projects()
-            .create_card(self.review_column.id, &request)
-            .await?
-            .into_inner();
+            .create_project_card(self.review_column.id, &request)
+            .await?;
 
         pull.project_card_id = Some(card.id);
 
@@ -74,18 +69,18 @@ impl ProjectBoard {
 
     pub async fn delete_card(
         &self,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         pull: &mut PullRequestState,
     ) -> Result<()> {
         if let Some(card_id) = pull.project_card_id.take() {
-            github.projects().delete_card(card_id).await?;
+            github.delete_project_card(card_id).await?;
         }
 
         Ok(())
     }
 
     pub async fn synchronize_or_init(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         config: &RepoConfig,
         open_pulls: &mut HashMap<u64, PullRequestState>,
     ) -> Result<Self> {
@@ -114,16 +109,11 @@ impl ProjectBoard {
     }
 
     async fn create_or_get_project_board(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         config: &RepoConfig,
     ) -> Result<github::Project> {
         let mut project_board = None;
-        for project in github
-            .projects()
-            .list_for_repo(config.owner(), config.name(), None)
-            .await?
-            .into_inner()
-        {
+        for project in github.list_projects(config.owner(), config.name()).await? {
             if project.name == PROJECT_BOARD_NAME {
                 project_board = Some(project);
                 break;
@@ -134,17 +124,15 @@ impl ProjectBoard {
             board
         } else {
             github
-                .projects()
-                .create_for_repo(config.owner(), config.name(), "bors", None)
+                .create_project(config.owner(), config.name(), "bors")
                 .await?
-                .into_inner()
         };
 
         Ok(project_board)
     }
 
     async fn create_or_get_columns(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         project_id: u64,
     ) -> Result<(
         github::ProjectColumn,
@@ -157,12 +145,7 @@ impl ProjectBoard {
         let mut testing_column = None;
         let mut canary_column = None;
 
-        for column in github
-            .projects()
-            .list_columns(project_id, None)
-            .await?
-            .into_inner()
-        {
+        for column in github.list_project_columns(project_id).await? {
             match column.name.as_ref() {
                 REVIEW_COLUMN_NAME => review_column = Some(column),
                 QUEUED_COLUMN_NAME => queued_column = Some(column),
@@ -170,7 +153,7 @@ impl ProjectBoard {
                 CANARY_COLUMN_NAME => canary_column = Some(column),
                 // Delete columns which don't match
                 _ => {
-                    github.projects().delete_column(column.id).await?;
+                    github.delete_project_column(column.id).await?;
                 }
             }
         }
@@ -194,23 +177,21 @@ impl ProjectBoard {
         column: Option<github::ProjectColumn>,
         column_name: &str,
         project_id: u64,
-        github: &GithubClient,
+        github: &dyn GithubApi,
     ) -> Result<github::ProjectColumn> {
         let column = if let Some(column) = column {
             column
         } else {
             github
-                .projects()
-                .create_column(project_id, column_name)
+                .create_project_column(project_id, column_name)
                 .await?
-                .into_inner()
         };
 
         Ok(column)
     }
 
     async fn init_project_cards(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         open_pulls: &mut HashMap<u64, PullRequestState>,
         review_column_id: u64,
         queued_column_id: u64,
@@ -249,10 +230,8 @@ impl ProjectBoard {
                     content_type: Some("PullRequest".into()),
                 };
                 let card = github
-                    .projects()
-                    .create_card(review_column_id, &request)
-                    .await?
-                    .into_inner();
+                    .create_project_card(review_column_id, &request)
+                    .await?;
 
                 pull.project_card_id = Some(card.id);
             }
@@ -265,44 +244,16 @@ impl ProjectBoard {
         &self,
         ctx: &ActivePullRequestContext<'_>,
     ) -> Result<Vec<ProjectCard>> {
-        Self::list_cards(ctx.github(), self.canary_column.id).await
-    }
-
-    pub async fn list_cards(github: &GithubClient, column_id: u64) -> Result<Vec<ProjectCard>> {
-        let mut list_options = ListProjectCardsOptions {
-            archived_state: None,
-            pagination_options: PaginationOptions {
-                page: None,
-                per_page: Some(100),
-            },
-        };
-        let mut has_next_page = true;
-        let mut cards = Vec::new();
-
-        while has_next_page {
-            let response = github
-                .projects()
-                .list_cards(column_id, Some(&list_options))
-                .await?;
-
-            list_options.pagination_options.page = response.pagination().next_page;
-            if list_options.pagination_options.page.is_none() {
-                has_next_page = false;
-            }
-
-            cards.extend(response.into_inner())
-        }
-
-        Ok(cards)
+        ctx.github().list_project_cards(self.canary_column.id).await
     }
 
     async fn assign_or_delete_cards_in_column(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         open_pulls: &mut HashMap<u64, PullRequestState>,
         column_id: u64,
         dst_column: Option<u64>,
     ) -> Result<()> {
-        for card in Self::list_cards(&github, column_id).await? {
+        for card in github.list_project_cards(column_id).await? {
             match card.issue_number().and_then(|n| open_pulls.get_mut(&n)) {
                 Some(pull) => {
                     pull.project_card_id = Some(card.id);
@@ -311,7 +262,7 @@ impl ProjectBoard {
                     }
                 }
                 None => {
-                    github.projects().delete_card(card.id).await.unwrap();
+                    github.delete_project_card(card.id).await.unwrap();
                 }
             }
         }
@@ -319,7 +270,7 @@ impl ProjectBoard {
     }
 
     async fn move_card_to_column(
-        github: &GithubClient,
+        github: &dyn GithubApi,
         card_id: u64,
         column_id: u64,
     ) -> Result<()> {
@@ -327,7 +278,7 @@ impl ProjectBoard {
             position: "bottom".into(),
             column_id: Some(column_id),
         };
-        github.projects().move_card(card_id, &request).await?;
+        github.move_project_card(card_id, &request).await?;
         Ok(())
     }
 }
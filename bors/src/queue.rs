@@ -1,14 +1,36 @@
 use crate::{
+    changelog,
     config::RepoConfig,
-    git::GitRepository,
-    graphql::GithubClient,
+    git::GitApi,
+    github_api::{CommitCheck, GithubApi},
+    jenkins,
     project_board::ProjectBoard,
-    state::{Priority, PullRequestState, Status, StatusType, TestSuiteResult},
+    release,
+    state::{
+        CanaryResult, CanaryRun, Priority, PullRequestState, Status, StatusType, TestResult,
+        TestSuiteResult,
+    },
+    store::{LandingAttempt, LandingResult, Store},
     Result,
 };
 use github::Oid;
-use log::info;
-use std::{collections::HashMap, time::Instant};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+use tracing::{error, info, instrument, warn};
+
+/// The outcome of creating a merge commit for a PR and pushing it to be tested
+enum Merge {
+    /// The merge commit was pushed and is ready to be tested
+    Pushed(Oid),
+    /// The PR can't be merged and goes back to being reviewed
+    Failed,
+    /// Something else pushed to the branch, so the merge commit was abandoned and needs to be
+    /// rebuilt from fresh state
+    Raced,
+}
 
 #[derive(Debug, PartialEq, PartialOrd, Eq, Ord)]
 pub struct QueueEntry {
@@ -17,7 +39,7 @@ pub struct QueueEntry {
     /// Indicates the priority of the PR
     priority: Priority,
 
-    timestamp: Option<Instant>,
+    timestamp: Option<SystemTime>,
 
     number: u64,
 }
@@ -27,7 +49,7 @@ impl QueueEntry {
         number: u64,
         status: StatusType,
         priority: Priority,
-        timestamp: Option<Instant>,
+        timestamp: Option<SystemTime>,
     ) -> Self {
         Self {
             number,
@@ -38,35 +60,98 @@ impl QueueEntry {
     }
 }
 
-#[derive(Clone, Debug)]
+/// Why testing new PRs is paused, which is tracked separately for each reason so that one
+/// reason going away doesn't resume a queue which is still paused for another
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PauseReasons {
+    /// Whether the queue has been paused by an admin
+    admin: bool,
+    /// Whether the tree has been closed by the repo's tracking issue
+    tree_closed: bool,
+    /// The latest commit on the repo's default branch which checks have reported on, along with
+    /// those of `checks` which are failing on it
+    base_failures: Option<(Oid, HashSet<String>)>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct MergeQueue {
     /// The current head of the queue, the PR that is currently being tested
     head: Option<u64>,
+    /// Why testing new PRs is paused, if it is
+    paused: PauseReasons,
 }
 
 impl MergeQueue {
     pub fn new() -> Self {
-        Self { head: None }
+        Self {
+            head: None,
+            paused: PauseReasons::default(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.admin || self.paused.tree_closed || self.is_base_red()
     }
 
-    pub fn reset(&mut self) {
-        self.head = None;
+    /// Pause or resume the queue on behalf of an admin. The queue stays paused while the tree is
+    /// closed or the base branch is red.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused.admin = paused;
     }
 
+    pub fn is_tree_closed(&self) -> bool {
+        self.paused.tree_closed
+    }
+
+    pub fn set_tree_closed(&mut self, closed: bool) {
+        self.paused.tree_closed = closed;
+    }
+
+    /// Whether any of `checks` is failing on the repo's default branch
+    pub fn is_base_red(&self) -> bool {
+        self.paused
+            .base_failures
+            .as_ref()
+            .is_some_and(|(_, failures)| !failures.is_empty())
+    }
+
+    /// Record the result of one of `checks` on the commit `sha` at the head of the repo's default
+    /// branch, forgetting the results on any earlier commit
+    pub fn add_base_result(&mut self, sha: &Oid, name: &str, conclusion: github::Conclusion) {
+        let failures = match &mut self.paused.base_failures {
+            Some((oid, failures)) if oid == sha => failures,
+            base_failures => &mut base_failures.insert((sha.clone(), HashSet::new())).1,
+        };
+        if matches!(conclusion, github::Conclusion::Success) {
+            failures.remove(name);
+        } else {
+            failures.insert(name.to_owned());
+        }
+    }
+
+    /// Replace what's known about the repo's default branch with the `failures` of `checks` on
+    /// the commit `sha` at its head
+    pub fn set_base_failures(&mut self, sha: Oid, failures: HashSet<String>) {
+        self.paused.base_failures = Some((sha, failures));
+    }
+
+    /// Merge the PR at the head of the queue into its base branch, returning `false` if the base
+    /// branch couldn't be updated
+    #[instrument(skip_all, fields(pr = self.head))]
     async fn land_pr(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         project_board: Option<&ProjectBoard>,
         pulls: &mut HashMap<u64, PullRequestState>,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let head = self
             .head
             .take()
             .expect("land_pr should only be called when there is a PR to land");
 
-        let mut pull = pulls.get_mut(&head).expect("PR should exist");
+        let pull = pulls.get_mut(&head).expect("PR should exist");
         let merge_oid = match &pull.status {
             Status::Testing { merge_oid, .. } => merge_oid,
             // XXX Fix this
@@ -74,6 +159,7 @@ impl MergeQueue {
         };
 
         // Attempt to update the PR in-place
+        let mut updated_in_place = false;
         if let Some(head_repo) = pull.head_repo.as_ref() {
             // Before 'merging' the PR into the base ref we first update the PR with the rebased
             // commits that are to be imminently merged using the `maintainer_can_modify` feature.
@@ -82,10 +168,10 @@ impl MergeQueue {
             if config.maintainer_mode() {
                 if repo
                     .push_to_remote(
-                        &head_repo,
+                        head_repo,
                         &pull.head_ref_name,
                         &pull.head_ref_oid,
-                        &merge_oid,
+                        merge_oid,
                     )
                     .is_err()
                 {
@@ -101,10 +187,11 @@ impl MergeQueue {
                     is enabled, allowing Bors to update the PR in-place before merging and convince Github to mark the PR as \"Merged\".";
 
                     github
-                        .issues()
-                        .create_comment(config.owner(), config.name(), pull.number, &comment)
+                        .create_comment(config.owner(), config.name(), pull.number, comment)
                         .await?;
                 } else {
+                    updated_in_place = true;
+
                     // TODO we probably shouldn't spin waiting here. It might be better to wait till we
                     // get a webhook back from Github that the PR was updated
                     let r = format!("refs/pull/{}/head", pull.number);
@@ -118,10 +205,9 @@ impl MergeQueue {
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
                         let github = github
-                            .pulls()
-                            .get(config.owner(), config.name(), pull.number)
+                            .get_pull(config.owner(), config.name(), pull.number)
                             .await
-                            .map(|p| p.into_inner().head.sha);
+                            .map(|p| p.head.sha);
                         let git = repo.fetch_ref(&r);
 
                         match (git, github) {
@@ -142,12 +228,11 @@ impl MergeQueue {
 
         // Finally 'merge' the PR by updating the 'base_ref' with `merge_oid`
         if let Err(e) = github
-            .git()
             .update_ref(
                 config.owner(),
                 config.name(),
                 &format!("heads/{}", pull.base_ref_name),
-                &merge_oid,
+                merge_oid,
                 false,
             )
             .await
@@ -161,39 +246,110 @@ impl MergeQueue {
             );
 
             github
-                .issues()
                 .create_comment(config.owner(), config.name(), pull.number, &comment)
                 .await?;
 
-            return Ok(());
+            return Ok(false);
+        }
+
+        // Tag the release once its release PR lands
+        if let Some(tag) = release::tag(config, pull) {
+            if let Err(e) = github
+                .create_ref(
+                    config.owner(),
+                    config.name(),
+                    &format!("refs/tags/{}", tag),
+                    merge_oid,
+                )
+                .await
+            {
+                warn!("Unable to tag {} with {}: {:?}", merge_oid, tag, e);
+                let comment = format!(
+                    ":warning: Landed, but unable to tag the release {}:\n```\n{:#?}\n```",
+                    tag, e
+                );
+                github
+                    .create_comment(config.owner(), config.name(), pull.number, &comment)
+                    .await?;
+            }
         }
 
         if let Some(board) = project_board {
-            board.delete_card(github, &mut pull).await?;
+            board.delete_card(github, pull).await?;
         }
 
         // Actually remove the PR
-        pulls.remove(&head);
+        let pull = pulls.remove(&head).expect("PR should exist");
+        Self::clean_up_landed(config, github, &pull, updated_in_place, pulls).await?;
+
+        Ok(true)
+    }
+
+    /// Close a PR which landed without Github marking it as merged, since it wasn't updated
+    /// in-place with the commits which landed, so that it doesn't look like it's still open for
+    /// landing. Then delete its branch, if configured to and nothing else needs it.
+    async fn clean_up_landed(
+        config: &RepoConfig,
+        github: &dyn GithubApi,
+        pull: &PullRequestState,
+        updated_in_place: bool,
+        pulls: &HashMap<u64, PullRequestState>,
+    ) -> Result<()> {
+        // The summary of the landing links the commit which landed it
+        if !updated_in_place {
+            if let Err(e) = github
+                .close_pull(config.owner(), config.name(), pull.number)
+                .await
+            {
+                warn!("Unable to close landed pr #{}: {:?}", pull.number, e);
+            }
+        }
+
+        let branch = &pull.head_ref_name;
+        let in_use = pulls.values().any(|other| {
+            &other.base_ref_name == branch
+                || (&other.head_ref_name == branch && other.head_repo == pull.head_repo)
+        });
+        if config.delete_landed_branches()
+            && pull.head_repo.as_ref() == Some(config.repo())
+            && !in_use
+        {
+            info!(
+                "Deleting the branch '{}' of landed pr #{}",
+                branch, pull.number
+            );
+            // Github refuses to delete protected branches
+            if let Err(e) = github
+                .delete_ref(config.owner(), config.name(), &format!("heads/{}", branch))
+                .await
+            {
+                warn!("Unable to delete branch '{}': {:?}", branch, e);
+            }
+        }
 
         Ok(())
     }
 
+    /// Make progress on the queue, returning the attempt to land a PR which finished while doing
+    /// so, if any
     pub async fn process_queue(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         project_board: Option<&ProjectBoard>,
         pulls: &mut HashMap<u64, PullRequestState>,
-    ) -> Result<()> {
+        store: &dyn Store,
+    ) -> Result<Option<LandingAttempt>> {
         // Ensure that only ever 1 PR is in "Testing" at a time
         assert!(pulls.iter().filter(|(_n, p)| p.status.is_testing()).count() <= 1);
 
         // Process the PR at the head of the queue
-        self.process_head(config, github, repo, project_board, pulls)
+        let finished = self
+            .process_head(config, github, repo, project_board, pulls, store)
             .await?;
 
-        if self.head.is_none() {
+        if self.head.is_none() && !self.is_paused() {
             self.process_next_head(config, github, repo, project_board, pulls)
                 .await?;
         }
@@ -201,19 +357,20 @@ impl MergeQueue {
         self.process_canaries(config, github, repo, project_board, pulls)
             .await?;
 
-        Ok(())
+        Ok(finished)
     }
 
     async fn process_canaries(
         &self,
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         project_board: Option<&ProjectBoard>,
         pulls: &mut HashMap<u64, PullRequestState>,
     ) -> Result<()> {
         for (_, pull) in pulls.iter_mut().filter(|(_n, p)| p.status.is_canary()) {
-            let (merge_oid, test_suite_result) = match &pull.status {
+            let (merge_oid, tests_started_at, test_results, test_suite_result) = match &pull.status
+            {
                 Status::Canary {
                     merge_oid,
                     tests_started_at,
@@ -221,71 +378,107 @@ impl MergeQueue {
                 } => {
                     let test_suite_result =
                         TestSuiteResult::new(*tests_started_at, test_results, config);
-                    (merge_oid, test_suite_result)
+                    (
+                        merge_oid.clone(),
+                        *tests_started_at,
+                        test_results.clone(),
+                        test_suite_result,
+                    )
                 }
                 _ => continue,
             };
 
             Self::update_github_based_on_test_suite_results(
-                &pull,
+                pull,
                 &test_suite_result,
-                merge_oid,
+                &merge_oid,
                 config,
                 github,
             )
             .await?;
 
-            match test_suite_result {
-                TestSuiteResult::Failed { .. } | TestSuiteResult::TimedOut => {
-                    pull.update_status(Status::InReview, config, github, project_board)
-                        .await?;
-                }
+            let result = match test_suite_result {
+                TestSuiteResult::Failed { .. } => CanaryResult::Failed,
+                TestSuiteResult::TimedOut => CanaryResult::TimedOut,
+                TestSuiteResult::Passed => CanaryResult::Passed,
+                TestSuiteResult::Pending => continue,
+            };
 
-                TestSuiteResult::Passed => {
-                    pull.update_status(Status::InReview, config, github, project_board)
-                        .await?;
-                    github
-                        .issues()
-                        .create_comment(
-                            config.owner(),
-                            config.name(),
-                            pull.number,
-                            ":sunny: Canary successful",
-                        )
-                        .await?;
-                }
+            pull.update_status(Status::InReview, config, github, project_board)
+                .await?;
+            if result == CanaryResult::Passed {
+                github
+                    .create_comment(
+                        config.owner(),
+                        config.name(),
+                        pull.number,
+                        ":sunny: Canary successful",
+                    )
+                    .await?;
+            }
 
-                TestSuiteResult::Pending => {}
+            let run = CanaryRun {
+                merge_oid,
+                head_ref_oid: pull.head_ref_oid.clone(),
+                base_ref_name: pull.base_ref_name.clone(),
+                started_at: tests_started_at,
+                finished_at: SystemTime::now(),
+                result,
+                test_results,
+            };
+            if let Some(previous) = pull.canary_runs.last() {
+                github
+                    .create_comment(
+                        config.owner(),
+                        config.name(),
+                        pull.number,
+                        &run.compare(previous),
+                    )
+                    .await?;
             }
+            pull.record_canary_run(run);
         }
 
         for (_, pull) in pulls.iter_mut().filter(|(_n, p)| p.canary_requested) {
             pull.canary_requested = false;
 
-            if let Some(merge_oid) =
-                Self::create_merge_and_update_github(config, github, repo, pull, "canary").await?
+            match Self::create_merge_and_update_github(
+                config,
+                github,
+                repo,
+                pull,
+                config.canary_branch(),
+            )
+            .await?
             {
-                pull.update_status(Status::canary(merge_oid), config, github, project_board)
-                    .await?;
+                Merge::Pushed(merge_oid) => {
+                    pull.update_status(Status::canary(merge_oid), config, github, project_board)
+                        .await?;
+                }
+                Merge::Failed => {}
+                // Try again once the branch has settled
+                Merge::Raced => pull.canary_requested = true,
             }
         }
 
         Ok(())
     }
 
+    #[instrument(skip_all, fields(pr = self.head))]
     async fn process_head(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         project_board: Option<&ProjectBoard>,
         pulls: &mut HashMap<u64, PullRequestState>,
-    ) -> Result<()> {
+        store: &dyn Store,
+    ) -> Result<Option<LandingAttempt>> {
         // Early return if there isn't anything at the head of the Queue currently being tested
         let head = if let Some(head) = self.head {
             head
         } else {
-            return Ok(());
+            return Ok(None);
         };
 
         // Early return if the PR that was currently being tested was closed for some reason
@@ -293,55 +486,149 @@ impl MergeQueue {
             Some(pull) => pull,
             None => {
                 self.head = None;
-                return Ok(());
+                return Ok(None);
             }
         };
 
         // Early return if the PR that was currently being tested had its state changed from
         // `Status::Testing`, e.g. if the land was canceled.
-        let (merge_oid, test_suite_result) = match &pull.status {
+        let (
+            merge_oid,
+            queued_at,
+            tests_started_at,
+            passed_checks,
+            ci_runs,
+            advisory_failures,
+            test_suite_result,
+        ) = match &pull.status {
             Status::Testing {
                 merge_oid,
+                queued_at,
                 tests_started_at,
                 test_results,
             } => {
                 let test_suite_result =
                     TestSuiteResult::new(*tests_started_at, test_results, config);
-                (merge_oid, test_suite_result)
+                let passed_checks = test_results
+                    .iter()
+                    .filter(|(_, result)| result.passed)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                let mut ci_runs: Vec<(String, String)> = test_results
+                    .iter()
+                    .map(|(name, result)| (name.clone(), result.details_url.clone()))
+                    .collect();
+                ci_runs.sort();
+                // Advisory checks which haven't reported by the time the PR lands aren't
+                // waited on
+                let mut advisory_failures: Vec<(String, String)> = test_results
+                    .iter()
+                    .filter(|(name, result)| {
+                        !result.passed && config.advisory_checks().any(|check| check == *name)
+                    })
+                    .map(|(name, result)| (name.clone(), result.details_url.clone()))
+                    .collect();
+                advisory_failures.sort();
+                (
+                    merge_oid.clone(),
+                    *queued_at,
+                    *tests_started_at,
+                    passed_checks,
+                    ci_runs,
+                    advisory_failures,
+                    test_suite_result,
+                )
             }
             _ => {
                 self.head = None;
-                return Ok(());
+                return Ok(None);
             }
         };
 
+        let base_ref = pull.base_ref_name.clone();
         Self::update_github_based_on_test_suite_results(
-            &pull,
+            pull,
             &test_suite_result,
-            merge_oid,
+            &merge_oid,
             config,
             github,
         )
         .await?;
 
-        match test_suite_result {
-            TestSuiteResult::Failed { .. } | TestSuiteResult::TimedOut => {
+        let (result, failing_checks) = match test_suite_result {
+            TestSuiteResult::Failed { name, .. } => {
                 // Remove the PR from the Queue
                 // XXX Maybe mark as "Failed"?
                 pull.update_status(Status::InReview, config, github, project_board)
                     .await?;
                 self.head.take();
+                (LandingResult::Failed, vec![name])
             }
 
-            TestSuiteResult::Passed => {
-                self.land_pr(config, github, repo, project_board, pulls)
+            TestSuiteResult::TimedOut => {
+                pull.update_status(Status::InReview, config, github, project_board)
                     .await?;
+                self.head.take();
+                (LandingResult::TimedOut, Vec::new())
             }
 
-            TestSuiteResult::Pending => {}
+            TestSuiteResult::Passed => {
+                let result = if self
+                    .land_pr(config, github, repo, project_board, pulls)
+                    .await?
+                {
+                    LandingResult::Landed
+                } else {
+                    LandingResult::MergeFailed
+                };
+                (result, Vec::new())
+            }
+
+            TestSuiteResult::Pending => return Ok(None),
+        };
+
+        let attempt = LandingAttempt {
+            number: head,
+            merge_oid,
+            queued_at: Some(queued_at),
+            started_at: tests_started_at,
+            finished_at: SystemTime::now(),
+            result,
+            passed_checks,
+            failing_checks,
+        };
+
+        info!(
+            "Landing attempt for pr #{} finished after {:?}: {}",
+            head,
+            attempt.duration(),
+            attempt.result.as_str()
+        );
+
+        // Failing to record the attempt shouldn't hold up the rest of the queue
+        if let Err(e) = store.record_landing(config.repo(), &attempt).await {
+            error!("Error while recording landing attempt: {:?}", e);
         }
 
-        Ok(())
+        if attempt.result == LandingResult::Landed {
+            let attempts = match store.landings(config.repo()).await {
+                Ok(landings) => Some(landings.iter().filter(|a| a.number == head).count()),
+                Err(e) => {
+                    warn!(
+                        "Unable to count the landing attempts of pr #{}: {:?}",
+                        head, e
+                    );
+                    None
+                }
+            };
+            let summary =
+                landed_summary(&attempt, &base_ref, attempts, &ci_runs, &advisory_failures);
+            github
+                .create_comment(config.owner(), config.name(), head, &summary)
+                .await?;
+        }
+
+        Ok(Some(attempt))
     }
 
     async fn update_github_based_on_test_suite_results(
@@ -349,13 +636,12 @@ impl MergeQueue {
         test_suite_result: &TestSuiteResult,
         merge_oid: &Oid,
         config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
     ) -> Result<()> {
         match test_suite_result {
             TestSuiteResult::Failed { name, result } => {
                 // Create github status/check
                 github
-                    .repos()
                     .create_status(
                         config.owner(),
                         config.name(),
@@ -371,7 +657,6 @@ impl MergeQueue {
 
                 // Report the Error
                 github
-                    .issues()
                     .create_comment(
                         config.owner(),
                         config.name(),
@@ -386,7 +671,6 @@ impl MergeQueue {
             TestSuiteResult::Passed => {
                 // Create github status/check on the merge commit
                 github
-                    .repos()
                     .create_status(
                         config.owner(),
                         config.name(),
@@ -405,7 +689,6 @@ impl MergeQueue {
                 info!("PR #{} timed-out", pull.number);
 
                 github
-                    .repos()
                     .create_status(
                         config.owner(),
                         config.name(),
@@ -419,14 +702,29 @@ impl MergeQueue {
                     )
                     .await?;
 
+                // Checks which have started but not finished are only known to Github
+                let checks = match github
+                    .commit_checks(config.owner(), config.name(), merge_oid)
+                    .await
+                {
+                    Ok(checks) => checks,
+                    Err(e) => {
+                        warn!("Unable to fetch the checks of {}: {:?}", merge_oid, e);
+                        Vec::new()
+                    }
+                };
+                let test_results = match &pull.status {
+                    Status::Testing { test_results, .. } => test_results.clone(),
+                    _ => HashMap::new(),
+                };
+
                 // Report the Error
                 github
-                    .issues()
                     .create_comment(
                         config.owner(),
                         config.name(),
                         pull.number,
-                        ":boom: Tests timed-out",
+                        &timed_out_report(config, &test_results, &checks),
                     )
                     .await?;
             }
@@ -439,61 +737,280 @@ impl MergeQueue {
     async fn process_next_head(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         project_board: Option<&ProjectBoard>,
         pulls: &mut HashMap<u64, PullRequestState>,
     ) -> Result<()> {
         assert!(self.head.is_none());
 
+        let now = SystemTime::now();
         let mut queue: Vec<_> = pulls
             .iter_mut()
             .map(|(_n, p)| p)
-            .filter(|p| p.status.is_queued())
+            .filter(|p| p.status.is_queued() && has_boarded(config, p, now))
             .collect();
         queue.sort_unstable_by_key(|p| p.to_queue_entry(config));
         let mut queue = queue.into_iter();
 
         while let (None, Some(pull)) = (self.head, queue.next()) {
-            if let Some(merge_oid) =
-                Self::create_merge_and_update_github(config, github, repo, pull, "auto").await?
+            let queued_at = match pull.status {
+                Status::Queued(queued_at) => queued_at,
+                _ => unreachable!(),
+            };
+
+            let merge_oid = match Self::create_merge_and_update_github(
+                config,
+                github,
+                repo,
+                pull,
+                config.auto_branch(),
+            )
+            .await?
             {
-                pull.update_status(Status::testing(merge_oid), config, github, project_board)
+                Merge::Pushed(merge_oid) => merge_oid,
+                Merge::Failed => {
+                    pull.update_status(Status::InReview, config, github, project_board)
+                        .await?;
+                    continue;
+                }
+                // The PR stays queued and its merge commit is rebuilt on the next pass
+                Merge::Raced => break,
+            };
+
+            if let Some(jenkins) = config.jenkins() {
+                // CI is still left to time out if it can't be started
+                if let Err(e) =
+                    jenkins::trigger_build(jenkins, config.auto_branch(), &merge_oid).await
+                {
+                    warn!(
+                        "Unable to trigger a Jenkins build of #{}: {:?}",
+                        pull.number, e
+                    );
+                }
+            }
+
+            if config.comment_on_testing() {
+                github
+                    .create_comment(
+                        config.owner(),
+                        config.name(),
+                        pull.number,
+                        &testing_started(config, pull, &merge_oid),
+                    )
                     .await?;
-                self.head = Some(pull.number);
+            }
+
+            pull.update_status(
+                Status::testing(merge_oid, queued_at),
+                config,
+                github,
+                project_board,
+            )
+            .await?;
+            self.head = Some(pull.number);
+        }
+
+        Ok(())
+    }
+
+    /// Perform a trial merge of a PR so that merge conflicts can be reported as early as possible,
+    /// instead of only once the PR reaches the head of the queue.
+    ///
+    /// Returns `false` if the PR has a merge conflict with its base branch.
+    #[instrument(skip_all, fields(pr = pull.number))]
+    pub async fn trial_merge(
+        config: &RepoConfig,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
+        pull: &PullRequestState,
+    ) -> Result<bool> {
+        info!("Performing trial merge for pr #{}", pull.number);
+
+        if repo.trial_merge(&pull.base_ref_name, &pull.head_ref_oid, pull.number)? {
+            return Ok(true);
+        }
+
+        github
+            .create_status(
+                config.owner(),
+                config.name(),
+                &pull.head_ref_oid.to_string(),
+                &github::client::CreateStatusRequest {
+                    state: github::StatusEventState::Error,
+                    target_url: None,
+                    description: Some("Merge Conflict"),
+                    context: "bors",
+                },
+            )
+            .await?;
+
+        github
+            .create_comment(
+                config.owner(),
+                config.name(),
+                pull.number,
+                &format!(
+                    ":lock: Merge Conflict: this PR no longer applies cleanly on top of `{}`. \
+                    Please rebase it before queuing it for landing.",
+                    pull.base_ref_name
+                ),
+            )
+            .await?;
+
+        Ok(false)
+    }
+
+    /// Push `branch` and then verify that the remote ref actually points to `merge_oid`.
+    ///
+    /// Something else could have pushed to `branch` in between bors pushing and PR's state being
+    /// updated, which would result in bors testing and merging a commit it didn't create. Pushing
+    /// again would clobber whatever that was without knowing why it was pushed, so `false` is
+    /// returned instead for the merge commit to be abandoned and rebuilt from fresh state.
+    fn push_and_verify(repo: &mut dyn GitApi, branch: &str, merge_oid: &Oid) -> Result<bool> {
+        repo.push_branch(branch)?;
+        info!("pushed '{}' branch", branch);
+
+        let remote_oid = repo.fetch_ref(branch)?;
+        if &remote_oid != merge_oid {
+            warn!(
+                "remote '{}' branch doesn't match pushed commit, something else pushed to it\nExpected: '{}'\nActual: '{}'",
+                branch, merge_oid, remote_oid
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Render the configured trailer templates for a PR
+    fn trailers(config: &RepoConfig, pull: &PullRequestState) -> Vec<String> {
+        let mut approvers: Vec<&str> = pull.approved_by.iter().map(AsRef::as_ref).collect();
+        approvers.sort_unstable();
+        let closes: Vec<String> = closed_issues(&pull.body)
+            .into_iter()
+            .map(|n| format!("#{}", n))
+            .collect();
+        let closes: Vec<&str> = closes.iter().map(AsRef::as_ref).collect();
+
+        let mut trailers = Vec::new();
+        for template in config.trailers() {
+            let trailer = template
+                .replace("{number}", &pull.number.to_string())
+                .replace("{author}", pull.author.as_deref().unwrap_or(""))
+                .replace("{priority}", &pull.priority(config).to_string());
+
+            // Templates which reference a list of values are repeated once for each value
+            if trailer.contains("{approvers}") {
+                trailers.extend(approvers.iter().map(|a| trailer.replace("{approvers}", a)));
+            } else if trailer.contains("{closes}") {
+                trailers.extend(closes.iter().map(|c| trailer.replace("{closes}", c)));
             } else {
-                pull.update_status(Status::InReview, config, github, project_board)
-                    .await?;
+                trailers.push(trailer);
             }
         }
 
+        // Omit any trailers which ended up without a value
+        trailers.retain(|t| {
+            t.split_once(':')
+                .map(|(_key, value)| !value.trim().is_empty())
+                .unwrap_or(false)
+        });
+
+        trailers
+    }
+
+    async fn report_temporary_commits(
+        config: &RepoConfig,
+        github: &dyn GithubApi,
+        pull: &PullRequestState,
+        temporary_commits: &[String],
+    ) -> Result<()> {
+        info!(
+            "pr #{} contains {} temporary commits",
+            pull.number,
+            temporary_commits.len()
+        );
+
+        github
+            .create_status(
+                config.owner(),
+                config.name(),
+                &pull.head_ref_oid.to_string(),
+                &github::client::CreateStatusRequest {
+                    state: github::StatusEventState::Error,
+                    target_url: None,
+                    description: Some("Temporary Commits"),
+                    context: "bors",
+                },
+            )
+            .await?;
+
+        let mut comment = ":construction: This PR contains temporary commits which can't be \
+            squashed into another commit in the PR. Please squash or reword them before queuing \
+            this PR for landing:\n"
+            .to_owned();
+        for subject in temporary_commits {
+            comment.push_str(&format!("- `{}`\n", subject));
+        }
+
+        github
+            .create_comment(config.owner(), config.name(), pull.number, &comment)
+            .await?;
+
         Ok(())
     }
 
+    #[instrument(skip_all, fields(pr = pull.number))]
     async fn create_merge_and_update_github(
         config: &RepoConfig,
-        github: &GithubClient,
-        repo: &mut GitRepository,
+        github: &dyn GithubApi,
+        repo: &mut dyn GitApi,
         pull: &PullRequestState,
         branch: &str,
-    ) -> Result<Option<Oid>> {
+    ) -> Result<Merge> {
         info!("Creating merge for pr #{}", pull.number);
 
         // Attempt to rebase the PR onto 'base_ref' and push to the 'auto' branch for
         // testing
-        let merge = if let Some(merge_oid) = repo.fetch_and_rebase(
+        let merge_oid = repo.fetch_and_rebase(
             &pull.base_ref_name,
             &pull.head_ref_oid,
             branch,
-            pull.number,
+            &Self::trailers(config, pull),
             pull.has_label(config.labels().squash()),
-        )? {
-            repo.push_branch(branch)?;
-            info!("pushed '{}' branch", branch);
+        )?;
+
+        // Refuse to land any temporary commits that weren't able to be autosquashed so that they
+        // never make it into the base branch's history
+        if let Some(merge_oid) = &merge_oid {
+            let temporary_commits = repo.temporary_commits(&pull.base_ref_name, merge_oid)?;
+            if !temporary_commits.is_empty() {
+                Self::report_temporary_commits(config, github, pull, &temporary_commits).await?;
+                return Ok(Merge::Failed);
+            }
+        }
+
+        // The PR's changelog entry lands along with it, but isn't needed to canary it
+        let merge_oid = match (merge_oid, config.changelog()) {
+            (Some(merge_oid), Some(changelog)) if branch == config.auto_branch() => {
+                match changelog::entry(changelog, pull) {
+                    Some(entry) => {
+                        Some(repo.append_to_tip(branch, &merge_oid, &changelog.file, &entry)?)
+                    }
+                    None => Some(merge_oid),
+                }
+            }
+            (merge_oid, _) => merge_oid,
+        };
+
+        let merge = if let Some(merge_oid) = merge_oid {
+            if !Self::push_and_verify(repo, branch, &merge_oid)? {
+                return Ok(Merge::Raced);
+            }
 
             // Create github status
             github
-                .repos()
                 .create_status(
                     config.owner(),
                     config.name(),
@@ -507,10 +1024,9 @@ impl MergeQueue {
                 )
                 .await?;
 
-            Some(merge_oid)
+            Merge::Pushed(merge_oid)
         } else {
             github
-                .repos()
                 .create_status(
                     config.owner(),
                     config.name(),
@@ -525,7 +1041,6 @@ impl MergeQueue {
                 .await?;
 
             github
-                .issues()
                 .create_comment(
                     config.owner(),
                     config.name(),
@@ -534,13 +1049,180 @@ impl MergeQueue {
                 )
                 .await?;
 
-            None
+            Merge::Failed
         };
 
         Ok(merge)
     }
 }
 
+/// Whether a queued PR can be landed as of `now`, which if PRs are landed at the departures of
+/// landing trains is once a train has departed since the PR was queued
+pub fn has_boarded(config: &RepoConfig, pull: &PullRequestState, now: SystemTime) -> bool {
+    match (config.landing_cutoff(now), pull.status.queued_at()) {
+        (Some(cutoff), Some(queued_at)) => queued_at <= cutoff,
+        _ => true,
+    }
+}
+
+/// The comment letting a PR's author know that the PR has started being tested, so that they can
+/// watch its build
+fn testing_started(config: &RepoConfig, pull: &PullRequestState, merge_oid: &Oid) -> String {
+    let author = match &pull.author {
+        Some(author) => format!("@{}, this", author),
+        None => "This".to_owned(),
+    };
+    format!(
+        ":arrow_forward: {} PR has reached the front of the queue and is being tested as {}. Its \
+        CI can be followed [here](https://github.com/{}/{}/commit/{}/checks).",
+        author,
+        merge_oid,
+        config.owner(),
+        config.name(),
+        merge_oid
+    )
+}
+
+/// The comment reporting that a PR's tests timed-out, listing which of the required checks were
+/// still running, with their details URLs, and which never reported at all
+fn timed_out_report(
+    config: &RepoConfig,
+    test_results: &HashMap<String, TestResult>,
+    checks: &[CommitCheck],
+) -> String {
+    fn link(name: &str, details_url: &str) -> String {
+        if details_url.is_empty() {
+            format!("* {}", name)
+        } else {
+            format!("* [{}]({})", name, details_url)
+        }
+    }
+
+    let (mut running, mut unheard, mut missing) = (Vec::new(), Vec::new(), Vec::new());
+    // Checks which finished have passed, or else the tests would have failed instead
+    for name in config
+        .checks()
+        .filter(|name| !test_results.contains_key(*name))
+    {
+        let check = checks.iter().find(|check| {
+            let mut check_name = check.name.clone();
+            if check.is_status {
+                if let Some(buildkite) = config.buildkite() {
+                    check_name = buildkite.check_name(&check_name).to_owned();
+                }
+                if let Some(jenkins) = config.jenkins() {
+                    check_name = jenkins.check_name(&check_name);
+                }
+            }
+            check_name == name
+        });
+        match check {
+            Some(check) if check.conclusion.is_none() => {
+                running.push(link(name, &check.details_url))
+            }
+            Some(check) => unheard.push(link(name, &check.details_url)),
+            None => missing.push(format!("* {}", name)),
+        }
+    }
+
+    let mut report = format!(
+        ":boom: Tests timed-out after {}",
+        humantime::format_duration(config.timeout())
+    );
+    for (heading, checks) in [
+        ("Still running", running),
+        ("Finished, but never reported to bors", unheard),
+        ("Never reported", missing),
+    ]
+    .iter()
+    {
+        if !checks.is_empty() {
+            report.push_str(&format!("\n\n{}:\n{}", heading, checks.join("\n")));
+        }
+    }
+    report
+}
+
+/// Find the issues which a PR's description indicates that it closes, e.g. "Fixes #12"
+fn closed_issues(body: &str) -> Vec<u64> {
+    const KEYWORDS: &[&str] = &[
+        "close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved",
+    ];
+
+    let words: Vec<&str> = body.split_whitespace().collect();
+    let mut issues = Vec::new();
+
+    for pair in words.windows(2) {
+        let keyword = pair[0].trim_end_matches(':').to_lowercase();
+        if !KEYWORDS.contains(&keyword.as_str()) {
+            continue;
+        }
+
+        let issue = pair[1]
+            .strip_prefix('#')
+            .map(|s| s.trim_end_matches(|c: char| !c.is_ascii_digit()))
+            .and_then(|s| s.parse().ok());
+        if let Some(issue) = issue {
+            if !issues.contains(&issue) {
+                issues.push(issue);
+            }
+        }
+    }
+
+    issues
+}
+
+/// A comment summarizing how a PR landed, for tracing deployments back to the landing
+fn landed_summary(
+    attempt: &LandingAttempt,
+    base_ref: &str,
+    attempts: Option<usize>,
+    ci_runs: &[(String, String)],
+    advisory_failures: &[(String, String)],
+) -> String {
+    let round = |duration: Duration| Duration::from_secs(duration.as_secs());
+
+    let mut summary = format!(
+        ":sunny: Landed on `{}` as {}\n",
+        base_ref, attempt.merge_oid
+    );
+    if let Some(time_in_queue) = attempt.time_in_queue() {
+        summary.push_str(&format!(
+            "\n* Time in queue: {}",
+            humantime::format_duration(round(time_in_queue))
+        ));
+    }
+    summary.push_str(&format!(
+        "\n* Time testing: {}",
+        humantime::format_duration(round(attempt.duration()))
+    ));
+    match attempts {
+        Some(attempts) if attempts > 1 => {
+            summary.push_str(&format!("\n* Retries: {}", attempts - 1))
+        }
+        Some(_) => summary.push_str("\n* Retries: none"),
+        None => {}
+    }
+    if !ci_runs.is_empty() {
+        let runs: Vec<String> = ci_runs
+            .iter()
+            .map(|(name, url)| format!("[{}]({})", name, url))
+            .collect();
+        summary.push_str(&format!("\n* CI: {}", runs.join(", ")));
+    }
+    if !advisory_failures.is_empty() {
+        let failures: Vec<String> = advisory_failures
+            .iter()
+            .map(|(name, url)| format!("[{}]({})", name, url))
+            .collect();
+        summary.push_str(&format!(
+            "\n* :warning: Advisory checks failed: {}",
+            failures.join(", ")
+        ));
+    }
+    summary
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -586,4 +1268,110 @@ mod test {
         ];
         assert_eq!(entries, expected);
     }
+
+    #[test]
+    fn timed_out() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            checks = ["build", "ci", "lint", "test"]
+            timeout-seconds = 3600
+
+            [buildkite]
+            organization = "codertocat"
+            "#,
+        )
+        .unwrap();
+        let mut test_results = HashMap::new();
+        test_results.insert(
+            "build".to_owned(),
+            TestResult {
+                passed: true,
+                details_url: "https://ci/build".to_owned(),
+            },
+        );
+        let checks = vec![
+            CommitCheck {
+                name: "buildkite/ci".to_owned(),
+                details_url: "https://buildkite.com/ci/1".to_owned(),
+                conclusion: None,
+                is_status: true,
+            },
+            CommitCheck {
+                name: "test".to_owned(),
+                details_url: "https://ci/test".to_owned(),
+                conclusion: Some(github::Conclusion::Success),
+                is_status: false,
+            },
+        ];
+
+        assert_eq!(
+            timed_out_report(&config, &test_results, &checks),
+            ":boom: Tests timed-out after 1h\n\n\
+            Still running:\n* [ci](https://buildkite.com/ci/1)\n\n\
+            Finished, but never reported to bors:\n* [test](https://ci/test)\n\n\
+            Never reported:\n* lint"
+        );
+        assert_eq!(
+            timed_out_report(&config, &test_results, &[]),
+            ":boom: Tests timed-out after 1h\n\nNever reported:\n* ci\n* lint\n* test"
+        );
+    }
+
+    #[test]
+    fn closed_issues_from_body() {
+        assert_eq!(closed_issues(""), Vec::<u64>::new());
+        assert_eq!(
+            closed_issues("Refactor the queue, see #4"),
+            Vec::<u64>::new()
+        );
+        assert_eq!(
+            closed_issues("Fixes #12, and closes: #7.\nAlso resolves #12"),
+            vec![12, 7]
+        );
+        assert_eq!(closed_issues("fixes #abc"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn summary() {
+        let started_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let attempt = LandingAttempt {
+            number: 1,
+            merge_oid: Oid::from_str("abc123"),
+            queued_at: Some(started_at - Duration::from_secs(125)),
+            started_at,
+            finished_at: started_at + Duration::from_millis(3_600_500),
+            result: LandingResult::Landed,
+            passed_checks: vec!["ci".to_owned()],
+            failing_checks: Vec::new(),
+        };
+        let ci_runs = vec![("ci".to_owned(), "https://ci.example.com/1".to_owned())];
+
+        assert_eq!(
+            landed_summary(&attempt, "main", Some(3), &ci_runs, &[]),
+            ":sunny: Landed on `main` as abc123\n\
+            \n* Time in queue: 2m 5s\
+            \n* Time testing: 1h\
+            \n* Retries: 2\
+            \n* CI: [ci](https://ci.example.com/1)"
+        );
+
+        let attempt = LandingAttempt {
+            queued_at: None,
+            ..attempt
+        };
+        assert_eq!(
+            landed_summary(&attempt, "main", None, &[], &[]),
+            ":sunny: Landed on `main` as abc123\n\n* Time testing: 1h"
+        );
+
+        let advisory_failures = vec![("lint".to_owned(), "https://ci.example.com/2".to_owned())];
+        assert_eq!(
+            landed_summary(&attempt, "main", None, &[], &advisory_failures),
+            ":sunny: Landed on `main` as abc123\n\
+            \n* Time testing: 1h\
+            \n* :warning: Advisory checks failed: [lint](https://ci.example.com/2)"
+        );
+    }
 }
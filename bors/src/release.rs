@@ -0,0 +1,172 @@
+//! Releases are cut through release PRs, which bump the version in the repo's version file and list
+//! the changes made since the last release. Once a release PR lands through the merge queue its
+//! merge commit is tagged with the new version.
+
+use crate::{
+    config::{Bump, ReleaseConfig, RepoConfig},
+    git::{GitApi, RELEASE_BRANCH_PREFIX},
+    github_api::GithubApi,
+    state::PullRequestState,
+    Result,
+};
+use anyhow::anyhow;
+use github::{client::NewPullRequest, PullRequest};
+use regex::Regex;
+use tracing::info;
+
+/// How many changes are listed in a release PR's description before the rest are elided
+const MAX_LISTED_CHANGES: usize = 100;
+
+/// Open a PR bumping the version of the release's base branch, unless nothing has changed since the
+/// last release
+pub async fn open_pull(
+    config: &RepoConfig,
+    release: &ReleaseConfig,
+    github: &dyn GithubApi,
+    git: &mut dyn GitApi,
+    bump: Bump,
+) -> Result<Option<PullRequest>> {
+    let (last_tag, changes) = git.changes_since_tag(&release.base, release.tag_prefix())?;
+    if changes.is_empty() {
+        info!("Nothing has changed since {:?}, skipping release", last_tag);
+        return Ok(None);
+    }
+
+    let contents = git.read_file(&release.base, &release.version_file)?;
+    let (version, contents) = bump_version(&contents, bump)
+        .ok_or_else(|| anyhow!("no version found in {}", release.version_file))?;
+    let tag = format!("{}{}", release.tag_prefix(), version);
+
+    let branch = format!("{}{}", RELEASE_BRANCH_PREFIX, tag);
+    let title = format!("Release {}", tag);
+    git.commit_file(
+        &release.base,
+        &branch,
+        &release.version_file,
+        &contents,
+        &title,
+    )?;
+    git.push_branch(&branch)?;
+
+    let mut body = match &last_tag {
+        Some(last_tag) => format!("Changes since {}:\n\n", last_tag),
+        None => "Changes:\n\n".to_owned(),
+    };
+    for change in changes.iter().take(MAX_LISTED_CHANGES) {
+        body.push_str(&format!("- {}\n", change));
+    }
+    if changes.len() > MAX_LISTED_CHANGES {
+        body.push_str(&format!(
+            "- and {} more\n",
+            changes.len() - MAX_LISTED_CHANGES
+        ));
+    }
+    body.push_str(&format!(
+        "\nbors will tag the release {} once this PR lands.",
+        tag
+    ));
+
+    let request = NewPullRequest {
+        title,
+        body: Some(body),
+        head: branch,
+        base: release.base.clone(),
+        maintainer_can_modify: Some(true),
+        draft: Some(false),
+    };
+    let pull = github
+        .create_pull(config.owner(), config.name(), request)
+        .await?;
+    info!("Opened release PR #{} for {}", pull.number, tag);
+
+    Ok(Some(pull))
+}
+
+/// The tag to tag a PR's merge commit with once it lands, if it's a release PR
+pub fn tag<'a>(config: &RepoConfig, pull: &'a PullRequestState) -> Option<&'a str> {
+    config.release()?;
+    if pull.head_repo.as_ref() != Some(config.repo()) {
+        return None;
+    }
+    pull.head_ref_name.strip_prefix(RELEASE_BRANCH_PREFIX)
+}
+
+/// Whether a PR is a release PR opened by bors
+pub fn is_release_pull(config: &RepoConfig, pull: &PullRequestState) -> bool {
+    tag(config, pull).is_some()
+}
+
+/// Bump the first version in the contents of a version file, e.g. the `version = "1.2.3"` of a
+/// `Cargo.toml`, the `"version": "1.2.3"` of a `package.json` or a file holding just the version.
+///
+/// Returns the new version and the file's updated contents
+pub fn bump_version(contents: &str, bump: Bump) -> Option<(String, String)> {
+    let field = Regex::new(r#"(?m)^(\s*"?version"?\s*[:=]\s*")(\d+)\.(\d+)\.(\d+)""#).unwrap();
+    if let Some(captures) = field.captures(contents) {
+        let version = bumped(&captures[2], &captures[3], &captures[4], bump)?;
+        let whole = captures.get(0).unwrap();
+        let contents = format!(
+            "{}{}{}\"{}",
+            &contents[..whole.start()],
+            &captures[1],
+            version,
+            &contents[whole.end()..]
+        );
+        return Some((version, contents));
+    }
+
+    let parts: Vec<&str> = contents.trim().split('.').collect();
+    match parts[..] {
+        [major, minor, patch] => {
+            let version = bumped(major, minor, patch, bump)?;
+            let contents = format!("{}\n", version);
+            Some((version, contents))
+        }
+        _ => None,
+    }
+}
+
+fn bumped(major: &str, minor: &str, patch: &str, bump: Bump) -> Option<String> {
+    let (major, minor, patch): (u64, u64, u64) = (
+        major.parse().ok()?,
+        minor.parse().ok()?,
+        patch.parse().ok()?,
+    );
+    let (major, minor, patch) = match bump {
+        Bump::Major => (major + 1, 0, 0),
+        Bump::Minor => (major, minor + 1, 0),
+        Bump::Patch => (major, minor, patch + 1),
+    };
+    Some(format!("{}.{}.{}", major, minor, patch))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let cargo = "[package]\nname = \"bors\"\nversion = \"1.2.3\"\n\n[dependencies]\nfoo = { version = \"0.4.0\" }\n";
+        let (version, contents) = bump_version(cargo, Bump::Minor).unwrap();
+        assert_eq!(version, "1.3.0");
+        assert_eq!(
+            contents,
+            "[package]\nname = \"bors\"\nversion = \"1.3.0\"\n\n[dependencies]\nfoo = { version = \"0.4.0\" }\n"
+        );
+
+        let package = "{\n  \"name\": \"bors\",\n  \"version\": \"0.9.9\"\n}\n";
+        let (version, contents) = bump_version(package, Bump::Major).unwrap();
+        assert_eq!(version, "1.0.0");
+        assert_eq!(
+            contents,
+            "{\n  \"name\": \"bors\",\n  \"version\": \"1.0.0\"\n}\n"
+        );
+
+        assert_eq!(
+            bump_version("2.0.9\n", Bump::Patch),
+            Some(("2.0.10".to_owned(), "2.0.10\n".to_owned()))
+        );
+        assert_eq!(bump_version("name = \"bors\"\n", Bump::Patch), None);
+        assert_eq!(bump_version("version = \"1.2\"\n", Bump::Patch), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Commands which talk to a running instance over its HTTP API, rather than to the configured repos
+//! directly, so that operators can check on and act on a queue from a terminal
+
+use crate::{
+    store::{LandingAttempt, LandingResult},
+    Result,
+};
+use anyhow::anyhow;
+use reqwest::{header::AUTHORIZATION, Client, RequestBuilder, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use structopt::StructOpt;
+
+// How to reach a running instance
+#[derive(StructOpt)]
+struct RemoteOptions {
+    #[structopt(long, default_value = "http://localhost:3000")]
+    /// URL of the running instance
+    url: String,
+
+    #[structopt(long, env = "BORS_ADMIN_TOKEN", hide_env_values = true)]
+    /// Admin or scoped token to present, required to act on PRs or if the instance requires logging
+    /// in with Github
+    token: Option<String>,
+}
+
+impl RemoteOptions {
+    fn request(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, client: &Client, path: String) -> Result<T> {
+        let url = Url::parse(&self.url)?.join(&path)?;
+        let response = self.request(client.get(url)).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("GET {} failed: {}", path, response.status()));
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn post(&self, client: &Client, path: String, body: serde_json::Value) -> Result<()> {
+        let url = Url::parse(&self.url)?.join(&path)?;
+        let response = self.request(client.post(url)).json(&body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(anyhow!(
+                "POST {} failed: {} {}",
+                path,
+                status,
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub struct QueueOptions {
+    #[structopt(flatten)]
+    remote: RemoteOptions,
+
+    #[structopt(long, default_value = "5")]
+    /// Number of recent failed landings to show
+    failures: usize,
+
+    /// Repo to show, as `<owner>/<name>`
+    repo: String,
+}
+
+/// A PR as served by `/api/`, of which only the fields shown are deserialized
+#[derive(Debug, Deserialize)]
+struct Pull {
+    number: u64,
+    title: String,
+    author: Option<String>,
+    approved: bool,
+    mergeable: bool,
+    priority: String,
+    status: String,
+    queued_at: Option<u64>,
+    tests_started_at: Option<u64>,
+    #[serde(default)]
+    test_results: BTreeMap<String, TestResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TestResult {
+    passed: bool,
+}
+
+/// Print a repo's queue, the status of its open PRs and its recent failed landings as tables
+pub async fn run_queue(options: &QueueOptions) -> Result<()> {
+    let (owner, name) = options
+        .repo
+        .split_once('/')
+        .ok_or_else(|| anyhow!("repo must be given as <owner>/<name>"))?;
+    let client = Client::new();
+    let remote = &options.remote;
+
+    let pulls: Vec<Pull> = remote
+        .get(&client, format!("api/repos/{}/{}/pulls", owner, name))
+        .await?;
+    let landings: Vec<LandingAttempt> = remote
+        .get(&client, format!("repos/{}/{}/history", owner, name))
+        .await?;
+    let now = SystemTime::now();
+
+    // PRs are served in the order they will land
+    let queue: Vec<_> = pulls
+        .iter()
+        .filter(|pull| pull.status == "testing" || pull.status == "queued")
+        .collect();
+    println!("{}/{}: {} queued", owner, name, queue.len());
+
+    println!("\nQueue");
+    let rows = queue.iter().enumerate().map(|(position, pull)| {
+        vec![
+            (position + 1).to_string(),
+            format!("#{}", pull.number),
+            pull.status.clone(),
+            pull.priority.clone(),
+            ago(pull.queued_at, now),
+            ago(pull.tests_started_at, now),
+            checks(pull),
+            pull.title.clone(),
+        ]
+    });
+    print!(
+        "{}",
+        table(
+            &["", "PR", "STATUS", "PRIORITY", "QUEUED", "TESTING", "CHECKS", "TITLE"],
+            rows
+        )
+    );
+
+    println!("\nPull requests");
+    let rows = pulls.iter().map(|pull| {
+        vec![
+            format!("#{}", pull.number),
+            pull.status.clone(),
+            yes_no(pull.approved),
+            yes_no(pull.mergeable),
+            pull.author.clone().unwrap_or_default(),
+            pull.title.clone(),
+        ]
+    });
+    print!(
+        "{}",
+        table(
+            &["PR", "STATUS", "APPROVED", "MERGEABLE", "AUTHOR", "TITLE"],
+            rows
+        )
+    );
+
+    println!("\nRecent failures");
+    let rows = landings
+        .iter()
+        .rev()
+        .filter(|attempt| attempt.result != LandingResult::Landed)
+        .take(options.failures)
+        .map(|attempt| {
+            vec![
+                format!("#{}", attempt.number),
+                attempt.result.as_str().to_owned(),
+                ago(Some(unix_secs(attempt.finished_at)), now),
+                attempt.failing_checks.join(", "),
+            ]
+        });
+    print!(
+        "{}",
+        table(&["PR", "RESULT", "FINISHED", "FAILING CHECKS"], rows)
+    );
+
+    Ok(())
+}
+
+#[derive(StructOpt)]
+pub enum PrCommand {
+    #[structopt(name = "land")]
+    /// Approve a PR and add it to the queue, as `/land` does
+    Land {
+        #[structopt(flatten)]
+        target: PrTarget,
+
+        #[structopt(long)]
+        /// Priority to queue the PR with: high, normal or low
+        priority: Option<String>,
+    },
+
+    #[structopt(name = "evict")]
+    /// Remove a PR from the queue, as `/cancel` does
+    Evict {
+        #[structopt(flatten)]
+        target: PrTarget,
+    },
+
+    #[structopt(name = "priority")]
+    /// Set the priority of a PR, as `/priority` does
+    Priority {
+        #[structopt(flatten)]
+        target: PrTarget,
+
+        /// The priority: high, normal or low
+        priority: String,
+    },
+}
+
+// The PR to act on and who to act as
+#[derive(StructOpt)]
+pub struct PrTarget {
+    #[structopt(flatten)]
+    remote: RemoteOptions,
+
+    #[structopt(long)]
+    /// Repo of the PR, as `<owner>/<name>`
+    repo: String,
+
+    #[structopt(long = "as", env = "BORS_USER")]
+    /// Github user to act as, who must be allowed to run the command as if they had commented it
+    sender: String,
+
+    /// Number of the PR
+    number: u64,
+}
+
+/// Run a command on a PR through the admin API, for when commenting on Github isn't possible. The
+/// command is authorized and handled exactly as if `--as` had commented it on the PR.
+pub async fn run_pr(command: &PrCommand) -> Result<()> {
+    let (target, command) = match command {
+        PrCommand::Land { target, priority } => match priority {
+            Some(priority) => (target, format!("/land priority={}", priority)),
+            None => (target, "/land".to_owned()),
+        },
+        PrCommand::Evict { target } => (target, "/cancel".to_owned()),
+        PrCommand::Priority { target, priority } => (target, format!("/priority {}", priority)),
+    };
+
+    target
+        .remote
+        .post(
+            &Client::new(),
+            format!(
+                "admin/repos/{}/pulls/{}/command",
+                target.repo, target.number
+            ),
+            json!({
+                "command": command,
+                "sender": target.sender,
+            }),
+        )
+        .await?;
+    println!("Queued `{}` for {}#{}", command, target.repo, target.number);
+
+    Ok(())
+}
+
+/// Lay out `rows` in columns under `header`, padding every column but the last
+fn table(header: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let rows: Vec<Vec<String>> = std::iter::once(header.iter().map(|s| s.to_string()).collect())
+        .chain(rows)
+        .collect();
+    if rows.len() == 1 {
+        return "  (none)\n".to_owned();
+    }
+
+    let mut widths = vec![0; header.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let mut line = String::from(" ");
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            line.push(' ');
+            if i + 1 == row.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:width$} ", cell, width = width));
+            }
+        }
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// How many of a PR's checks have passed out of those which have reported
+fn checks(pull: &Pull) -> String {
+    if pull.test_results.is_empty() {
+        return String::new();
+    }
+    let passed = pull
+        .test_results
+        .values()
+        .filter(|result| result.passed)
+        .count();
+    format!("{}/{}", passed, pull.test_results.len())
+}
+
+/// How long ago `secs`, in seconds since the unix epoch, was, in the largest whole unit
+fn ago(secs: Option<u64>, now: SystemTime) -> String {
+    let secs = match secs {
+        Some(secs) => unix_secs(now).saturating_sub(secs),
+        None => return String::new(),
+    };
+
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn queue_table() {
+        let rows = vec![
+            vec!["#12".to_owned(), "testing".to_owned(), "Fix it".to_owned()],
+            vec!["#7".to_owned(), "queued".to_owned(), "".to_owned()],
+        ];
+        assert_eq!(
+            table(&["PR", "STATUS", "TITLE"], rows.into_iter()),
+            "  PR   STATUS   TITLE\n  #12  testing  Fix it\n  #7   queued\n"
+        );
+        assert_eq!(table(&["PR"], std::iter::empty()), "  (none)\n");
+
+        let now = UNIX_EPOCH + Duration::from_secs(100_000);
+        assert_eq!(ago(Some(100_000 - 30), now), "30s ago");
+        assert_eq!(ago(Some(100_000 - 7200), now), "2h ago");
+        assert_eq!(ago(None, now), "");
+    }
+}
@@ -0,0 +1,127 @@
+//! A stand-in for the Github API used when replaying recorded webhooks, see `bors replay`
+
+use crate::{Error, Result};
+use futures::future;
+use hyper::{
+    body,
+    header::CONTENT_TYPE,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server as HyperServer, StatusCode,
+};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tracing::{error, info};
+
+/// Serves just enough of the Github API for an event processor to handle webhooks without touching
+/// the real repo. Every request is logged, and responded to with a canned response:
+///
+/// * GraphQL queries get an empty response, so PRs have no review decision, etc
+/// * Everyone is a collaborator
+/// * Anything else that is fetched doesn't exist
+/// * Anything that is created or updated succeeds
+#[derive(Debug)]
+pub struct StubGithub {
+    addr: SocketAddr,
+}
+
+impl StubGithub {
+    /// Start serving on a random local port
+    pub fn start() -> Result<Self> {
+        let make_service =
+            make_service_fn(|_socket: &AddrStream| future::ok::<_, Error>(service_fn(respond)));
+
+        let server = HyperServer::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("stub Github server failed: {:?}", e);
+            }
+        });
+
+        Ok(Self { addr })
+    }
+
+    /// The base URL to point a `GithubClient` at
+    pub fn base_url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+}
+
+async fn respond(request: Request<Body>) -> Result<Response<Body>> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let body = body::to_bytes(request.into_body()).await?;
+    info!(
+        "github: {} {} {}",
+        method,
+        path,
+        String::from_utf8_lossy(&body)
+    );
+
+    let (status, response) = if path == "/graphql" {
+        // Every field of a query's response is nullable so this parses as any response
+        (StatusCode::OK, json!({ "data": {} }))
+    } else if method == Method::GET && path.contains("/collaborators/") {
+        // Assume that whoever made a recorded comment was allowed to
+        (StatusCode::NO_CONTENT, Value::Null)
+    } else if method == Method::GET {
+        (StatusCode::NOT_FOUND, json!({ "message": "Not Found" }))
+    } else if path.contains("/issues/") && path.contains("/labels") {
+        // Adding or removing labels responds with the issue's labels
+        (StatusCode::OK, json!([]))
+    } else {
+        (StatusCode::OK, stub_object())
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(response.to_string()))?)
+}
+
+/// An object with the fields required by all of the objects bors creates: comments, statuses,
+/// labels, etc
+fn stub_object() -> Value {
+    const TIMESTAMP: &str = "1970-01-01T00:00:00Z";
+
+    let user = json!({
+        "login": "bors",
+        "id": 0,
+        "node_id": "",
+        "avatar_url": "",
+        "gravatar_id": "",
+        "url": "",
+        "html_url": "",
+        "followers_url": "",
+        "following_url": "",
+        "gists_url": "",
+        "starred_url": "",
+        "subscriptions_url": "",
+        "organizations_url": "",
+        "repos_url": "",
+        "events_url": "",
+        "received_events_url": "",
+        "type": "Bot",
+        "site_admin": false,
+    });
+
+    json!({
+        "id": 0,
+        "node_id": "",
+        "url": "",
+        "html_url": "",
+        "name": "",
+        "description": null,
+        "color": "",
+        "default": false,
+        "body": null,
+        "state": "success",
+        "context": "",
+        "target_url": null,
+        "user": user,
+        "creator": user,
+        "created_at": TIMESTAMP,
+        "updated_at": TIMESTAMP,
+    })
+}
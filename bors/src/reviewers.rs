@@ -0,0 +1,140 @@
+//! Approvals required on top of the one `require-review` asks for: from the teams owning the paths
+//! a PR changes, e.g. so that changes to consensus are always looked over by the consensus team,
+//! from an admin for changes to protected paths, and a second one for large PRs.
+
+use crate::{
+    config::RepoConfig,
+    github_api::{Approval, GithubApi},
+    Result,
+};
+
+/// How many approvals PRs changing more than `large-diff-lines` lines need
+pub const LARGE_DIFF_APPROVALS: usize = 2;
+
+/// The teams owning any of `files`, the paths a PR changes, which haven't approved it, i.e. none of
+/// whose members are among the PR's `approvals` which count
+pub async fn missing_approvals<'a>(
+    config: &'a RepoConfig,
+    github: &dyn GithubApi,
+    files: &[String],
+    approvals: &[Approval],
+) -> Result<Vec<&'a str>> {
+    let owners = config
+        .required_reviewers()
+        .iter()
+        .filter(|reviewers| files.iter().any(|file| reviewers.owns(file)));
+    let mut missing = Vec::new();
+    'teams: for reviewers in owners {
+        // A team may own several sets of paths
+        if missing.contains(&reviewers.team()) {
+            continue;
+        }
+
+        let (org, team) = reviewers.org_and_slug();
+        for approval in approvals {
+            if github.is_team_member(org, team, &approval.user).await? {
+                continue 'teams;
+            }
+        }
+        missing.push(reviewers.team());
+    }
+
+    Ok(missing)
+}
+
+/// The protected paths among `files`, the paths a PR changes, if none of the admins are among the
+/// PR's `approvals` which count
+pub async fn missing_admin_approval(
+    config: &RepoConfig,
+    github: &dyn GithubApi,
+    files: &[String],
+    approvals: &[Approval],
+) -> Result<Option<Vec<String>>> {
+    let protected_paths = match config.protected_paths() {
+        Some(protected_paths) => protected_paths,
+        None => return Ok(None),
+    };
+
+    let files = files
+        .iter()
+        .filter(|file| protected_paths.protects(file))
+        .cloned()
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    for admin in protected_paths.admins() {
+        for approval in approvals {
+            let is_admin = match admin.split_once('/') {
+                Some((org, team)) => github.is_team_member(org, team, &approval.user).await?,
+                None => admin.eq_ignore_ascii_case(&approval.user),
+            };
+            if is_admin {
+                return Ok(None);
+            }
+        }
+    }
+
+    Ok(Some(files))
+}
+
+/// Whether a PR changing `lines` lines is a large PR without enough `approvals` which count
+pub fn missing_large_diff_approval(
+    config: &RepoConfig,
+    lines: u64,
+    approvals: &[Approval],
+) -> bool {
+    config
+        .large_diff_lines()
+        .is_some_and(|threshold| lines > threshold && approvals.len() < LARGE_DIFF_APPROVALS)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::RequiredReviewers;
+
+    #[test]
+    fn owns() {
+        let reviewers: RequiredReviewers = toml::from_str(
+            r#"
+            paths = ["consensus/**", "/config/*.toml", "docs", "**/Cargo.lock", "ab?.rs"]
+            team = "@aptos-labs/consensus"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(reviewers.team(), "aptos-labs/consensus");
+        assert_eq!(reviewers.org_and_slug(), ("aptos-labs", "consensus"));
+
+        for path in &[
+            "consensus/src/lib.rs",
+            "config/node.toml",
+            "docs/README.md",
+            "Cargo.lock",
+            "third-party/foo/Cargo.lock",
+            "abc.rs",
+        ] {
+            assert!(reviewers.owns(path), "{}", path);
+        }
+        for path in &[
+            "consensus.rs",
+            "src/consensus/lib.rs",
+            "config/node.yaml",
+            "config.toml",
+            "docsite/index.md",
+            "Cargo.lock.bak",
+            "abcd.rs",
+            "a/c.rs",
+        ] {
+            assert!(!reviewers.owns(path), "{}", path);
+        }
+
+        assert!(toml::from_str::<RequiredReviewers>(
+            r#"
+            paths = ["consensus/**"]
+            team = "consensus"
+            "#,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! An authenticated HTTP API for operators to act on a repo's merge queue without shelling into
+//! the host or crafting Github comments
+//!
+//! Every endpoint is a `POST` under `/admin/repos/<owner>/<name>/` which hands a request to the
+//! repo's event processor and returns once it has been queued, not once it has been handled.
+//! Requests must either present the admin token or a scoped token with the `admin` capability for
+//! the repo or, when logging in with Github is configured, be made by a user who can push to the
+//! repo.
+
+use super::Installation;
+use crate::{
+    command::Command,
+    config::{AdminConfig, Bump, ScopedTokenConfig, TokenCapability},
+    github_api::Permission,
+    state::Repo,
+    Result,
+};
+use hyper::{header::AUTHORIZATION, Body, Request, Response, StatusCode};
+use serde::Deserialize;
+
+/// A command to run on a PR, as if it had been commented on it
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    /// The command, e.g. `/land priority=high`
+    command: String,
+    /// The Github user to run the command as, whose permissions are checked as for commands in
+    /// comments. Ignored for users logged in with Github, who run commands as themselves.
+    sender: Option<String>,
+}
+
+/// Route an authorized `POST` request for a path under `/admin/`, made by `user` if they are
+/// logged in with Github
+pub async fn route(
+    installations: &[Installation],
+    request: Request<Body>,
+    user: Option<String>,
+) -> Result<Response<Body>> {
+    let path = request.uri().path().to_owned();
+    let segments: Vec<_> = path
+        .trim_start_matches("/admin/")
+        .trim_end_matches('/')
+        .split('/')
+        .collect();
+    let (owner, name, rest) = match segments.as_slice() {
+        ["repos", owner, name, rest @ ..] => (*owner, *name, rest),
+        _ => return respond(StatusCode::NOT_FOUND, ""),
+    };
+
+    let event_processor = match installations
+        .iter()
+        .find(|installation| installation.owner() == owner && installation.name() == name)
+    {
+        Some(installation) => installation.event_processor(),
+        None => return respond(StatusCode::NOT_FOUND, ""),
+    };
+
+    match rest {
+        ["pause"] => event_processor.pause().await?,
+        ["resume"] => event_processor.resume().await?,
+        ["sync"] => event_processor.sync().await?,
+        ["process"] => event_processor.process_queue().await?,
+        ["release"] => {
+            let bump = request
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .find_map(|param| param.strip_prefix("bump="));
+            let bump = match bump {
+                Some(bump) => match bump.parse::<Bump>() {
+                    Ok(bump) => Some(bump),
+                    Err(_) => return respond(StatusCode::BAD_REQUEST, "invalid bump"),
+                },
+                None => None,
+            };
+            event_processor.release(bump).await?
+        }
+        ["pulls", number, "command"] => {
+            let number = match number.parse::<u64>() {
+                Ok(number) => number,
+                Err(_) => return respond(StatusCode::NOT_FOUND, ""),
+            };
+            let body = hyper::body::to_bytes(request.into_body()).await?;
+            let command: CommandRequest = match serde_json::from_slice(&body) {
+                Ok(command) => command,
+                Err(_) => return respond(StatusCode::BAD_REQUEST, "invalid command request"),
+            };
+            let sender = match user.or(command.sender) {
+                Some(sender) => sender,
+                None => return respond(StatusCode::BAD_REQUEST, "missing sender"),
+            };
+            if !matches!(Command::from_comment(&command.command), Some(Ok(_))) {
+                return respond(StatusCode::BAD_REQUEST, "invalid command");
+            }
+
+            event_processor
+                .command(sender, number, command.command)
+                .await?
+        }
+        ["pulls", number, action] => {
+            let number = match number.parse::<u64>() {
+                Ok(number) => number,
+                Err(_) => return respond(StatusCode::NOT_FOUND, ""),
+            };
+            match *action {
+                "evict" => event_processor.evict(number).await?,
+                "resync" => event_processor.resync(number).await?,
+                _ => return respond(StatusCode::NOT_FOUND, ""),
+            }
+        }
+        _ => return respond(StatusCode::NOT_FOUND, ""),
+    }
+
+    respond(StatusCode::ACCEPTED, "queued")
+}
+
+/// A configured token which was presented with a request
+#[derive(Clone, Copy, Debug)]
+pub enum Token<'a> {
+    /// The admin token, which is granted everything
+    Admin,
+    Scoped(&'a ScopedTokenConfig),
+}
+
+impl Token<'_> {
+    /// What the token is allowed to do with `repo`
+    pub fn permission(&self, repo: &Repo) -> Permission {
+        match self {
+            Token::Admin => Permission::Write,
+            Token::Scoped(token) if !token.covers(repo) => Permission::None,
+            Token::Scoped(token) => match token.capability {
+                TokenCapability::Read => Permission::Read,
+                TokenCapability::Admin => Permission::Write,
+            },
+        }
+    }
+}
+
+/// The configured token which `request` presents as a bearer token, if any
+pub fn presented_token<'a>(request: &Request<Body>, admin: &'a AdminConfig) -> Option<Token<'a>> {
+    if has_token(request, admin.token()) {
+        return Some(Token::Admin);
+    }
+    admin
+        .tokens
+        .iter()
+        .find(|token| has_token(request, Some(&token.token)))
+        .map(Token::Scoped)
+}
+
+/// Whether a request presents `token`, if one is configured, as a bearer token
+pub fn has_token(request: &Request<Body>, token: Option<&str>) -> bool {
+    let token = match token {
+        Some(token) => token,
+        None => return false,
+    };
+    let presented = match request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(presented) => presented,
+        None => return false,
+    };
+
+    // Compare every byte so that the time taken doesn't leak how much of the token matched
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub fn respond(status: StatusCode, body: &'static str) -> Result<Response<Body>> {
+    Ok(Response::builder().status(status).body(Body::from(body))?)
+}
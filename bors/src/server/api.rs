@@ -0,0 +1,480 @@
+//! A read-only JSON API over the state of each repo, so that other tools, e.g. release dashboards or
+//! chatbots, can consume queue information without scraping comments
+
+use super::Installation;
+use crate::{
+    config::RepoConfig,
+    event_processor::QueueEvent,
+    state::{PullRequestState, Status, TestResult},
+    store::{AuditEntry, CommandSource, Store},
+    Result,
+};
+use futures::future;
+use github::Oid;
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Body, Response, StatusCode,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+/// How often an idle event stream is sent a comment, so that proxies don't time it out
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Serialize)]
+struct ApiRepo<'a> {
+    owner: &'a str,
+    name: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(super) enum ApiStatus {
+    InReview,
+    Queued,
+    Testing,
+    Canary,
+}
+
+/// A PR as exposed by the API. Timestamps are in seconds since the unix epoch.
+#[derive(Debug, Serialize)]
+pub(super) struct ApiPull {
+    pub(super) number: u64,
+    pub(super) title: String,
+    pub(super) author: Option<String>,
+    pub(super) head_ref_name: String,
+    pub(super) head_ref_oid: Oid,
+    pub(super) base_ref_name: String,
+    pub(super) is_draft: bool,
+    pub(super) approved: bool,
+    pub(super) approved_by: Vec<String>,
+    pub(super) mergeable: bool,
+    pub(super) labels: Vec<String>,
+    pub(super) priority: String,
+    pub(super) status: ApiStatus,
+    pub(super) queued_at: Option<u64>,
+    pub(super) merge_oid: Option<Oid>,
+    pub(super) tests_started_at: Option<u64>,
+    pub(super) test_results: BTreeMap<String, TestResult>,
+}
+
+impl ApiPull {
+    pub(super) fn new(pull: PullRequestState, config: &RepoConfig) -> Self {
+        let priority = pull.priority(config).to_string();
+        let is_draft = pull.is_draft();
+
+        let (status, queued_at, merge_oid, tests_started_at, test_results) = match pull.status {
+            Status::InReview => (ApiStatus::InReview, None, None, None, Default::default()),
+            Status::Queued(queued_at) => (
+                ApiStatus::Queued,
+                Some(queued_at),
+                None,
+                None,
+                Default::default(),
+            ),
+            Status::Testing {
+                merge_oid,
+                queued_at,
+                tests_started_at,
+                test_results,
+            } => (
+                ApiStatus::Testing,
+                Some(queued_at),
+                Some(merge_oid),
+                Some(tests_started_at),
+                test_results,
+            ),
+            Status::Canary {
+                merge_oid,
+                tests_started_at,
+                test_results,
+            } => (
+                ApiStatus::Canary,
+                None,
+                Some(merge_oid),
+                Some(tests_started_at),
+                test_results,
+            ),
+        };
+
+        let mut approved_by: Vec<_> = pull.approved_by.into_iter().collect();
+        approved_by.sort_unstable();
+        let mut labels: Vec<_> = pull.labels.into_iter().collect();
+        labels.sort_unstable();
+
+        Self {
+            number: pull.number,
+            title: pull.title,
+            author: pull.author,
+            head_ref_name: pull.head_ref_name,
+            head_ref_oid: pull.head_ref_oid,
+            base_ref_name: pull.base_ref_name,
+            is_draft,
+            approved: pull.approved,
+            approved_by,
+            mergeable: pull.mergeable,
+            labels,
+            priority,
+            status,
+            queued_at: queued_at.map(unix_secs),
+            merge_oid,
+            tests_started_at: tests_started_at.map(unix_secs),
+            test_results: test_results.into_iter().collect(),
+        }
+    }
+}
+
+/// A command from a repo's audit log as exposed by the API
+#[derive(Debug, Serialize)]
+struct ApiAuditEntry {
+    number: u64,
+    user: String,
+    command: String,
+    source: CommandSource,
+    authorized: bool,
+    at: u64,
+}
+
+impl From<AuditEntry> for ApiAuditEntry {
+    fn from(entry: AuditEntry) -> Self {
+        Self {
+            number: entry.number,
+            user: entry.user,
+            command: entry.command,
+            source: entry.source,
+            authorized: entry.authorized,
+            at: unix_secs(entry.at),
+        }
+    }
+}
+
+/// Which entries of a repo's audit log to serve, as given by the `user`, `pr`, `since` and `until`
+/// query parameters. Times are in seconds since the unix epoch, and `until` is exclusive.
+#[derive(Debug, Default, PartialEq)]
+struct AuditFilter {
+    user: Option<String>,
+    number: Option<u64>,
+    since: Option<u64>,
+    until: Option<u64>,
+}
+
+impl AuditFilter {
+    /// Parse a filter from a query string, returning `None` if any parameter is invalid
+    fn from_query(query: Option<&str>) -> Option<Self> {
+        let mut filter = Self::default();
+        for param in query
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .filter(|param| !param.is_empty())
+        {
+            let (key, value) = param.split_once('=')?;
+            match key {
+                "user" => filter.user = Some(value.to_owned()),
+                "pr" => filter.number = Some(value.parse().ok()?),
+                "since" => filter.since = Some(value.parse().ok()?),
+                "until" => filter.until = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+
+        Some(filter)
+    }
+
+    fn matches(&self, entry: &ApiAuditEntry) -> bool {
+        // Github logins are case insensitive
+        self.user
+            .as_ref()
+            .is_none_or(|user| user.eq_ignore_ascii_case(&entry.user))
+            && self.number.is_none_or(|number| number == entry.number)
+            && self.since.is_none_or(|since| entry.at >= since)
+            && self.until.is_none_or(|until| entry.at < until)
+    }
+}
+
+pub(super) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The PRs which are being tested or waiting to be, in the order they will land. `pulls` must
+/// already be sorted by their queue entries, as returned by `Installation::state`.
+pub(super) fn queue(pulls: Vec<PullRequestState>, config: &RepoConfig) -> Vec<ApiPull> {
+    pulls
+        .into_iter()
+        .filter(|pull| pull.status.is_testing() || pull.status.is_queued())
+        .map(|pull| ApiPull::new(pull, config))
+        .collect()
+}
+
+/// Route a `GET` request for a path under `/api/`
+pub async fn route(
+    installations: &[Installation],
+    store: &dyn Store,
+    path: &str,
+    query: Option<&str>,
+) -> Result<Response<Body>> {
+    let segments: Vec<_> = path
+        .trim_start_matches("/api/")
+        .trim_end_matches('/')
+        .split('/')
+        .collect();
+
+    let (owner, name, rest) = match segments.as_slice() {
+        ["repos"] => {
+            let repos: Vec<_> = installations
+                .iter()
+                .map(|installation| ApiRepo {
+                    owner: installation.owner(),
+                    name: installation.name(),
+                })
+                .collect();
+            return json(&repos);
+        }
+        ["repos", owner, name, rest @ ..] => (*owner, *name, rest),
+        ["events"] => return events(installations.iter().map(subscribe).collect()),
+        _ => return not_found(),
+    };
+
+    let installation = match installations
+        .iter()
+        .find(|installation| installation.owner() == owner && installation.name() == name)
+    {
+        Some(installation) => installation,
+        None => return not_found(),
+    };
+    let config = installation.config();
+
+    match rest {
+        ["queue"] => json(&queue(installation.state().await, config)),
+        ["pulls"] => {
+            let pulls: Vec<_> = installation
+                .state()
+                .await
+                .into_iter()
+                .map(|pull| ApiPull::new(pull, config))
+                .collect();
+            json(&pulls)
+        }
+        ["events"] => events(vec![subscribe(installation)]),
+        ["audit"] => {
+            let filter = match AuditFilter::from_query(query) {
+                Some(filter) => filter,
+                None => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(Body::from("invalid audit log filter"))?)
+                }
+            };
+            let entries: Vec<_> = store
+                .audit_log(config.repo())
+                .await?
+                .into_iter()
+                .map(ApiAuditEntry::from)
+                .filter(|entry| filter.matches(entry))
+                .collect();
+            json(&entries)
+        }
+        ["pulls", number] => {
+            let number = match number.parse::<u64>() {
+                Ok(number) => number,
+                Err(_) => return not_found(),
+            };
+            match installation
+                .state()
+                .await
+                .into_iter()
+                .find(|pull| pull.number == number)
+            {
+                Some(pull) => json(&ApiPull::new(pull, config)),
+                None => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+/// A queue event along with the repo it happened to, as streamed to clients
+#[derive(Debug, Serialize)]
+struct RepoQueueEvent<'a> {
+    repo: &'a str,
+    #[serde(flatten)]
+    event: &'a QueueEvent,
+}
+
+/// Stream changes to merge queues as Server-Sent Events, each of which is a `QueueEvent` as JSON
+/// along with the `<owner>/<name>` of its repo. If the client falls too far behind to be sent every
+/// event for a repo, it is sent a `lagged` event with the repo and should refetch its queue.
+fn events(
+    mut subscriptions: Vec<(String, broadcast::Receiver<QueueEvent>)>,
+) -> Result<Response<Body>> {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+        while !subscriptions.is_empty() {
+            let received = tokio::select! {
+                (received, index, _) = future::select_all(
+                    subscriptions
+                        .iter_mut()
+                        .map(|(_, queue_events)| Box::pin(queue_events.recv())),
+                ) => Some((index, received)),
+                _ = keep_alive.tick() => None,
+            };
+
+            let message = match received {
+                Some((index, Ok(event))) => {
+                    let event = RepoQueueEvent {
+                        repo: &subscriptions[index].0,
+                        event: &event,
+                    };
+                    match serde_json::to_string(&event) {
+                        Ok(data) => format!("data: {}\n\n", data),
+                        Err(e) => {
+                            warn!("Error while serializing queue event: {:?}", e);
+                            continue;
+                        }
+                    }
+                }
+                Some((index, Err(RecvError::Lagged(_)))) => format!(
+                    "event: lagged\ndata: {}\n\n",
+                    serde_json::json!({ "repo": subscriptions[index].0 })
+                ),
+                Some((index, Err(RecvError::Closed))) => {
+                    subscriptions.swap_remove(index);
+                    continue;
+                }
+                None => ":\n\n".to_owned(),
+            };
+
+            // The client has gone away
+            if sender.send_data(message.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body)?)
+}
+
+/// Subscribe to the queue events of `installation`
+fn subscribe(installation: &Installation) -> (String, broadcast::Receiver<QueueEvent>) {
+    (
+        format!("{}/{}", installation.owner(), installation.name()),
+        installation.event_processor().subscribe(),
+    )
+}
+
+fn json<T: Serialize>(value: &T) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string_pretty(value)?))?)
+}
+
+fn not_found() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::{oid, FakeGithub};
+    use std::time::Duration;
+
+    #[test]
+    fn queue_order() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        let queued_at = UNIX_EPOCH + Duration::from_secs(100);
+
+        let mut pulls: Vec<_> = (1..=3)
+            .map(|number| PullRequestState::from_pull_request(&github.open_pull(number, "head")))
+            .collect();
+        pulls[0].status = Status::testing(oid("merge"), queued_at);
+        pulls[1].status = Status::Queued(queued_at);
+        pulls.sort_unstable_by_key(|pull| pull.to_queue_entry(&config));
+
+        let queue = queue(pulls, &config);
+        assert_eq!(
+            queue.iter().map(|pull| pull.number).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(queue[0].status, ApiStatus::Testing);
+        assert_eq!(queue[0].merge_oid, Some(oid("merge")));
+        assert_eq!(queue[0].queued_at, Some(100));
+        assert_eq!(queue[1].status, ApiStatus::Queued);
+        assert_eq!(queue[1].priority, "normal");
+
+        let json = serde_json::to_value(&queue[0]).unwrap();
+        assert_eq!(json["status"], "testing");
+    }
+
+    #[test]
+    fn audit_filter() {
+        assert_eq!(AuditFilter::from_query(None), Some(AuditFilter::default()));
+        let filter = AuditFilter::from_query(Some("user=Alice&pr=5&since=100&until=200")).unwrap();
+        assert_eq!(
+            filter,
+            AuditFilter {
+                user: Some("Alice".to_owned()),
+                number: Some(5),
+                since: Some(100),
+                until: Some(200),
+            }
+        );
+        assert_eq!(AuditFilter::from_query(Some("pr=five")), None);
+        assert_eq!(AuditFilter::from_query(Some("who=alice")), None);
+
+        let entry = |user: &str, number, at| ApiAuditEntry {
+            number,
+            user: user.to_owned(),
+            command: "/land".to_owned(),
+            source: CommandSource::Comment,
+            authorized: true,
+            at,
+        };
+        assert!(filter.matches(&entry("alice", 5, 100)));
+        assert!(!filter.matches(&entry("bob", 5, 100)));
+        assert!(!filter.matches(&entry("alice", 6, 100)));
+        assert!(!filter.matches(&entry("alice", 5, 99)));
+        assert!(!filter.matches(&entry("alice", 5, 200)));
+    }
+
+    #[test]
+    fn repo_queue_event() {
+        let event = RepoQueueEvent {
+            repo: "Codertocat/Hello-World",
+            event: &QueueEvent::Dequeued {
+                number: 5,
+                author: None,
+                evicted: false,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "repo": "Codertocat/Hello-World",
+                "event": "dequeued",
+                "number": 5,
+                "author": null,
+                "evicted": false,
+            })
+        );
+    }
+}
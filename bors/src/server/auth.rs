@@ -0,0 +1,382 @@
+//! Logging in with Github. When an OAuth app is configured, each repo's dashboard and APIs are
+//! only available to users who can see the repo on Github, and acting on its queue to those who
+//! can push to it.
+
+use crate::{
+    config::GithubConfig,
+    github_api::{GithubApi, Permission},
+    graphql::GithubClient,
+    state::Repo,
+    Result,
+};
+use anyhow::anyhow;
+use hyper::{
+    header::{ACCEPT, COOKIE, LOCATION, SET_COOKIE},
+    Body, Request, Response, StatusCode,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+const AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+const ACCESS_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+
+const SESSION_COOKIE: &str = "bors_session";
+/// How long a user stays logged in
+const SESSION_TTL: Duration = Duration::from_secs(8 * 60 * 60);
+/// How long a user has to complete a login on Github
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// How long a user's permission on a repo is cached before it is looked up again, bounding how
+/// long a user keeps access after it is revoked on Github
+const PERMISSION_TTL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug)]
+struct PendingLogin {
+    /// Where to send the user once they have logged in
+    next: String,
+    started_at: Instant,
+}
+
+#[derive(Debug)]
+struct Session {
+    user: String,
+    created_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Auth {
+    github_config: GithubConfig,
+    client_id: String,
+    client_secret: String,
+    /// Client used to look up users' permissions, authenticated as bors
+    github: Box<dyn GithubApi>,
+    http: reqwest::Client,
+
+    /// Logins waiting on Github to redirect back, keyed by the `state` they were started with
+    logins: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    permissions: Mutex<HashMap<(String, Repo), (Permission, Instant)>>,
+}
+
+impl Auth {
+    /// Returns `None` unless an OAuth app is configured
+    pub fn from_config(config: &GithubConfig) -> Option<Self> {
+        let github = Box::new(GithubClient::new(config));
+        Self::with_client(config, github)
+    }
+
+    fn with_client(config: &GithubConfig, github: Box<dyn GithubApi>) -> Option<Self> {
+        Some(Self {
+            github_config: config.clone(),
+            client_id: config.client_id.clone()?,
+            client_secret: config.client_secret.clone()?,
+            github,
+            http: reqwest::Client::new(),
+            logins: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            permissions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Send the user to Github to log in, after which they are sent back to the `next` path
+    pub fn login(&self, query: Option<&str>) -> Result<Response<Body>> {
+        // Only redirect within bors once logged in
+        let next = query_param(query, "next")
+            .filter(|next| next.starts_with('/') && !next.starts_with("//"))
+            .unwrap_or("/")
+            .to_owned();
+
+        let state = random_token();
+        let mut logins = self.logins.lock().unwrap();
+        logins.retain(|_, login| login.started_at.elapsed() < LOGIN_TTL);
+        logins.insert(
+            state.clone(),
+            PendingLogin {
+                next,
+                started_at: Instant::now(),
+            },
+        );
+
+        redirect(&format!(
+            "{}?client_id={}&state={}&allow_signup=false",
+            AUTHORIZE_URL, self.client_id, state
+        ))
+    }
+
+    /// Complete a login once Github has redirected the user back to bors
+    pub async fn callback(&self, query: Option<&str>) -> Result<Response<Body>> {
+        let login = query_param(query, "state").and_then(|state| {
+            self.logins
+                .lock()
+                .unwrap()
+                .remove(state)
+                .filter(|login| login.started_at.elapsed() < LOGIN_TTL)
+        });
+        let (login, code) = match (login, query_param(query, "code")) {
+            (Some(login), Some(code)) => (login, code),
+            _ => {
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("invalid or expired login, try logging in again"))?)
+            }
+        };
+
+        let token = self.exchange_code(code).await?;
+        let user = GithubClient::with_token(&self.github_config, &token)
+            .users()
+            .get_authenticated()
+            .await?
+            .into_inner()
+            .login;
+        info!("{} logged in", user);
+
+        let session_id = random_token();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.created_at.elapsed() < SESSION_TTL);
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                user,
+                created_at: Instant::now(),
+            },
+        );
+
+        Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header(LOCATION, login.next)
+            .header(
+                SET_COOKIE,
+                format!(
+                    "{}={}; Max-Age={}; Path=/; HttpOnly; Secure; SameSite=Lax",
+                    SESSION_COOKIE,
+                    session_id,
+                    SESSION_TTL.as_secs()
+                ),
+            )
+            .body(Body::empty())?)
+    }
+
+    pub fn logout(&self, request: &Request<Body>) -> Result<Response<Body>> {
+        if let Some(session_id) = session_id(request) {
+            self.sessions.lock().unwrap().remove(session_id);
+        }
+
+        Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header(LOCATION, "/")
+            .header(
+                SET_COOKIE,
+                format!("{}=; Max-Age=0; Path=/", SESSION_COOKIE),
+            )
+            .body(Body::empty())?)
+    }
+
+    /// The user logged in to make `request`, if any
+    pub fn user(&self, request: &Request<Body>) -> Option<String> {
+        let session_id = session_id(request)?;
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .filter(|session| session.created_at.elapsed() < SESSION_TTL)
+            .map(|session| session.user.clone())
+    }
+
+    /// What `user` may do with `repo`
+    pub async fn permission(&self, user: &str, repo: &Repo) -> Result<Permission> {
+        let key = (user.to_owned(), repo.clone());
+        if let Some((permission, looked_up_at)) = self.permissions.lock().unwrap().get(&key) {
+            if looked_up_at.elapsed() < PERMISSION_TTL {
+                return Ok(*permission);
+            }
+        }
+
+        let level = self
+            .github
+            .collaborator_permission(repo.owner(), repo.name(), user)
+            .await?;
+        let permission = Permission::from_level(&level);
+        self.permissions
+            .lock()
+            .unwrap()
+            .insert(key, (permission, Instant::now()));
+
+        Ok(permission)
+    }
+
+    async fn exchange_code(&self, code: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct AccessTokenResponse {
+            access_token: Option<String>,
+            error_description: Option<String>,
+        }
+
+        let response = self
+            .http
+            .post(ACCESS_TOKEN_URL)
+            .header(ACCEPT, "application/json")
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("code", code),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let AccessTokenResponse {
+            access_token,
+            error_description,
+        } = serde_json::from_slice(&response)?;
+        access_token.ok_or_else(|| {
+            anyhow!(
+                "unable to exchange OAuth code for a token: {}",
+                error_description.unwrap_or_default()
+            )
+        })
+    }
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|param| {
+            param
+                .strip_prefix(name)
+                .and_then(|param| param.strip_prefix('='))
+        })
+}
+
+fn session_id(request: &Request<Body>) -> Option<&str> {
+    request
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(SESSION_COOKIE)
+                .and_then(|cookie| cookie.strip_prefix('='))
+        })
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn redirect(location: &str) -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, location)
+        .body(Body::empty())?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeGithub;
+
+    fn auth(github: &FakeGithub) -> Auth {
+        let config = GithubConfig {
+            github_api_token: "".to_owned(),
+            webhook_secret: None,
+            previous_webhook_secret: None,
+            require_webhook_signature: false,
+            timeout_seconds: None,
+            client_id: Some("client".to_owned()),
+            client_secret: Some("secret".to_owned()),
+        };
+        Auth::with_client(&config, Box::new(github.clone())).unwrap()
+    }
+
+    fn request_with_cookie(cookie: &str) -> Request<Body> {
+        Request::builder()
+            .header(COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn permissions() {
+        let github = FakeGithub::new();
+        github.add_collaborator("alice");
+        github.add_reader("bob");
+        github.add_admin("carol");
+        let auth = auth(&github);
+        let repo = Repo::new("Codertocat", "Hello-World");
+
+        assert_eq!(
+            auth.permission("alice", &repo).await.unwrap(),
+            Permission::Write
+        );
+        assert_eq!(
+            auth.permission("carol", &repo).await.unwrap(),
+            Permission::Admin
+        );
+        assert!(Permission::Admin > Permission::Write);
+        assert_eq!(
+            auth.permission("bob", &repo).await.unwrap(),
+            Permission::Read
+        );
+        assert_eq!(
+            auth.permission("eve", &repo).await.unwrap(),
+            Permission::None
+        );
+
+        // Permissions are cached for a while
+        github.add_collaborator("bob");
+        assert_eq!(
+            auth.permission("bob", &repo).await.unwrap(),
+            Permission::Read
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        let auth = auth(&FakeGithub::new());
+        auth.sessions.lock().unwrap().insert(
+            "abc".to_owned(),
+            Session {
+                user: "alice".to_owned(),
+                created_at: Instant::now(),
+            },
+        );
+
+        let request = request_with_cookie("theme=dark; bors_session=abc");
+        assert_eq!(auth.user(&request).as_deref(), Some("alice"));
+        assert_eq!(auth.user(&request_with_cookie("bors_session=xyz")), None);
+        assert_eq!(auth.user(&request_with_cookie("bors_sessionx=abc")), None);
+
+        auth.logout(&request).unwrap();
+        assert_eq!(auth.user(&request), None);
+    }
+
+    #[test]
+    fn login_redirect() {
+        let auth = auth(&FakeGithub::new());
+
+        let response = auth
+            .login(Some("next=/repos/Codertocat/Hello-World"))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers()[LOCATION].to_str().unwrap();
+        assert!(location.starts_with("https://github.com/login/oauth/authorize?client_id=client"));
+
+        // Logging in can't be used to redirect to another site
+        auth.login(Some("next=//example.com")).unwrap();
+        let logins = auth.logins.lock().unwrap();
+        let mut next: Vec<_> = logins.values().map(|login| login.next.as_str()).collect();
+        next.sort_unstable();
+        assert_eq!(next, ["/", "/repos/Codertocat/Hello-World"]);
+    }
+}
@@ -0,0 +1,72 @@
+//! A status badge for each repo, served as an SVG so that it can be embedded in e.g. READMEs and
+//! wikis, showing whether the tree is open and how many PRs are queued
+//!
+//! The tree is closed while the repo's merge queue is paused.
+
+const LABEL: &str = "bors";
+const OPEN_COLOR: &str = "#4c1";
+const CLOSED_COLOR: &str = "#e05d44";
+const LABEL_COLOR: &str = "#555";
+
+/// Render a badge for a repo with `queued` PRs being tested or waiting to be
+pub fn render(queued: usize, paused: bool) -> String {
+    let (state, color) = if paused {
+        ("closed", CLOSED_COLOR)
+    } else {
+        ("open", OPEN_COLOR)
+    };
+    let message = format!("{} | {} queued", state, queued);
+
+    let label_width = text_width(LABEL);
+    let message_width = text_width(&message);
+    let width = label_width + message_width;
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)">
+<rect width="{label_width}" height="20" fill="{label_color}"/>
+<rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/>
+<rect width="{width}" height="20" fill="url(#s)"/>
+</g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="14">{label}</text>
+<text x="{message_x}" y="14">{message}</text>
+</g>
+</svg>
+"##,
+        width = width,
+        label = LABEL,
+        message = message,
+        label_width = label_width,
+        message_width = message_width,
+        label_color = LABEL_COLOR,
+        color = color,
+        label_x = label_width / 2,
+        message_x = label_width + message_width / 2,
+    )
+}
+
+/// An approximation of the width of `text` in 11px Verdana, plus padding
+fn text_width(text: &str) -> usize {
+    text.chars().count() * 7 + 10
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn badge() {
+        let open = render(3, false);
+        assert!(open.contains("bors: open | 3 queued"));
+        assert!(open.contains(OPEN_COLOR));
+
+        let closed = render(0, true);
+        assert!(closed.contains("bors: closed | 0 queued"));
+        assert!(closed.contains(CLOSED_COLOR));
+        assert!(closed.contains(r#"width="167""#));
+    }
+}
@@ -0,0 +1,211 @@
+//! A feed of each repo's scheduled freeze windows, upcoming release train departures and recent
+//! landings, served both as iCal so that release managers can subscribe from their calendars and as
+//! JSON for other tooling
+
+use super::api::unix_secs;
+use crate::{
+    config::RepoConfig,
+    store::{LandingAttempt, LandingResult},
+};
+use serde::Serialize;
+use std::time::{Duration, SystemTime};
+
+/// How far ahead release train departures are published
+const DEPARTURES_HORIZON: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+/// How far back landings are published
+const LANDINGS_HORIZON: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FeedEventKind {
+    Freeze,
+    ReleaseTrain,
+    Landing,
+}
+
+/// An event in a repo's feed. Timestamps are in seconds since the unix epoch.
+#[derive(Debug, Serialize)]
+pub struct FeedEvent {
+    kind: FeedEventKind,
+    summary: String,
+    start: u64,
+    end: u64,
+    /// The PR which landed, for landings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<u64>,
+}
+
+/// The feed of a repo with the merge history `landings`, ordered by when each event starts
+pub fn events(config: &RepoConfig, landings: &[LandingAttempt], now: SystemTime) -> Vec<FeedEvent> {
+    let freezes = config.freezes().iter().map(|freeze| FeedEvent {
+        kind: FeedEventKind::Freeze,
+        summary: format!("Freeze: {}", freeze.name),
+        start: unix_secs(freeze.start),
+        end: unix_secs(freeze.end),
+        number: None,
+    });
+
+    let departures = config.release_trains().iter().flat_map(|train| {
+        train
+            .departures(now, now + DEPARTURES_HORIZON)
+            .map(move |departure| FeedEvent {
+                kind: FeedEventKind::ReleaseTrain,
+                summary: format!("Release train: {}", train.name),
+                start: unix_secs(departure),
+                end: unix_secs(departure),
+                number: None,
+            })
+    });
+
+    let since = now - LANDINGS_HORIZON;
+    let landings = landings
+        .iter()
+        .filter(|attempt| attempt.result == LandingResult::Landed && attempt.finished_at >= since)
+        .map(|attempt| FeedEvent {
+            kind: FeedEventKind::Landing,
+            summary: format!("Landed #{}", attempt.number),
+            start: unix_secs(attempt.started_at),
+            end: unix_secs(attempt.finished_at),
+            number: Some(attempt.number),
+        });
+
+    let mut events: Vec<_> = freezes.chain(departures).chain(landings).collect();
+    events.sort_by_key(|event| event.start);
+    events
+}
+
+/// Render a repo's feed as an iCalendar
+pub fn ical(config: &RepoConfig, events: &[FeedEvent], now: SystemTime) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        "PRODID:-//bors//merge feed//EN".to_owned(),
+        format!(
+            "X-WR-CALNAME:{}",
+            escape(&format!("{}/{}", config.owner(), config.name()))
+        ),
+    ];
+
+    for event in events {
+        // Stable across requests so that calendars update events rather than duplicating them
+        let uid: String = event
+            .summary
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        lines.push("BEGIN:VEVENT".to_owned());
+        lines.push(format!(
+            "UID:{}-{}@{}.{}",
+            uid,
+            event.start,
+            escape(config.name()),
+            escape(config.owner()),
+        ));
+        lines.push(format!("DTSTAMP:{}", timestamp(unix_secs(now))));
+        lines.push(format!("DTSTART:{}", timestamp(event.start)));
+        lines.push(format!("DTEND:{}", timestamp(event.end)));
+        lines.push(format!("SUMMARY:{}", escape(&event.summary)));
+        if let Some(number) = event.number {
+            lines.push(format!(
+                "URL:https://github.com/{}/{}/pull/{}",
+                config.owner(),
+                config.name(),
+                number
+            ));
+        }
+        lines.push("END:VEVENT".to_owned());
+    }
+
+    lines.push("END:VCALENDAR".to_owned());
+
+    // iCalendar requires CRLF line endings
+    let mut ical = lines.join("\r\n");
+    ical.push_str("\r\n");
+    ical
+}
+
+/// Format seconds since the unix epoch as an iCalendar UTC date-time, e.g. `20200828T090000Z`
+fn timestamp(secs: u64) -> String {
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    humantime::format_rfc3339_seconds(time)
+        .to_string()
+        .replace(&['-', ':'][..], "")
+}
+
+/// Escape text for an iCalendar property value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use github::Oid;
+    use std::time::UNIX_EPOCH;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn feed() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+
+            [[freezes]]
+            name = "1.0, final"
+            start = "1970-01-20T00:00:00Z"
+            end = "1970-01-22T00:00:00Z"
+
+            [[release-trains]]
+            name = "weekly"
+            first-departure = "1970-01-01T09:00:00Z"
+            interval-days = 7
+            "#,
+        )
+        .unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(20 * DAY);
+
+        let landing = |number, result, finished_at| LandingAttempt {
+            number,
+            merge_oid: Oid::from_str("deadbeef"),
+            queued_at: None,
+            started_at: UNIX_EPOCH + Duration::from_secs(finished_at - 60),
+            finished_at: UNIX_EPOCH + Duration::from_secs(finished_at),
+            result,
+            passed_checks: vec![],
+            failing_checks: vec![],
+        };
+        let landings = [
+            // Too long ago
+            landing(1, LandingResult::Landed, 5 * DAY),
+            landing(2, LandingResult::Failed, 19 * DAY),
+            landing(3, LandingResult::Landed, 19 * DAY),
+        ];
+
+        let events = events(&config, &landings, now);
+        let kinds: Vec<_> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds[..3],
+            [
+                FeedEventKind::Landing,
+                FeedEventKind::Freeze,
+                FeedEventKind::ReleaseTrain,
+            ]
+        );
+        assert_eq!(events[0].number, Some(3));
+        // Trains depart on days 21, 28, .. 105
+        assert_eq!(events[2].start, 21 * DAY + 9 * 60 * 60);
+        assert_eq!(events.len(), 2 + 13);
+
+        let ical = ical(&config, &events, now);
+        assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ical.ends_with("END:VCALENDAR\r\n"));
+        assert!(ical.contains("SUMMARY:Freeze: 1.0\\, final\r\n"));
+        assert!(ical.contains("DTSTART:19700122T090000Z\r\n"));
+        assert!(ical.contains("URL:https://github.com/Codertocat/Hello-World/pull/3\r\n"));
+    }
+}
@@ -0,0 +1,416 @@
+//! A GraphQL API over the state of each repo, for clients which need more than the JSON API offers,
+//! e.g. filtering PRs or paging through a repo's merge history
+//!
+//! PRs are exposed with the same fields as in the JSON API and lists are exposed as connections,
+//! whose cursors are opaque offsets into the list.
+
+use super::{
+    api::{self, ApiPull, ApiStatus},
+    Installation,
+};
+use crate::{
+    store::{LandingAttempt, LandingResult, Store},
+    Result,
+};
+use async_graphql::{
+    connection::{self, Connection, Edge},
+    EmptyMutation, EmptySubscription, Enum, Json, Object, OutputType, Schema,
+};
+use hyper::{body, header::CONTENT_TYPE, Body, Request, Response, StatusCode};
+use std::{collections::BTreeMap, sync::Arc};
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "Status", remote = "ApiStatus")]
+enum GraphQLStatus {
+    InReview,
+    Queued,
+    Testing,
+    Canary,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+#[graphql(name = "LandingResult", remote = "LandingResult")]
+enum GraphQLLandingResult {
+    Landed,
+    Failed,
+    TimedOut,
+    MergeFailed,
+}
+
+struct QueryRoot {
+    installations: Vec<Installation>,
+    store: Arc<dyn Store>,
+}
+
+#[Object]
+impl QueryRoot {
+    async fn repos(&self) -> Vec<Repo> {
+        self.installations
+            .iter()
+            .map(|installation| self.repo_for(installation))
+            .collect()
+    }
+
+    async fn repo(&self, owner: String, name: String) -> Option<Repo> {
+        self.installations
+            .iter()
+            .find(|installation| installation.owner() == owner && installation.name() == name)
+            .map(|installation| self.repo_for(installation))
+    }
+}
+
+impl QueryRoot {
+    fn repo_for(&self, installation: &Installation) -> Repo {
+        Repo {
+            installation: installation.clone(),
+            store: self.store.clone(),
+        }
+    }
+}
+
+struct Repo {
+    installation: Installation,
+    store: Arc<dyn Store>,
+}
+
+#[Object]
+impl Repo {
+    async fn owner(&self) -> &str {
+        self.installation.owner()
+    }
+
+    async fn name(&self) -> &str {
+        self.installation.name()
+    }
+
+    /// The PRs which are being tested or waiting to be, in the order they will land
+    async fn queue(
+        &self,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Connection<usize, ApiPull>> {
+        let config = self.installation.config();
+        let queue = api::queue(self.installation.state().await, config);
+        paginate(queue, after, first).await
+    }
+
+    /// The PRs tracked by bors, in queue order
+    async fn pulls(
+        &self,
+        status: Option<GraphQLStatus>,
+        label: Option<String>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Connection<usize, ApiPull>> {
+        let config = self.installation.config();
+        let status = status.map(ApiStatus::from);
+        let pulls = self
+            .installation
+            .state()
+            .await
+            .into_iter()
+            .map(|pull| ApiPull::new(pull, config))
+            .filter(|pull| status.is_none() || status == Some(pull.status))
+            .filter(|pull| label.iter().all(|label| pull.labels.contains(label)))
+            .collect();
+        paginate(pulls, after, first).await
+    }
+
+    async fn pull(&self, number: u64) -> Option<ApiPull> {
+        let config = self.installation.config();
+        self.installation
+            .state()
+            .await
+            .into_iter()
+            .find(|pull| pull.number == number)
+            .map(|pull| ApiPull::new(pull, config))
+    }
+
+    /// The attempts to land PRs, most recent first
+    async fn history(
+        &self,
+        number: Option<u64>,
+        result: Option<GraphQLLandingResult>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> async_graphql::Result<Connection<usize, LandingAttempt>> {
+        let result = result.map(LandingResult::from);
+        let history = self
+            .store
+            .landings(self.installation.config().repo())
+            .await?
+            .into_iter()
+            .rev()
+            .filter(|attempt| number.is_none() || number == Some(attempt.number))
+            .filter(|attempt| result.is_none() || result == Some(attempt.result))
+            .collect();
+        paginate(history, after, first).await
+    }
+}
+
+/// A PR, with the same fields as in the JSON API. Timestamps are in seconds since the unix epoch.
+#[Object(name = "Pull")]
+impl ApiPull {
+    async fn number(&self) -> u64 {
+        self.number
+    }
+
+    async fn title(&self) -> &str {
+        &self.title
+    }
+
+    async fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    async fn head_ref_name(&self) -> &str {
+        &self.head_ref_name
+    }
+
+    async fn head_ref_oid(&self) -> String {
+        self.head_ref_oid.to_string()
+    }
+
+    async fn base_ref_name(&self) -> &str {
+        &self.base_ref_name
+    }
+
+    async fn is_draft(&self) -> bool {
+        self.is_draft
+    }
+
+    async fn approved(&self) -> bool {
+        self.approved
+    }
+
+    async fn approved_by(&self) -> &[String] {
+        &self.approved_by
+    }
+
+    async fn mergeable(&self) -> bool {
+        self.mergeable
+    }
+
+    async fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    async fn priority(&self) -> &str {
+        &self.priority
+    }
+
+    async fn status(&self) -> GraphQLStatus {
+        self.status.into()
+    }
+
+    async fn queued_at(&self) -> Option<u64> {
+        self.queued_at
+    }
+
+    async fn merge_oid(&self) -> Option<String> {
+        self.merge_oid.as_ref().map(ToString::to_string)
+    }
+
+    async fn tests_started_at(&self) -> Option<u64> {
+        self.tests_started_at
+    }
+
+    /// The results of the PR's checks, keyed by the name of the check, as persisted by bors
+    async fn test_results(&self) -> Json<BTreeMap<String, crate::state::TestResult>> {
+        Json(self.test_results.clone())
+    }
+}
+
+/// An attempt to land a PR. Timestamps are in seconds since the unix epoch.
+#[Object(name = "LandingAttempt")]
+impl LandingAttempt {
+    async fn number(&self) -> u64 {
+        self.number
+    }
+
+    async fn merge_oid(&self) -> String {
+        self.merge_oid.to_string()
+    }
+
+    /// When the PR was queued, which isn't known for attempts recorded by older versions of bors
+    async fn queued_at(&self) -> Option<u64> {
+        self.queued_at.map(api::unix_secs)
+    }
+
+    async fn started_at(&self) -> u64 {
+        api::unix_secs(self.started_at)
+    }
+
+    async fn finished_at(&self) -> u64 {
+        api::unix_secs(self.finished_at)
+    }
+
+    async fn result(&self) -> GraphQLLandingResult {
+        self.result.into()
+    }
+
+    async fn passed_checks(&self) -> &[String] {
+        &self.passed_checks
+    }
+
+    async fn failing_checks(&self) -> &[String] {
+        &self.failing_checks
+    }
+}
+
+/// Page through `items`, where the cursor of each item is its offset in `items`
+async fn paginate<T: OutputType>(
+    items: Vec<T>,
+    after: Option<String>,
+    first: Option<i32>,
+) -> async_graphql::Result<Connection<usize, T>> {
+    connection::query(
+        after,
+        None,
+        first,
+        None,
+        |after: Option<usize>, _, first: Option<usize>, _| async move {
+            let start = after.map_or(0, |after| after + 1).min(items.len());
+            let end = first.map_or(items.len(), |first| (start + first).min(items.len()));
+
+            let mut connection = Connection::new(start > 0, end < items.len());
+            connection.edges.extend(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(end - start)
+                    .map(|(offset, item)| Edge::new(offset, item)),
+            );
+            Ok::<_, async_graphql::Error>(connection)
+        },
+    )
+    .await
+}
+
+fn schema(
+    installations: Vec<Installation>,
+    store: Arc<dyn Store>,
+) -> Schema<QueryRoot, EmptyMutation, EmptySubscription> {
+    Schema::new(
+        QueryRoot {
+            installations,
+            store,
+        },
+        EmptyMutation,
+        EmptySubscription,
+    )
+}
+
+/// Execute a GraphQL query `POST`ed to `/api/graphql`
+pub async fn route(
+    installations: Vec<Installation>,
+    store: Arc<dyn Store>,
+    request: Request<Body>,
+) -> Result<Response<Body>> {
+    let body = body::to_bytes(request.into_body()).await?;
+    let query = match serde_json::from_slice::<async_graphql::Request>(&body) {
+        Ok(query) => query,
+        Err(e) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("invalid GraphQL request: {}", e)))?)
+        }
+    };
+
+    let response = schema(installations, store).execute(query).await;
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&response)?))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{config::RepoConfig, event_processor::EventProcessorSender, store::SqliteStore};
+    use async_graphql::value;
+    use github::Oid;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn history() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            "#,
+        )
+        .unwrap();
+        let store = Arc::new(SqliteStore::in_memory().unwrap());
+        let results = [
+            LandingResult::Landed,
+            LandingResult::Failed,
+            LandingResult::Landed,
+            LandingResult::Landed,
+        ];
+        for (number, result) in (1..).zip(results.iter().copied()) {
+            let started_at = UNIX_EPOCH + Duration::from_secs(number * 100);
+            let attempt = LandingAttempt {
+                number,
+                merge_oid: Oid::from_str("deadbeef"),
+                queued_at: None,
+                started_at,
+                finished_at: started_at + Duration::from_secs(10),
+                result,
+                passed_checks: vec![],
+                failing_checks: vec![],
+            };
+            store.record_landing(config.repo(), &attempt).await.unwrap();
+        }
+
+        let (tx, _rx) = EventProcessorSender::channel(config.event_queue_capacity());
+        let schema = schema(vec![Installation::new(config, tx)], store);
+
+        let query = r#"{
+            repo(owner: "Codertocat", name: "Hello-World") {
+                history(result: LANDED, first: 2) {
+                    edges { cursor node { number result finishedAt } }
+                    pageInfo { hasNextPage endCursor }
+                }
+            }
+        }"#;
+        let data = schema.execute(query).await.into_result().unwrap().data;
+        let history = &value!({
+            "repo": {
+                "history": {
+                    "edges": [
+                        { "cursor": "0", "node": { "number": 4, "result": "LANDED", "finishedAt": 410 } },
+                        { "cursor": "1", "node": { "number": 3, "result": "LANDED", "finishedAt": 310 } },
+                    ],
+                    "pageInfo": { "hasNextPage": true, "endCursor": "1" },
+                }
+            }
+        });
+        assert_eq!(&data, history);
+
+        let query = r#"{
+            repo(owner: "Codertocat", name: "Hello-World") {
+                history(result: LANDED, after: "1") {
+                    edges { node { number } }
+                    pageInfo { hasPreviousPage hasNextPage }
+                }
+            }
+        }"#;
+        let data = schema.execute(query).await.into_result().unwrap().data;
+        assert_eq!(
+            data,
+            value!({
+                "repo": {
+                    "history": {
+                        "edges": [{ "node": { "number": 1 } }],
+                        "pageInfo": { "hasPreviousPage": true, "hasNextPage": false },
+                    }
+                }
+            })
+        );
+
+        let query = r#"{ repo(owner: "Codertocat", name: "Goodbye-World") { name } }"#;
+        let data = schema.execute(query).await.into_result().unwrap().data;
+        assert_eq!(data, value!({ "repo": null }));
+    }
+}
@@ -0,0 +1,520 @@
+//! The state API and admin operations served over gRPC, as defined by `proto/bors.proto`, for
+//! infrastructure which prefers typed clients over JSON
+//!
+//! Methods are routed by hand rather than by code generated with `tonic-build`, so that building
+//! bors doesn't require `protoc`. Every call must present the admin token or a scoped token which
+//! grants what the call needs of its repo.
+
+mod proto;
+
+use super::{
+    admin::{self, Token},
+    api::{self, ApiPull, ApiStatus},
+    Installation,
+};
+use crate::{config::AdminConfig, github_api::Permission, state::Repo, Result};
+use futures::future::{self, Future};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request as HttpRequest, Response as HttpResponse, Server as HyperServer,
+};
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::RwLock;
+use tonic::{
+    body::BoxBody, codec::ProstCodec, codegen::Service, server::Grpc, Request, Response, Status,
+};
+use tracing::info;
+
+/// Prefix of the path of each of the service's methods
+const SERVICE_PATH: &str = "/bors.v1.Bors/";
+
+type GrpcResult<T> = std::result::Result<Response<T>, Status>;
+
+#[derive(Clone, Debug)]
+pub struct BorsService {
+    installations: Arc<RwLock<Vec<Installation>>>,
+    admin: AdminConfig,
+}
+
+impl BorsService {
+    pub fn new(installations: Arc<RwLock<Vec<Installation>>>, admin: AdminConfig) -> Self {
+        Self {
+            installations,
+            admin,
+        }
+    }
+
+    /// Serve the service on `addr` until `shutdown` completes
+    pub async fn serve<F>(self, addr: SocketAddr, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let make_service = make_service_fn(move |_| {
+            let service = self.clone();
+            future::ok::<_, Infallible>(service_fn(move |request| {
+                let service = service.clone();
+                async move { Ok::<_, Infallible>(service.route(request).await) }
+            }))
+        });
+
+        let server = HyperServer::try_bind(&addr)?
+            .http2_only(true)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown);
+        info!("Serving gRPC on {}", addr);
+        server.await?;
+
+        Ok(())
+    }
+
+    async fn route(&self, request: HttpRequest<Body>) -> HttpResponse<BoxBody> {
+        let token = match admin::presented_token(&request, &self.admin) {
+            Some(token) => token,
+            None => return Status::unauthenticated("missing or invalid token").to_http(),
+        };
+
+        let method = request
+            .uri()
+            .path()
+            .strip_prefix(SERVICE_PATH)
+            .unwrap_or_default()
+            .to_owned();
+        match method.as_str() {
+            "ListRepos" => unary(request, |request| self.list_repos(token, request)).await,
+            "GetQueue" => unary(request, |request| self.get_queue(token, request)).await,
+            "ListPulls" => unary(request, |request| self.list_pulls(token, request)).await,
+            "GetPull" => unary(request, |request| self.get_pull(token, request)).await,
+            "Pause" => unary(request, |request| self.pause(token, request)).await,
+            "Resume" => unary(request, |request| self.resume(token, request)).await,
+            "Sync" => unary(request, |request| self.sync(token, request)).await,
+            "ProcessQueue" => unary(request, |request| self.process_queue(token, request)).await,
+            "Evict" => unary(request, |request| self.evict(token, request)).await,
+            "Resync" => unary(request, |request| self.resync(token, request)).await,
+            _ => Status::unimplemented(format!("unknown method {}", method)).to_http(),
+        }
+    }
+
+    /// The installation for `repo`, which `token` must grant `required` for
+    async fn installation(
+        &self,
+        repo: Option<proto::Repo>,
+        token: Token<'_>,
+        required: Permission,
+    ) -> Result<Installation, Status> {
+        let repo = repo.ok_or_else(|| Status::invalid_argument("missing repo"))?;
+        if token.permission(&Repo::new(&repo.owner, &repo.name)) < required {
+            return Err(Status::permission_denied(format!(
+                "token isn't allowed to do this to {}/{}",
+                repo.owner, repo.name
+            )));
+        }
+        self.installations
+            .read()
+            .await
+            .iter()
+            .find(|installation| {
+                installation.owner() == repo.owner && installation.name() == repo.name
+            })
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("unknown repo {}/{}", repo.owner, repo.name)))
+    }
+
+    async fn list_repos(
+        &self,
+        token: Token<'_>,
+        _request: Request<proto::ListReposRequest>,
+    ) -> GrpcResult<proto::ListReposResponse> {
+        let repos = self
+            .installations
+            .read()
+            .await
+            .iter()
+            .filter(|installation| {
+                token.permission(installation.config().repo()) >= Permission::Read
+            })
+            .map(|installation| proto::Repo {
+                owner: installation.owner().to_owned(),
+                name: installation.name().to_owned(),
+            })
+            .collect();
+        Ok(Response::new(proto::ListReposResponse { repos }))
+    }
+
+    async fn get_queue(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::PullsResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Read)
+            .await?;
+        let pulls = api::queue(installation.state().await, installation.config())
+            .into_iter()
+            .map(proto::Pull::from)
+            .collect();
+        Ok(Response::new(proto::PullsResponse { pulls }))
+    }
+
+    async fn list_pulls(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::PullsResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Read)
+            .await?;
+        let pulls = installation
+            .state()
+            .await
+            .into_iter()
+            .map(|pull| ApiPull::new(pull, installation.config()).into())
+            .collect();
+        Ok(Response::new(proto::PullsResponse { pulls }))
+    }
+
+    async fn get_pull(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::PullRequest>,
+    ) -> GrpcResult<proto::Pull> {
+        let proto::PullRequest { repo, number } = request.into_inner();
+        let installation = self.installation(repo, token, Permission::Read).await?;
+        match installation
+            .state()
+            .await
+            .into_iter()
+            .find(|pull| pull.number == number)
+        {
+            Some(pull) => Ok(Response::new(
+                ApiPull::new(pull, installation.config()).into(),
+            )),
+            None => Err(Status::not_found(format!("unknown PR #{}", number))),
+        }
+    }
+
+    async fn pause(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().pause().await)
+    }
+
+    async fn resume(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().resume().await)
+    }
+
+    async fn sync(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().sync().await)
+    }
+
+    async fn process_queue(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::RepoRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let installation = self
+            .installation(request.into_inner().repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().process_queue().await)
+    }
+
+    async fn evict(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::PullRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let request = request.into_inner();
+        let installation = self
+            .installation(request.repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().evict(request.number).await)
+    }
+
+    async fn resync(
+        &self,
+        token: Token<'_>,
+        request: Request<proto::PullRequest>,
+    ) -> GrpcResult<proto::QueuedResponse> {
+        let request = request.into_inner();
+        let installation = self
+            .installation(request.repo, token, Permission::Write)
+            .await?;
+        queued(installation.event_processor().resync(request.number).await)
+    }
+}
+
+/// The response to an admin operation, which fails if the repo's event processor has stopped
+#[allow(clippy::result_large_err)]
+fn queued(result: Result<()>) -> GrpcResult<proto::QueuedResponse> {
+    result
+        .map(|()| Response::new(proto::QueuedResponse {}))
+        .map_err(|e| Status::unavailable(e.to_string()))
+}
+
+/// Decode a unary call's request, have `handler` handle it and encode its response
+async fn unary<Req, Res, F, Fut>(request: HttpRequest<Body>, handler: F) -> HttpResponse<BoxBody>
+where
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+    F: FnMut(Request<Req>) -> Fut,
+    Fut: Future<Output = GrpcResult<Res>>,
+{
+    let mut grpc = Grpc::new(ProstCodec::<Res, Req>::default());
+    grpc.unary(Handler(handler, PhantomData), request).await
+}
+
+/// Adapts a handler to the `Service` tonic expects of a unary method
+struct Handler<F, Res>(F, PhantomData<fn() -> Res>);
+
+impl<F, Fut, Req, Res> Service<Request<Req>> for Handler<F, Res>
+where
+    F: FnMut(Request<Req>) -> Fut,
+    Fut: Future<Output = GrpcResult<Res>>,
+{
+    type Response = Response<Res>;
+    type Error = Status;
+    type Future = Fut;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Req>) -> Fut {
+        (self.0)(request)
+    }
+}
+
+impl From<ApiPull> for proto::Pull {
+    fn from(pull: ApiPull) -> Self {
+        let status = match pull.status {
+            ApiStatus::InReview => proto::PullStatus::InReview,
+            ApiStatus::Queued => proto::PullStatus::Queued,
+            ApiStatus::Testing => proto::PullStatus::Testing,
+            ApiStatus::Canary => proto::PullStatus::Canary,
+        };
+
+        Self {
+            number: pull.number,
+            title: pull.title,
+            author: pull.author.unwrap_or_default(),
+            head_ref_name: pull.head_ref_name,
+            head_ref_oid: pull.head_ref_oid.to_string(),
+            base_ref_name: pull.base_ref_name,
+            is_draft: pull.is_draft,
+            approved: pull.approved,
+            approved_by: pull.approved_by,
+            mergeable: pull.mergeable,
+            labels: pull.labels,
+            priority: pull.priority,
+            status: status as i32,
+            queued_at: pull.queued_at.unwrap_or_default(),
+            merge_oid: pull
+                .merge_oid
+                .map(|oid| oid.to_string())
+                .unwrap_or_default(),
+            tests_started_at: pull.tests_started_at.unwrap_or_default(),
+            test_results: pull
+                .test_results
+                .into_iter()
+                .map(|(name, result)| {
+                    let result = proto::TestResult {
+                        passed: result.passed,
+                        details_url: result.details_url,
+                    };
+                    (name, result)
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        config::RepoConfig,
+        event_processor::{EventProcessorSender, Request as ProcessorRequest},
+    };
+    use hyper::{header::CONTENT_TYPE, Method};
+    use prost::Message;
+    use tonic::Code;
+
+    async fn call(
+        service: &BorsService,
+        method: &str,
+        token: &str,
+        message: impl Message,
+    ) -> HttpResponse<BoxBody> {
+        // Messages are framed by a compression flag and their length
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.encoded_len() as u32).to_be_bytes());
+        message.encode(&mut frame).unwrap();
+
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(format!("{}{}", SERVICE_PATH, method))
+            .header(CONTENT_TYPE, "application/grpc")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::from(frame))
+            .unwrap();
+
+        // The router's future is too large for a test thread's stack
+        Box::pin(service.route(request)).await
+    }
+
+    async fn response<T: Message + Default>(response: HttpResponse<BoxBody>) -> Result<T, Code> {
+        if let Some(status) = Status::from_header_map(response.headers()) {
+            return Err(status.code());
+        }
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        Ok(T::decode(&body[5..]).unwrap())
+    }
+
+    fn repo(owner: &str, name: &str) -> Option<proto::Repo> {
+        Some(proto::Repo {
+            owner: owner.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+
+    #[tokio::test]
+    async fn admin_operations() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            "#,
+        )
+        .unwrap();
+        let (tx, mut rx) = EventProcessorSender::channel(config.event_queue_capacity());
+        let installations = vec![Installation::new(config, tx)];
+        let admin: AdminConfig = toml::from_str(
+            r#"
+            token = "secret"
+
+            [[tokens]]
+            token = "dashboard"
+            repos = ["Codertocat/Hello-World"]
+            capability = "read"
+            "#,
+        )
+        .unwrap();
+        let service = BorsService::new(Arc::new(RwLock::new(installations)), admin);
+
+        let resp = call(&service, "ListRepos", "secrets", proto::ListReposRequest {}).await;
+        assert_eq!(
+            response::<proto::ListReposResponse>(resp).await,
+            Err(Code::Unauthenticated)
+        );
+
+        let resp = call(&service, "ListRepos", "secret", proto::ListReposRequest {}).await;
+        let repos = response::<proto::ListReposResponse>(resp).await.unwrap();
+        assert_eq!(repos.repos, [repo("Codertocat", "Hello-World").unwrap()]);
+
+        let resp = call(
+            &service,
+            "Pause",
+            "secret",
+            proto::RepoRequest {
+                repo: repo("Codertocat", "Hello-World"),
+            },
+        )
+        .await;
+        assert!(response::<proto::QueuedResponse>(resp).await.is_ok());
+        assert!(matches!(rx.recv().await, Some(ProcessorRequest::Pause)));
+
+        let resp = call(
+            &service,
+            "Evict",
+            "secret",
+            proto::PullRequest {
+                repo: repo("Codertocat", "Hello-World"),
+                number: 5,
+            },
+        )
+        .await;
+        assert!(response::<proto::QueuedResponse>(resp).await.is_ok());
+        assert!(matches!(rx.recv().await, Some(ProcessorRequest::Evict(5))));
+
+        let resp = call(
+            &service,
+            "Pause",
+            "secret",
+            proto::RepoRequest {
+                repo: repo("Codertocat", "Goodbye-World"),
+            },
+        )
+        .await;
+        assert_eq!(
+            response::<proto::QueuedResponse>(resp).await,
+            Err(Code::NotFound)
+        );
+
+        // Scoped tokens can only do what they're granted
+        let resp = call(
+            &service,
+            "ListRepos",
+            "dashboard",
+            proto::ListReposRequest {},
+        )
+        .await;
+        let repos = response::<proto::ListReposResponse>(resp).await.unwrap();
+        assert_eq!(repos.repos.len(), 1);
+        let resp = call(
+            &service,
+            "Pause",
+            "dashboard",
+            proto::RepoRequest {
+                repo: repo("Codertocat", "Hello-World"),
+            },
+        )
+        .await;
+        assert_eq!(
+            response::<proto::QueuedResponse>(resp).await,
+            Err(Code::PermissionDenied)
+        );
+        let resp = call(
+            &service,
+            "GetQueue",
+            "dashboard",
+            proto::RepoRequest {
+                repo: repo("Codertocat", "Goodbye-World"),
+            },
+        )
+        .await;
+        assert_eq!(
+            response::<proto::PullsResponse>(resp).await,
+            Err(Code::PermissionDenied)
+        );
+
+        let resp = call(&service, "Merge", "secret", proto::RepoRequest::default()).await;
+        assert_eq!(
+            response::<proto::QueuedResponse>(resp).await,
+            Err(Code::Unimplemented)
+        );
+    }
+}
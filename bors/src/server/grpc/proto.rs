@@ -0,0 +1,100 @@
+//! Messages of the `bors.v1` package defined in `proto/bors.proto`, which must be kept in sync with
+//! it
+
+use std::collections::HashMap;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Repo {
+    #[prost(string, tag = "1")]
+    pub owner: String,
+    #[prost(string, tag = "2")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListReposRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListReposResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub repos: Vec<Repo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RepoRequest {
+    #[prost(message, optional, tag = "1")]
+    pub repo: Option<Repo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PullRequest {
+    #[prost(message, optional, tag = "1")]
+    pub repo: Option<Repo>,
+    #[prost(uint64, tag = "2")]
+    pub number: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueuedResponse {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PullsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub pulls: Vec<Pull>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum PullStatus {
+    Unspecified = 0,
+    InReview = 1,
+    Queued = 2,
+    Testing = 3,
+    Canary = 4,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TestResult {
+    #[prost(bool, tag = "1")]
+    pub passed: bool,
+    #[prost(string, tag = "2")]
+    pub details_url: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Pull {
+    #[prost(uint64, tag = "1")]
+    pub number: u64,
+    #[prost(string, tag = "2")]
+    pub title: String,
+    #[prost(string, tag = "3")]
+    pub author: String,
+    #[prost(string, tag = "4")]
+    pub head_ref_name: String,
+    #[prost(string, tag = "5")]
+    pub head_ref_oid: String,
+    #[prost(string, tag = "6")]
+    pub base_ref_name: String,
+    #[prost(bool, tag = "7")]
+    pub is_draft: bool,
+    #[prost(bool, tag = "8")]
+    pub approved: bool,
+    #[prost(string, repeated, tag = "9")]
+    pub approved_by: Vec<String>,
+    #[prost(bool, tag = "10")]
+    pub mergeable: bool,
+    #[prost(string, repeated, tag = "11")]
+    pub labels: Vec<String>,
+    #[prost(string, tag = "12")]
+    pub priority: String,
+    #[prost(enumeration = "PullStatus", tag = "13")]
+    pub status: i32,
+    #[prost(uint64, tag = "14")]
+    pub queued_at: u64,
+    #[prost(string, tag = "15")]
+    pub merge_oid: String,
+    #[prost(uint64, tag = "16")]
+    pub tests_started_at: u64,
+    #[prost(map = "string, message", tag = "17")]
+    pub test_results: HashMap<String, TestResult>,
+}
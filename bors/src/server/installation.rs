@@ -1,12 +1,16 @@
+use super::Backpressure;
 use crate::{
     config::RepoConfig,
     event_processor::EventProcessorSender,
     state::{Priority, PullRequestState},
+    Result,
 };
 use github::Event;
 use serde::Serialize;
+use std::collections::HashMap;
+use tracing::warn;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Installation {
     config: RepoConfig,
     event_processor: EventProcessorSender,
@@ -32,39 +36,71 @@ impl Installation {
         self.config.name()
     }
 
-    #[allow(dead_code)]
     pub fn event_processor(&self) -> &EventProcessorSender {
         &self.event_processor
     }
 
-    pub async fn handle_webhook(&self, event: &Event, delivery_id: &str) {
-        self.event_processor
-            .webhook(event.clone(), delivery_id.to_owned())
-            .await
-            .unwrap();
+    pub async fn handle_webhook(
+        &self,
+        event: &Event,
+        delivery_id: &str,
+        backpressure: Backpressure,
+    ) -> Result<()> {
+        let (event, delivery_id) = (event.clone(), delivery_id.to_owned());
+        match backpressure {
+            Backpressure::Wait => self.event_processor.webhook(event, delivery_id).await?,
+            Backpressure::Reject => self.event_processor.try_webhook(event, delivery_id)?,
+        }
+        Ok(())
     }
 
     pub async fn state(&self) -> Vec<PullRequestState> {
         let (_queue, pulls) = self.event_processor.get_state().await.unwrap();
+        self.sorted_pulls(pulls)
+    }
 
-        let mut pulls = pulls.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+    /// Sort PRs by their queue entries
+    fn sorted_pulls(&self, pulls: HashMap<u64, PullRequestState>) -> Vec<PullRequestState> {
+        let mut pulls = pulls.into_values().collect::<Vec<_>>();
         pulls.sort_unstable_by_key(|p| p.to_queue_entry(self.config()));
         pulls
     }
 
+    /// The number of PRs being tested or waiting to be, and whether the merge queue is paused
+    pub async fn queue_status(&self) -> (usize, bool) {
+        let (merge_queue, pulls) = self.event_processor.get_state().await.unwrap();
+        let queued = pulls
+            .values()
+            .filter(|pull| pull.status.is_testing() || pull.status.is_queued())
+            .count();
+        (queued, merge_queue.is_paused())
+    }
+
     pub async fn sync(&self) {
         self.event_processor.sync().await.unwrap();
     }
 
+    pub async fn shutdown(&self) {
+        if self.event_processor.shutdown().await.is_err() {
+            warn!(
+                "event processor for {}/{} had already stopped",
+                self.owner(),
+                self.name()
+            );
+        }
+    }
+
     pub async fn repo_liquid_object(&self) -> liquid::Object {
-        let pull_requests = self.state().await;
-        let pull_requests = pull_requests
+        let (merge_queue, pulls) = self.event_processor.get_state().await.unwrap();
+        let pull_requests = self
+            .sorted_pulls(pulls)
             .into_iter()
             .map(|p| LiquidPullRequest::from_pull_request_state(p, self.config()))
             .collect::<Vec<_>>();
 
         let object = liquid::object!({
             "repo": self.config().repo(),
+            "paused": merge_queue.is_paused(),
             "total": pull_requests.len(),
             "pull_requests": pull_requests,
         });
@@ -0,0 +1,180 @@
+//! Limits on webhook deliveries, so that a misbehaving sender can't exhaust bors' memory or starve
+//! the event processors of the repos it serves
+
+use crate::config::ServerConfig;
+use hyper::{body::HttpBody, server::conn::AddrStream, Body, StatusCode};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::{TcpStream, UnixStream};
+use tokio_native_tls::TlsStream;
+
+/// The window over which deliveries from each address are counted
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct WebhookLimits {
+    max_payload_bytes: usize,
+    read_timeout: Duration,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Default for WebhookLimits {
+    fn default() -> Self {
+        Self::from_config(&ServerConfig::default())
+    }
+}
+
+impl WebhookLimits {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            max_payload_bytes: config.max_webhook_payload_bytes(),
+            read_timeout: config.webhook_timeout(),
+            rate_limiter: config
+                .webhook_rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit, RATE_LIMIT_WINDOW))),
+        }
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    /// Whether a delivery from `ip` is allowed by the rate limit. Deliveries whose address isn't
+    /// known, e.g. over a Unix socket, are always allowed.
+    pub fn allow(&self, ip: Option<IpAddr>) -> bool {
+        match (&self.rate_limiter, ip) {
+            (Some(rate_limiter), Some(ip)) => rate_limiter.allow(ip, Instant::now()),
+            _ => true,
+        }
+    }
+
+    /// Read a delivery's payload, failing with the status to respond with if it is too large or
+    /// isn't sent in time
+    pub async fn read_payload(&self, mut body: Body) -> Result<Vec<u8>, StatusCode> {
+        let read = async {
+            let mut payload = Vec::new();
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+                if payload.len() + chunk.len() > self.max_payload_bytes {
+                    return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                }
+                payload.extend_from_slice(&chunk);
+            }
+            Ok(payload)
+        };
+
+        match tokio::time::timeout(self.read_timeout, read).await {
+            Ok(result) => result,
+            Err(_) => Err(StatusCode::REQUEST_TIMEOUT),
+        }
+    }
+}
+
+/// The address of the peer which made a request, attached to the request as an extension
+#[derive(Clone, Copy, Debug)]
+pub struct PeerIp(pub IpAddr);
+
+/// A connection whose peer's address may be known
+pub trait PeerAddr {
+    fn peer_ip(&self) -> Option<IpAddr>;
+}
+
+impl PeerAddr for AddrStream {
+    fn peer_ip(&self) -> Option<IpAddr> {
+        Some(self.remote_addr().ip())
+    }
+}
+
+impl PeerAddr for TlsStream<TcpStream> {
+    fn peer_ip(&self) -> Option<IpAddr> {
+        let stream = self.get_ref().get_ref().get_ref();
+        stream.peer_addr().ok().map(|addr| addr.ip())
+    }
+}
+
+impl PeerAddr for UnixStream {
+    fn peer_ip(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+/// Limits the number of requests from each address within a fixed window
+#[derive(Debug)]
+struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    /// The start of the current window of each address and the number of requests made within it
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut windows = self.windows.lock().unwrap();
+
+        // Forget addresses which haven't been seen in a while, so they don't accumulate forever
+        let window = self.window;
+        windows.retain(|_, (started_at, _)| now.duration_since(*started_at) < window);
+
+        let (_, count) = windows.entry(ip).or_insert((now, 0));
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limit() {
+        let rate_limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        let (a, b) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+
+        assert!(rate_limiter.allow(a, now));
+        assert!(rate_limiter.allow(a, now + Duration::from_secs(1)));
+        assert!(!rate_limiter.allow(a, now + Duration::from_secs(2)));
+        assert!(rate_limiter.allow(b, now + Duration::from_secs(2)));
+
+        // The limit is reset once the window has passed
+        assert!(rate_limiter.allow(a, now + Duration::from_secs(60)));
+    }
+
+    #[tokio::test]
+    async fn payload_limit() {
+        let config: ServerConfig = toml::from_str("max-webhook-payload-bytes = 4").unwrap();
+        let limits = WebhookLimits::from_config(&config);
+
+        assert_eq!(
+            limits.read_payload(Body::from("ok")).await,
+            Ok(b"ok".to_vec())
+        );
+        assert_eq!(
+            limits.read_payload(Body::from("too large")).await,
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+
+        // A sender which never finishes sending its payload is timed out
+        let limits = WebhookLimits {
+            read_timeout: Duration::from_millis(10),
+            ..limits
+        };
+        let (_sender, body) = Body::channel();
+        assert_eq!(
+            limits.read_payload(body).await,
+            Err(StatusCode::REQUEST_TIMEOUT)
+        );
+    }
+}
@@ -1,55 +1,107 @@
+mod admin;
+mod api;
+mod auth;
+mod badge;
+mod feed;
+#[cfg(feature = "graphql-api")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod installation;
+mod limits;
 mod smee_client;
+mod tls;
+mod unix;
 
 #[cfg(test)]
 mod test;
 
 pub use self::{installation::Installation, smee_client::SmeeClient};
 
-use crate::{config::GithubConfig, Error, Result};
+use self::{
+    admin::Token,
+    auth::Auth,
+    limits::{PeerAddr, PeerIp, WebhookLimits},
+    unix::UnixIncoming,
+};
+use crate::{
+    config::{AdminConfig, GithubConfig, ServerConfig},
+    event_processor::{QueueFull, QueueMetrics},
+    export::{self, ExportFormat},
+    github_api::Permission,
+    metrics::Histogram,
+    state::Repo,
+    stats::{self, QueueStats, DEFAULT_WINDOW},
+    store::{Delivery, Store},
+    telemetry, Error, Result,
+};
 use anyhow::anyhow;
-use futures::future::{self, TryFutureExt};
+use futures::future::{self, Future, FutureExt, TryFutureExt};
 use github::{
     EventType, Webhook, DELIVERY_ID_HEADER, EVENT_TYPE_HEADER, SIGNATURE_256_HEADER,
     SIGNATURE_HEADER,
 };
 use hyper::{
-    body,
-    header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
-    server::conn::AddrStream,
+    header::{
+        HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RETRY_AFTER,
+    },
+    server::{accept::Accept, conn::AddrIncoming},
     service::{make_service_fn, service_fn},
     Body, Method, Request, Response, Server as HyperServer, StatusCode,
 };
-use log::{debug, error, info, trace, warn};
 use lru::LruCache;
 use std::{
-    net::SocketAddr,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{Mutex, RwLock},
 };
-use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error, info, instrument, trace, warn, Span};
 
 const INDEX_HTML: &str = include_str!("../../html/index.html");
 const REPO_HTML: &str = include_str!("../../html/repo.html");
 
+/// How a webhook is handed to an event processor whose queue is full
+#[derive(Clone, Copy, Debug)]
+pub enum Backpressure {
+    /// Wait until there is room in the queue
+    Wait,
+    /// Fail with `QueueFull`
+    Reject,
+}
+
 #[derive(Clone, Debug)]
 pub struct Server {
     config: GithubConfig,
+    admin: AdminConfig,
+    /// Logging in with Github, if an OAuth app is configured
+    auth: Option<Arc<Auth>>,
     counter: Arc<AtomicUsize>,
     /// Installations which contain various services
     installations: Arc<RwLock<Vec<Installation>>>,
     lru_webhooks: Arc<Mutex<LruCache<String, ()>>>,
+    limits: WebhookLimits,
+    /// Journal of webhook deliveries
+    store: Arc<dyn Store>,
 }
 
 impl Server {
-    pub fn new(config: GithubConfig) -> Self {
+    pub fn new(config: GithubConfig, admin: AdminConfig, store: Arc<dyn Store>) -> Self {
         Self {
+            auth: Auth::from_config(&config).map(Arc::new),
             config,
+            admin,
             counter: Arc::new(AtomicUsize::new(0)),
             installations: Arc::new(RwLock::new(Vec::new())),
             lru_webhooks: Arc::new(Mutex::new(LruCache::new(10000))),
+            limits: WebhookLimits::default(),
+            store,
         }
     }
 
@@ -57,25 +109,107 @@ impl Server {
         self.installations.write().await.push(installation);
     }
 
-    pub async fn start(self, addr: SocketAddr) -> Result<()> {
+    /// Shutdown the event processors of all installations, waiting for them to finish the work
+    /// they've already been handed
+    pub async fn shutdown(&self) {
+        let installations = self.installations.read().await;
+        future::join_all(installations.iter().map(Installation::shutdown)).await;
+    }
+
+    /// Serve HTTP requests, and gRPC if it is configured, until `shutdown` completes, after which
+    /// no new connections are accepted and in-flight requests are allowed to finish
+    pub async fn start<F>(mut self, config: ServerConfig, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        self.limits = WebhookLimits::from_config(&config);
+
+        let shutdown = shutdown.shared();
+        #[cfg(feature = "grpc")]
+        let grpc = match self.admin.grpc_bind {
+            Some(addr) if self.admin.has_tokens() => {
+                let service =
+                    grpc::BorsService::new(self.installations.clone(), self.admin.clone());
+                Some(service.serve(addr, shutdown.clone()))
+            }
+            Some(_) => return Err(anyhow!("serving gRPC requires an admin or scoped token")),
+            None => None,
+        };
+        #[cfg(not(feature = "grpc"))]
+        let grpc: Option<future::Ready<Result<()>>> = match self.admin.grpc_bind {
+            Some(_) => return Err(anyhow!("bors was built without the `grpc` feature")),
+            None => None,
+        };
+        let grpc = async {
+            match grpc {
+                Some(grpc) => grpc.await,
+                None => Ok(()),
+            }
+        };
+
+        future::try_join(self.serve_http(config, shutdown), grpc).await?;
+        Ok(())
+    }
+
+    /// Listen on the configured Unix socket if there is one, otherwise on the configured address,
+    /// over TLS if it is configured
+    async fn serve_http<F>(self, config: ServerConfig, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let addr = config.bind();
+        match (config.socket, config.tls) {
+            (Some(_), Some(_)) => Err(anyhow!("TLS can't be terminated on a Unix socket")),
+            (Some(socket), None) => {
+                let incoming = UnixIncoming::bind(&socket)?;
+                info!("Listening on {}", socket.display());
+                self.serve_connections(incoming, shutdown).await
+            }
+            (None, Some(tls)) => {
+                let listener = TcpListener::bind(&addr).await?;
+                info!("Listening on https://{}", addr);
+                self.serve_connections(tls::incoming(listener, tls)?, shutdown)
+                    .await
+            }
+            (None, None) => {
+                let incoming = AddrIncoming::bind(&addr)?;
+                info!("Listening on http://{}", addr);
+                self.serve_connections(incoming, shutdown).await
+            }
+        }
+    }
+
+    async fn serve_connections<I, F>(self, incoming: I, shutdown: F) -> Result<()>
+    where
+        I: Accept,
+        I::Conn: PeerAddr + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        F: Future<Output = ()>,
+    {
         // The closure inside `make_service_fn` is run for each connection,
         // creating a 'service' to handle requests for that specific connection.
-        let make_service = make_service_fn(|_socket: &AddrStream| {
+        let make_service = make_service_fn(|connection: &I::Conn| {
             // While the state was moved into the make_service closure,
             // we need to clone it here because this closure is called
             // once for every connection.
             let server = self.clone();
+            let peer_ip = connection.peer_ip();
 
             // This is the `Service` that will handle the connection.
-            future::ok::<_, Error>(service_fn(move |request| {
+            future::ok::<_, Error>(service_fn(move |mut request: Request<Body>| {
+                if let Some(ip) = peer_ip {
+                    request.extensions_mut().insert(PeerIp(ip));
+                }
                 let server = server.clone();
                 server.serve(request)
             }))
         });
 
-        info!("Listening on http://{}", addr);
-        let hyper_server = HyperServer::bind(&addr)
+        // Don't let a client which never finishes sending its headers hold onto a connection
+        let hyper_server = HyperServer::builder(incoming)
+            .http1_header_read_timeout(self.limits.read_timeout())
             .serve(make_service)
+            .with_graceful_shutdown(shutdown)
             .map_err(Error::from);
 
         hyper_server.await?;
@@ -89,6 +223,21 @@ impl Server {
     }
 
     async fn route_http_request(&mut self, request: Request<Body>) -> Result<Response<Body>> {
+        if let Some(auth) = self.auth.clone() {
+            match (request.method(), request.uri().path()) {
+                (&Method::GET, "/login") => return auth.login(request.uri().query()),
+                (&Method::GET, "/oauth/callback") => {
+                    return auth.callback(request.uri().query()).await
+                }
+                (&Method::GET, "/logout") => return auth.logout(&request),
+                _ => {}
+            }
+
+            if let Some(response) = self.authorize(&auth, &request).await? {
+                return Ok(response);
+            }
+        }
+
         match (request.method(), request.uri().path()) {
             (&Method::GET, "/") => {
                 let count = self.counter.load(Ordering::Relaxed);
@@ -100,9 +249,8 @@ impl Server {
                     .unwrap();
 
                 let repos = self
-                    .installations
-                    .read()
-                    .await
+                    .visible_installations(&request)
+                    .await?
                     .iter()
                     .map(|i| i.config().repo().to_owned())
                     .collect::<Vec<_>>();
@@ -118,7 +266,46 @@ impl Server {
             (&Method::GET, "/github") => Ok(Response::builder()
                 .status(StatusCode::METHOD_NOT_ALLOWED)
                 .body(Body::empty())?),
-            (&Method::POST, "/github") => self.route_github(request).await,
+            (&Method::POST, route) if route == "/github" || route.starts_with("/github/") => {
+                self.route_github(request).await
+            }
+            (&Method::GET, "/metrics") => self.route_metrics().await,
+            (&Method::GET, route) if route.starts_with("/badge/") => self.route_badge(route).await,
+            (&Method::POST, route)
+                if route.starts_with("/deliveries/") && route.ends_with("/replay") =>
+            {
+                let delivery_id = &route["/deliveries/".len()..route.len() - "/replay".len()];
+                self.route_replay(delivery_id, &request).await
+            }
+            (&Method::POST, route) if route.starts_with("/admin/") => {
+                // Without logging in with Github, the admin API can only be used with a token
+                if self.auth.is_none() {
+                    if !self.admin.has_tokens() {
+                        return admin::respond(StatusCode::NOT_FOUND, "");
+                    }
+                    match admin::presented_token(&request, &self.admin) {
+                        Some(token) => {
+                            if let Some(response) = authorize_token(token, route)? {
+                                return Ok(response);
+                            }
+                        }
+                        None => return admin::respond(StatusCode::UNAUTHORIZED, "invalid token"),
+                    }
+                }
+
+                let user = self.auth.as_ref().and_then(|auth| auth.user(&request));
+                let installations = self.installations.read().await;
+                admin::route(&installations, request, user).await
+            }
+            #[cfg(feature = "graphql-api")]
+            (&Method::POST, "/api/graphql") => {
+                let installations = self.visible_installations(&request).await?;
+                graphql::route(installations, self.store.clone(), request).await
+            }
+            (&Method::GET, route) if route.starts_with("/api/") => {
+                let installations = self.visible_installations(&request).await?;
+                api::route(&installations, &*self.store, route, request.uri().query()).await
+            }
             (_, route) if route == "/repos" || route.starts_with("/repos/") => {
                 self.route_repos(request).await
             }
@@ -128,6 +315,201 @@ impl Server {
         }
     }
 
+    /// Check that the user logged in to make `request` is allowed to make it, returning the response
+    /// to send instead if not. Webhooks, metrics and badges don't require logging in, and requests
+    /// presenting a token, e.g. from the admin API or `bors queue`, are allowed whatever the token
+    /// grants instead.
+    async fn authorize(
+        &self,
+        auth: &Auth,
+        request: &Request<Body>,
+    ) -> Result<Option<Response<Body>>> {
+        let path = request.uri().path();
+        if path == "/github"
+            || path.starts_with("/github/")
+            || path == "/metrics"
+            || path.starts_with("/badge/")
+        {
+            return Ok(None);
+        }
+        if let Some(token) = admin::presented_token(request, &self.admin) {
+            return authorize_token(token, path);
+        }
+
+        let user = match auth.user(request) {
+            Some(user) => user,
+            // Send people browsing the dashboard to log in
+            None if request.method() == Method::GET && !path.starts_with("/api/") => {
+                return Ok(Some(
+                    Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header(LOCATION, format!("/login?next={}", path))
+                        .body(Body::empty())?,
+                ))
+            }
+            None => {
+                return Ok(Some(
+                    Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .body(Body::from("log in at /login"))?,
+                ))
+            }
+        };
+
+        if let Some((repo, required)) = required_permission(path) {
+            if auth.permission(&user, &repo).await? < required {
+                return Ok(Some(forbidden()?));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The installations which the user logged in to make `request` can see
+    async fn visible_installations(&self, request: &Request<Body>) -> Result<Vec<Installation>> {
+        let installations = self.installations.read().await.clone();
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Ok(installations),
+        };
+        if let Some(token) = admin::presented_token(request, &self.admin) {
+            return Ok(installations
+                .into_iter()
+                .filter(|installation| {
+                    token.permission(installation.config().repo()) >= Permission::Read
+                })
+                .collect());
+        }
+
+        let mut visible = Vec::new();
+        if let Some(user) = auth.user(request) {
+            for installation in installations {
+                if auth.permission(&user, installation.config().repo()).await? >= Permission::Read {
+                    visible.push(installation);
+                }
+            }
+        }
+        Ok(visible)
+    }
+
+    /// Serve the status badge for the repo at `/badge/<owner>/<name>.svg`
+    async fn route_badge(&self, path: &str) -> Result<Response<Body>> {
+        let repo = path
+            .strip_prefix("/badge/")
+            .and_then(|rest| rest.strip_suffix(".svg"))
+            .and_then(|rest| rest.split_once('/'));
+
+        let installations = self.installations.read().await;
+        let installation = repo.and_then(|(owner, name)| {
+            installations
+                .iter()
+                .find(|installation| installation.owner() == owner && installation.name() == name)
+        });
+        let (queued, paused) = match installation {
+            Some(installation) => installation.queue_status().await,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())?)
+            }
+        };
+
+        // Badges are embedded in pages which would otherwise cache them, e.g. by Github's camo
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, "image/svg+xml")
+            .header(CACHE_CONTROL, "max-age=0, no-cache")
+            .body(Body::from(badge::render(queued, paused)))?)
+    }
+
+    /// Expose the health of each repo's event queue in the Prometheus text format
+    async fn route_metrics(&mut self) -> Result<Response<Body>> {
+        let installations = self.installations.read().await;
+
+        let mut body = String::new();
+        let mut metric =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&Installation) -> u64| {
+                body.push_str(&format!(
+                    "# HELP {} {}\n# TYPE {} {}\n",
+                    name, help, name, kind
+                ));
+                for installation in installations.iter() {
+                    body.push_str(&format!(
+                        "{}{{repo=\"{}/{}\"}} {}\n",
+                        name,
+                        installation.owner(),
+                        installation.name(),
+                        value(installation)
+                    ));
+                }
+            };
+
+        metric(
+            "bors_event_queue_capacity",
+            "gauge",
+            "Number of requests which can be queued for an event processor",
+            &|i| i.event_processor().metrics().capacity() as u64,
+        );
+        metric(
+            "bors_event_queue_depth",
+            "gauge",
+            "Number of requests waiting to be handled by an event processor",
+            &|i| i.event_processor().metrics().depth() as u64,
+        );
+        metric(
+            "bors_event_queue_send_failures_total",
+            "counter",
+            "Number of requests which couldn't be queued for an event processor",
+            &|i| i.event_processor().metrics().send_failures(),
+        );
+        metric(
+            "bors_queue_stuck",
+            "gauge",
+            "Whether a merge queue has stopped making progress without having been paused",
+            &|i| i.event_processor().metrics().is_stuck() as u64,
+        );
+        metric(
+            "bors_queue_stuck_alerts_total",
+            "counter",
+            "Number of times a merge queue has gotten stuck",
+            &|i| i.event_processor().metrics().stuck_alerts(),
+        );
+
+        let mut histogram =
+            |name: &str, help: &str, value: &dyn Fn(&QueueMetrics) -> &Histogram| {
+                body.push_str(&format!(
+                    "# HELP {} {}\n# TYPE {} histogram\n",
+                    name, help, name
+                ));
+                for installation in installations.iter() {
+                    let labels =
+                        format!("repo=\"{}/{}\"", installation.owner(), installation.name());
+                    body.push_str(
+                        &value(installation.event_processor().metrics()).samples(name, &labels),
+                    );
+                }
+            };
+
+        histogram(
+            "bors_command_to_queued_seconds",
+            "Time from a command to land a PR until it was queued",
+            &|metrics| metrics.command_to_queued(),
+        );
+        histogram(
+            "bors_queued_to_testing_seconds",
+            "Time PRs waited in the merge queue before being tested",
+            &|metrics| metrics.queued_to_testing(),
+        );
+        histogram(
+            "bors_testing_to_merged_seconds",
+            "Time PRs which landed were tested for before being merged",
+            &|metrics| metrics.testing_to_merged(),
+        );
+
+        Ok(Response::builder()
+            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(Body::from(body))?)
+    }
+
     // XXX Really rough code for dumping internal state
     async fn route_repos(&mut self, request: Request<Body>) -> Result<Response<Body>> {
         let path = request.uri().path();
@@ -136,7 +518,7 @@ impl Server {
             let mut body = String::new();
             body.push_str("Repositories:\n\n");
 
-            for installation in self.installations.read().await.iter() {
+            for installation in self.visible_installations(&request).await? {
                 body.push_str(&format!(
                     "{}/{}\n",
                     installation.owner(),
@@ -176,6 +558,94 @@ impl Server {
                 );
 
                 return Ok(Response::new(Body::from(body)));
+            } else if path.starts_with(&route) && path.ends_with("/history") {
+                let landings = self.store.landings(installation.config().repo()).await?;
+                return Ok(Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(serde_json::to_string_pretty(&landings)?))?);
+            } else if path.starts_with(&route) && path.ends_with("/canaries") {
+                let number = path[route.len()..]
+                    .strip_prefix("pulls/")
+                    .and_then(|rest| rest.strip_suffix("/canaries"))
+                    .and_then(|number| number.parse::<u64>().ok());
+                let canary_runs = match number {
+                    Some(number) => installation
+                        .state()
+                        .await
+                        .into_iter()
+                        .find(|pull| pull.number == number)
+                        .map(|pull| pull.canary_runs),
+                    None => None,
+                };
+
+                return match canary_runs {
+                    Some(canary_runs) => Ok(Response::builder()
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(Body::from(serde_json::to_string_pretty(&canary_runs)?))?),
+                    None => Ok(Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())?),
+                };
+            } else if path.starts_with(&route)
+                && (path.ends_with("/feed.ics") || path.ends_with("/feed.json"))
+            {
+                let now = SystemTime::now();
+                let landings = self.store.landings(installation.config().repo()).await?;
+                let events = feed::events(installation.config(), &landings, now);
+                return if path.ends_with(".ics") {
+                    Ok(Response::builder()
+                        .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+                        .body(Body::from(feed::ical(installation.config(), &events, now)))?)
+                } else {
+                    Ok(Response::builder()
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(Body::from(serde_json::to_string_pretty(&events)?))?)
+                };
+            } else if path.starts_with(&route) && path.ends_with("/stats") {
+                let window = match stats_window(request.uri().query()) {
+                    Some(window) => window,
+                    None => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("invalid days"))?)
+                    }
+                };
+                let landings = self.store.landings(installation.config().repo()).await?;
+                let stats = QueueStats::from_history(&landings, window, SystemTime::now());
+                return Ok(Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(serde_json::to_string_pretty(&stats)?))?);
+            } else if path.starts_with(&route) && path.ends_with("/flaky") {
+                let window = match stats_window(request.uri().query()) {
+                    Some(window) => window,
+                    None => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("invalid days"))?)
+                    }
+                };
+                let landings = self.store.landings(installation.config().repo()).await?;
+                let checks = stats::flaky_checks(&landings, window, SystemTime::now());
+                return Ok(Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(serde_json::to_string_pretty(&checks)?))?);
+            } else if let Some(format) =
+                ExportFormat::from_path(path).filter(|_| path.starts_with(&route))
+            {
+                let since = match export_since(request.uri().query(), SystemTime::now()) {
+                    Ok(since) => since,
+                    Err(_) => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("invalid days"))?)
+                    }
+                };
+                let repo = installation.config().repo();
+                let landings = self.store.landings(repo).await?;
+                let rows = export::rows(repo, &landings, since);
+                return Ok(Response::builder()
+                    .header(CONTENT_TYPE, format.content_type())
+                    .body(Body::from(export::encode(&rows, format)?))?);
             } else if path.starts_with(&route) && path.ends_with("/sync") {
                 installation.sync().await;
                 return Ok(Response::new(Body::from("Syncing Pull Requests!")));
@@ -187,11 +657,130 @@ impl Server {
             .body(Body::empty())?)
     }
 
+    async fn route_replay(
+        &mut self,
+        delivery_id: &str,
+        request: &Request<Body>,
+    ) -> Result<Response<Body>> {
+        // Without logging in with Github, deliveries can only be replayed with a token
+        if self.auth.is_none() && !self.admin.has_tokens() {
+            return admin::respond(StatusCode::NOT_FOUND, "");
+        }
+        let token = admin::presented_token(request, &self.admin);
+        if self.auth.is_none() && token.is_none() {
+            return admin::respond(StatusCode::UNAUTHORIZED, "invalid token");
+        }
+
+        let delivery = match self.store.delivery(delivery_id).await? {
+            Some(delivery) => delivery,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())?)
+            }
+        };
+        let webhook = match webhook_from_delivery(delivery) {
+            Ok(webhook) => webhook,
+            Err(e) => {
+                return Ok(Response::builder()
+                    .status(StatusCode::UNPROCESSABLE_ENTITY)
+                    .body(Body::from(e.to_string()))?)
+            }
+        };
+
+        // Replaying a delivery acts on its repo's queue, so requires being able to push to it
+        let permitted = match (token, webhook_repo(&webhook)) {
+            (Some(Token::Admin), _) => true,
+            (Some(token), Some(repo)) => token.permission(&repo) >= Permission::Write,
+            (None, Some(repo)) => match self
+                .auth
+                .as_ref()
+                .and_then(|auth| auth.user(request).map(|user| (auth, user)))
+            {
+                Some((auth, user)) => auth.permission(&user, &repo).await? >= Permission::Write,
+                None => false,
+            },
+            (_, None) => false,
+        };
+        if !permitted {
+            return forbidden();
+        }
+
+        info!("Replaying delivery {}", webhook.delivery_id);
+        match self.dispatch_webhook(&webhook, Backpressure::Reject).await {
+            Err(e) if e.is::<QueueFull>() => return service_unavailable(),
+            result => result?,
+        }
+
+        Ok(Response::new(Body::from("Replaying delivery!")))
+    }
+
+    /// Replay any journaled deliveries for `repo` which were never successfully processed, e.g.
+    /// due to a crash
+    pub async fn replay_unprocessed_deliveries(&mut self, repo: &Repo) -> Result<()> {
+        for delivery in self.store.unprocessed_deliveries().await? {
+            let webhook = match webhook_from_delivery(delivery) {
+                Ok(webhook) => webhook,
+                Err(e) => {
+                    warn!("Skipping unprocessed delivery: {:#}", e);
+                    continue;
+                }
+            };
+            let is_for_repo = webhook
+                .to_event()
+                .ok()
+                .and_then(|event| {
+                    event
+                        .repository()
+                        .map(|r| r.owner.login == repo.owner() && r.name == repo.name())
+                })
+                .unwrap_or(false);
+
+            if is_for_repo {
+                info!("Replaying unprocessed delivery {}", webhook.delivery_id);
+                self.dispatch_webhook(&webhook, Backpressure::Wait).await?;
+            }
+        }
+
+        Ok(())
+    }
+
     async fn route_github(&mut self, request: Request<Body>) -> Result<Response<Body>> {
         assert_eq!(request.method(), &Method::POST);
-        assert_eq!(request.uri().path(), "/github");
 
-        let webhook = match webhook_from_request(request).await {
+        // Deliveries to `/github/<owner>/<name>` may only be for that repo
+        let routed_to = match request.uri().path().strip_prefix("/github/") {
+            Some(rest) => match rest.trim_end_matches('/').split_once('/') {
+                Some((owner, name)) => Some(Repo::new(owner, name)),
+                None => {
+                    return Ok(Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())?)
+                }
+            },
+            None => None,
+        };
+
+        // Don't let a single sender flood the event processors
+        let peer_ip = request.extensions().get::<PeerIp>().map(|PeerIp(ip)| *ip);
+        if !self.limits.allow(peer_ip) {
+            warn!("Rate limit exceeded by {:?}, rejecting webhook", peer_ip);
+            return Ok(Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(RETRY_AFTER, 60)
+                .body(Body::empty())?);
+        }
+
+        let (parts, body) = request.into_parts();
+        let payload = match self.limits.read_payload(body).await {
+            Ok(payload) => payload,
+            Err(status) => {
+                warn!("Unable to read webhook from {:?}: {}", peer_ip, status);
+                return Ok(Response::builder().status(status).body(Body::empty())?);
+            }
+        };
+
+        let webhook = match webhook_from_request(&parts.headers, payload) {
             Ok(webhook) => webhook,
             Err(e) => {
                 error!("parsing payload: {:#?}", e);
@@ -201,7 +790,49 @@ impl Server {
             }
         };
 
-        self.handle_webhook(webhook).await?;
+        let repo = webhook_repo(&webhook);
+        if let Some(routed_to) = &routed_to {
+            let is_configured = self
+                .installations
+                .read()
+                .await
+                .iter()
+                .any(|installation| installation.config().repo() == routed_to);
+            if !is_configured || repo.iter().any(|repo| repo != routed_to) {
+                warn!(
+                    "Misrouted delivery to {}/{}! Rejecting Event. [{:?},{}]",
+                    routed_to.owner(),
+                    routed_to.name(),
+                    webhook.event_type,
+                    webhook.delivery_id
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("delivery is not for this repo"))?);
+            }
+        }
+
+        // Let Github record that the delivery failed instead of acknowledging it and dropping it
+        let repo = routed_to.or(repo);
+        if self.config.require_webhook_signature
+            && !self.check_signature(&webhook, repo.as_ref()).await
+        {
+            warn!(
+                "Signature check FAILED! Rejecting Event. [{:?},{}]",
+                webhook.event_type, webhook.delivery_id
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::empty())?);
+        }
+
+        // Rather than waiting for room in a full queue, and potentially having Github give up on
+        // the delivery, reject it so that it can be retried once the backlog has cleared. Handling
+        // is boxed as its future, with the span it's traced in, is too large to keep on the stack.
+        match Box::pin(self.handle_webhook(webhook, Backpressure::Reject)).await {
+            Err(e) if e.is::<QueueFull>() => return service_unavailable(),
+            result => result?,
+        }
 
         Ok(Response::builder()
             .status(StatusCode::OK)
@@ -211,7 +842,18 @@ impl Server {
     }
 
     /// Handles an incoming webhook request.  Drops duplicate requests
-    pub(super) async fn handle_webhook(&mut self, webhook: Webhook) -> Result<()> {
+    #[instrument(
+        name = "receive_webhook",
+        skip_all,
+        fields(delivery_id = %webhook.delivery_id, event = webhook.event_type.as_str())
+    )]
+    pub(super) async fn handle_webhook(
+        &mut self,
+        webhook: Webhook,
+        backpressure: Backpressure,
+    ) -> Result<()> {
+        telemetry::link_to_delivery(&Span::current(), &webhook.delivery_id);
+
         // If we've recently seen this webhook, let's drop it to prevent duplicates
         if let Some(()) = self
             .lru_webhooks
@@ -228,7 +870,10 @@ impl Server {
 
         // Process the current webhook
         trace!("Handling Webhook: {}", webhook.delivery_id);
-        if !webhook.check_signature(self.config.webhook_secret().map(str::as_bytes)) {
+        if !self
+            .check_signature(&webhook, webhook_repo(&webhook).as_ref())
+            .await
+        {
             warn!(
                 "Signature check FAILED! Skipping Event. [{:?},{}]",
                 webhook.event_type, webhook.delivery_id
@@ -236,6 +881,65 @@ impl Server {
             return Ok(());
         }
 
+        // Journal the delivery before processing it so that it isn't lost if bors crashes, or
+        // fails to process it, and can be replayed later. The journal outlives the in-memory
+        // cache above, so it also catches redeliveries of webhooks seen before a restart.
+        // Deliberately retrying a delivery should go through the replay endpoint instead.
+        let is_new = self
+            .store
+            .record_delivery(&Delivery {
+                delivery_id: webhook.delivery_id.clone(),
+                event_type: webhook.event_type.as_str().to_owned(),
+                payload: webhook.body.clone(),
+                received_at: SystemTime::now(),
+            })
+            .await?;
+        if !is_new {
+            info!(
+                "Webhook {} has already been delivered.  Dropping it...",
+                webhook.delivery_id
+            );
+            return Ok(());
+        }
+
+        let result = self.dispatch_webhook(&webhook, backpressure).await;
+        if let Err(e) = &result {
+            if e.is::<QueueFull>() {
+                warn!(
+                    "Event queue is full, rejecting webhook {}",
+                    webhook.delivery_id
+                );
+                // Forget the delivery so that it isn't dropped as a duplicate when it is retried
+                self.lru_webhooks.lock().await.pop(&webhook.delivery_id);
+                self.store.forget_delivery(&webhook.delivery_id).await?;
+            }
+        }
+        result
+    }
+
+    /// Check a webhook's signature against the secrets of `repo`, if it has its own, otherwise
+    /// against the global secrets
+    async fn check_signature(&self, webhook: &Webhook, repo: Option<&Repo>) -> bool {
+        let installations = self.installations.read().await;
+        let repo_secrets = repo.and_then(|repo| {
+            installations
+                .iter()
+                .find(|installation| installation.config().repo() == repo)
+                .and_then(|installation| installation.config().webhook_secrets())
+        });
+
+        match repo_secrets {
+            Some(secrets) => webhook.check_signature(&secrets),
+            None => webhook.check_signature(&self.config.webhook_secrets()),
+        }
+    }
+
+    /// Dispatch a webhook to the installation for the repo it pertains to
+    async fn dispatch_webhook(
+        &mut self,
+        webhook: &Webhook,
+        backpressure: Backpressure,
+    ) -> Result<()> {
         // Convert the webhook to an event so that we can get out the installation information
         let event = match webhook.to_event() {
             Ok(webhook) => webhook,
@@ -263,23 +967,145 @@ impl Server {
                 .find(|i| i.owner() == repository.owner.login && i.name() == repository.name)
         }) {
             installation
-                .handle_webhook(&event, &webhook.delivery_id)
-                .await;
+                .handle_webhook(&event, &webhook.delivery_id, backpressure)
+                .await?;
+        } else {
+            // Nothing will ever process this delivery
+            self.store
+                .mark_delivery_processed(&webhook.delivery_id)
+                .await?;
         }
 
         Ok(())
     }
 }
 
-async fn webhook_from_request(request: Request<Body>) -> Result<Webhook> {
+fn service_unavailable() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(RETRY_AFTER, 60)
+        .body(Body::empty())?)
+}
+
+fn forbidden() -> Result<Response<Body>> {
+    Ok(Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::empty())?)
+}
+
+/// Check that `token` grants the permission which a request for `path` requires, returning the
+/// response to send instead if not
+fn authorize_token(token: Token, path: &str) -> Result<Option<Response<Body>>> {
+    match required_permission(path) {
+        Some((repo, required)) if token.permission(&repo) < required => Ok(Some(forbidden()?)),
+        _ => Ok(None),
+    }
+}
+
+/// The repo which a request for `path` acts on, if any, and the permission needed on it. Reading a
+/// repo's dashboard or API requires being able to read the repo, while acting on its queue requires
+/// being able to push to it.
+fn required_permission(path: &str) -> Option<(Repo, Permission)> {
+    let (rest, permission) = if let Some(rest) = path.strip_prefix("/admin/repos/") {
+        (rest, Permission::Write)
+    } else if let Some(rest) = path
+        .strip_prefix("/api/repos/")
+        .or_else(|| path.strip_prefix("/repos/"))
+    {
+        let is_sync = rest.trim_end_matches('/').ends_with("/sync");
+        (
+            rest,
+            if is_sync {
+                Permission::Write
+            } else {
+                Permission::Read
+            },
+        )
+    } else {
+        return None;
+    };
+
+    let mut segments = rest.split('/');
+    match (segments.next(), segments.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => {
+            Some((Repo::new(owner, name), permission))
+        }
+        _ => None,
+    }
+}
+
+/// The window to aggregate the merge history over, from an optional `days=<n>` query, or `None` if
+/// the query is invalid
+fn stats_window(query: Option<&str>) -> Option<Duration> {
+    let days = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|param| param.strip_prefix("days="));
+
+    match days {
+        Some(days) => match days.parse::<u64>() {
+            Ok(days) if days > 0 => Some(Duration::from_secs(days * 24 * 60 * 60)),
+            _ => None,
+        },
+        None => Some(DEFAULT_WINDOW),
+    }
+}
+
+/// When the merge history exported by `export.csv` and `export.parquet` starts, given by the number
+/// of `days` back in the query string, or `None` to export all of it
+fn export_since(query: Option<&str>, now: SystemTime) -> Result<Option<SystemTime>> {
+    let days = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|param| param.strip_prefix("days="));
+
+    match days {
+        Some(days) => match days.parse::<u64>() {
+            Ok(days) if days > 0 => Ok(now.checked_sub(Duration::from_secs(days * 24 * 60 * 60))),
+            _ => Err(anyhow!("invalid days '{}'", days)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The repo a webhook pertains to, according to its payload
+fn webhook_repo(webhook: &Webhook) -> Option<Repo> {
+    webhook
+        .to_event()
+        .ok()?
+        .repository()
+        .map(Repo::from_repository)
+}
+
+/// Rebuild a journaled delivery's webhook, which fails if it's of an event type this version of
+/// bors doesn't know, e.g. because a different version journaled it
+fn webhook_from_delivery(delivery: Delivery) -> Result<Webhook> {
+    let event_type = delivery.event_type.parse().unwrap_or(EventType::Unknown);
+    if matches!(event_type, EventType::Unknown) {
+        return Err(anyhow!(
+            "delivery {} has unknown event type '{}'",
+            delivery.delivery_id,
+            delivery.event_type
+        ));
+    }
+
+    Ok(Webhook {
+        event_type,
+        delivery_id: delivery.delivery_id,
+        signature: None,
+        signature_256: None,
+        body: delivery.payload,
+    })
+}
+
+fn webhook_from_request(headers: &HeaderMap, body: Vec<u8>) -> Result<Webhook> {
     // Webhooks from github should only contain json payloads
-    match request.headers().get(CONTENT_TYPE).map(HeaderValue::to_str) {
+    match headers.get(CONTENT_TYPE).map(HeaderValue::to_str) {
         Some(Ok("application/json")) => {}
         _ => return Err(anyhow!("unknown content type")),
     }
 
-    let event_type = match request
-        .headers()
+    let event_type = match headers
         .get(EVENT_TYPE_HEADER)
         .and_then(|h| HeaderValue::to_str(h).ok())
         .and_then(|s| s.parse::<EventType>().ok())
@@ -288,8 +1114,7 @@ async fn webhook_from_request(request: Request<Body>) -> Result<Webhook> {
         _ => return Err(anyhow!("missing valid X-GitHub-Event header")),
     };
 
-    let delivery_id = match request
-        .headers()
+    let delivery_id = match headers
         .get(DELIVERY_ID_HEADER)
         .and_then(|h| HeaderValue::to_str(h).ok())
     {
@@ -297,25 +1122,15 @@ async fn webhook_from_request(request: Request<Body>) -> Result<Webhook> {
         _ => return Err(anyhow!("missing valid X-GitHub-Delivery header")),
     };
 
-    let signature = match request
-        .headers()
+    let signature = headers
         .get(SIGNATURE_HEADER)
         .and_then(|h| HeaderValue::to_str(h).ok())
-    {
-        Some(signature) => Some(signature.to_owned()),
-        _ => None,
-    };
+        .map(|signature| signature.to_owned());
 
-    let signature_256 = match request
-        .headers()
+    let signature_256 = headers
         .get(SIGNATURE_256_HEADER)
         .and_then(|h| HeaderValue::to_str(h).ok())
-    {
-        Some(signature) => Some(signature.to_owned()),
-        _ => None,
-    };
-
-    let body = body::to_bytes(request.into_body()).await?.to_vec();
+        .map(|signature| signature.to_owned());
 
     Ok(Webhook {
         event_type,
@@ -1,12 +1,20 @@
-use super::Server;
+//! A client for smee.io, or another smee compatible relay, which forwards webhooks delivered to a
+//! channel on the relay to bors. This lets bors be run against a test repo, e.g. during local
+//! development, without exposing a public endpoint for Github to deliver webhooks to.
+
+use super::{Backpressure, Server};
 use crate::Result;
+use anyhow::anyhow;
 use bytes::{Buf, BytesMut};
 use github::{EventType, Webhook};
-use log::{debug, info, trace, warn};
-use reqwest::{Client, Response};
+use reqwest::{header::LOCATION, redirect::Policy, Client, Response, Url};
 use serde::Deserialize;
 use serde_json::value::RawValue;
 use std::{borrow::Cow, str};
+use tracing::{debug, info, trace, warn};
+
+/// Creates a new channel on smee.io by redirecting to it
+const NEW_CHANNEL_URI: &str = "https://smee.io/new";
 
 pub struct SmeeClient {
     uri: String,
@@ -14,7 +22,8 @@ pub struct SmeeClient {
 }
 
 impl SmeeClient {
-    //TODO handle `http://smee.io/new` uri
+    /// Forward webhooks from the channel at `uri`. If `uri` is `new`, or a relay's `/new`
+    /// endpoint, a new channel is created.
     pub fn with_uri<U: Into<String>>(uri: U, server: Server) -> Self {
         SmeeClient {
             uri: uri.into(),
@@ -35,6 +44,14 @@ impl SmeeClient {
     }
 
     async fn run(&mut self) -> Result<()> {
+        if is_new_channel_uri(&self.uri) {
+            self.uri = new_channel(&self.uri).await?;
+            info!(
+                "Created smee channel {}, set it as the webhook URL of the repos to forward from",
+                self.uri
+            );
+        }
+
         info!("Starting SmeeClient with {}", self.uri);
 
         let client = Client::new();
@@ -54,7 +71,9 @@ impl SmeeClient {
                 SmeeEvent::Message(webhook) => {
                     trace!("message!");
                     // Have the server process the webhook
-                    self.server.handle_webhook(webhook).await?;
+                    self.server
+                        .handle_webhook(webhook, Backpressure::Wait)
+                        .await?;
                 }
             }
         }
@@ -63,6 +82,25 @@ impl SmeeClient {
     }
 }
 
+fn is_new_channel_uri(uri: &str) -> bool {
+    uri == "new" || uri.trim_end_matches('/').ends_with("/new")
+}
+
+/// Create a new channel through a relay's `/new` endpoint, which redirects to the new channel
+async fn new_channel(uri: &str) -> Result<String> {
+    let uri = if uri == "new" { NEW_CHANNEL_URI } else { uri };
+
+    let client = Client::builder().redirect(Policy::none()).build()?;
+    let response = client.head(uri).send().await?;
+    let channel = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .ok_or_else(|| anyhow!("{} didn't redirect to a new channel", uri))?;
+
+    Ok(Url::parse(uri)?.join(channel)?.into())
+}
+
 #[allow(clippy::large_enum_variant)]
 enum SmeeEvent {
     Ready,
@@ -187,7 +225,7 @@ impl<'b> SmeeEventParser<'b> {
                 "data" => {
                     if let Some(value) = value {
                         if let Some(data) = data.as_mut() {
-                            data.to_mut().push_str("\n");
+                            data.to_mut().push('\n');
                             data.to_mut().push_str(value);
                         } else {
                             data = Some(Cow::Borrowed(value));
@@ -216,3 +254,50 @@ impl<'b> SmeeEventParser<'b> {
             .or_else(|| find_end_of_subsequence(&self.buffer, b"\r\n\r\n"))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn parse_events() {
+        let stream = concat!(
+            "event: ready\ndata: {}\n\n",
+            ": a comment\r\n\r\n",
+            "event: ping\ndata: {}\n\n",
+            r#"data: {"x-github-event":"ping","x-github-delivery":"1234","#,
+            r#""x-hub-signature-256":"sha256=abcd","body":{"zen":"hi"}}"#,
+            "\n\n",
+        );
+        let mut response = Response::from(hyper::http::Response::new(stream));
+        let mut parser = SmeeEventParser::from_body(&mut response);
+
+        assert!(matches!(
+            parser.next().await.unwrap(),
+            Some(SmeeEvent::Ready)
+        ));
+        assert!(matches!(
+            parser.next().await.unwrap(),
+            Some(SmeeEvent::Ping)
+        ));
+        match parser.next().await.unwrap() {
+            Some(SmeeEvent::Message(webhook)) => {
+                assert!(matches!(webhook.event_type, EventType::Ping));
+                assert_eq!(webhook.delivery_id, "1234");
+                assert_eq!(webhook.signature, None);
+                assert_eq!(webhook.signature_256.as_deref(), Some("sha256=abcd"));
+                assert_eq!(webhook.body, br#"{"zen":"hi"}"#);
+            }
+            _ => panic!("expected a message"),
+        }
+        assert!(parser.next().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn new_channel_uri() {
+        assert!(is_new_channel_uri("new"));
+        assert!(is_new_channel_uri("https://smee.io/new"));
+        assert!(is_new_channel_uri("https://relay.example.com/new/"));
+        assert!(!is_new_channel_uri("https://smee.io/abcdEFGH1234"));
+    }
+}
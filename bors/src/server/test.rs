@@ -1,22 +1,551 @@
-use super::Server;
-use crate::config::GithubConfig;
+use super::{
+    limits::{PeerIp, WebhookLimits},
+    required_permission, stats_window, Installation, Server,
+};
+use crate::{
+    config::{AdminConfig, Bump, GithubConfig, RepoConfig, ServerConfig},
+    event_processor::{EventProcessorSender, Request as ProcessorRequest},
+    fake::comment_payload,
+    github_api::Permission,
+    state::Repo,
+    store::{Delivery, SqliteStore, Store},
+};
 use hyper::{Body, Method, Request, StatusCode, Uri, Version};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 #[tokio::test]
 async fn pull_request_event() {
     static PAYLOAD: &str = include_str!("../../test-input/pull-request-event-payload");
     let request = request_from_raw_http(PAYLOAD);
 
-    let mut service = Server::new(GithubConfig {
-        github_api_token: "".to_string(),
-        webhook_secret: None,
-    });
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: None,
+            previous_webhook_secret: None,
+            require_webhook_signature: false,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        AdminConfig::default(),
+        Arc::new(SqliteStore::in_memory().unwrap()),
+    );
 
     let resp = service.route_github(request).await.unwrap();
     assert_eq!(resp.status(), StatusCode::OK);
     println!("{:?}", resp);
 }
 
+#[tokio::test]
+async fn full_event_queue() {
+    static PAYLOAD: &str = include_str!("../../test-input/pull-request-event-payload");
+    const SECRET: &str = "secret";
+
+    let store = Arc::new(SqliteStore::in_memory().unwrap());
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: Some(SECRET.to_string()),
+            previous_webhook_secret: None,
+            require_webhook_signature: false,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        AdminConfig::default(),
+        store.clone(),
+    );
+
+    let repo: RepoConfig = toml::from_str(
+        r#"
+        owner = "Codertocat"
+        name = "Hello-World"
+        event-queue-capacity = 1
+        "#,
+    )
+    .unwrap();
+    let (tx, mut rx) = EventProcessorSender::channel(repo.event_queue_capacity());
+    service
+        .add_installation(Installation::new(repo, tx.clone()))
+        .await;
+
+    // Fill up the queue so that the webhook can't be accepted
+    tx.sync().await.unwrap();
+    let resp = service
+        .route_github(signed_request_from_raw_http(PAYLOAD, SECRET))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(tx.metrics().depth(), 1);
+    assert_eq!(tx.metrics().send_failures(), 1);
+    assert!(store.delivery("bbb-11-22-3").await.unwrap().is_none());
+
+    // Once there is room the redelivered webhook is accepted
+    rx.recv().await.unwrap();
+    let resp = service
+        .route_github(signed_request_from_raw_http(PAYLOAD, SECRET))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(tx.metrics().depth(), 1);
+}
+
+#[tokio::test]
+async fn require_webhook_signature() {
+    static PAYLOAD: &str = include_str!("../../test-input/pull-request-event-payload");
+
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: Some("new".to_string()),
+            previous_webhook_secret: Some("old".to_string()),
+            require_webhook_signature: true,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        AdminConfig::default(),
+        Arc::new(SqliteStore::in_memory().unwrap()),
+    );
+
+    let resp = service
+        .route_github(request_from_raw_http(PAYLOAD))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let resp = service
+        .route_github(signed_request_from_raw_http(PAYLOAD, "other"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Deliveries signed with the previous secret are still accepted during a rotation
+    let resp = service
+        .route_github(signed_request_from_raw_http(PAYLOAD, "old"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn webhook_routing() {
+    static PAYLOAD: &str = include_str!("../../test-input/pull-request-event-payload");
+
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: Some("global".to_string()),
+            previous_webhook_secret: None,
+            require_webhook_signature: true,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        AdminConfig::default(),
+        Arc::new(SqliteStore::in_memory().unwrap()),
+    );
+
+    let mut receivers = Vec::new();
+    for config in [
+        r#"
+        owner = "Codertocat"
+        name = "Hello-World"
+        webhook-secret = "repo"
+        "#,
+        r#"
+        owner = "Codertocat"
+        name = "Goodbye-World"
+        "#,
+    ]
+    .iter()
+    {
+        let repo: RepoConfig = toml::from_str(config).unwrap();
+        let (tx, rx) = EventProcessorSender::channel(repo.event_queue_capacity());
+        service.add_installation(Installation::new(repo, tx)).await;
+        receivers.push(rx);
+    }
+
+    let request = |path: &str, secret: &str| {
+        let mut request = signed_request_from_raw_http(PAYLOAD, secret);
+        *request.uri_mut() = path.parse().unwrap();
+        request
+    };
+
+    // The repo's own secret replaces the global one
+    let resp = service
+        .route_github(request("/github/Codertocat/Hello-World", "global"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Deliveries for another repo, or a repo which isn't configured, are rejected
+    for path in [
+        "/github/Codertocat/Goodbye-World",
+        "/github/Codertocat/Spoon-Knife",
+    ]
+    .iter()
+    {
+        let resp = service.route_github(request(path, "repo")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let resp = service
+        .route_github(request("/github/Codertocat/Hello-World", "repo"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(matches!(
+        receivers[0].recv().await,
+        Some(ProcessorRequest::Webhook { .. })
+    ));
+}
+
+#[tokio::test]
+async fn webhook_rate_limit() {
+    static PAYLOAD: &str = include_str!("../../test-input/pull-request-event-payload");
+
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: None,
+            previous_webhook_secret: None,
+            require_webhook_signature: false,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        AdminConfig::default(),
+        Arc::new(SqliteStore::in_memory().unwrap()),
+    );
+    let config: ServerConfig = toml::from_str("webhook-rate-limit = 1").unwrap();
+    service.limits = WebhookLimits::from_config(&config);
+
+    let request = |ip: [u8; 4]| {
+        let mut request = request_from_raw_http(PAYLOAD);
+        request.extensions_mut().insert(PeerIp(ip.into()));
+        request
+    };
+
+    let resp = service.route_github(request([10, 0, 0, 1])).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = service.route_github(request([10, 0, 0, 1])).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let resp = service.route_github(request([10, 0, 0, 2])).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+/// Route `request` through `service`, whose router's future is too large for a test thread's stack
+/// unless it is boxed
+async fn route(service: &mut Server, request: Request<Body>) -> hyper::Response<Body> {
+    Box::pin(service.route_http_request(request)).await.unwrap()
+}
+
+#[tokio::test]
+async fn admin_api() {
+    let mut service = Server::new(
+        GithubConfig {
+            github_api_token: "".to_string(),
+            webhook_secret: None,
+            previous_webhook_secret: None,
+            require_webhook_signature: false,
+            timeout_seconds: None,
+            client_id: None,
+            client_secret: None,
+        },
+        toml::from_str(
+            r#"
+            token = "secret"
+
+            [[tokens]]
+            token = "dashboard"
+            capability = "read"
+
+            [[tokens]]
+            token = "other-repo"
+            repos = ["Codertocat/Goodbye-World"]
+            capability = "admin"
+
+            [[tokens]]
+            token = "release-bot"
+            repos = ["Codertocat/Hello-World"]
+            capability = "admin"
+            "#,
+        )
+        .unwrap(),
+        Arc::new(SqliteStore::in_memory().unwrap()),
+    );
+
+    let repo: RepoConfig = toml::from_str(
+        r#"
+        owner = "Codertocat"
+        name = "Hello-World"
+        "#,
+    )
+    .unwrap();
+    let (tx, mut rx) = EventProcessorSender::channel(repo.event_queue_capacity());
+    service.add_installation(Installation::new(repo, tx)).await;
+
+    let admin_request = |path: &str, token: Option<&str>| {
+        let mut request = Request::builder().method(Method::POST).uri(path);
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    for token in [None, Some("secrets")].iter() {
+        let resp = route(
+            &mut service,
+            admin_request("/admin/repos/Codertocat/Hello-World/pause", *token),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let resp = route(
+        &mut service,
+        admin_request("/admin/repos/Codertocat/Hello-World/pause", Some("secret")),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    assert!(matches!(rx.recv().await, Some(ProcessorRequest::Pause)));
+
+    // Scoped tokens can only act on the repos they're granted admin over
+    for (token, status) in [
+        ("dashboard", StatusCode::FORBIDDEN),
+        ("other-repo", StatusCode::FORBIDDEN),
+        ("release-bot", StatusCode::ACCEPTED),
+    ]
+    .iter()
+    {
+        let resp = route(
+            &mut service,
+            admin_request("/admin/repos/Codertocat/Hello-World/resume", Some(token)),
+        )
+        .await;
+        assert_eq!(resp.status(), *status);
+    }
+    assert!(matches!(rx.recv().await, Some(ProcessorRequest::Resume)));
+
+    let resp = route(
+        &mut service,
+        admin_request(
+            "/admin/repos/Codertocat/Hello-World/pulls/5/evict",
+            Some("secret"),
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    assert!(matches!(rx.recv().await, Some(ProcessorRequest::Evict(5))));
+
+    // Releases bump the configured part of the version unless told otherwise
+    for (path, status) in [
+        (
+            "/admin/repos/Codertocat/Hello-World/release?bump=major",
+            StatusCode::ACCEPTED,
+        ),
+        (
+            "/admin/repos/Codertocat/Hello-World/release?bump=huge",
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            "/admin/repos/Codertocat/Hello-World/release",
+            StatusCode::ACCEPTED,
+        ),
+    ]
+    .iter()
+    {
+        let resp = route(&mut service, admin_request(path, Some("secret"))).await;
+        assert_eq!(resp.status(), *status);
+    }
+    assert!(matches!(
+        rx.recv().await,
+        Some(ProcessorRequest::Release(Some(Bump::Major)))
+    ));
+    assert!(matches!(
+        rx.recv().await,
+        Some(ProcessorRequest::Release(None))
+    ));
+
+    // Commands are run as the given sender, and must parse
+    for (command, status) in [
+        ("/land priority=high", StatusCode::ACCEPTED),
+        ("/jump", StatusCode::BAD_REQUEST),
+    ]
+    .iter()
+    {
+        let mut request = admin_request(
+            "/admin/repos/Codertocat/Hello-World/pulls/5/command",
+            Some("secret"),
+        );
+        *request.body_mut() =
+            Body::from(serde_json::json!({"command": command, "sender": "octocat"}).to_string());
+        let resp = route(&mut service, request).await;
+        assert_eq!(resp.status(), *status);
+    }
+    match rx.recv().await {
+        Some(ProcessorRequest::RunCommand {
+            sender,
+            number,
+            command,
+        }) => {
+            assert_eq!((sender.as_str(), number), ("octocat", 5));
+            assert_eq!(command, "/land priority=high");
+        }
+        request => panic!("unexpected request {:?}", request),
+    }
+
+    let resp = route(
+        &mut service,
+        admin_request(
+            "/admin/repos/Codertocat/Goodbye-World/pause",
+            Some("secret"),
+        ),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn replay_delivery() {
+    let github = GithubConfig {
+        github_api_token: "".to_string(),
+        webhook_secret: None,
+        previous_webhook_secret: None,
+        require_webhook_signature: false,
+        timeout_seconds: None,
+        client_id: None,
+        client_secret: None,
+    };
+    let replay_request = |id: &str, token: Option<&str>| {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("/deliveries/{}/replay", id));
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    };
+    let store = Arc::new(SqliteStore::in_memory().unwrap());
+    for (id, event_type) in [("1", "issue_comment"), ("2", "from_the_future")].iter() {
+        store
+            .record_delivery(&Delivery {
+                delivery_id: id.to_string(),
+                event_type: event_type.to_string(),
+                payload: comment_payload(1, "alice", "/land")
+                    .to_string()
+                    .into_bytes(),
+                received_at: SystemTime::now(),
+            })
+            .await
+            .unwrap();
+    }
+
+    // Without logging in with Github or any tokens, nobody can replay deliveries
+    let mut service = Server::new(github.clone(), AdminConfig::default(), store.clone());
+    let resp = route(&mut service, replay_request("1", None)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let mut service = Server::new(
+        github,
+        toml::from_str(
+            r#"
+            token = "secret"
+
+            [[tokens]]
+            token = "dashboard"
+            capability = "read"
+            "#,
+        )
+        .unwrap(),
+        store,
+    );
+    let repo: RepoConfig = toml::from_str(
+        r#"
+        owner = "Codertocat"
+        name = "Hello-World"
+        "#,
+    )
+    .unwrap();
+    let (tx, mut rx) = EventProcessorSender::channel(repo.event_queue_capacity());
+    service.add_installation(Installation::new(repo, tx)).await;
+
+    // Otherwise replaying needs a token which can push to the delivery's repo
+    for (token, status) in [
+        (None, StatusCode::UNAUTHORIZED),
+        (Some("dashboard"), StatusCode::FORBIDDEN),
+        (Some("secret"), StatusCode::OK),
+    ]
+    .iter()
+    {
+        let resp = route(&mut service, replay_request("1", *token)).await;
+        assert_eq!(resp.status(), *status);
+    }
+    assert!(matches!(
+        rx.recv().await,
+        Some(ProcessorRequest::Webhook { .. })
+    ));
+
+    // Deliveries of event types this version doesn't know are refused rather than replayed
+    let resp = route(&mut service, replay_request("2", Some("secret"))).await;
+    assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let resp = route(&mut service, replay_request("3", Some("secret"))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn stats_window_query() {
+    let day = Duration::from_secs(24 * 60 * 60);
+    assert_eq!(stats_window(None), Some(day * 7));
+    assert_eq!(stats_window(Some("days=30")), Some(day * 30));
+    assert_eq!(stats_window(Some("pretty&days=1")), Some(day));
+    assert_eq!(stats_window(Some("days=0")), None);
+    assert_eq!(stats_window(Some("days=week")), None);
+}
+
+#[test]
+fn repo_permissions() {
+    let repo = Repo::new("Codertocat", "Hello-World");
+    assert_eq!(
+        required_permission("/repos/Codertocat/Hello-World"),
+        Some((repo.clone(), Permission::Read))
+    );
+    assert_eq!(
+        required_permission("/api/repos/Codertocat/Hello-World/events"),
+        Some((repo.clone(), Permission::Read))
+    );
+    assert_eq!(
+        required_permission("/repos/Codertocat/Hello-World/sync"),
+        Some((repo.clone(), Permission::Write))
+    );
+    assert_eq!(
+        required_permission("/admin/repos/Codertocat/Hello-World/pause"),
+        Some((repo, Permission::Write))
+    );
+    assert_eq!(required_permission("/repos"), None);
+    assert_eq!(required_permission("/api/repos/Codertocat"), None);
+}
+
+fn signed_request_from_raw_http(raw: &'static str, secret: &str) -> Request<Body> {
+    let payload = &raw[raw.find("{\n").unwrap()..];
+    let signature = hex::encode(hmac_sha256::HMAC::mac(
+        payload.as_bytes(),
+        secret.as_bytes(),
+    ));
+
+    let mut request = request_from_raw_http(raw);
+    request.headers_mut().insert(
+        github::SIGNATURE_256_HEADER,
+        format!("sha256={}", signature).parse().unwrap(),
+    );
+    request
+}
+
 // Super quick and dirty parsing of raw http into a `Request<Body>` type.
 // This assumes that the content is JSON
 fn request_from_raw_http(raw: &'static str) -> Request<Body> {
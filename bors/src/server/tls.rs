@@ -0,0 +1,189 @@
+//! Terminating TLS on the server's listener, for deployments without a fronting proxy
+//!
+//! The certificate and key are reloaded whenever either changes on disk, e.g. when they are renewed,
+//! so that new connections pick them up without restarting bors.
+
+use crate::{config::TlsConfig, Result};
+use hyper::server::accept::Accept;
+use std::{
+    fs, io,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_native_tls::{native_tls, TlsAcceptor, TlsStream};
+use tracing::{debug, info, warn};
+
+/// How often the certificate and key are checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+/// How long a client has to complete the TLS handshake before its connection is dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of connections which have completed a handshake but not yet been served
+const ACCEPT_BACKLOG: usize = 64;
+
+struct Certificate {
+    acceptor: TlsAcceptor,
+    /// When the certificate and key were last modified when they were loaded
+    modified: Option<(SystemTime, SystemTime)>,
+}
+
+impl Certificate {
+    fn load(config: &TlsConfig) -> Result<Self> {
+        let modified = modified(config);
+        let cert = fs::read(&config.cert_file)?;
+        let key = fs::read(&config.key_file)?;
+        let identity = native_tls::Identity::from_pkcs8(&cert, &key)?;
+
+        Ok(Self {
+            acceptor: native_tls::TlsAcceptor::new(identity)?.into(),
+            modified,
+        })
+    }
+}
+
+fn modified(config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(&config.cert_file).ok()?.modified().ok()?;
+    let key = fs::metadata(&config.key_file).ok()?.modified().ok()?;
+    Some((cert, key))
+}
+
+/// Connections which have completed a TLS handshake
+pub struct TlsIncoming {
+    connections: mpsc::Receiver<TlsStream<TcpStream>>,
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsStream<TcpStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Self::Conn>>> {
+        self.connections.poll_recv(cx).map(|stream| stream.map(Ok))
+    }
+}
+
+/// Accept TLS connections on `listener`. Handshakes are completed in the background so that a slow
+/// client can't hold up accepting other connections.
+pub fn incoming(listener: TcpListener, config: TlsConfig) -> Result<TlsIncoming> {
+    let certificate = Arc::new(RwLock::new(Certificate::load(&config)?));
+    let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+
+    tokio::spawn(reload(config, certificate.clone(), tx.clone()));
+    tokio::spawn(async move {
+        loop {
+            // Stop accepting connections once the server has shut down
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Unable to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = tx.closed() => break,
+            };
+
+            let acceptor = certificate.read().unwrap().acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(stream).await;
+                    }
+                    Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+                    Err(_) => debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+    });
+
+    Ok(TlsIncoming { connections: rx })
+}
+
+/// Reload the certificate and key whenever they change, until the server has shut down
+async fn reload(
+    config: TlsConfig,
+    certificate: Arc<RwLock<Certificate>>,
+    tx: mpsc::Sender<TlsStream<TcpStream>>,
+) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tx.closed() => break,
+        }
+
+        if modified(&config) == certificate.read().unwrap().modified {
+            continue;
+        }
+
+        // The files may be mid-update, in which case loading them is retried on the next tick
+        match Certificate::load(&config) {
+            Ok(reloaded) => {
+                info!("Reloaded TLS certificate {}", config.cert_file.display());
+                *certificate.write().unwrap() = reloaded;
+            }
+            Err(e) => warn!("Unable to reload TLS certificate: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server,
+    };
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn serves_tls() {
+        let config = TlsConfig {
+            cert_file: PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/test-input/tls-cert.pem"
+            )),
+            key_file: PathBuf::from(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/test-input/tls-key.pem"
+            )),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let make_service = make_service_fn(|_| {
+            future::ok::<_, io::Error>(service_fn(|_| {
+                future::ok::<_, io::Error>(Response::new(Body::from("OK")))
+            }))
+        });
+        let server = Server::builder(incoming(listener, config).unwrap()).serve(make_service);
+        tokio::spawn(server);
+
+        let connector: tokio_native_tls::TlsConnector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .into();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect("localhost", stream).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("OK"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Listening on a Unix domain socket, e.g. for a proxy running on the same host
+
+use crate::Result;
+use hyper::server::accept::Accept;
+use std::{
+    fs, io,
+    os::unix::fs::FileTypeExt,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::net::{UnixListener, UnixStream};
+
+pub struct UnixIncoming {
+    listener: UnixListener,
+}
+
+impl UnixIncoming {
+    /// Bind to the socket at `path`, replacing any socket left behind by a previous run
+    pub fn bind(path: &Path) -> Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+        })
+    }
+}
+
+impl Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<Self::Conn>>> {
+        self.listener
+            .poll_accept(cx)
+            .map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::future;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Response, Server,
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn serves_unix_socket() {
+        let path = std::env::temp_dir().join(format!("bors-test-{}.sock", std::process::id()));
+
+        // A socket left behind by a previous run is replaced
+        for _ in 0..2 {
+            let make_service = make_service_fn(|_| {
+                future::ok::<_, io::Error>(service_fn(|_| {
+                    future::ok::<_, io::Error>(Response::new(Body::from("OK")))
+                }))
+            });
+            let server = Server::builder(UnixIncoming::bind(&path).unwrap()).serve(make_service);
+            let server = tokio::spawn(server);
+
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            stream
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+            server.abort();
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,66 +1,582 @@
 use crate::{
-    config::{GitConfig, GithubConfig, RepoConfig},
-    event_processor::EventProcessor,
+    config::{GitConfig, GithubConfig, ReleaseTrain, RepoConfig},
+    datadog::{self, DogStatsd},
+    event_processor::{EventProcessor, EventProcessorReceiver, EventProcessorSender, Request},
+    git::GitRepository,
+    graphql::GithubClient,
+    notify::{self, Notifier},
+    replay::StubGithub,
     server::{Installation, Server, SmeeClient},
-    Config, Result,
+    store::{self, RepoState, SqliteStore, StateSnapshot, Store},
+    telemetry, Config, Result,
+};
+use anyhow::anyhow;
+use futures::future::{try_join_all, FutureExt};
+use github::{Event, EventType};
+use std::{
+    any::Any,
+    fs,
+    io::{self, Write},
+    net::SocketAddr,
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
-use futures::future::try_join_all;
 use structopt::StructOpt;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info};
 
 #[derive(StructOpt)]
 pub struct ServeOptions {
-    #[structopt(long, default_value = "3000")]
-    port: u16,
+    #[structopt(long)]
+    /// Port to listen on, overriding the port of the configured address
+    port: Option<u16>,
 
     #[structopt(long)]
-    /// smee.io URL
+    /// URL of a smee.io channel to forward webhooks from, or `new` to create one
     smee: Option<String>,
 }
 
 pub async fn run_serve(config: Config, options: &ServeOptions) -> Result<()> {
-    let mut tasks = Vec::new();
-    let server = Server::new(config.github.clone());
+    let is_tracing = config.tracing.is_some();
+
+    let store = store::from_config(&config.store).await?;
+    let server = Server::new(config.github.clone(), config.admin.clone(), store.clone());
 
     // Start up the server and optionally a smee client
-    let addr = ([0, 0, 0, 0], options.port).into();
-    tasks.push(tokio::spawn(server.clone().start(addr)));
+    let mut server_config = config.server.clone();
+    if let Some(port) = options.port {
+        let ip = server_config.bind().ip();
+        server_config.bind = Some(SocketAddr::new(ip, port));
+    }
+    let server_handle = tokio::spawn(server.clone().start(server_config, shutdown_signal()));
 
-    if let Some(smee_uri) = &options.smee {
+    let smee_handle = options.smee.as_ref().map(|smee_uri| {
         let smee_client = SmeeClient::with_uri(smee_uri.clone(), server.clone());
-        let smee_handle = tokio::spawn(smee_client.start());
-        tasks.push(smee_handle);
-    }
+        tokio::spawn(smee_client.start())
+    });
 
     // Start up all of the configured repos
-    let Config { repo, github, git } = config;
+    let mut tasks = Vec::new();
+    let mut notifiers = notify::from_config(&config.notifications)?;
+    let datadog = match &config.datadog {
+        Some(datadog) => Some(DogStatsd::new(datadog).await?),
+        None => None,
+    };
+    if let Some(datadog) = &datadog {
+        notifiers.push(Box::new(datadog.clone()));
+    }
+    let notifiers = Arc::new(notifiers);
+    let Config {
+        repo, github, git, ..
+    } = config;
     for repo in repo {
         let github = github.clone();
         let git = git.clone();
         let server = server.clone();
+        let store = store.clone();
         tasks.push(tokio::spawn(start_event_processor(
-            server, repo, github, git,
+            server,
+            repo,
+            github,
+            git,
+            store,
+            notifiers.clone(),
+            datadog.clone(),
         )));
     }
 
     // Join all of the spawned tasks
     try_join_all(tasks).await?;
+
+    // The server only returns once a shutdown has been requested and it has stopped accepting
+    // webhooks
+    server_handle.await??;
+    if let Some(smee_handle) = smee_handle {
+        smee_handle.abort();
+    }
+
+    // Let the event processors finish what they're doing, and persist their state, so that the
+    // auto branch and project board aren't left in an inconsistent state. An event processor which
+    // is being restarted may never get around to shutting down so don't wait on them forever.
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60 * 2);
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, server.shutdown())
+        .await
+        .is_err()
+    {
+        error!("timed out waiting for event processors to shutdown");
+    } else {
+        info!("bors shutdown cleanly");
+    }
+    if is_tracing {
+        telemetry::shutdown().await;
+    }
+
     Ok(())
 }
 
+/// Completes once bors has been asked to shutdown, either by a SIGTERM or a ctrl-c
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    info!("Shutdown requested, draining in-flight work");
+}
+
 async fn start_event_processor(
     mut server: Server,
     repo: RepoConfig,
     github: GithubConfig,
     git: GitConfig,
+    store: Arc<dyn Store>,
+    notifiers: Arc<Vec<Box<dyn Notifier>>>,
+    datadog: Option<DogStatsd>,
 ) -> Result<()> {
-    let repo_clone = repo.clone();
-    let (tx, event_processor) =
-        tokio::task::spawn_blocking(move || EventProcessor::new(repo_clone, &github, &git))
-            .await??;
-    tokio::spawn(event_processor.start());
+    let (tx, requests_rx) = EventProcessorSender::channel(repo.event_queue_capacity());
+    if !notifiers.is_empty() {
+        tokio::spawn(notify::run(repo.repo().clone(), tx.subscribe(), notifiers));
+    }
+    let event_processor = new_event_processor(&repo, &github, &git, &store).await?;
+    tokio::spawn(supervise_event_processor(
+        event_processor,
+        requests_rx,
+        repo.clone(),
+        github,
+        git,
+        store,
+    ));
 
-    let installation = Installation::new(repo, tx);
+    if let Some(interval) = repo.sync_interval() {
+        tokio::spawn(synchronize_periodically(tx.clone(), interval));
+    }
+    if let Some(interval) = repo.sweep_interval() {
+        tokio::spawn(sweep_periodically(tx.clone(), interval));
+    }
+    tokio::spawn(check_stuck_periodically(tx.clone()));
+    if repo.stale().is_some() {
+        tokio::spawn(nag_stale_periodically(tx.clone()));
+    }
+    if let Some(train) = repo.release_train() {
+        tokio::spawn(release_on_departures(tx.clone(), train.clone()));
+    }
+    for train in repo.landing_trains() {
+        tokio::spawn(land_on_departures(tx.clone(), train.clone()));
+    }
+    if let Some(datadog) = datadog {
+        tokio::spawn(datadog::report_metrics(
+            repo.repo().clone(),
+            tx.clone(),
+            datadog,
+        ));
+    }
+
+    let installation = Installation::new(repo.clone(), tx);
     server.add_installation(installation).await;
 
+    // Now that the repo's installation is registered, replay any deliveries which weren't
+    // processed before bors was last shutdown
+    server.replay_unprocessed_deliveries(repo.repo()).await?;
+
+    Ok(())
+}
+
+async fn new_event_processor(
+    repo: &RepoConfig,
+    github: &GithubConfig,
+    git: &GitConfig,
+    store: &Arc<dyn Store>,
+) -> Result<EventProcessor> {
+    let (repo, github, git, store) = (repo.clone(), github.clone(), git.clone(), store.clone());
+    tokio::task::spawn_blocking(move || EventProcessor::new(repo, &github, &git, store)).await?
+}
+
+/// Run an event processor, restarting it with an exponential backoff if it panics so that a single
+/// bad request doesn't permanently stop the repo from being served. A restarted event processor
+/// restores its state from the store and resynchronizes with Github.
+async fn supervise_event_processor(
+    mut event_processor: EventProcessor,
+    mut requests_rx: EventProcessorReceiver,
+    repo: RepoConfig,
+    github: GithubConfig,
+    git: GitConfig,
+    store: Arc<dyn Store>,
+) {
+    const MIN_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60 * 5);
+
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started_at = Instant::now();
+        match AssertUnwindSafe(event_processor.start(&mut requests_rx))
+            .catch_unwind()
+            .await
+        {
+            // The event processor only returns once it has been shutdown
+            Ok(()) => return,
+            Err(panic) => error!(
+                "event processor for {}/{} panicked: {}",
+                repo.owner(),
+                repo.name(),
+                panic_message(&*panic)
+            ),
+        }
+
+        // An event processor which ran for a while before panicking isn't stuck in a crash loop
+        if started_at.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+
+        event_processor = loop {
+            info!(
+                "restarting event processor for {}/{} in {:?}",
+                repo.owner(),
+                repo.name(),
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+
+            match new_event_processor(&repo, &github, &git, &store).await {
+                Ok(event_processor) => break event_processor,
+                Err(e) => error!("unable to create event processor: {:?}", e),
+            }
+        };
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Periodically resynchronize an event processor's state with Github so that any drift, e.g. from
+/// missed webhooks, is corrected without manual intervention
+async fn synchronize_periodically(event_processor: EventProcessorSender, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+
+    // The first tick completes immediately and the event processor has only just synchronized
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        // Once the event processor has shutdown there is nothing left to synchronize
+        if event_processor.sync().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Periodically evict PRs which are no longer open from an event processor's state. This is much
+/// cheaper than a full synchronize so it can be done far more often.
+async fn sweep_periodically(event_processor: EventProcessorSender, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if event_processor.sweep().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Periodically check whether an event processor's merge queue has stopped making progress
+async fn check_stuck_periodically(event_processor: EventProcessorSender) {
+    const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if event_processor.check_stuck().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Periodically ping PRs which have had no activity for a while
+async fn nag_stale_periodically(event_processor: EventProcessorSender) {
+    const NAG_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+    let mut interval = tokio::time::interval(NAG_INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if event_processor.nag_stale().await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Open a release PR each time the repo's release train departs
+async fn release_on_departures(event_processor: EventProcessorSender, train: ReleaseTrain) {
+    loop {
+        let now = SystemTime::now();
+        // Step past a departure which has only just happened
+        let departure = train.next_departure(now + Duration::from_secs(1));
+        tokio::time::sleep(departure.duration_since(now).unwrap_or_default()).await;
+
+        if event_processor.release(None).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Start landing the PRs queued before each departure of a landing train
+async fn land_on_departures(event_processor: EventProcessorSender, train: ReleaseTrain) {
+    loop {
+        let now = SystemTime::now();
+        let departure = train.next_departure(now + Duration::from_secs(1));
+        tokio::time::sleep(departure.duration_since(now).unwrap_or_default()).await;
+
+        if event_processor.process_queue().await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(StructOpt)]
+pub enum GitCommand {
+    #[structopt(name = "refresh")]
+    /// Clone, or refresh if already cloned, the on-disk repos used by the server
+    Refresh(RefreshOptions),
+}
+
+#[derive(StructOpt)]
+pub struct RefreshOptions {
+    /// Only refresh the specified repo, either `<owner>/<name>` or `<name>`
+    repo: Option<String>,
+}
+
+pub fn run_git(config: Config, command: &GitCommand) -> Result<()> {
+    match command {
+        GitCommand::Refresh(options) => run_git_refresh(config, options),
+    }
+}
+
+fn run_git_refresh(config: Config, options: &RefreshOptions) -> Result<()> {
+    for repo in select_repos(&config, options.repo.as_deref())? {
+        info!("refreshing {}/{}", repo.owner(), repo.name());
+        GitRepository::from_config(&config.git, repo)?.refresh()?;
+    }
+
+    Ok(())
+}
+
+#[derive(StructOpt)]
+pub enum StateCommand {
+    #[structopt(name = "export")]
+    /// Write the persisted state of the configured repos to stdout as JSON
+    Export(StateOptions),
+
+    #[structopt(name = "import")]
+    /// Replace the persisted state of the configured repos with JSON read from stdin. bors
+    /// should not be running while importing as it would overwrite the imported state.
+    Import(StateOptions),
+}
+
+#[derive(StructOpt)]
+pub struct StateOptions {
+    /// Only export or import the specified repo, either `<owner>/<name>` or `<name>`
+    repo: Option<String>,
+}
+
+/// Snapshot of the persisted state of a set of repos, keyed by `<owner>/<name>`
+pub async fn run_state(config: Config, command: &StateCommand) -> Result<()> {
+    let store = store::from_config(&config.store).await?;
+
+    match command {
+        StateCommand::Export(options) => run_state_export(&config, &*store, options).await,
+        StateCommand::Import(options) => run_state_import(&config, &*store, options).await,
+    }
+}
+
+async fn run_state_export(
+    config: &Config,
+    store: &dyn Store,
+    options: &StateOptions,
+) -> Result<()> {
+    let mut snapshot = StateSnapshot::new();
+    for repo in select_repos(config, options.repo.as_deref())? {
+        if let Some(state) = store.load(repo.repo()).await? {
+            snapshot.insert(format!("{}/{}", repo.owner(), repo.name()), state);
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &snapshot)?;
+    writeln!(stdout)?;
+
     Ok(())
 }
+
+async fn run_state_import(
+    config: &Config,
+    store: &dyn Store,
+    options: &StateOptions,
+) -> Result<()> {
+    let snapshot: StateSnapshot = serde_json::from_reader(io::stdin().lock())?;
+    let repos = select_repos(config, options.repo.as_deref())?;
+
+    for (name, state) in snapshot {
+        let repo = match config
+            .repo
+            .iter()
+            .find(|repo| name == format!("{}/{}", repo.owner(), repo.name()))
+        {
+            Some(repo) => repo,
+            None => {
+                return Err(anyhow!(
+                    "snapshot contains state for unconfigured repo {}",
+                    name
+                ))
+            }
+        };
+
+        if repos.iter().any(|selected| selected.repo() == repo.repo()) {
+            info!("importing state for {}", name);
+            store.save(repo.repo(), &state).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(StructOpt)]
+pub struct ReplayOptions {
+    #[structopt(long)]
+    /// Repo the webhooks were recorded for, either `<owner>/<name>` or `<name>`. Required if more
+    /// than one repo is configured.
+    repo: Option<String>,
+
+    #[structopt(parse(from_os_str))]
+    /// Directory of recorded webhook payloads, named `<sequence>-<event-type>.json`, and
+    /// optionally a `state.json` produced by `state export` to start from
+    dir: PathBuf,
+}
+
+/// Replay recorded webhooks through an event processor which talks to a stub instead of Github and
+/// never pushes, writing the resulting state to stdout in the same format as `state export`
+pub async fn run_replay(config: Config, options: &ReplayOptions) -> Result<()> {
+    let repo = match select_repos(&config, options.repo.as_deref())?.as_slice() {
+        [repo] => (*repo).clone(),
+        _ => {
+            return Err(anyhow!(
+                "multiple repos are configured, specify one with --repo"
+            ))
+        }
+    };
+    let name = format!("{}/{}", repo.owner(), repo.name());
+
+    let state_path = options.dir.join("state.json");
+    let state = if state_path.exists() {
+        let mut snapshot: StateSnapshot = serde_json::from_slice(&fs::read(&state_path)?)?;
+        snapshot.remove(&name).ok_or_else(|| {
+            anyhow!(
+                "{} doesn't contain state for {}",
+                state_path.display(),
+                name
+            )
+        })?
+    } else {
+        RepoState {
+            merge_queue: Default::default(),
+            pulls: Default::default(),
+        }
+    };
+    let requests = load_recorded_webhooks(&options.dir)?;
+
+    let stub = StubGithub::start()?;
+    let github = GithubClient::with_base_url(&config.github, &stub.base_url());
+    let git_repository = GitRepository::from_config(&config.git, &repo)?.with_dry_run();
+    let store = Arc::new(SqliteStore::in_memory()?);
+
+    info!("replaying {} webhooks for {}", requests.len(), name);
+    let event_processor =
+        EventProcessor::with_clients(repo, Box::new(github), Box::new(git_repository), store);
+    let state = event_processor.replay(state, requests).await;
+
+    let mut snapshot = StateSnapshot::new();
+    snapshot.insert(name, state);
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &snapshot)?;
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+/// Load the webhooks recorded in `dir`, ordered by their file names
+fn load_recorded_webhooks(dir: &Path) -> Result<Vec<Request>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some("json".as_ref())
+            && path.file_name() != Some("state.json".as_ref())
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut requests = Vec::new();
+    for path in paths {
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let event_type = match stem.split_once('-') {
+            Some((_sequence, event_type)) => event_type.parse::<EventType>().unwrap(),
+            None => {
+                return Err(anyhow!(
+                    "{} isn't named `<sequence>-<event-type>.json`",
+                    path.display()
+                ))
+            }
+        };
+
+        let event = Event::from_json(event_type, &fs::read(&path)?)
+            .map_err(|e| anyhow!("unable to parse {}: {}", path.display(), e))?;
+        requests.push(Request::Webhook {
+            event,
+            delivery_id: stem.to_owned(),
+        });
+    }
+
+    Ok(requests)
+}
+
+/// Select the configured repos matching `name`, either `<owner>/<name>` or `<name>`, or all
+/// configured repos if no name is provided
+fn select_repos<'a>(config: &'a Config, name: Option<&str>) -> Result<Vec<&'a RepoConfig>> {
+    let repos: Vec<_> = config
+        .repo
+        .iter()
+        .filter(|repo| match name {
+            Some(name) => {
+                name == repo.name() || name == format!("{}/{}", repo.owner(), repo.name())
+            }
+            None => true,
+        })
+        .collect();
+
+    if repos.is_empty() {
+        return Err(anyhow!("no configured repo matches the provided name"));
+    }
+
+    Ok(repos)
+}
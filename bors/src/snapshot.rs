@@ -0,0 +1,82 @@
+//! Snapshots of an event processor's state which are dumped when it fails to handle a request, so
+//! that the failure can be reconstructed, e.g. with `bors replay`, instead of guessed from logs
+
+use crate::{
+    event_processor::Request,
+    state::Repo,
+    store::{RepoState, StateSnapshot, Store},
+    Error, Result,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The state an event processor was in before handling a request
+#[derive(Debug)]
+pub struct FailureSnapshot {
+    state: RepoState,
+    /// Debug representation of the request, since requests themselves can't be serialized
+    request: String,
+    delivery_id: Option<String>,
+}
+
+impl FailureSnapshot {
+    pub fn new(state: RepoState, request: &Request) -> Self {
+        let delivery_id = match request {
+            Request::Webhook { delivery_id, .. } => Some(delivery_id.clone()),
+            _ => None,
+        };
+
+        Self {
+            state,
+            request: format!("{:#?}", request),
+            delivery_id,
+        }
+    }
+
+    /// Write the snapshot, along with the error handling the request failed with, to a new
+    /// directory in `dir`, returning the path of the new directory.
+    ///
+    /// The directory is laid out so that it can be passed directly to `bors replay`: the state is
+    /// written to `state.json` and the webhook's payload, if it was journaled, to
+    /// `0001-<event-type>.json`. The error and the request are written to `error.txt`.
+    pub async fn write(
+        self,
+        dir: &Path,
+        repo: &Repo,
+        error: &Error,
+        store: &dyn Store,
+    ) -> Result<PathBuf> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let dir = dir.join(format!("{}-{}-{}", repo.owner(), repo.name(), millis));
+        fs::create_dir_all(&dir)?;
+
+        let mut snapshot = StateSnapshot::new();
+        snapshot.insert(format!("{}/{}", repo.owner(), repo.name()), self.state);
+        fs::write(
+            dir.join("state.json"),
+            serde_json::to_vec_pretty(&snapshot)?,
+        )?;
+
+        if let Some(delivery_id) = &self.delivery_id {
+            if let Some(delivery) = store.delivery(delivery_id).await? {
+                fs::write(
+                    dir.join(format!("0001-{}.json", delivery.event_type)),
+                    delivery.payload,
+                )?;
+            }
+        }
+
+        fs::write(
+            dir.join("error.txt"),
+            format!("{:?}\n\n{}\n", error, self.request),
+        )?;
+
+        Ok(dir)
+    }
+}
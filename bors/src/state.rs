@@ -1,16 +1,17 @@
 use crate::{
-    config::RepoConfig, graphql::GithubClient, project_board::ProjectBoard, queue::QueueEntry,
+    config::RepoConfig, github_api::GithubApi, project_board::ProjectBoard, queue::QueueEntry,
     Result,
 };
 use github::Oid;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     str::FromStr,
+    time::SystemTime,
 };
 use thiserror::Error;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PullRequestState {
     pub number: u64,
     pub id: u64,
@@ -31,6 +32,10 @@ pub struct PullRequestState {
     pub is_draft: bool,
     pub approved_by: HashSet<String>,
     pub approved: bool,
+    /// Who pushed the commits which dismissed the PR's approval, until it's approved again.
+    /// Github keeps reporting the PR as approved, so its review decision is overridden until then.
+    #[serde(default)]
+    pub approval_dismissed_by: Option<String>,
     pub maintainer_can_modify: bool, // Use to enable 'rebase' merging and having github know a PR has been merged
     pub mergeable: bool,
     pub labels: HashSet<String>,
@@ -39,14 +44,87 @@ pub struct PullRequestState {
     pub project_card_id: Option<u64>,
 
     pub canary_requested: bool,
+    /// The most recent canary runs, oldest first
+    pub canary_runs: Vec<CanaryRun>,
+
+    /// When there was last activity on the PR, e.g. a push, comment or review, which isn't known
+    /// for PRs tracked by older versions of bors
+    #[serde(default)]
+    pub last_activity: Option<SystemTime>,
+    /// When the PR's current head was pushed, which isn't known if it hasn't changed since bors
+    /// started tracking the PR
+    #[serde(default)]
+    pub head_pushed_at: Option<SystemTime>,
+    /// When bors last pinged the PR for having gone stale, or warned that it will be closed
+    #[serde(default)]
+    pub nagged_at: Option<SystemTime>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TestResult {
     pub passed: bool,
     pub details_url: String,
 }
 
+/// How many of a PR's past canary runs are kept
+const CANARY_HISTORY_LEN: usize = 10;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CanaryResult {
+    Passed,
+    Failed,
+    TimedOut,
+}
+
+/// A finished canary run, kept so that a PR's new canaries can be compared against its previous
+/// ones
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CanaryRun {
+    pub merge_oid: Oid,
+    pub head_ref_oid: Oid,
+    pub base_ref_name: String,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    pub result: CanaryResult,
+    pub test_results: HashMap<String, TestResult>,
+}
+
+impl CanaryRun {
+    /// A markdown table comparing the outcome of each check with that of a previous run
+    pub fn compare(&self, previous: &CanaryRun) -> String {
+        fn outcome(run: &CanaryRun, check: &str) -> &'static str {
+            match run.test_results.get(check) {
+                Some(result) if result.passed => ":white_check_mark:",
+                Some(_) => ":x:",
+                None => "-",
+            }
+        }
+
+        let checks: BTreeSet<_> = previous
+            .test_results
+            .keys()
+            .chain(self.test_results.keys())
+            .collect();
+
+        let mut comparison = format!(
+            ":bar_chart: Compared to the previous canary of `{}` onto `{}`:\n\n\
+            | Check | Previous | This canary |\n\
+            | --- | --- | --- |\n",
+            previous.head_ref_oid, previous.base_ref_name
+        );
+        for check in checks {
+            comparison.push_str(&format!(
+                "| {} | {} | {} |\n",
+                check,
+                outcome(previous, check),
+                outcome(self, check)
+            ));
+        }
+        comparison
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq, Ord, Eq)]
 pub enum StatusType {
     Testing,
@@ -55,18 +133,22 @@ pub enum StatusType {
     InReview,
 }
 
-#[derive(Clone, Debug)]
+// Timestamps are recorded as `SystemTime` instead of `Instant` so that they remain meaningful when
+// persisted and reloaded after a restart
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Status {
     InReview,
-    Queued(std::time::Instant),
+    Queued(SystemTime),
     Testing {
         merge_oid: Oid,
-        tests_started_at: std::time::Instant,
+        /// When the PR was queued, retained so that a requeued PR keeps its place in the queue
+        queued_at: SystemTime,
+        tests_started_at: SystemTime,
         test_results: HashMap<String, TestResult>,
     },
     Canary {
         merge_oid: Oid,
-        tests_started_at: std::time::Instant,
+        tests_started_at: SystemTime,
         test_results: HashMap<String, TestResult>,
     },
     // Failed {
@@ -84,6 +166,14 @@ impl Status {
         matches!(self, Status::Queued(_))
     }
 
+    /// When a PR which is queued, or being tested, was queued
+    pub fn queued_at(&self) -> Option<SystemTime> {
+        match self {
+            Status::Queued(queued_at) | Status::Testing { queued_at, .. } => Some(*queued_at),
+            Status::InReview | Status::Canary { .. } => None,
+        }
+    }
+
     pub fn is_testing(&self) -> bool {
         matches!(self, Status::Testing { .. })
     }
@@ -93,13 +183,14 @@ impl Status {
     }
 
     pub fn queued() -> Status {
-        Status::Queued(std::time::Instant::now())
+        Status::Queued(SystemTime::now())
     }
 
-    pub fn testing(merge_oid: Oid) -> Status {
+    pub fn testing(merge_oid: Oid, queued_at: SystemTime) -> Status {
         Status::Testing {
             merge_oid,
-            tests_started_at: std::time::Instant::now(),
+            queued_at,
+            tests_started_at: SystemTime::now(),
             test_results: HashMap::new(),
         }
     }
@@ -107,7 +198,7 @@ impl Status {
     pub fn canary(merge_oid: Oid) -> Status {
         Status::Canary {
             merge_oid,
-            tests_started_at: std::time::Instant::now(),
+            tests_started_at: SystemTime::now(),
             test_results: HashMap::new(),
         }
     }
@@ -146,12 +237,17 @@ impl PullRequestState {
             is_draft: pull.draft.unwrap_or(false),
             approved_by: HashSet::new(),
             approved: false,
+            approval_dismissed_by: None,
             maintainer_can_modify: pull.maintainer_can_modify.unwrap_or(false),
             mergeable: pull.mergeable.unwrap_or(false),
             labels,
             status: Status::InReview,
             project_card_id: None,
             canary_requested: false,
+            canary_runs: Vec::new(),
+            last_activity: Some(pull.updated_at.clone().into()),
+            head_pushed_at: None,
+            nagged_at: None,
         }
     }
 
@@ -169,33 +265,80 @@ impl PullRequestState {
     pub async fn update_head(
         &mut self,
         oid: Oid,
+        pusher: &str,
+        my_username: &str,
         config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         project_board: Option<&ProjectBoard>,
     ) -> Result<()> {
         self.head_ref_oid = oid.clone();
+        self.head_pushed_at = Some(SystemTime::now());
+
+        // If the oid we're being updated to is the same as the merge_oid then we don't need to
+        // do anything
+        if let Status::Testing { merge_oid, .. } | Status::Canary { merge_oid, .. } = &self.status {
+            if merge_oid == &oid {
+                return Ok(());
+            }
+        }
+
+        if config.dismisses_approval(pusher, my_username)
+            && (self.approved || !self.approved_by.is_empty())
+        {
+            self.approved = false;
+            self.approved_by.clear();
+            self.approval_dismissed_by = Some(pusher.to_owned());
+        }
+
+        let approved = self.approved || !config.require_review();
 
         match &self.status {
-            // If the oid we're being updated to is the same as the merge_oid then we don't need to
-            // do anything
-            Status::Testing { merge_oid, .. } | Status::Canary { merge_oid, .. }
-                if merge_oid == &oid => {}
             Status::InReview => {}
-            _ => {
-                if let Status::Testing { .. } | Status::Queued(_) = &self.status {
-                    let msg = ":exclamation: Land has been canceled due to this PR being updated with new commits. \
-                    Please issue another Land command if you want to requeue this PR.";
-
-                    github
-                        .issues()
-                        .create_comment(
-                            config.repo().owner(),
-                            config.repo().name(),
-                            self.number,
-                            msg,
-                        )
-                        .await?;
-                }
+            Status::Canary { .. } => {
+                self.update_status(Status::InReview, config, github, project_board)
+                    .await?;
+            }
+            Status::Testing { .. } | Status::Queued(_) if config.requeue_on_push() && approved => {
+                // Any in-flight test run is for stale code so the PR is placed back into the
+                // queue, retaining its position, so that the new commits are tested instead
+                let queued_at = match &self.status {
+                    Status::Queued(queued_at) | Status::Testing { queued_at, .. } => *queued_at,
+                    _ => unreachable!(),
+                };
+
+                let msg = ":arrows_counterclockwise: Land has been restarted due to this PR being updated with new commits. \
+                This PR has been requeued and the new commits will be tested once it reaches the head of the queue.";
+
+                github
+                    .create_comment(
+                        config.repo().owner(),
+                        config.repo().name(),
+                        self.number,
+                        msg,
+                    )
+                    .await?;
+
+                self.update_status(Status::Queued(queued_at), config, github, project_board)
+                    .await?;
+            }
+            Status::Testing { .. } | Status::Queued(_) => {
+                let msg = if approved {
+                    ":exclamation: Land has been canceled due to this PR being updated with new commits. \
+                    Please issue another Land command if you want to requeue this PR."
+                } else {
+                    ":exclamation: Land has been canceled due to this PR being updated with new commits, \
+                    which invalidated its approval. Please have the PR re-approved and issue another Land \
+                    command if you want to requeue this PR."
+                };
+
+                github
+                    .create_comment(
+                        config.repo().owner(),
+                        config.repo().name(),
+                        self.number,
+                        msg,
+                    )
+                    .await?;
 
                 self.update_status(Status::InReview, config, github, project_board)
                     .await?;
@@ -212,7 +355,7 @@ impl PullRequestState {
         base_ref_name: &str,
         base_ref_oid: &Oid,
         config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         project_board: Option<&ProjectBoard>,
     ) -> Result<()> {
         let mut changed = false;
@@ -238,7 +381,6 @@ impl PullRequestState {
                 Please issue another Land command if you want to requeue this PR.";
 
             github
-                .issues()
                 .create_comment(
                     config.repo().owner(),
                     config.repo().name(),
@@ -254,17 +396,57 @@ impl PullRequestState {
         Ok(())
     }
 
+    /// Carry over the state which bors tracks itself, and can't be recovered from Github, from a
+    /// previous snapshot of this PR, provided that the PR hasn't been updated since.
+    ///
+    /// Returns `false`, without carrying over anything but its canary history, if the PR has been
+    /// updated.
+    pub fn carry_over(&mut self, previous: PullRequestState) -> bool {
+        // Past canaries are kept even across pushes so that the new commits can be compared
+        self.canary_runs = previous.canary_runs;
+        // A head pushed while bors wasn't watching was pushed no later than the PR's last update
+        self.head_pushed_at = if self.head_ref_oid == previous.head_ref_oid {
+            previous.head_pushed_at
+        } else {
+            self.last_activity
+        };
+
+        if self.head_ref_oid != previous.head_ref_oid
+            || self.base_ref_name != previous.base_ref_name
+        {
+            return false;
+        }
+
+        self.approved_by = previous.approved_by;
+        self.approval_dismissed_by = previous.approval_dismissed_by;
+        self.approved &= self.approval_dismissed_by.is_none();
+        self.canary_requested = previous.canary_requested;
+        self.status = previous.status;
+        self.last_activity = self.last_activity.or(previous.last_activity);
+        self.nagged_at = previous.nagged_at;
+        true
+    }
+
+    /// Add a finished canary run to the PR's history, dropping the oldest run if the history is
+    /// full
+    pub fn record_canary_run(&mut self, run: CanaryRun) {
+        if self.canary_runs.len() >= CANARY_HISTORY_LEN {
+            self.canary_runs.remove(0);
+        }
+        self.canary_runs.push(run);
+    }
+
     pub async fn update_status(
         &mut self,
         status: Status,
         _config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         project_board: Option<&ProjectBoard>,
     ) -> Result<()> {
         self.status = status;
 
         if let Some(board) = project_board {
-            board.move_pr_to_status_column(github, &self).await?;
+            board.move_pr_to_status_column(github, self).await?;
         }
 
         Ok(())
@@ -273,12 +455,11 @@ impl PullRequestState {
     pub async fn add_label(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         label: &str,
     ) -> Result<()> {
         github
-            .issues()
-            .add_lables(
+            .add_labels(
                 config.owner(),
                 config.name(),
                 self.number,
@@ -306,12 +487,11 @@ impl PullRequestState {
     pub async fn remove_label(
         &mut self,
         config: &RepoConfig,
-        github: &GithubClient,
+        github: &dyn GithubApi,
         label: &str,
     ) -> Result<()> {
         if self.labels.contains(label) {
             github
-                .issues()
                 .remove_label(config.owner(), config.name(), self.number, label)
                 .await?;
             self.labels.remove(label);
@@ -375,7 +555,7 @@ pub enum TestSuiteResult {
 
 impl TestSuiteResult {
     pub fn new(
-        tests_started_at: std::time::Instant,
+        tests_started_at: SystemTime,
         test_results: &HashMap<String, TestResult>,
         config: &RepoConfig,
     ) -> Self {
@@ -397,7 +577,7 @@ impl TestSuiteResult {
         {
             TestSuiteResult::Passed
         // Check if the test has timed-out
-        } else if tests_started_at.elapsed() >= config.timeout() {
+        } else if tests_started_at.elapsed().unwrap_or_default() >= config.timeout() {
             TestSuiteResult::TimedOut
         } else {
             TestSuiteResult::Pending
@@ -412,6 +592,17 @@ pub enum Priority {
     Low,
 }
 
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        };
+        f.write_str(s)
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("invalid priority")]
 pub struct ParsePriorityError;
@@ -428,7 +619,7 @@ impl FromStr for Priority {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Repo {
     owner: String,
     name: String,
@@ -465,3 +656,84 @@ impl Repo {
         format!("https://github.com/{}/{}.git", self.owner, self.name)
     }
 }
+
+impl std::fmt::Display for Repo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.owner, self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::{oid, FakeGithub};
+    use std::time::Duration;
+
+    #[test]
+    fn carry_over_head_pushed_at() {
+        let github = FakeGithub::new();
+        let pushed_at = SystemTime::now() - Duration::from_secs(60);
+        let mut previous = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        previous.head_pushed_at = Some(pushed_at);
+
+        let mut pull = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        assert!(pull.carry_over(previous.clone()));
+        assert_eq!(pull.head_pushed_at, Some(pushed_at));
+
+        // A push bors missed happened no later than the PR was last updated
+        let mut pull = PullRequestState::from_pull_request(&github.open_pull(1, "head-b"));
+        assert!(!pull.carry_over(previous));
+        assert_eq!(pull.head_pushed_at, pull.last_activity);
+    }
+
+    #[tokio::test]
+    async fn update_head_dismisses_approval() {
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            require-review = true
+            trusted-pushers = ["octocat"]
+            "#,
+        )
+        .unwrap();
+        let github = FakeGithub::new();
+        let mut pull = PullRequestState::from_pull_request(&github.open_pull(1, "head"));
+        pull.approved = true;
+
+        // Pushes by bors itself and trusted pushers keep the approval, anyone else's dismiss it
+        pull.update_head(oid("head-a"), "bors", "bors", &config, &github, None)
+            .await
+            .unwrap();
+        assert!(pull.approved);
+        assert!(pull.head_pushed_at.is_some());
+        pull.update_head(oid("head-b"), "octocat", "bors", &config, &github, None)
+            .await
+            .unwrap();
+        assert!(pull.approved);
+
+        pull.update_head(oid("head-c"), "Codertocat", "bors", &config, &github, None)
+            .await
+            .unwrap();
+        assert!(!pull.approved);
+        assert_eq!(pull.approval_dismissed_by.as_deref(), Some("Codertocat"));
+
+        // Unless the repo keeps approvals across pushes
+        let config: RepoConfig = toml::from_str(
+            r#"
+            owner = "Codertocat"
+            name = "Hello-World"
+            require-review = true
+            approval-on-push = "keep"
+            "#,
+        )
+        .unwrap();
+        let mut pull = PullRequestState::from_pull_request(&github.open_pull(2, "head"));
+        pull.approved = true;
+        pull.update_head(oid("head-b"), "Codertocat", "bors", &config, &github, None)
+            .await
+            .unwrap();
+        assert!(pull.approved);
+        assert_eq!(pull.approval_dismissed_by, None);
+    }
+}
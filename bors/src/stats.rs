@@ -0,0 +1,302 @@
+//! Queue health metrics aggregated from a repo's merge history
+
+use crate::store::{LandingAttempt, LandingResult};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+/// How far back the merge history is aggregated over by default
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct CheckStats {
+    /// Number of attempts which this check reported a result for
+    pub runs: usize,
+    pub passed: usize,
+    pub pass_rate: f64,
+}
+
+/// Metrics over the attempts to land PRs which finished within a window of time
+#[derive(Debug, Serialize)]
+pub struct QueueStats {
+    pub window_secs: u64,
+    pub attempts: usize,
+    pub landed: usize,
+    pub failed: usize,
+    pub timed_out: usize,
+    pub merge_failed: usize,
+    pub landings_per_day: f64,
+    /// Median time between a PR being queued and its tests being started. Attempts recorded by
+    /// older versions of bors, which didn't record when PRs were queued, aren't included.
+    pub median_time_in_queue_secs: Option<u64>,
+    pub median_test_duration_secs: Option<u64>,
+    pub checks: BTreeMap<String, CheckStats>,
+}
+
+impl QueueStats {
+    /// Aggregate the attempts in `history` which finished within `window` of `now`
+    pub fn from_history(history: &[LandingAttempt], window: Duration, now: SystemTime) -> Self {
+        let attempts: Vec<_> = history
+            .iter()
+            .filter(|attempt| {
+                now.duration_since(attempt.finished_at)
+                    .map(|age| age <= window)
+                    .unwrap_or(true)
+            })
+            .collect();
+
+        let count = |result: LandingResult| {
+            attempts
+                .iter()
+                .filter(|attempt| attempt.result == result)
+                .count()
+        };
+        let landed = count(LandingResult::Landed);
+
+        let mut checks = BTreeMap::<_, CheckStats>::new();
+        for attempt in &attempts {
+            for name in &attempt.passed_checks {
+                let check = checks.entry(name.clone()).or_default();
+                check.runs += 1;
+                check.passed += 1;
+            }
+            for name in &attempt.failing_checks {
+                checks.entry(name.clone()).or_default().runs += 1;
+            }
+        }
+        for check in checks.values_mut() {
+            check.pass_rate = check.passed as f64 / check.runs as f64;
+        }
+
+        Self {
+            window_secs: window.as_secs(),
+            attempts: attempts.len(),
+            landed,
+            failed: count(LandingResult::Failed),
+            timed_out: count(LandingResult::TimedOut),
+            merge_failed: count(LandingResult::MergeFailed),
+            landings_per_day: landed as f64 / (window.as_secs_f64() / DAY.as_secs_f64()),
+            median_time_in_queue_secs: median(
+                attempts
+                    .iter()
+                    .filter_map(|attempt| attempt.time_in_queue())
+                    .collect(),
+            ),
+            median_test_duration_secs: median(
+                attempts.iter().map(|attempt| attempt.duration()).collect(),
+            ),
+            checks,
+        }
+    }
+}
+
+/// How a check behaved on PRs which failed to land but then landed when retried, whose failures
+/// were most likely flakes rather than real breakage
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct FlakyCheck {
+    pub name: String,
+    /// Number of attempts to land retried PRs which this check reported a result for
+    pub runs: usize,
+    pub failures: usize,
+    pub failure_rate: f64,
+    /// Time spent testing the attempts which this check failed, which the queue could have spent
+    /// landing other PRs
+    pub wasted_test_secs: u64,
+}
+
+/// Rank the checks which failed attempts to land PRs that later landed, over the attempts in
+/// `history` which finished within `window` of `now`, from the highest failure rate. PRs which were
+/// changed between attempts can't be told apart from those which were simply retried, so a real
+/// failure which was then fixed is also counted.
+pub fn flaky_checks(
+    history: &[LandingAttempt],
+    window: Duration,
+    now: SystemTime,
+) -> Vec<FlakyCheck> {
+    let attempts: Vec<_> = history
+        .iter()
+        .filter(|attempt| {
+            now.duration_since(attempt.finished_at)
+                .map(|age| age <= window)
+                .unwrap_or(true)
+        })
+        .collect();
+
+    // PRs which landed after at least one failed attempt
+    let mut failed = BTreeMap::new();
+    let mut retried = BTreeMap::new();
+    for attempt in &attempts {
+        match attempt.result {
+            LandingResult::Landed if failed.contains_key(&attempt.number) => {
+                retried.insert(attempt.number, ());
+            }
+            LandingResult::Landed => {}
+            _ => {
+                failed.insert(attempt.number, ());
+            }
+        }
+    }
+
+    let mut checks = BTreeMap::<_, FlakyCheck>::new();
+    for attempt in attempts
+        .iter()
+        .filter(|attempt| retried.contains_key(&attempt.number))
+    {
+        for name in &attempt.passed_checks {
+            checks.entry(name.clone()).or_default().runs += 1;
+        }
+        for name in &attempt.failing_checks {
+            let check = checks.entry(name.clone()).or_default();
+            check.runs += 1;
+            check.failures += 1;
+            check.wasted_test_secs += attempt.duration().as_secs();
+        }
+    }
+
+    let mut checks: Vec<_> = checks
+        .into_iter()
+        .filter(|(_, check)| check.failures > 0)
+        .map(|(name, check)| FlakyCheck {
+            name,
+            failure_rate: check.failures as f64 / check.runs as f64,
+            ..check
+        })
+        .collect();
+    checks.sort_by(|a, b| {
+        b.failure_rate
+            .total_cmp(&a.failure_rate)
+            .then(b.failures.cmp(&a.failures))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    checks
+}
+
+fn median(mut durations: Vec<Duration>) -> Option<u64> {
+    durations.sort_unstable();
+    let middle = durations.len() / 2;
+    let median = match durations.len() {
+        0 => return None,
+        len if len % 2 == 0 => (durations[middle - 1] + durations[middle]) / 2,
+        _ => durations[middle],
+    };
+    Some(median.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use github::Oid;
+
+    fn attempt(
+        finished_ago: u64,
+        queued_for: Option<u64>,
+        result: LandingResult,
+        passed_checks: &[&str],
+        failing_checks: &[&str],
+    ) -> LandingAttempt {
+        let finished_at = SystemTime::UNIX_EPOCH + DAY * 30 - Duration::from_secs(finished_ago);
+        let started_at = finished_at - Duration::from_secs(600);
+        LandingAttempt {
+            number: 1,
+            merge_oid: Oid::from_str("deadbeef"),
+            queued_at: queued_for.map(|secs| started_at - Duration::from_secs(secs)),
+            started_at,
+            finished_at,
+            result,
+            passed_checks: passed_checks.iter().map(|c| c.to_string()).collect(),
+            failing_checks: failing_checks.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn aggregate() {
+        let now = SystemTime::UNIX_EPOCH + DAY * 30;
+        let history = vec![
+            // Outside of the window
+            attempt(DAY.as_secs() * 8, Some(10), LandingResult::Landed, &[], &[]),
+            attempt(300, Some(60), LandingResult::Landed, &["ci", "lint"], &[]),
+            attempt(200, Some(120), LandingResult::Failed, &["lint"], &["ci"]),
+            attempt(100, None, LandingResult::TimedOut, &["lint"], &[]),
+            attempt(0, Some(300), LandingResult::Landed, &["ci", "lint"], &[]),
+        ];
+
+        let stats = QueueStats::from_history(&history, DEFAULT_WINDOW, now);
+        assert_eq!(stats.attempts, 4);
+        assert_eq!(stats.landed, 2);
+        assert_eq!(stats.failed, 1);
+        assert_eq!(stats.timed_out, 1);
+        assert_eq!(stats.merge_failed, 0);
+        assert!((stats.landings_per_day - 2.0 / 7.0).abs() < 1e-9);
+        assert_eq!(stats.median_time_in_queue_secs, Some(120));
+        assert_eq!(stats.median_test_duration_secs, Some(600));
+        assert_eq!(
+            stats.checks["ci"],
+            CheckStats {
+                runs: 3,
+                passed: 2,
+                pass_rate: 2.0 / 3.0,
+            }
+        );
+        assert_eq!(stats.checks["lint"].pass_rate, 1.0);
+
+        let empty = QueueStats::from_history(&[], DEFAULT_WINDOW, now);
+        assert_eq!(empty.attempts, 0);
+        assert_eq!(empty.median_time_in_queue_secs, None);
+        assert!(empty.checks.is_empty());
+    }
+
+    #[test]
+    fn flaky() {
+        let now = SystemTime::UNIX_EPOCH + DAY * 30;
+        let numbered = |number, attempt| LandingAttempt { number, ..attempt };
+        let history = vec![
+            // Retried and landed, with lint failing once and ci failing on both failed attempts
+            numbered(
+                1,
+                attempt(500, None, LandingResult::Failed, &["ci"], &["lint"]),
+            ),
+            numbered(
+                1,
+                attempt(400, None, LandingResult::Failed, &["lint"], &["ci"]),
+            ),
+            numbered(
+                1,
+                attempt(300, None, LandingResult::Landed, &["ci", "lint"], &[]),
+            ),
+            numbered(
+                2,
+                attempt(250, None, LandingResult::Failed, &["lint"], &["ci"]),
+            ),
+            numbered(
+                2,
+                attempt(200, None, LandingResult::Landed, &["ci", "lint"], &[]),
+            ),
+            // Never landed, so its failure is counted against neither check
+            numbered(
+                3,
+                attempt(100, None, LandingResult::Failed, &["ci"], &["docs"]),
+            ),
+            numbered(
+                4,
+                attempt(0, None, LandingResult::Landed, &["ci", "docs"], &[]),
+            ),
+        ];
+
+        let checks = flaky_checks(&history, DEFAULT_WINDOW, now);
+        assert_eq!(
+            checks
+                .iter()
+                .map(|check| (check.name.as_str(), check.runs, check.failures))
+                .collect::<Vec<_>>(),
+            [("ci", 5, 2), ("lint", 5, 1)]
+        );
+        assert_eq!(checks[0].failure_rate, 2.0 / 5.0);
+        assert_eq!(checks[0].wasted_test_secs, 1200);
+
+        assert!(flaky_checks(&history, Duration::from_secs(150), now).is_empty());
+    }
+}
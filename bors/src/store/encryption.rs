@@ -0,0 +1,157 @@
+use crate::Result;
+#[cfg(feature = "encryption")]
+use aes_gcm::{
+    aead::{Aead, NewAead},
+    Aes256Gcm, Nonce,
+};
+use anyhow::anyhow;
+use std::convert::TryInto;
+
+/// Without the `encryption` feature a key can't be configured, so there's never a cipher
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone)]
+enum Aes256Gcm {}
+
+/// Marks a value as having been encrypted, allowing values which were persisted before encryption
+/// was enabled to continue to be read
+const PREFIX: &[u8] = b"enc:v1:";
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts values before they are persisted, and decrypts them when they're read back, if an
+/// encryption key has been configured
+#[derive(Clone, Default)]
+pub struct Encryption {
+    cipher: Option<Aes256Gcm>,
+}
+
+impl std::fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Encryption")
+            .field("enabled", &self.cipher.is_some())
+            .finish()
+    }
+}
+
+impl Encryption {
+    /// Create an `Encryption` from a base64 encoded 256-bit key, or one which leaves values
+    /// unencrypted if no key is provided
+    pub fn new(key: Option<&str>) -> Result<Self> {
+        let cipher = match key {
+            Some(key) => Some(new_cipher(&base64::decode(key.trim())?)?),
+            None => None,
+        };
+
+        Ok(Self { cipher })
+    }
+
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    pub fn seal(&self, plaintext: Vec<u8>) -> Result<Vec<u8>> {
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => return Ok(plaintext),
+        };
+
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let mut sealed = nonce.to_vec();
+        sealed.extend(encrypt(cipher, nonce, &plaintext)?);
+
+        let mut value = PREFIX.to_vec();
+        value.extend(base64::encode(&sealed).into_bytes());
+        Ok(value)
+    }
+
+    pub fn open(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if !value.starts_with(PREFIX) {
+            return Ok(value);
+        }
+
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or_else(|| anyhow!("value is encrypted but no encryption key is configured"))?;
+        let sealed = base64::decode(&value[PREFIX.len()..])?;
+        if sealed.len() < NONCE_LEN {
+            return Err(anyhow!("encrypted value is truncated"));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        decrypt(cipher, nonce.try_into()?, ciphertext)
+    }
+
+    /// Seal a string, which remains valid UTF-8 once sealed
+    pub fn seal_str(&self, plaintext: String) -> Result<String> {
+        Ok(String::from_utf8(self.seal(plaintext.into_bytes())?)?)
+    }
+
+    pub fn open_str(&self, value: String) -> Result<String> {
+        Ok(String::from_utf8(self.open(value.into_bytes())?)?)
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn new_cipher(key: &[u8]) -> Result<Aes256Gcm> {
+    Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("encryption key must be 32 bytes"))
+}
+
+#[cfg(feature = "encryption")]
+fn encrypt(cipher: &Aes256Gcm, nonce: [u8; NONCE_LEN], plaintext: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .encrypt(&Nonce::from(nonce), plaintext)
+        .map_err(|_| anyhow!("unable to encrypt value"))
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(cipher: &Aes256Gcm, nonce: [u8; NONCE_LEN], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    cipher
+        .decrypt(&Nonce::from(nonce), ciphertext)
+        .map_err(|_| anyhow!("unable to decrypt value, is the encryption key correct?"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn new_cipher(_key: &[u8]) -> Result<Aes256Gcm> {
+    Err(anyhow!("bors was built without the `encryption` feature"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encrypt(cipher: &Aes256Gcm, _nonce: [u8; NONCE_LEN], _plaintext: &[u8]) -> Result<Vec<u8>> {
+    match *cipher {}
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(cipher: &Aes256Gcm, _nonce: [u8; NONCE_LEN], _ciphertext: &[u8]) -> Result<Vec<u8>> {
+    match *cipher {}
+}
+
+#[cfg(test)]
+#[cfg(feature = "encryption")]
+mod test {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn round_trip() {
+        let encryption = Encryption::new(Some(KEY)).unwrap();
+        let sealed = encryption.seal_str("private title".to_owned()).unwrap();
+        assert!(!sealed.contains("private title"));
+        assert_eq!(
+            encryption.open_str(sealed.clone()).unwrap(),
+            "private title"
+        );
+
+        // Values can't be read without the key
+        assert!(Encryption::default().open_str(sealed.clone()).is_err());
+        let other = Encryption::new(Some(&base64::encode([1; 32]))).unwrap();
+        assert!(other.open_str(sealed).is_err());
+
+        // Values persisted before encryption was enabled can still be read
+        assert_eq!(encryption.open_str("{}".to_owned()).unwrap(), "{}");
+
+        assert!(Encryption::new(Some("c2hvcnQ=")).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Persistent storage of each repo's state so that bors can be restarted without losing track of
+//! which PRs are queued or being tested
+
+use crate::{
+    config::{StoreBackend, StoreConfig},
+    queue::MergeQueue,
+    state::{PullRequestState, Repo},
+    Result,
+};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use github::Oid;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+mod encryption;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "redis")]
+mod redis;
+mod schema;
+mod sqlite;
+
+pub use self::encryption::Encryption;
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresStore;
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+pub use sqlite::SqliteStore;
+
+/// How long a lease is held for without being renewed. A standby instance is able to take over a
+/// repo within this long of the instance holding its lease dying.
+pub const LEASE_TTL: Duration = Duration::from_secs(10);
+
+/// How often the holder of a lease renews it
+pub const LEASE_RENEWAL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The state of a single repo which is persisted across restarts. It is serialized along with the
+/// version of its schema, see `schema`, so that state persisted by older versions of bors is
+/// migrated when it is loaded.
+#[derive(Clone, Debug)]
+pub struct RepoState {
+    pub merge_queue: MergeQueue,
+    pub pulls: HashMap<u64, PullRequestState>,
+}
+
+/// The state of a set of repos, keyed by `<owner>/<name>`, as exported by `state export`
+pub type StateSnapshot = BTreeMap<String, RepoState>;
+
+/// A webhook delivery which is journaled before being processed so that it can be replayed
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    pub delivery_id: String,
+    pub event_type: String,
+    pub payload: Vec<u8>,
+    pub received_at: SystemTime,
+}
+
+/// The outcome of an attempt to land a PR
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LandingResult {
+    /// Tests passed and the PR was merged into its base branch
+    Landed,
+    /// Tests failed
+    Failed,
+    /// Tests didn't complete in time
+    TimedOut,
+    /// Tests passed but the base branch couldn't be updated
+    MergeFailed,
+}
+
+impl LandingResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LandingResult::Landed => "landed",
+            LandingResult::Failed => "failed",
+            LandingResult::TimedOut => "timed-out",
+            LandingResult::MergeFailed => "merge-failed",
+        }
+    }
+}
+
+impl FromStr for LandingResult {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "landed" => Ok(LandingResult::Landed),
+            "failed" => Ok(LandingResult::Failed),
+            "timed-out" => Ok(LandingResult::TimedOut),
+            "merge-failed" => Ok(LandingResult::MergeFailed),
+            _ => Err(anyhow!("unknown landing result '{}'", s)),
+        }
+    }
+}
+
+/// A record of an attempt to land a PR from the merge queue, kept as part of a repo's merge
+/// history
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LandingAttempt {
+    pub number: u64,
+    pub merge_oid: Oid,
+    /// When the PR was queued, which isn't known for attempts recorded by older versions of bors
+    #[serde(default)]
+    pub queued_at: Option<SystemTime>,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    pub result: LandingResult,
+    /// The checks which passed before the attempt finished
+    #[serde(default)]
+    pub passed_checks: Vec<String>,
+    /// The checks which caused the attempt to fail
+    pub failing_checks: Vec<String>,
+}
+
+impl LandingAttempt {
+    pub fn duration(&self) -> Duration {
+        self.finished_at
+            .duration_since(self.started_at)
+            .unwrap_or_default()
+    }
+
+    /// How long the PR waited in the queue before its tests were started
+    pub fn time_in_queue(&self) -> Option<Duration> {
+        self.queued_at.map(|queued_at| {
+            self.started_at
+                .duration_since(queued_at)
+                .unwrap_or_default()
+        })
+    }
+}
+
+/// How a command came to be run on a PR
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CommandSource {
+    /// A comment on the PR
+    Comment,
+    /// The admin API, e.g. from `bors pr`
+    Admin,
+}
+
+impl CommandSource {
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandSource::Comment => "comment",
+            CommandSource::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for CommandSource {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "comment" => Ok(CommandSource::Comment),
+            "admin" => Ok(CommandSource::Admin),
+            _ => Err(anyhow!("unknown command source '{}'", s)),
+        }
+    }
+}
+
+/// A record of a command run on a PR, kept as part of a repo's audit log. Commands are recorded
+/// whether or not the user running them was allowed to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    pub number: u64,
+    pub user: String,
+    /// The command as it was given, e.g. `/land priority=high`
+    pub command: String,
+    pub source: CommandSource,
+    pub authorized: bool,
+    pub at: SystemTime,
+}
+
+#[async_trait]
+pub trait Store: std::fmt::Debug + Send + Sync {
+    /// Load the last persisted state for `repo`, if any
+    async fn load(&self, repo: &Repo) -> Result<Option<RepoState>>;
+
+    /// Persist the state for `repo`, replacing any previously persisted state
+    async fn save(&self, repo: &Repo, state: &RepoState) -> Result<()>;
+
+    /// Record a webhook delivery in the journal, returning `false` if a delivery with the same id
+    /// had already been recorded, in which case the journal is left unchanged.
+    async fn record_delivery(&self, delivery: &Delivery) -> Result<bool>;
+
+    /// Mark a journaled delivery as having been successfully processed
+    async fn mark_delivery_processed(&self, delivery_id: &str) -> Result<()>;
+
+    /// Remove a delivery from the journal, e.g. because it couldn't be accepted and Github will
+    /// redeliver it
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<()>;
+
+    /// Lookup a delivery in the journal
+    async fn delivery(&self, delivery_id: &str) -> Result<Option<Delivery>>;
+
+    /// List the deliveries which have never been successfully processed, oldest first
+    async fn unprocessed_deliveries(&self) -> Result<Vec<Delivery>>;
+
+    /// Append an attempt to land a PR to `repo`'s merge history
+    async fn record_landing(&self, repo: &Repo, attempt: &LandingAttempt) -> Result<()>;
+
+    /// List the recorded attempts to land PRs in `repo`, oldest first
+    async fn landings(&self, repo: &Repo) -> Result<Vec<LandingAttempt>>;
+
+    /// Append a command run on a PR to `repo`'s audit log
+    async fn record_command(&self, repo: &Repo, entry: &AuditEntry) -> Result<()>;
+
+    /// List the commands recorded in `repo`'s audit log, oldest first
+    async fn audit_log(&self, repo: &Repo) -> Result<Vec<AuditEntry>>;
+
+    /// Acquire, or renew, the lease granting this instance exclusive ownership of `repo` for
+    /// `LEASE_TTL`, returning `false` if it is held by another instance. Stores which can't be
+    /// shared between instances always grant the lease.
+    async fn acquire_lease(&self, _repo: &Repo) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+pub async fn from_config(config: &StoreConfig) -> Result<Arc<dyn Store>> {
+    let encryption = Encryption::new(config.encryption_key.as_deref())?;
+    let store: Arc<dyn Store> = match &config.backend {
+        StoreBackend::Sqlite { path } => Arc::new(SqliteStore::open(path, encryption)?),
+        #[cfg(feature = "postgres")]
+        StoreBackend::Postgres { url } => Arc::new(PostgresStore::connect(url, encryption).await?),
+        #[cfg(not(feature = "postgres"))]
+        StoreBackend::Postgres { .. } => {
+            return Err(anyhow!("bors was built without the `postgres` feature"))
+        }
+        #[cfg(feature = "redis")]
+        StoreBackend::Redis { url } => Arc::new(RedisStore::connect(url, encryption).await?),
+        #[cfg(not(feature = "redis"))]
+        StoreBackend::Redis { .. } => {
+            return Err(anyhow!("bors was built without the `redis` feature"))
+        }
+    };
+
+    Ok(store)
+}
@@ -0,0 +1,428 @@
+use super::{
+    schema::STATE_VERSION, AuditEntry, Delivery, Encryption, LandingAttempt, RepoState, Store,
+};
+use crate::{state::Repo, Result};
+use async_trait::async_trait;
+use github::Oid;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, NoTls, Row};
+use tracing::{error, info};
+
+/// Migrations which are applied, in order, to bring the database's schema up to date. Each
+/// migration is only ever applied once so existing entries must never be modified, only appended
+/// to.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE merge_queues (
+        owner TEXT NOT NULL,
+        name TEXT NOT NULL,
+        merge_queue JSONB NOT NULL,
+        PRIMARY KEY (owner, name)
+    )",
+    "CREATE TABLE pull_requests (
+        owner TEXT NOT NULL,
+        name TEXT NOT NULL,
+        number BIGINT NOT NULL,
+        pull_request JSONB NOT NULL,
+        PRIMARY KEY (owner, name, number)
+    )",
+    "CREATE TABLE deliveries (
+        delivery_id TEXT NOT NULL PRIMARY KEY,
+        event_type TEXT NOT NULL,
+        payload BYTEA NOT NULL,
+        received_at TIMESTAMPTZ NOT NULL,
+        processed BOOLEAN NOT NULL DEFAULT FALSE
+    )",
+    "CREATE TABLE landings (
+        id BIGSERIAL PRIMARY KEY,
+        owner TEXT NOT NULL,
+        name TEXT NOT NULL,
+        number BIGINT NOT NULL,
+        merge_oid TEXT NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL,
+        finished_at TIMESTAMPTZ NOT NULL,
+        result TEXT NOT NULL,
+        failing_checks TEXT[] NOT NULL
+    )",
+    "ALTER TABLE merge_queues ADD COLUMN state_version BIGINT NOT NULL DEFAULT 0",
+    "ALTER TABLE landings
+        ADD COLUMN queued_at TIMESTAMPTZ,
+        ADD COLUMN passed_checks TEXT[] NOT NULL DEFAULT '{}'",
+    "CREATE TABLE audit_log (
+        id BIGSERIAL PRIMARY KEY,
+        owner TEXT NOT NULL,
+        name TEXT NOT NULL,
+        number BIGINT NOT NULL,
+        user_login TEXT NOT NULL,
+        command TEXT NOT NULL,
+        source TEXT NOT NULL,
+        authorized BOOLEAN NOT NULL,
+        at TIMESTAMPTZ NOT NULL
+    )",
+];
+
+/// A store backed by PostgreSQL.
+///
+/// Each PR is stored as its own row so that state can easily be inspected with SQL.
+#[derive(Debug)]
+pub struct PostgresStore {
+    client: Mutex<Client>,
+    encryption: Encryption,
+}
+
+impl PostgresStore {
+    pub async fn connect(url: &str, encryption: Encryption) -> Result<Self> {
+        let (mut client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("postgres connection error: {}", e);
+            }
+        });
+
+        Self::migrate(&mut client).await?;
+
+        Ok(Self {
+            client: Mutex::new(client),
+            encryption,
+        })
+    }
+
+    /// Encrypted values are stored as JSON strings so that they can still live in `JSONB` columns
+    fn encode_jsonb<T: Serialize>(&self, value: &T) -> Result<Value> {
+        if self.encryption.is_enabled() {
+            let sealed = self.encryption.seal_str(serde_json::to_string(value)?)?;
+            Ok(Value::String(sealed))
+        } else {
+            Ok(serde_json::to_value(value)?)
+        }
+    }
+
+    fn decode_jsonb<T: DeserializeOwned>(&self, value: Value) -> Result<T> {
+        match value {
+            Value::String(sealed) => Ok(serde_json::from_str(&self.encryption.open_str(sealed)?)?),
+            value => Ok(serde_json::from_value(value)?),
+        }
+    }
+
+    fn open_delivery(&self, mut delivery: Delivery) -> Result<Delivery> {
+        delivery.payload = self.encryption.open(delivery.payload)?;
+        Ok(delivery)
+    }
+
+    async fn migrate(client: &mut Client) -> Result<()> {
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER PRIMARY KEY)",
+            )
+            .await?;
+
+        let row = client
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM schema_migrations",
+                &[],
+            )
+            .await?;
+        let current: i32 = row.get(0);
+
+        for (version, migration) in MIGRATIONS.iter().enumerate() {
+            let version = version as i32 + 1;
+            if version <= current {
+                continue;
+            }
+
+            info!("applying postgres schema migration {}", version);
+            let transaction = client.transaction().await?;
+            transaction.batch_execute(migration).await?;
+            transaction
+                .execute(
+                    "INSERT INTO schema_migrations (version) VALUES ($1)",
+                    &[&version],
+                )
+                .await?;
+            transaction.commit().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for PostgresStore {
+    async fn load(&self, repo: &Repo) -> Result<Option<RepoState>> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt(
+                "SELECT merge_queue, state_version FROM merge_queues \
+                WHERE owner = $1 AND name = $2",
+                &[&repo.owner(), &repo.name()],
+            )
+            .await?;
+
+        let (merge_queue, version): (Value, i64) = match row {
+            Some(row) => (self.decode_jsonb(row.get(0))?, row.get(1)),
+            None => return Ok(None),
+        };
+
+        let mut pulls = Map::new();
+        for row in client
+            .query(
+                "SELECT number, pull_request FROM pull_requests WHERE owner = $1 AND name = $2",
+                &[&repo.owner(), &repo.name()],
+            )
+            .await?
+        {
+            let number: i64 = row.get(0);
+            pulls.insert(number.to_string(), self.decode_jsonb(row.get(1))?);
+        }
+
+        // Reassemble the state so that it's migrated in the same way as in the other stores
+        let state = json!({
+            "version": version,
+            "merge_queue": merge_queue,
+            "pulls": pulls,
+        });
+        Ok(Some(serde_json::from_value(state)?))
+    }
+
+    async fn save(&self, repo: &Repo, state: &RepoState) -> Result<()> {
+        let merge_queue = self.encode_jsonb(&state.merge_queue)?;
+        let pulls = state
+            .pulls
+            .iter()
+            .map(|(number, pull)| Ok((*number as i64, self.encode_jsonb(pull)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let numbers: Vec<i64> = pulls.iter().map(|(number, _)| *number).collect();
+
+        let mut client = self.client.lock().await;
+        let transaction = client.transaction().await?;
+
+        transaction
+            .execute(
+                "INSERT INTO merge_queues (owner, name, merge_queue, state_version) \
+                VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (owner, name) DO UPDATE \
+                SET merge_queue = EXCLUDED.merge_queue, state_version = EXCLUDED.state_version",
+                &[
+                    &repo.owner(),
+                    &repo.name(),
+                    &merge_queue,
+                    &(STATE_VERSION as i64),
+                ],
+            )
+            .await?;
+
+        transaction
+            .execute(
+                "DELETE FROM pull_requests \
+                WHERE owner = $1 AND name = $2 AND NOT (number = ANY($3))",
+                &[&repo.owner(), &repo.name(), &numbers],
+            )
+            .await?;
+
+        for (number, pull) in &pulls {
+            transaction
+                .execute(
+                    "INSERT INTO pull_requests (owner, name, number, pull_request) \
+                    VALUES ($1, $2, $3, $4) \
+                    ON CONFLICT (owner, name, number) \
+                    DO UPDATE SET pull_request = EXCLUDED.pull_request",
+                    &[&repo.owner(), &repo.name(), number, pull],
+                )
+                .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn record_delivery(&self, delivery: &Delivery) -> Result<bool> {
+        let payload = self.encryption.seal(delivery.payload.clone())?;
+        let inserted = self
+            .client
+            .lock()
+            .await
+            .execute(
+                "INSERT INTO deliveries (delivery_id, event_type, payload, received_at) \
+                VALUES ($1, $2, $3, $4) ON CONFLICT (delivery_id) DO NOTHING",
+                &[
+                    &delivery.delivery_id,
+                    &delivery.event_type,
+                    &payload,
+                    &delivery.received_at,
+                ],
+            )
+            .await?;
+        Ok(inserted == 1)
+    }
+
+    async fn mark_delivery_processed(&self, delivery_id: &str) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .execute(
+                "UPDATE deliveries SET processed = TRUE WHERE delivery_id = $1",
+                &[&delivery_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .execute(
+                "DELETE FROM deliveries WHERE delivery_id = $1",
+                &[&delivery_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delivery(&self, delivery_id: &str) -> Result<Option<Delivery>> {
+        let row = self
+            .client
+            .lock()
+            .await
+            .query_opt(
+                "SELECT delivery_id, event_type, payload, received_at FROM deliveries \
+                WHERE delivery_id = $1",
+                &[&delivery_id],
+            )
+            .await?;
+        row.as_ref()
+            .map(|row| self.open_delivery(delivery_from_row(row)))
+            .transpose()
+    }
+
+    async fn unprocessed_deliveries(&self) -> Result<Vec<Delivery>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "SELECT delivery_id, event_type, payload, received_at FROM deliveries \
+                WHERE NOT processed ORDER BY received_at",
+                &[],
+            )
+            .await?;
+        rows.iter()
+            .map(|row| self.open_delivery(delivery_from_row(row)))
+            .collect()
+    }
+
+    async fn record_landing(&self, repo: &Repo, attempt: &LandingAttempt) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .execute(
+                "INSERT INTO landings \
+                (owner, name, number, merge_oid, queued_at, started_at, finished_at, result, \
+                passed_checks, failing_checks) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+                &[
+                    &repo.owner(),
+                    &repo.name(),
+                    &(attempt.number as i64),
+                    &attempt.merge_oid.to_string(),
+                    &attempt.queued_at,
+                    &attempt.started_at,
+                    &attempt.finished_at,
+                    &attempt.result.as_str(),
+                    &attempt.passed_checks,
+                    &attempt.failing_checks,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn landings(&self, repo: &Repo) -> Result<Vec<LandingAttempt>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "SELECT number, merge_oid, queued_at, started_at, finished_at, result, \
+                passed_checks, failing_checks \
+                FROM landings WHERE owner = $1 AND name = $2 ORDER BY id",
+                &[&repo.owner(), &repo.name()],
+            )
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(LandingAttempt {
+                    number: row.get::<_, i64>(0) as u64,
+                    merge_oid: Oid::from_str(row.get::<_, String>(1)),
+                    queued_at: row.get(2),
+                    started_at: row.get(3),
+                    finished_at: row.get(4),
+                    result: row.get::<_, &str>(5).parse()?,
+                    passed_checks: row.get(6),
+                    failing_checks: row.get(7),
+                })
+            })
+            .collect()
+    }
+
+    async fn record_command(&self, repo: &Repo, entry: &AuditEntry) -> Result<()> {
+        self.client
+            .lock()
+            .await
+            .execute(
+                "INSERT INTO audit_log \
+                (owner, name, number, user_login, command, source, authorized, at) \
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                &[
+                    &repo.owner(),
+                    &repo.name(),
+                    &(entry.number as i64),
+                    &entry.user,
+                    &entry.command,
+                    &entry.source.as_str(),
+                    &entry.authorized,
+                    &entry.at,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn audit_log(&self, repo: &Repo) -> Result<Vec<AuditEntry>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "SELECT number, user_login, command, source, authorized, at \
+                FROM audit_log WHERE owner = $1 AND name = $2 ORDER BY id",
+                &[&repo.owner(), &repo.name()],
+            )
+            .await?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(AuditEntry {
+                    number: row.get::<_, i64>(0) as u64,
+                    user: row.get(1),
+                    command: row.get(2),
+                    source: row.get::<_, &str>(3).parse()?,
+                    authorized: row.get(4),
+                    at: row.get(5),
+                })
+            })
+            .collect()
+    }
+}
+
+fn delivery_from_row(row: &Row) -> Delivery {
+    Delivery {
+        delivery_id: row.get(0),
+        event_type: row.get(1),
+        payload: row.get(2),
+        received_at: row.get::<_, SystemTime>(3),
+    }
+}
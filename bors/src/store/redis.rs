@@ -0,0 +1,299 @@
+use super::{AuditEntry, Delivery, Encryption, LandingAttempt, RepoState, Store, LEASE_TTL};
+use crate::{state::Repo, Result};
+use anyhow::anyhow;
+use async_trait::async_trait;
+use redis::{aio::ConnectionManager, AsyncCommands, Client, Script};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+/// Replace a repo's state only if it hasn't been modified since it was last seen, returning the
+/// state's new version or -1 if it had been modified
+const SAVE_SCRIPT: &str = r#"
+local current = tonumber(redis.call('GET', KEYS[2]) or '0')
+if ARGV[2] ~= '' and current ~= tonumber(ARGV[2]) then
+    return -1
+end
+redis.call('SET', KEYS[1], ARGV[1])
+return redis.call('INCR', KEYS[2])
+"#;
+
+/// Acquire a lease, or extend it if it is already held by the same holder
+const LEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    return 1
+end
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+
+/// Journal a delivery unless it has already been journaled
+const RECORD_DELIVERY_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return 0
+end
+redis.call('HSET', KEYS[1], 'event_type', ARGV[1], 'payload', ARGV[2], 'received_at', ARGV[3])
+redis.call('ZADD', KEYS[2], ARGV[3], ARGV[4])
+return 1
+"#;
+
+const UNPROCESSED_DELIVERIES_KEY: &str = "bors:deliveries:unprocessed";
+
+/// A store backed by Redis.
+///
+/// Unlike the other stores, a Redis store can be shared by multiple bors instances so that a
+/// standby instance can take over as soon as the primary dies. Only the instance holding a repo's
+/// lease processes it, and state is only saved if it hasn't been modified by another instance
+/// since it was loaded, preventing two instances from landing PRs concurrently.
+pub struct RedisStore {
+    connection: ConnectionManager,
+
+    /// Identifies this instance as the holder of a lease
+    instance_id: String,
+
+    /// The version of each repo's state as of when it was last loaded or saved by this instance
+    versions: Mutex<HashMap<String, i64>>,
+
+    encryption: Encryption,
+}
+
+impl std::fmt::Debug for RedisStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisStore")
+            .field("instance_id", &self.instance_id)
+            .finish()
+    }
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str, encryption: Encryption) -> Result<Self> {
+        info!("using redis store");
+        let connection = ConnectionManager::new(Client::open(url)?).await?;
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let instance_id = format!("{}-{}", std::process::id(), nanos);
+
+        Ok(Self {
+            connection,
+            instance_id,
+            versions: Mutex::new(HashMap::new()),
+            encryption,
+        })
+    }
+
+    fn repo_key(repo: &Repo, suffix: &str) -> String {
+        format!("bors:{}/{}:{}", repo.owner(), repo.name(), suffix)
+    }
+
+    fn delivery_key(delivery_id: &str) -> String {
+        format!("bors:delivery:{}", delivery_id)
+    }
+}
+
+#[async_trait]
+impl Store for RedisStore {
+    async fn load(&self, repo: &Repo) -> Result<Option<RepoState>> {
+        let state_key = Self::repo_key(repo, "state");
+        let version_key = Self::repo_key(repo, "version");
+        let (state, version): (Option<String>, Option<i64>) = self
+            .connection
+            .clone()
+            .get(&[&state_key, &version_key][..])
+            .await?;
+
+        self.versions
+            .lock()
+            .unwrap()
+            .insert(state_key, version.unwrap_or(0));
+
+        match state {
+            Some(state) => Ok(Some(serde_json::from_str(
+                &self.encryption.open_str(state)?,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, repo: &Repo, state: &RepoState) -> Result<()> {
+        let state_key = Self::repo_key(repo, "state");
+        let expected = self.versions.lock().unwrap().get(&state_key).copied();
+
+        let version: i64 = Script::new(SAVE_SCRIPT)
+            .key(&state_key)
+            .key(Self::repo_key(repo, "version"))
+            .arg(self.encryption.seal_str(serde_json::to_string(state)?)?)
+            .arg(expected.map(|v| v.to_string()).unwrap_or_default())
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+
+        if version < 0 {
+            return Err(anyhow!(
+                "state of {}/{} was modified by another bors instance",
+                repo.owner(),
+                repo.name()
+            ));
+        }
+
+        self.versions.lock().unwrap().insert(state_key, version);
+        Ok(())
+    }
+
+    async fn record_delivery(&self, delivery: &Delivery) -> Result<bool> {
+        let received_at = delivery
+            .received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let inserted: i64 = Script::new(RECORD_DELIVERY_SCRIPT)
+            .key(Self::delivery_key(&delivery.delivery_id))
+            .key(UNPROCESSED_DELIVERIES_KEY)
+            .arg(&delivery.event_type)
+            .arg(self.encryption.seal(delivery.payload.clone())?)
+            .arg(received_at)
+            .arg(&delivery.delivery_id)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        Ok(inserted == 1)
+    }
+
+    async fn mark_delivery_processed(&self, delivery_id: &str) -> Result<()> {
+        self.connection
+            .clone()
+            .zrem::<_, _, ()>(UNPROCESSED_DELIVERIES_KEY, delivery_id)
+            .await?;
+        Ok(())
+    }
+
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<()> {
+        redis::pipe()
+            .atomic()
+            .del(Self::delivery_key(delivery_id))
+            .zrem(UNPROCESSED_DELIVERIES_KEY, delivery_id)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn delivery(&self, delivery_id: &str) -> Result<Option<Delivery>> {
+        let fields: HashMap<String, Vec<u8>> = self
+            .connection
+            .clone()
+            .hgetall(Self::delivery_key(delivery_id))
+            .await?;
+        match delivery_from_hash(delivery_id, fields)? {
+            Some(mut delivery) => {
+                delivery.payload = self.encryption.open(delivery.payload)?;
+                Ok(Some(delivery))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn unprocessed_deliveries(&self) -> Result<Vec<Delivery>> {
+        let delivery_ids: Vec<String> = self
+            .connection
+            .clone()
+            .zrange(UNPROCESSED_DELIVERIES_KEY, 0, -1)
+            .await?;
+
+        let mut deliveries = Vec::new();
+        for delivery_id in delivery_ids {
+            if let Some(delivery) = self.delivery(&delivery_id).await? {
+                deliveries.push(delivery);
+            }
+        }
+        Ok(deliveries)
+    }
+
+    async fn record_landing(&self, repo: &Repo, attempt: &LandingAttempt) -> Result<()> {
+        self.connection
+            .clone()
+            .rpush::<_, _, ()>(
+                Self::repo_key(repo, "landings"),
+                serde_json::to_string(attempt)?,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn landings(&self, repo: &Repo) -> Result<Vec<LandingAttempt>> {
+        let attempts: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(Self::repo_key(repo, "landings"), 0, -1)
+            .await?;
+
+        attempts
+            .iter()
+            .map(|attempt| Ok(serde_json::from_str(attempt)?))
+            .collect()
+    }
+
+    async fn record_command(&self, repo: &Repo, entry: &AuditEntry) -> Result<()> {
+        self.connection
+            .clone()
+            .rpush::<_, _, ()>(
+                Self::repo_key(repo, "audit-log"),
+                serde_json::to_string(entry)?,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn audit_log(&self, repo: &Repo) -> Result<Vec<AuditEntry>> {
+        let entries: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(Self::repo_key(repo, "audit-log"), 0, -1)
+            .await?;
+
+        entries
+            .iter()
+            .map(|entry| Ok(serde_json::from_str(entry)?))
+            .collect()
+    }
+
+    async fn acquire_lease(&self, repo: &Repo) -> Result<bool> {
+        let acquired: i64 = Script::new(LEASE_SCRIPT)
+            .key(Self::repo_key(repo, "lease"))
+            .arg(&self.instance_id)
+            .arg(LEASE_TTL.as_millis() as u64)
+            .invoke_async(&mut self.connection.clone())
+            .await?;
+        Ok(acquired == 1)
+    }
+}
+
+fn delivery_from_hash(
+    delivery_id: &str,
+    fields: HashMap<String, Vec<u8>>,
+) -> Result<Option<Delivery>> {
+    if fields.is_empty() {
+        return Ok(None);
+    }
+
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("delivery {} is missing '{}'", delivery_id, name))
+    };
+    let received_at: u64 = String::from_utf8(field("received_at")?)?.parse()?;
+
+    Ok(Some(Delivery {
+        delivery_id: delivery_id.to_owned(),
+        event_type: String::from_utf8(field("event_type")?)?,
+        payload: field("payload")?,
+        received_at: UNIX_EPOCH + Duration::from_secs(received_at),
+    }))
+}
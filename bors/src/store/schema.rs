@@ -0,0 +1,272 @@
+//! Versioning of persisted `RepoState`s, so that state persisted by an older version of bors can
+//! still be loaded after the types it is made of, e.g. `PullRequestState` or `Status`, change.
+//!
+//! State is serialized with a `version` field holding the version of the schema it was written
+//! with. When it is deserialized, the migrations needed to bring it up to date are applied to its
+//! JSON before it is converted to a `RepoState`.
+
+use super::RepoState;
+use crate::{queue::MergeQueue, state::PullRequestState, Result};
+use anyhow::anyhow;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::info;
+
+/// A migration rewrites the JSON of a repo's state from one version of the schema to the next
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Migrations which are applied, in order, to bring persisted state up to date. State persisted
+/// before it was versioned is at version 0, and state at version `n` has had the first `n`
+/// migrations applied to it. Existing entries must never be modified, only appended to.
+const MIGRATIONS: &[Migration] = &[
+    testing_queued_at,
+    canary_runs,
+    merge_queue_paused,
+    pause_reasons,
+];
+
+/// The version of the schema state is persisted with
+pub const STATE_VERSION: u64 = MIGRATIONS.len() as u64;
+
+#[derive(Serialize)]
+struct VersionedRepoState<'a> {
+    version: u64,
+    merge_queue: &'a MergeQueue,
+    pulls: &'a HashMap<u64, PullRequestState>,
+}
+
+#[derive(Deserialize)]
+struct CurrentRepoState {
+    merge_queue: MergeQueue,
+    pulls: HashMap<u64, PullRequestState>,
+}
+
+impl Serialize for RepoState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        VersionedRepoState {
+            version: STATE_VERSION,
+            merge_queue: &self.merge_queue,
+            pulls: &self.pulls,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RepoState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value =
+            migrate(Value::deserialize(deserializer)?, MIGRATIONS).map_err(de::Error::custom)?;
+        let state = CurrentRepoState::deserialize(value).map_err(de::Error::custom)?;
+
+        Ok(RepoState {
+            merge_queue: state.merge_queue,
+            pulls: state.pulls,
+        })
+    }
+}
+
+/// Version 1: PRs being tested record when they were queued. It is unknown for PRs which were
+/// already being tested, so when their tests started is used instead.
+fn testing_queued_at(state: &mut Value) -> Result<()> {
+    let pulls = state["pulls"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("pulls aren't an object"))?;
+
+    for pull in pulls.values_mut() {
+        if let Some(testing) = pull["status"]
+            .get_mut("Testing")
+            .and_then(Value::as_object_mut)
+        {
+            let tests_started_at = testing
+                .get("tests_started_at")
+                .cloned()
+                .ok_or_else(|| anyhow!("testing status is missing tests_started_at"))?;
+            testing.insert("queued_at".to_owned(), tests_started_at);
+        }
+    }
+
+    Ok(())
+}
+
+/// Version 2: PRs keep a history of their canary runs, which starts out empty
+fn canary_runs(state: &mut Value) -> Result<()> {
+    let pulls = state["pulls"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("pulls aren't an object"))?;
+
+    for pull in pulls.values_mut() {
+        pull["canary_runs"] = json!([]);
+    }
+
+    Ok(())
+}
+
+/// Version 3: The merge queue can be paused, which it never was before
+fn merge_queue_paused(state: &mut Value) -> Result<()> {
+    let merge_queue = state["merge_queue"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("merge queue isn't an object"))?;
+    merge_queue.insert("paused".to_owned(), json!(false));
+
+    Ok(())
+}
+
+/// Version 4: The merge queue tracks each reason it's paused for separately. A pause from before
+/// then is kept as an admin's, since a closed tree or red base branch is rechecked on startup but
+/// an admin's pause can't be.
+fn pause_reasons(state: &mut Value) -> Result<()> {
+    let merge_queue = state["merge_queue"]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("merge queue isn't an object"))?;
+    let paused = merge_queue
+        .get("paused")
+        .and_then(Value::as_bool)
+        .ok_or_else(|| anyhow!("merge queue has no paused flag"))?;
+    merge_queue.insert(
+        "paused".to_owned(),
+        json!({ "admin": paused, "tree_closed": false, "base_failures": null }),
+    );
+
+    Ok(())
+}
+
+/// Bring the JSON of a repo's state up to date by applying whichever of `migrations` haven't been
+/// applied to it yet. The returned JSON no longer has a `version` field.
+fn migrate(mut value: Value, migrations: &[Migration]) -> Result<Value> {
+    let version = match value
+        .as_object_mut()
+        .and_then(|state| state.remove("version"))
+    {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid state version {}", version))?,
+        None => 0,
+    };
+
+    if version > migrations.len() as u64 {
+        return Err(anyhow!(
+            "state has schema version {} but only versions up to {} are supported, \
+            it was probably persisted by a newer version of bors",
+            version,
+            migrations.len()
+        ));
+    }
+
+    for (applied, migration) in migrations.iter().enumerate().skip(version as usize) {
+        info!("migrating state to schema version {}", applied + 1);
+        migration(&mut value)?;
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rename_head(state: &mut Value) -> Result<()> {
+        let queue = state["merge_queue"]
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("merge queue isn't an object"))?;
+        let head = queue.remove("current").unwrap_or(Value::Null);
+        queue.insert("head".to_owned(), head);
+        Ok(())
+    }
+
+    fn add_flag(state: &mut Value) -> Result<()> {
+        state["flag"] = json!(true);
+        Ok(())
+    }
+
+    #[test]
+    fn versioned() {
+        let state = RepoState {
+            merge_queue: MergeQueue::new(),
+            pulls: HashMap::new(),
+        };
+
+        let value = serde_json::to_value(&state).unwrap();
+        assert_eq!(value["version"], json!(STATE_VERSION));
+        serde_json::from_value::<RepoState>(value).unwrap();
+
+        // State persisted before it was versioned
+        serde_json::from_value::<RepoState>(json!({ "merge_queue": {}, "pulls": {} })).unwrap();
+
+        let error = serde_json::from_value::<RepoState>(json!({
+            "version": STATE_VERSION + 1,
+            "merge_queue": {},
+            "pulls": {},
+        }))
+        .unwrap_err();
+        assert!(error.to_string().contains("newer version of bors"));
+    }
+
+    #[test]
+    fn migrate_unversioned() {
+        let started_at = json!({ "secs_since_epoch": 1600000000, "nanos_since_epoch": 0 });
+        let unversioned = json!({
+            "merge_queue": { "head": 7 },
+            "pulls": {
+                "6": { "status": "InReview" },
+                "7": {
+                    "status": {
+                        "Testing": {
+                            "merge_oid": "deadbeef",
+                            "tests_started_at": started_at,
+                            "test_results": {},
+                        }
+                    }
+                },
+            },
+        });
+
+        let migrated = migrate(unversioned, MIGRATIONS).unwrap();
+        assert_eq!(migrated["pulls"]["6"]["status"], json!("InReview"));
+        assert_eq!(
+            migrated["pulls"]["7"]["status"]["Testing"]["queued_at"],
+            started_at
+        );
+        assert_eq!(migrated["pulls"]["6"]["canary_runs"], json!([]));
+        assert_eq!(migrated["pulls"]["7"]["canary_runs"], json!([]));
+        assert_eq!(migrated["merge_queue"]["paused"]["admin"], json!(false));
+    }
+
+    #[test]
+    fn migrate_paused() {
+        let paused = json!({
+            "version": 3,
+            "merge_queue": { "head": null, "paused": true },
+            "pulls": {},
+        });
+
+        let migrated = migrate(paused, MIGRATIONS).unwrap();
+        assert_eq!(
+            migrated["merge_queue"]["paused"],
+            json!({ "admin": true, "tree_closed": false, "base_failures": null })
+        );
+    }
+
+    #[test]
+    fn pending_migrations() {
+        let migrations: &[Migration] = &[rename_head, add_flag];
+
+        let unversioned = json!({ "merge_queue": { "current": 7 }, "pulls": {} });
+        assert_eq!(
+            migrate(unversioned, migrations).unwrap(),
+            json!({ "merge_queue": { "head": 7 }, "pulls": {}, "flag": true })
+        );
+
+        let partially_migrated = json!({ "version": 1, "merge_queue": { "head": 7 }, "pulls": {} });
+        assert_eq!(
+            migrate(partially_migrated, migrations).unwrap(),
+            json!({ "merge_queue": { "head": 7 }, "pulls": {}, "flag": true })
+        );
+
+        let current = json!({ "version": 2, "merge_queue": { "head": 7 }, "pulls": {} });
+        assert_eq!(
+            migrate(current, migrations).unwrap(),
+            json!({ "merge_queue": { "head": 7 }, "pulls": {} })
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use super::{AuditEntry, Delivery, Encryption, LandingAttempt, RepoState, Store};
+use crate::{state::Repo, Result};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use tracing::info;
+
+#[derive(Debug)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    encryption: Encryption,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P, encryption: Encryption) -> Result<Self> {
+        info!("using sqlite store at {}", path.as_ref().display());
+        Self::with_connection(Connection::open(path)?, encryption)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?, Encryption::default())
+    }
+
+    fn with_connection(conn: Connection, encryption: Encryption) -> Result<Self> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS repo_state (
+                owner TEXT NOT NULL,
+                name TEXT NOT NULL,
+                state TEXT NOT NULL,
+                PRIMARY KEY (owner, name)
+            )",
+            params![],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS deliveries (
+                delivery_id TEXT NOT NULL PRIMARY KEY,
+                event_type TEXT NOT NULL,
+                payload BLOB NOT NULL,
+                received_at INTEGER NOT NULL,
+                processed INTEGER NOT NULL DEFAULT 0
+            )",
+            params![],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS landings (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                owner TEXT NOT NULL,
+                name TEXT NOT NULL,
+                attempt TEXT NOT NULL
+            )",
+            params![],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                owner TEXT NOT NULL,
+                name TEXT NOT NULL,
+                entry TEXT NOT NULL
+            )",
+            params![],
+        )?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            encryption,
+        })
+    }
+
+    /// Run `f` against the connection on tokio's blocking pool, since rusqlite's calls block on
+    /// disk IO and would otherwise stall every other task scheduled on the same worker
+    async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&conn.lock().unwrap())).await?
+    }
+
+    fn open_delivery(&self, mut delivery: Delivery) -> Result<Delivery> {
+        delivery.payload = self.encryption.open(delivery.payload)?;
+        Ok(delivery)
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn load(&self, repo: &Repo) -> Result<Option<RepoState>> {
+        let repo = repo.clone();
+        let state: Option<String> = self
+            .blocking(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT state FROM repo_state WHERE owner = ?1 AND name = ?2",
+                        params![repo.owner(), repo.name()],
+                        |row| row.get(0),
+                    )
+                    .optional()?)
+            })
+            .await?;
+
+        match state {
+            Some(state) => Ok(Some(serde_json::from_str(
+                &self.encryption.open_str(state)?,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, repo: &Repo, state: &RepoState) -> Result<()> {
+        let state = self.encryption.seal_str(serde_json::to_string(state)?)?;
+        let repo = repo.clone();
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO repo_state (owner, name, state) VALUES (?1, ?2, ?3)",
+                params![repo.owner(), repo.name(), state],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_delivery(&self, delivery: &Delivery) -> Result<bool> {
+        let received_at = delivery
+            .received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let payload = self.encryption.seal(delivery.payload.clone())?;
+        let (delivery_id, event_type) = (delivery.delivery_id.clone(), delivery.event_type.clone());
+        self.blocking(move |conn| {
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO deliveries (delivery_id, event_type, payload, received_at) \
+                VALUES (?1, ?2, ?3, ?4)",
+                params![delivery_id, event_type, payload, received_at],
+            )?;
+            Ok(inserted == 1)
+        })
+        .await
+    }
+
+    async fn mark_delivery_processed(&self, delivery_id: &str) -> Result<()> {
+        let delivery_id = delivery_id.to_owned();
+        self.blocking(move |conn| {
+            conn.execute(
+                "UPDATE deliveries SET processed = 1 WHERE delivery_id = ?1",
+                params![delivery_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<()> {
+        let delivery_id = delivery_id.to_owned();
+        self.blocking(move |conn| {
+            conn.execute(
+                "DELETE FROM deliveries WHERE delivery_id = ?1",
+                params![delivery_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delivery(&self, delivery_id: &str) -> Result<Option<Delivery>> {
+        let delivery_id = delivery_id.to_owned();
+        let delivery = self
+            .blocking(move |conn| {
+                Ok(conn
+                    .query_row(
+                        "SELECT delivery_id, event_type, payload, received_at FROM deliveries \
+                        WHERE delivery_id = ?1",
+                        params![delivery_id],
+                        delivery_from_row,
+                    )
+                    .optional()?)
+            })
+            .await?;
+        delivery.map(|d| self.open_delivery(d)).transpose()
+    }
+
+    async fn unprocessed_deliveries(&self) -> Result<Vec<Delivery>> {
+        let deliveries = self
+            .blocking(|conn| {
+                let mut statement = conn.prepare(
+                    "SELECT delivery_id, event_type, payload, received_at FROM deliveries \
+                    WHERE processed = 0 ORDER BY received_at",
+                )?;
+                let deliveries = statement
+                    .query_map(params![], delivery_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(deliveries)
+            })
+            .await?;
+        deliveries
+            .into_iter()
+            .map(|d| self.open_delivery(d))
+            .collect()
+    }
+
+    async fn record_landing(&self, repo: &Repo, attempt: &LandingAttempt) -> Result<()> {
+        let attempt = serde_json::to_string(attempt)?;
+        let repo = repo.clone();
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT INTO landings (owner, name, attempt) VALUES (?1, ?2, ?3)",
+                params![repo.owner(), repo.name(), attempt],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn landings(&self, repo: &Repo) -> Result<Vec<LandingAttempt>> {
+        let repo = repo.clone();
+        let attempts = self
+            .blocking(move |conn| {
+                let mut statement = conn.prepare(
+                    "SELECT attempt FROM landings WHERE owner = ?1 AND name = ?2 ORDER BY id",
+                )?;
+                let attempts = statement
+                    .query_map(params![repo.owner(), repo.name()], |row| {
+                        row.get::<_, String>(0)
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(attempts)
+            })
+            .await?;
+
+        attempts
+            .iter()
+            .map(|attempt| Ok(serde_json::from_str(attempt)?))
+            .collect()
+    }
+
+    async fn record_command(&self, repo: &Repo, entry: &AuditEntry) -> Result<()> {
+        let entry = serde_json::to_string(entry)?;
+        let repo = repo.clone();
+        self.blocking(move |conn| {
+            conn.execute(
+                "INSERT INTO audit_log (owner, name, entry) VALUES (?1, ?2, ?3)",
+                params![repo.owner(), repo.name(), entry],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn audit_log(&self, repo: &Repo) -> Result<Vec<AuditEntry>> {
+        let repo = repo.clone();
+        let entries = self
+            .blocking(move |conn| {
+                let mut statement = conn.prepare(
+                    "SELECT entry FROM audit_log WHERE owner = ?1 AND name = ?2 ORDER BY id",
+                )?;
+                let entries = statement
+                    .query_map(params![repo.owner(), repo.name()], |row| {
+                        row.get::<_, String>(0)
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(entries)
+            })
+            .await?;
+
+        entries
+            .iter()
+            .map(|entry| Ok(serde_json::from_str(entry)?))
+            .collect()
+    }
+}
+
+fn delivery_from_row(row: &Row) -> rusqlite::Result<Delivery> {
+    let received_at: i64 = row.get(3)?;
+    Ok(Delivery {
+        delivery_id: row.get(0)?,
+        event_type: row.get(1)?,
+        payload: row.get(2)?,
+        received_at: UNIX_EPOCH + Duration::from_secs(received_at as u64),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        queue::MergeQueue,
+        store::{CommandSource, LandingResult},
+    };
+    use github::Oid;
+    use std::{collections::HashMap, time::SystemTime};
+
+    #[tokio::test]
+    async fn delivery_journal() {
+        let store = SqliteStore::in_memory().unwrap();
+        let delivery = Delivery {
+            delivery_id: "1".to_owned(),
+            event_type: "pull_request".to_owned(),
+            payload: b"{}".to_vec(),
+            received_at: SystemTime::now(),
+        };
+
+        assert!(store.record_delivery(&delivery).await.unwrap());
+        assert!(!store.record_delivery(&delivery).await.unwrap());
+        assert_eq!(store.unprocessed_deliveries().await.unwrap().len(), 1);
+        assert!(store.delivery("1").await.unwrap().is_some());
+
+        store.mark_delivery_processed("1").await.unwrap();
+        assert!(store.unprocessed_deliveries().await.unwrap().is_empty());
+        assert!(store.delivery("1").await.unwrap().is_some());
+
+        // A forgotten delivery can be recorded again when it is redelivered
+        store.forget_delivery("1").await.unwrap();
+        assert!(store.delivery("1").await.unwrap().is_none());
+        assert!(store.record_delivery(&delivery).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "encryption")]
+    async fn encrypted() {
+        let key = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+        let store = SqliteStore::with_connection(
+            Connection::open_in_memory().unwrap(),
+            Encryption::new(Some(key)).unwrap(),
+        )
+        .unwrap();
+        let repo = Repo::new("bors-rs", "bors");
+
+        let state = RepoState {
+            merge_queue: MergeQueue::new(),
+            pulls: HashMap::new(),
+        };
+        store.save(&repo, &state).await.unwrap();
+        assert!(store.load(&repo).await.unwrap().is_some());
+
+        let delivery = Delivery {
+            delivery_id: "1".to_owned(),
+            event_type: "pull_request".to_owned(),
+            payload: b"{\"title\":\"private\"}".to_vec(),
+            received_at: SystemTime::now(),
+        };
+        store.record_delivery(&delivery).await.unwrap();
+        assert_eq!(
+            store.delivery("1").await.unwrap().unwrap().payload,
+            delivery.payload
+        );
+
+        // Nothing is persisted in the clear
+        let conn = store.conn.lock().unwrap();
+        let (state, payload): (String, Vec<u8>) = conn
+            .query_row(
+                "SELECT state, payload FROM repo_state, deliveries",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!(!state.contains("merge_queue"));
+        assert!(!String::from_utf8(payload).unwrap().contains("private"));
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let store = SqliteStore::in_memory().unwrap();
+        let repo = Repo::new("bors-rs", "bors");
+
+        assert!(store.load(&repo).await.unwrap().is_none());
+
+        let state = RepoState {
+            merge_queue: MergeQueue::new(),
+            pulls: HashMap::new(),
+        };
+        store.save(&repo, &state).await.unwrap();
+        store.save(&repo, &state).await.unwrap();
+
+        assert!(store.load(&repo).await.unwrap().is_some());
+        assert!(store
+            .load(&Repo::new("bors-rs", "other"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn landing_history() {
+        let store = SqliteStore::in_memory().unwrap();
+        let repo = Repo::new("bors-rs", "bors");
+        let started_at = SystemTime::now();
+
+        for (number, result) in [(1, LandingResult::Failed), (2, LandingResult::Landed)].iter() {
+            let attempt = LandingAttempt {
+                number: *number,
+                merge_oid: Oid::from_str("deadbeef"),
+                queued_at: None,
+                started_at,
+                finished_at: started_at + Duration::from_secs(60),
+                result: *result,
+                passed_checks: Vec::new(),
+                failing_checks: Vec::new(),
+            };
+            store.record_landing(&repo, &attempt).await.unwrap();
+        }
+
+        let landings = store.landings(&repo).await.unwrap();
+        assert_eq!(landings.len(), 2);
+        assert_eq!(landings[0].number, 1);
+        assert_eq!(landings[0].result, LandingResult::Failed);
+        assert_eq!(landings[1].result, LandingResult::Landed);
+        assert_eq!(landings[1].duration(), Duration::from_secs(60));
+        assert!(store
+            .landings(&Repo::new("bors-rs", "other"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn audit_log() {
+        let store = SqliteStore::in_memory().unwrap();
+        let repo = Repo::new("bors-rs", "bors");
+
+        for (user, authorized) in [("mallory", false), ("alice", true)].iter() {
+            let entry = AuditEntry {
+                number: 1,
+                user: (*user).to_owned(),
+                command: "/land".to_owned(),
+                source: CommandSource::Comment,
+                authorized: *authorized,
+                at: SystemTime::now(),
+            };
+            store.record_command(&repo, &entry).await.unwrap();
+        }
+
+        let entries = store.audit_log(&repo).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].user, "mallory");
+        assert!(!entries[0].authorized);
+        assert_eq!(entries[1].user, "alice");
+        assert_eq!(entries[1].source, CommandSource::Comment);
+        assert!(store
+            .audit_log(&Repo::new("bors-rs", "other"))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
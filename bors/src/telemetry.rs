@@ -0,0 +1,151 @@
+//! Logging, and distributed tracing of how webhooks are handled from receiving them through to the
+//! queue processing, git operations and Github API calls they lead to. Log lines carry the fields
+//! of the spans they're logged in, e.g. the repo, PR and delivery being handled, and spans can be
+//! exported over OTLP so that slow landings can be traced end to end.
+
+use crate::{config::TracingConfig, Result};
+use anyhow::anyhow;
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    Context, KeyValue,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::WithExportConfig;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{trace, Resource};
+use std::str::FromStr;
+use tracing::Span;
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// How log lines are written to stderr
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// A JSON object per line, for ingestion by log aggregators
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow!("unknown log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+/// Log at the level set by `RUST_LOG`, info by default, and export spans to an OTLP collector if
+/// tracing is configured. Logs from dependencies which use `log` are included.
+pub fn init_logging(format: LogFormat, tracing: Option<&TracingConfig>) -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let text = (format == LogFormat::Text).then(tracing_subscriber::fmt::layer);
+    let json = (format == LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json());
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json);
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(match tracing {
+        Some(config) => Some(tracing_opentelemetry::layer().with_tracer(otlp_tracer(config)?)),
+        None => None,
+    });
+    #[cfg(not(feature = "otel"))]
+    if tracing.is_some() {
+        return Err(anyhow!("bors was built without the `otel` feature"));
+    }
+
+    registry.try_init()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "otel")]
+fn otlp_tracer(config: &TracingConfig) -> Result<trace::Tracer> {
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(config.otlp_endpoint()),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                config.service_name().to_owned(),
+            )])),
+        )
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+
+    Ok(tracer)
+}
+
+/// Export any spans which are still buffered
+#[cfg(feature = "otel")]
+pub async fn shutdown() {
+    // Shutting down blocks until the batch exporter, which runs on the runtime, has flushed
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+#[cfg(not(feature = "otel"))]
+pub async fn shutdown() {}
+
+/// Make `span` part of the trace of a Github delivery, so that the spans handling a delivery, from
+/// receiving it to processing it after however long it was queued, are traced together. The trace
+/// ID is the delivery's GUID, so a delivery's trace can be looked up from Github's webhook log.
+#[cfg(feature = "otel")]
+pub fn link_to_delivery(span: &Span, delivery_id: &str) {
+    if let Some(parent) = delivery_span_context(delivery_id) {
+        span.set_parent(Context::new().with_remote_span_context(parent));
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn link_to_delivery(_span: &Span, _delivery_id: &str) {}
+
+/// The span context standing in for Github's side of a delivery, if its ID is a GUID
+#[cfg(feature = "otel")]
+fn delivery_span_context(delivery_id: &str) -> Option<SpanContext> {
+    let hex: String = delivery_id.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let trace_id = TraceId::from_hex(&hex).ok()?;
+    let span_id = SpanId::from_hex(&hex[16..]).ok()?;
+
+    Some(SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    ))
+}
+
+#[cfg(test)]
+#[cfg(feature = "otel")]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delivery_trace() {
+        let parent = delivery_span_context("72d3162e-cc78-11e3-81ab-4c9367dc0958").unwrap();
+        assert_eq!(
+            parent.trace_id(),
+            TraceId::from_hex("72d3162ecc7811e381ab4c9367dc0958").unwrap()
+        );
+        assert!(parent.is_valid() && parent.is_remote());
+
+        assert_eq!(delivery_span_context("1"), None);
+        assert_eq!(
+            delivery_span_context("not-a-guid-but-thirty-two-chars!"),
+            None
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Validation of PR titles, which become the subject of the commit a PR is squashed into, so that
+//! history can be parsed by tooling such as release note generators
+
+use crate::config::TitleCheckConfig;
+
+/// Why a PR's title doesn't follow the configured format, explaining the format expected
+pub fn problem(config: &TitleCheckConfig, title: &str) -> Option<String> {
+    if config.conventional() && !is_conventional(title, &config.types()) {
+        return Some(format!(
+            "doesn't follow [Conventional Commits](https://www.conventionalcommits.org): expected \
+             `<type>(<scope>): <description>` e.g. `fix(parser): handle empty input`, where the \
+             type is one of {}, the scope is optional and a `!` before the colon marks a breaking \
+             change",
+            config
+                .types()
+                .iter()
+                .map(|t| format!("`{}`", t))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    match config.pattern() {
+        Some(pattern) if !pattern.is_match(title) => Some(match config.help() {
+            Some(help) => format!("doesn't match the expected format: {}", help),
+            None => format!("doesn't match the expected format `{}`", pattern),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether a title is a conventional commit subject, `<type>[(<scope>)][!]: <description>`
+fn is_conventional(title: &str, types: &[&str]) -> bool {
+    let (prefix, description) = match title.split_once(": ") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let kind = match prefix.split_once('(') {
+        Some((kind, scope)) => match scope.strip_suffix(')') {
+            Some(scope) if !scope.is_empty() && !scope.contains(['(', ')']) => kind,
+            _ => return false,
+        },
+        None => prefix,
+    };
+
+    types.contains(&kind) && !description.trim().is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn titles() {
+        let config: TitleCheckConfig = toml::from_str("conventional = true").unwrap();
+        for title in &[
+            "fix: handle empty input",
+            "feat(parser): support comments",
+            "refactor(api)!: rename endpoints",
+            "chore!: drop support for 1.0",
+        ] {
+            assert_eq!(problem(&config, title), None, "{}", title);
+        }
+        for title in &[
+            "Fix a bug",
+            "fix handle empty input",
+            "fixed: handle empty input",
+            "feat(): support comments",
+            "feat(parser: support comments",
+            "fix: ",
+        ] {
+            assert!(problem(&config, title).is_some(), "{}", title);
+        }
+
+        let config: TitleCheckConfig = toml::from_str(
+            r#"
+            pattern = "^\\[[a-z]+\\] "
+            help = "prefix the title with the component, e.g. `[consensus] Fix a bug`"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(problem(&config, "[consensus] Fix a bug"), None);
+        assert_eq!(
+            problem(&config, "Fix a bug").as_deref(),
+            Some(
+                "doesn't match the expected format: prefix the title with the component, e.g. \
+                 `[consensus] Fix a bug`"
+            )
+        );
+
+        assert!(toml::from_str::<TitleCheckConfig>(r#"pattern = "(""#).is_err());
+    }
+}
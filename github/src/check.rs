@@ -56,26 +56,26 @@ pub struct CheckOutput {
 // Maybe rename these?
 #[derive(Clone, Debug, Deserialize)]
 pub struct CheckPullRequest {
-    url: String,
-    id: u64,
-    number: u64,
-    head: CheckBranch,
-    base: CheckBranch,
+    pub url: String,
+    pub id: u64,
+    pub number: u64,
+    pub head: CheckBranch,
+    pub base: CheckBranch,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CheckBranch {
     #[serde(rename = "ref")]
-    git_ref: String,
-    sha: Oid,
-    repo: CheckRepo,
+    pub git_ref: String,
+    pub sha: Oid,
+    pub repo: CheckRepo,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CheckRepo {
-    id: u64,
-    url: String,
-    name: String,
+    pub id: u64,
+    pub url: String,
+    pub name: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -86,6 +86,6 @@ mod test {
             "message": "Validation Failed"
         }"#;
 
-        let _e: GithubClientError = serde_json::from_str(&json).unwrap();
+        let _e: GithubClientError = serde_json::from_str(json).unwrap();
     }
 }
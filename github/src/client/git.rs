@@ -43,4 +43,45 @@ impl<'a> GitClient<'a> {
         //TODO actually return the ref here
         self.inner.empty(response).await
     }
+
+    /// Create a Ref, e.g. `refs/tags/v1.0.0`
+    ///
+    /// https://developer.github.com/v3/git/refs/#create-a-reference
+    pub async fn create_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        ref_name: &str,
+        sha: &Oid,
+    ) -> Result<Response<()>> {
+        #[derive(Debug, Serialize)]
+        struct CreateRefRequest {
+            #[serde(rename = "ref")]
+            ref_name: String,
+            sha: String,
+        }
+
+        let request = CreateRefRequest {
+            ref_name: ref_name.to_owned(),
+            sha: sha.to_string(),
+        };
+
+        let url = format!("repos/{}/{}/git/refs", owner, repo);
+        let response = self.inner.post(&url).json(&request).send().await?;
+        self.inner.empty(response).await
+    }
+
+    /// Delete a Ref, e.g. `heads/feature-a`
+    ///
+    /// https://developer.github.com/v3/git/refs/#delete-a-reference
+    pub async fn delete_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        ref_name: &str,
+    ) -> Result<Response<()>> {
+        let url = format!("repos/{}/{}/git/refs/{}", owner, repo, ref_name);
+        let response = self.inner.delete(&url).send().await?;
+        self.inner.empty(response).await
+    }
 }
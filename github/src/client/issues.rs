@@ -16,6 +16,7 @@ pub struct ListIssuesOptions {
     /// * mentioned: Issues mentioning you
     /// * subscribed: Issues you're subscribed to updates for
     /// * all: All issues the authenticated user can see, regardless of participation or creation
+    ///
     /// Default: assigned
     pub filter: ListIssuesFilter,
 
@@ -38,9 +39,10 @@ pub struct ListIssuesOptions {
     pub pagination_options: PaginationOptions,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ListIssuesFilter {
+    #[default]
     Assigned,
     Created,
     Mentioned,
@@ -48,12 +50,6 @@ pub enum ListIssuesFilter {
     All,
 }
 
-impl Default for ListIssuesFilter {
-    fn default() -> Self {
-        ListIssuesFilter::Assigned
-    }
-}
-
 #[derive(Debug, Default, Serialize)]
 pub struct ListIssuesForRepoOptions {
     // If an integer is passed, it should refer to a milestone by its number field. If the string *
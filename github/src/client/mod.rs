@@ -2,6 +2,7 @@
 
 use log::{debug, error};
 use reqwest::{header, Client as ReqwestClient, Method, RequestBuilder};
+use std::time::Duration;
 
 mod error;
 mod git;
@@ -16,6 +17,8 @@ mod pulls;
 mod rate_limit;
 mod reactions;
 mod repos;
+mod teams;
+mod users;
 
 pub use error::{Error, Result};
 pub use git::GitClient;
@@ -33,11 +36,13 @@ pub use project::{
 };
 pub use pulls::{
     ListPullsOptions, MergeMethod, MergePullRequest, MergePullRequestResponse, NewPullRequest,
-    PullsClient,
+    PullsClient, UpdatePullRequest,
 };
 pub use rate_limit::{Rate, RateLimitClient, RateLimits};
 pub use reactions::ReactionsClient;
 pub use repos::{CombinedStatus, CreateStatusRequest, RepoStatus, RepositoryClient};
+pub use teams::{TeamMembership, TeamsClient};
+pub use users::UsersClient;
 
 // Constants
 const DEFAULT_BASE_URL: &str = "https://api.github.com/";
@@ -210,6 +215,7 @@ pub struct ClientBuilder {
     base_url: Option<String>,
     user_agent: Option<String>,
     github_api_token: Option<String>,
+    timeout: Option<Duration>,
 }
 
 impl ClientBuilder {
@@ -218,6 +224,7 @@ impl ClientBuilder {
             base_url: None,
             user_agent: None,
             github_api_token: None,
+            timeout: None,
         }
     }
 
@@ -236,6 +243,12 @@ impl ClientBuilder {
         self
     }
 
+    /// Abort any request which hasn't completed within `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn build(self) -> Result<Client> {
         let base_url = self.base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_owned());
         let user_agent = self.user_agent.unwrap_or_else(|| USER_AGENT.to_owned());
@@ -252,6 +265,10 @@ impl ClientBuilder {
             client_builder = client_builder.default_headers(headers);
         }
 
+        if let Some(timeout) = self.timeout {
+            client_builder = client_builder.timeout(timeout);
+        }
+
         let client = client_builder.build()?;
 
         Ok(Client {
@@ -407,8 +424,8 @@ impl Client {
     }
 
     #[cfg(feature = "graphql")]
-    pub fn graphql(&self) -> GraphqlClient {
-        GraphqlClient::new(&self)
+    pub fn graphql(&self) -> GraphqlClient<'_> {
+        GraphqlClient::new(self)
     }
 
     // TODO: actions endpoint
@@ -434,8 +451,8 @@ impl Client {
 
     // git endpoint
     // https://developer.github.com/v3/git/
-    pub fn git(&self) -> GitClient {
-        GitClient::new(&self)
+    pub fn git(&self) -> GitClient<'_> {
+        GitClient::new(self)
     }
 
     // TODO gitignore endpoint
@@ -444,16 +461,16 @@ impl Client {
     // TODO interactions endpoint
     // https://developer.github.com/v3/interactions/
 
-    pub fn issues(&self) -> IssuesClient {
-        IssuesClient::new(&self)
+    pub fn issues(&self) -> IssuesClient<'_> {
+        IssuesClient::new(self)
     }
 
-    pub fn licenses(&self) -> LicenseClient {
-        LicenseClient::new(&self)
+    pub fn licenses(&self) -> LicenseClient<'_> {
+        LicenseClient::new(self)
     }
 
-    pub fn markdown(&self) -> MarkdownClient {
-        MarkdownClient::new(&self)
+    pub fn markdown(&self) -> MarkdownClient<'_> {
+        MarkdownClient::new(self)
     }
 
     // TODO meta endpoint
@@ -467,36 +484,42 @@ impl Client {
 
     // projects endpoint
     // https://developer.github.com/v3/projects/
-    pub fn projects(&self) -> ProjectClient {
-        ProjectClient::new(&self)
+    pub fn projects(&self) -> ProjectClient<'_> {
+        ProjectClient::new(self)
     }
 
-    pub fn pulls(&self) -> PullsClient {
-        PullsClient::new(&self)
+    pub fn pulls(&self) -> PullsClient<'_> {
+        PullsClient::new(self)
     }
 
-    pub fn rate_limit(&self) -> RateLimitClient {
-        RateLimitClient::new(&self)
+    pub fn rate_limit(&self) -> RateLimitClient<'_> {
+        RateLimitClient::new(self)
     }
 
-    pub fn reactions(&self) -> ReactionsClient {
-        ReactionsClient::new(&self)
+    pub fn reactions(&self) -> ReactionsClient<'_> {
+        ReactionsClient::new(self)
     }
 
     /// repos endpoint
     /// https://developer.github.com/v3/repos/
-    pub fn repos(&self) -> RepositoryClient {
-        RepositoryClient::new(&self)
+    pub fn repos(&self) -> RepositoryClient<'_> {
+        RepositoryClient::new(self)
     }
 
     // TODO search endpoint
     // https://developer.github.com/v3/search/
 
-    // TODO teams endpoint
+    // teams endpoint
     // https://developer.github.com/v3/teams/
+    pub fn teams(&self) -> TeamsClient<'_> {
+        TeamsClient::new(self)
+    }
 
-    // TODO users endpoint
+    // users endpoint
     // https://developer.github.com/v3/users/
+    pub fn users(&self) -> UsersClient<'_> {
+        UsersClient::new(self)
+    }
 }
 
 impl Default for Client {
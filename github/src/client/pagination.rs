@@ -91,48 +91,33 @@ pub struct PaginationCursorOptions {
     pub per_page: Option<usize>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum StateFilter {
+    #[default]
     Open,
     Closed,
     All,
 }
 
-impl Default for StateFilter {
-    fn default() -> Self {
-        StateFilter::Open
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum SortPages {
+    #[default]
     Created,
     Updated,
     Comments,
 }
 
-impl Default for SortPages {
-    fn default() -> Self {
-        SortPages::Created
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub enum SortDirection {
     #[serde(rename = "asc")]
     Ascending,
     #[serde(rename = "desc")]
+    #[default]
     Descending,
 }
 
-impl Default for SortDirection {
-    fn default() -> Self {
-        SortDirection::Descending
-    }
-}
-
 #[cfg(test)]
 mod test {
     use super::{Pagination, HEADER_LINK};
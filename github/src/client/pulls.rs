@@ -5,7 +5,7 @@ use crate::{
         MEDIA_TYPE_MULTI_LINE_COMMENTS_PREVIEW, MEDIA_TYPE_REACTIONS_PREVIEW,
         MEDIA_TYPE_UPDATE_PULL_REQUEST_BRANCH_PREVIEW,
     },
-    DateTime, PullRequest, Review, ReviewComment, Team, User,
+    DateTime, PullRequest, PullRequestFile, Review, ReviewComment, State, Team, User,
 };
 use serde::{Deserialize, Serialize};
 
@@ -56,28 +56,39 @@ pub struct NewPullRequest {
     pub draft: Option<bool>,
 }
 
+/// Only the fields which are set are updated, the rest are left as they are
 #[derive(Debug, Default, Serialize)]
 pub struct UpdatePullRequest {
     /// The title of the Pull Request
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
 
     /// The contents of the pull request cover letter
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
 
     /// The name of the branch where your changes are implemented. For cross-repository pull
     /// requests in the same network, namespace head with a user like this: username:branch.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub head: Option<String>,
 
     /// The name of the branch you want the changes pulled into. This should be an existing branch
     /// on the current repository. You cannot submit a pull request to one repository that requests
     /// a merge to a base of another repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub base: Option<String>,
 
     /// Indicates whether maintainers can modify the pull request
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub maintainer_can_modify: Option<bool>,
 
     /// Indicates whether the pull request is a draft
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub draft: Option<bool>,
+
+    /// Either `open` or `closed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<State>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -105,20 +116,15 @@ pub struct MergePullRequest {
     pub sha: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MergeMethod {
+    #[default]
     Merge,
     Squash,
     Rebase,
 }
 
-impl Default for MergeMethod {
-    fn default() -> Self {
-        MergeMethod::Merge
-    }
-}
-
 #[derive(Debug, Default, Deserialize)]
 pub struct MergePullRequestResponse {
     pub sha: String,
@@ -376,7 +382,6 @@ impl<'a> PullsClient<'a> {
         self.inner.empty(response).await
     }
 
-    //TODO add CommitFile type
     /// List files on a pull request
     ///
     /// GitHub API docs: https://developer.github.com/v3/pulls/#list-pull-requests-files
@@ -386,13 +391,11 @@ impl<'a> PullsClient<'a> {
         repo: &str,
         pull_number: u64,
         options: Option<PaginationOptions>,
-        //) -> Result<Response<Vec<CommitFile>>> {
-    ) -> Result<Response<()>> {
+    ) -> Result<Response<Vec<PullRequestFile>>> {
         let url = format!("repos/{}/{}/pulls/{}/files", owner, repo, pull_number);
         let response = self.inner.get(&url).query(&options).send().await?;
 
-        //self.inner.json(response).await
-        self.inner.empty(response).await
+        self.inner.json(response).await
     }
 
     /// Check if a pull request has been merged
@@ -0,0 +1,40 @@
+use crate::client::{Client, Response, Result};
+use serde::Deserialize;
+
+/// A user's membership of a team
+#[derive(Clone, Debug, Deserialize)]
+pub struct TeamMembership {
+    pub url: String,
+    /// Either member or maintainer
+    pub role: String,
+    /// Either active, or pending until the user accepts an invitation to join the team
+    pub state: String,
+}
+
+/// `TeamsClient` handles communication with the team related methods of the GitHub API.
+///
+/// GitHub API docs: https://developer.github.com/v3/teams/
+pub struct TeamsClient<'a> {
+    inner: &'a Client,
+}
+
+impl<'a> TeamsClient<'a> {
+    pub(super) fn new(client: &'a Client) -> Self {
+        Self { inner: client }
+    }
+
+    /// Get a user's membership of a team, which fails with a 404 if they aren't a member
+    ///
+    /// GitHub API docs: https://developer.github.com/v3/teams/members/#get-team-membership-for-a-user
+    pub async fn get_membership(
+        &self,
+        org: &str,
+        team_slug: &str,
+        user: &str,
+    ) -> Result<Response<TeamMembership>> {
+        let url = format!("orgs/{}/teams/{}/memberships/{}", org, team_slug, user);
+        let response = self.inner.get(&url).send().await?;
+
+        self.inner.json(response).await
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{
+    client::{Client, Response, Result},
+    User,
+};
+
+/// `UsersClient` handles communication with the user related methods of the GitHub API.
+///
+/// GitHub API docs: https://developer.github.com/v3/users/
+pub struct UsersClient<'a> {
+    inner: &'a Client,
+}
+
+impl<'a> UsersClient<'a> {
+    pub(super) fn new(client: &'a Client) -> Self {
+        Self { inner: client }
+    }
+
+    /// Get the user the client is authenticated as
+    ///
+    /// GitHub API docs: https://developer.github.com/v3/users/#get-the-authenticated-user
+    pub async fn get_authenticated(&self) -> Result<Response<User>> {
+        let response = self.inner.get("user").send().await?;
+
+        self.inner.json(response).await
+    }
+}
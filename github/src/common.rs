@@ -10,7 +10,7 @@ impl NodeId {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Oid(String);
 
 impl Oid {
@@ -29,6 +29,12 @@ impl fmt::Display for Oid {
 #[derive(Clone, Debug)]
 pub struct DateTime(chrono::DateTime<chrono::Utc>);
 
+impl From<DateTime> for std::time::SystemTime {
+    fn from(date_time: DateTime) -> Self {
+        date_time.0.into()
+    }
+}
+
 impl Serialize for DateTime {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -131,6 +131,67 @@ impl FromStr for EventType {
     }
 }
 
+impl EventType {
+    /// The name of the event type as it appears in the `X-GitHub-Event` header
+    pub fn as_str(&self) -> &'static str {
+        use EventType::*;
+
+        match self {
+            CheckRun => "check_run",
+            CheckSuite => "check_suite",
+            CommitComment => "commit_comment",
+            ContentReference => "content_reference",
+            Create => "create",
+            Delete => "delete",
+            DeployKey => "deploy_key",
+            Deployment => "deployment",
+            DeploymentStatus => "deployment_status",
+            Fork => "fork",
+            GithubAppAuthorization => "github_app_authorization",
+            Gollum => "gollum",
+            Installation => "installation",
+            InstallationRepositories => "installation_repositories",
+            IssueComment => "issue_comment",
+            Issues => "issues",
+            Label => "label",
+            MarketplacePurchase => "marketplace_purchase",
+            Member => "member",
+            Membership => "membership",
+            Meta => "meta",
+            Milestone => "milestone",
+            Organization => "organization",
+            OrgBlock => "org_block",
+            Package => "package",
+            PageBuild => "page_build",
+            Ping => "ping",
+            ProjectCard => "project_card",
+            ProjectColumn => "project_column",
+            Project => "project",
+            Public => "public",
+            PullRequest => "pull_request",
+            PullRequestReview => "pull_request_review",
+            PullRequestReviewComment => "pull_request_review_comment",
+            Push => "push",
+            RegistryPackage => "registry_package",
+            Release => "release",
+            RepositoryDispatch => "repository_dispatch",
+            Repository => "repository",
+            RepositoryImport => "repository_import",
+            RepositoryVulnerabilityAlert => "repository_vulnerability_alert",
+            SecurityAdvisory => "security_advisory",
+            Sponsorship => "sponsorship",
+            Star => "star",
+            Status => "status",
+            Team => "team",
+            TeamAdd => "team_add",
+            Watch => "watch",
+            WorkflowRun => "workflow_run",
+            Wildcard => "*",
+            Unknown => "unknown",
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for EventType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -261,9 +322,7 @@ impl Event {
             // TODO have an error type if we try to De a wildcard event payload since they don't
             // exist
             EventType::Wildcard => unimplemented!(),
-            EventType::Unknown => {
-                return Err(io::Error::new(io::ErrorKind::Other, "Unknown EventType"))
-            }
+            EventType::Unknown => return Err(io::Error::other("Unknown EventType")),
         };
 
         Ok(event)
@@ -367,7 +426,7 @@ impl Event {
             | Event::Status(StatusEvent { repository, .. })
             | Event::TeamAdd(TeamAddEvent { repository, .. })
             | Event::Watch(WatchEvent { repository, .. })
-            | Event::WorkflowRun(WorkflowRunEvent { repository, .. }) => Some(&repository),
+            | Event::WorkflowRun(WorkflowRunEvent { repository, .. }) => Some(repository),
 
             Event::Installation(_)
             | Event::InstallationRepositories(_)
@@ -545,12 +604,12 @@ pub struct GithubAppAuthorizationEvent {
 // Page represents a single Wiki page.
 #[derive(Clone, Debug, Deserialize)]
 pub struct Page {
-    page_name: String,
-    title: String,
-    summary: Option<String>,
-    action: String,
-    sha: Oid,
-    html_url: String,
+    pub page_name: String,
+    pub title: String,
+    pub summary: Option<String>,
+    pub action: String,
+    pub sha: Oid,
+    pub html_url: String,
 }
 
 /// GitHub API docs: https://developer.github.com/v3/activity/events/types/#gollumevent
@@ -608,11 +667,7 @@ pub enum IssueCommentEventAction {
 
 impl IssueCommentEventAction {
     pub fn is_created(&self) -> bool {
-        if let IssueCommentEventAction::Created = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, IssueCommentEventAction::Created)
     }
 }
 
@@ -955,11 +1010,7 @@ pub enum PullRequestReviewEventAction {
 
 impl PullRequestReviewEventAction {
     pub fn is_submitted(&self) -> bool {
-        if let PullRequestReviewEventAction::Submitted = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, PullRequestReviewEventAction::Submitted)
     }
 }
 
@@ -989,11 +1040,7 @@ pub enum PullRequestReviewCommentEventAction {
 
 impl PullRequestReviewCommentEventAction {
     pub fn is_created(&self) -> bool {
-        if let PullRequestReviewCommentEventAction::Created = self {
-            true
-        } else {
-            false
-        }
+        matches!(self, PullRequestReviewCommentEventAction::Created)
     }
 }
 
@@ -1191,12 +1238,25 @@ pub struct StatusEvent {
     pub description: Option<String>,
     pub target_url: Option<String>,
     pub context: String,
-    // branches: ???,
+    /// The branches containing the commit, which may or may not be their head
+    #[serde(default)]
+    pub branches: Vec<StatusEventBranch>,
     // commit: ???,
     pub repository: Repository,
     pub sender: User,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusEventBranch {
+    pub name: String,
+    pub commit: StatusEventBranchCommit,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusEventBranchCommit {
+    pub sha: Oid,
+}
+
 /// Triggered when an organization's team is created, deleted, edited, added_to_repository, or
 /// removed_from_repository
 ///
@@ -1285,10 +1345,18 @@ pub struct WorkflowRunEvent {
 #[cfg(test)]
 mod test {
     use super::{
-        CheckRunEvent, CheckSuiteEvent, IssueCommentEvent, IssueEvent,
+        CheckRunEvent, CheckSuiteEvent, EventType, IssueCommentEvent, IssueEvent,
         PullRequestReviewCommentEvent, PullRequestReviewEvent, PushEvent, StatusEvent,
     };
 
+    #[test]
+    fn event_type_round_trip() {
+        for name in &["pull_request", "pull_request_review", "status", "workflow_run", "*"] {
+            let event_type: EventType = name.parse().unwrap();
+            assert_eq!(event_type.as_str(), *name);
+        }
+    }
+
     #[test]
     fn push_event() {
         const PUSH_JSON: &str = include_str!("../test-input/push-event.json");
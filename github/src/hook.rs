@@ -4,33 +4,33 @@ use serde::Deserialize;
 #[derive(Clone, Debug, Deserialize)]
 pub struct Hook {
     #[serde(rename = "type")]
-    hook_type: String,
-    id: u64,
-    name: String,
-    active: bool,
-    events: Vec<EventType>,
-    config: HookConfig,
-    updated_at: DateTime,
-    created_at: DateTime,
-    url: String,
-    test_url: String,
-    ping_url: String,
-    last_response: HookResponse,
+    pub hook_type: String,
+    pub id: u64,
+    pub name: String,
+    pub active: bool,
+    pub events: Vec<EventType>,
+    pub config: HookConfig,
+    pub updated_at: DateTime,
+    pub created_at: DateTime,
+    pub url: String,
+    pub test_url: String,
+    pub ping_url: String,
+    pub last_response: HookResponse,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct HookConfig {
-    content_type: String,
-    insecure_ssl: String,
-    secret: Option<String>,
-    url: String,
+    pub content_type: String,
+    pub insecure_ssl: String,
+    pub secret: Option<String>,
+    pub url: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct HookResponse {
-    code: Option<String>,
-    status: String,
-    message: Option<String>,
+    pub code: Option<String>,
+    pub status: String,
+    pub message: Option<String>,
 }
 
 #[cfg(test)]
@@ -44,7 +44,7 @@ pub struct ProjectCard {
 
 impl ProjectCard {
     pub fn column_id(&self) -> Option<u64> {
-        self.column_url.split('/').last()?.parse().ok()
+        self.column_url.split('/').next_back()?.parse().ok()
     }
 
     pub fn issue_number(&self) -> Option<u64> {
@@ -53,7 +53,7 @@ impl ProjectCard {
         }
 
         if let Some(url) = &self.content_url {
-            url.split('/').last()?.parse().ok()
+            url.split('/').next_back()?.parse().ok()
         } else {
             None
         }
@@ -1,7 +1,7 @@
 use super::{
     DateTime, Label, Milestone, NodeId, Oid, ReactionSummary, Repository, State, Team, User,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct CommitRef {
@@ -13,7 +13,7 @@ pub struct CommitRef {
     pub repo: Option<Repository>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum PullRequestState {
     Open,
@@ -71,6 +71,22 @@ pub struct PullRequest {
     pub changed_files: Option<u64>,
 }
 
+/// A file changed by a pull request
+#[derive(Clone, Debug, Deserialize)]
+pub struct PullRequestFile {
+    pub sha: Option<String>,
+    pub filename: String,
+    /// One of added, removed, modified, renamed, copied, changed, or unchanged
+    pub status: String,
+    pub additions: u64,
+    pub deletions: u64,
+    pub changes: u64,
+    /// The file's name before it was renamed
+    pub previous_filename: Option<String>,
+    /// The diff of the file, missing for binary files and for diffs too large for Github to show
+    pub patch: Option<String>,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ReviewState {
@@ -28,17 +28,17 @@ pub struct Reaction {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct ReactionSummary {
-    total_count: usize,
+    pub total_count: usize,
     #[serde(rename = "+1")]
-    thumbs_up: usize,
+    pub thumbs_up: usize,
     #[serde(rename = "-1")]
-    thumbs_down: usize,
-    laugh: usize,
-    confused: usize,
-    heart: usize,
-    hooray: usize,
-    rocket: usize,
-    eyes: usize,
+    pub thumbs_down: usize,
+    pub laugh: usize,
+    pub confused: usize,
+    pub heart: usize,
+    pub hooray: usize,
+    pub rocket: usize,
+    pub eyes: usize,
 
-    url: String,
+    pub url: String,
 }
@@ -33,35 +33,35 @@ pub struct User {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Team {
-    id: u64,
-    node_id: NodeId,
-    url: String,
-    html_url: String,
-    name: String,
-    slug: String,
-    description: Option<String>,
-    privacy: String,
-    permission: String,
-    members_url: String,
-    repositories_url: String,
-    parent: Option<Box<Team>>,
+    pub id: u64,
+    pub node_id: NodeId,
+    pub url: String,
+    pub html_url: String,
+    pub name: String,
+    pub slug: String,
+    pub description: Option<String>,
+    pub privacy: String,
+    pub permission: String,
+    pub members_url: String,
+    pub repositories_url: String,
+    pub parent: Option<Box<Team>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Pusher {
-    name: String,
-    email: String,
+    pub name: String,
+    pub email: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Key {
-    id: u64,
-    key: String,
-    url: String,
-    title: String,
-    read_only: bool,
-    created_at: DateTime,
-    verified: bool,
+    pub id: u64,
+    pub key: String,
+    pub url: String,
+    pub title: String,
+    pub read_only: bool,
+    pub created_at: DateTime,
+    pub verified: bool,
 }
 
 #[cfg(test)]
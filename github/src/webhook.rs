@@ -27,10 +27,10 @@ pub struct Webhook {
 }
 
 impl Webhook {
-    pub fn check_signature(&self, key: Option<&[u8]>) -> bool {
-        // Skip validation if no key
-        // FIXME: Refactor code so it's required
-        if key.is_none() {
+    /// Check the webhook's SHA-256 signature against each of `keys`, any of which may have signed
+    /// it, e.g. while the webhook secret is being rotated
+    pub fn check_signature(&self, keys: &[&[u8]]) -> bool {
+        if keys.is_empty() {
             warn!(
                 "[webhook {}] No secret specified; signature ignored",
                 self.delivery_id
@@ -38,23 +38,69 @@ impl Webhook {
             return false;
         }
 
-        if let Some(ref signature) = self.signature_256 {
-            let hash = hex::encode(hmac_sha256::HMAC::mac(&self.body, key.unwrap()));
-            let signature = &signature["sha256=".len()..];
+        let signature = match self
+            .signature_256
+            .as_deref()
+            .and_then(|signature| signature.strip_prefix("sha256="))
+            .and_then(|signature| hex::decode(signature).ok())
+        {
+            Some(signature) => signature,
+            None => {
+                // There is no valid signature, reject it
+                warn!("[webhook {}] No signature present", self.delivery_id);
+                return false;
+            }
+        };
 
-            debug!(
-                "[webhook {}] SHA-256 Found: {} Expected: {}",
-                self.delivery_id, signature, hash
-            );
-            signature == hash
-        } else {
-            // There is no signature, reject it
-            warn!("[webhook {}] No signature present", self.delivery_id);
-            false
-        }
+        debug!(
+            "[webhook {}] SHA-256 Found: {}",
+            self.delivery_id,
+            hex::encode(&signature)
+        );
+        keys.iter().any(|key| {
+            let hash = hmac_sha256::HMAC::mac(&self.body, key);
+            constant_time_eq(&hash, &signature)
+        })
     }
 
     pub fn to_event(&self) -> Result<Event, std::io::Error> {
         Event::from_json(self.event_type, &self.body)
     }
 }
+
+/// Compare every byte so that the time taken doesn't leak how much of a signature matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn webhook(signature_256: Option<String>) -> Webhook {
+        Webhook {
+            event_type: EventType::Ping,
+            delivery_id: "delivery".to_owned(),
+            signature: None,
+            signature_256,
+            body: b"{}".to_vec(),
+        }
+    }
+
+    #[test]
+    fn signature() {
+        let signed_with = |key: &[u8]| {
+            let hash = hmac_sha256::HMAC::mac(b"{}", key);
+            webhook(Some(format!("sha256={}", hex::encode(hash))))
+        };
+
+        assert!(signed_with(b"new").check_signature(&[b"new", b"old"]));
+        assert!(signed_with(b"old").check_signature(&[b"new", b"old"]));
+        assert!(!signed_with(b"other").check_signature(&[b"new", b"old"]));
+        assert!(!signed_with(b"new").check_signature(&[]));
+
+        assert!(!webhook(None).check_signature(&[b"new"]));
+        assert!(!webhook(Some("sha256".to_owned())).check_signature(&[b"new"]));
+        assert!(!webhook(Some("sha256=zz".to_owned())).check_signature(&[b"new"]));
+    }
+}